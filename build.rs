@@ -1,11 +1,11 @@
-// Copyright (C) 2025 Roberto Viola <rviola@vicomtech.org>
-//
-// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
-// If a copy of the MPL was not distributed with this file, You can obtain one at
-// <https://mozilla.org/MPL/2.0/>.
-//
-// SPDX-License-Identifier: MPL-2.0
-
-fn main() {
-	gst_plugin_version_helper::info()
+// Copyright (C) 2025 Roberto Viola <rviola@vicomtech.org>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+fn main() {
+	gst_plugin_version_helper::info()
 }
\ No newline at end of file
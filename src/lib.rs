@@ -11,7 +11,8 @@ use gst::glib;
 mod sink;
 
 pub fn plugin_init(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
-	env_logger::init();
+	// The application or another plugin may have installed a logger already
+	let _ = env_logger::try_init();
 	sink::register(plugin)?;
 
 	Ok(())
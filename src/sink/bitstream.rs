@@ -0,0 +1,370 @@
+// Copyright (C) 2025 Roberto Viola <rviola@vicomtech.org>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+// Brands of the styp box added with ensure-styp, CMAF and DASH segment
+const STYP_BRANDS: [&[u8; 4]; 2] = [b"cmfs", b"msdh"];
+// Sampling rates of the AAC sampling frequency indices
+const AAC_SAMPLING_RATES: [u32; 13] = [96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350];
+
+/// Major and compatible brands of the ftyp box starting an init segment.
+pub(crate) fn ftyp_brands(init: &[u8]) -> Option<Vec<[u8; 4]>> {
+	let size = u32::from_be_bytes(init.get(0..4)?.try_into().ok()?) as usize;
+	if init.get(4..8)? != b"ftyp" || size < 16 {
+		return None;
+	}
+	let ftyp = init.get(8..size)?;
+
+	// The minor version sits between the major and compatible brands
+	let major = ftyp[0..4].try_into().ok()?;
+	let compatible = ftyp[8..].chunks_exact(4).filter_map(|brand| brand.try_into().ok());
+
+	Some(std::iter::once(major).chain(compatible).collect())
+}
+
+/// styp box of a CMAF media segment, also compatible with DASH.
+pub(crate) fn styp_box() -> Vec<u8> {
+	let size = 16 + 4 * STYP_BRANDS.len() as u32;
+	let mut styp = Vec::with_capacity(size as usize);
+
+	styp.extend_from_slice(&size.to_be_bytes());
+	styp.extend_from_slice(b"styp");
+	styp.extend_from_slice(STYP_BRANDS[0]);
+	// minor_version
+	styp.extend_from_slice(&0u32.to_be_bytes());
+	for brand in STYP_BRANDS {
+		styp.extend_from_slice(brand);
+	}
+
+	styp
+}
+
+/// Break duration of a SCTE-35 splice_info_section, `None` if it's neither
+/// a splice_insert nor a time_signal. Encrypted commands are splice points
+/// of unknown duration.
+pub(crate) fn scte35_splice_point(section: &[u8]) -> Option<Option<gst::ClockTime>> {
+	if *section.first()? != 0xfc || section.len() < 14 {
+		return None;
+	}
+	if section[4] & 0x80 != 0 {
+		return Some(None);
+	}
+
+	// splice_time(), 33 bits of PTS only if time_specified_flag
+	let splice_time_len = |byte: u8| if byte & 0x80 != 0 { 5 } else { 1 };
+
+	match section[13] {
+		// splice_insert
+		0x05 => {
+			let command = section.get(14..)?;
+			// splice_event_cancel_indicator
+			if command.get(4)? & 0x80 != 0 {
+				return None;
+			}
+			let flags = *command.get(5)?;
+			let (program_splice, duration_flag, immediate) = (flags & 0x40 != 0, flags & 0x20 != 0, flags & 0x10 != 0);
+
+			let mut pos = 6;
+			if program_splice && !immediate {
+				pos += splice_time_len(*command.get(pos)?);
+			}
+			if !program_splice {
+				let component_count = *command.get(pos)?;
+				pos += 1;
+				for _ in 0..component_count {
+					pos += 1; // component_tag
+					if !immediate {
+						pos += splice_time_len(*command.get(pos)?);
+					}
+				}
+			}
+			if !duration_flag {
+				return Some(None);
+			}
+
+			let break_duration = command.get(pos..pos + 5)?;
+			let ticks = ((break_duration[0] as u64 & 0x01) << 32)
+				| u32::from_be_bytes(break_duration[1..5].try_into().ok()?) as u64;
+			Some(Some(gst::ClockTime::from_nseconds(ticks * 100_000 / 9)))
+		}
+		// time_signal
+		0x06 => Some(None),
+		_ => None,
+	}
+}
+
+/// Audio object type of an AAC AudioSpecificConfig, escaped values above
+/// 31 taking 6 more bits, and the output sampling rate of the SBR tool
+/// signalled explicitly by HE-AAC and HE-AACv2.
+pub(crate) fn aac_config(asc: &[u8]) -> Option<(u8, Option<u32>)> {
+	let mut pos = 0;
+	let mut bits = |n: usize| {
+		let mut value = 0u32;
+		for _ in 0..n {
+			let byte = asc.get(pos / 8)?;
+			value = (value << 1) | ((byte >> (7 - pos % 8)) & 1) as u32;
+			pos += 1;
+		}
+		Some(value)
+	};
+
+	let object_type = match bits(5)? {
+		31 => 32 + bits(6)?,
+		object_type => object_type,
+	};
+	if object_type != 5 && object_type != 29 {
+		return Some((object_type as u8, None));
+	}
+
+	// Rates without a sampling frequency index take 24 bits
+	fn sampling_rate(bits: &mut impl FnMut(usize) -> Option<u32>) -> Option<u32> {
+		match bits(4)? {
+			15 => bits(24),
+			index => AAC_SAMPLING_RATES.get(index as usize).copied(),
+		}
+	}
+	// Core rate and channelConfiguration, then the extension rate
+	let sbr_sampling_rate = (|| {
+		sampling_rate(&mut bits)?;
+		bits(4)?;
+		sampling_rate(&mut bits)
+	})();
+
+	Some((object_type as u8, sbr_sampling_rate))
+}
+
+/// Reads the bits of an H.264 RBSP, emulation prevention bytes removed.
+struct BitReader {
+	data: Vec<u8>,
+	pos: usize,
+}
+
+impl BitReader {
+	fn new(nal: &[u8]) -> Self {
+		let mut data = Vec::with_capacity(nal.len());
+		for &byte in nal {
+			if byte == 0x03 && data.ends_with(&[0, 0]) {
+				continue;
+			}
+			data.push(byte);
+		}
+
+		Self { data, pos: 0 }
+	}
+
+	fn bit(&mut self) -> Option<u32> {
+		let byte = self.data.get(self.pos / 8)?;
+		let bit = (byte >> (7 - self.pos % 8)) & 1;
+		self.pos += 1;
+		Some(bit as u32)
+	}
+
+	fn bits(&mut self, count: u32) -> Option<u32> {
+		(0..count).try_fold(0, |value, _| Some((value << 1) | self.bit()?))
+	}
+
+	/// Exp-Golomb coded unsigned value
+	fn ue(&mut self) -> Option<u32> {
+		let mut zeros = 0;
+		while self.bit()? == 0 {
+			zeros += 1;
+			if zeros > 31 {
+				return None;
+			}
+		}
+
+		Some(((1u64 << zeros) - 1 + self.bits(zeros)? as u64) as u32)
+	}
+
+	/// Exp-Golomb coded signed value
+	fn se(&mut self) -> Option<i32> {
+		let value = self.ue()?;
+		Some(if value % 2 == 1 { value.div_ceil(2) as i32 } else { -((value / 2) as i32) })
+	}
+}
+
+/// Cropped resolution in the first SPS of an avcC codec_data.
+pub(crate) fn h264_dimensions(codec_data: &[u8]) -> Option<(u32, u32)> {
+	if codec_data.len() < 8 || codec_data[5] & 0x1f == 0 {
+		return None;
+	}
+	let sps_len = u16::from_be_bytes([codec_data[6], codec_data[7]]) as usize;
+	// Skip the NAL header
+	let sps = codec_data.get(9..8 + sps_len)?;
+	let mut r = BitReader::new(sps);
+
+	let profile_idc = r.bits(8)?;
+	r.bits(16)?; // constraint flags and level_idc
+	r.ue()?; // seq_parameter_set_id
+
+	let mut chroma_format_idc = 1;
+	let mut separate_colour_plane = 0;
+	if matches!(profile_idc, 100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 135) {
+		chroma_format_idc = r.ue()?;
+		if chroma_format_idc == 3 {
+			separate_colour_plane = r.bit()?;
+		}
+		r.ue()?; // bit_depth_luma_minus8
+		r.ue()?; // bit_depth_chroma_minus8
+		r.bit()?; // qpprime_y_zero_transform_bypass_flag
+		if r.bit()? == 1 {
+			let lists = if chroma_format_idc == 3 { 12 } else { 8 };
+			for i in 0..lists {
+				if r.bit()? == 0 {
+					continue;
+				}
+				let size = if i < 6 { 16 } else { 64 };
+				let (mut last, mut next) = (8i32, 8i32);
+				for _ in 0..size {
+					if next != 0 {
+						next = (last + r.se()? + 256) % 256;
+					}
+					if next != 0 {
+						last = next;
+					}
+				}
+			}
+		}
+	}
+
+	r.ue()?; // log2_max_frame_num_minus4
+	match r.ue()? {
+		0 => {
+			r.ue()?; // log2_max_pic_order_cnt_lsb_minus4
+		}
+		1 => {
+			r.bit()?; // delta_pic_order_always_zero_flag
+			r.se()?; // offset_for_non_ref_pic
+			r.se()?; // offset_for_top_to_bottom_field
+			for _ in 0..r.ue()? {
+				r.se()?; // offset_for_ref_frame
+			}
+		}
+		_ => (),
+	}
+	r.ue()?; // max_num_ref_frames
+	r.bit()?; // gaps_in_frame_num_value_allowed_flag
+
+	let width_in_mbs = r.ue()?.checked_add(1)?;
+	let height_in_map_units = r.ue()?.checked_add(1)?;
+	let frame_mbs_only = r.bit()?;
+	if frame_mbs_only == 0 {
+		r.bit()?; // mb_adaptive_frame_field_flag
+	}
+	r.bit()?; // direct_8x8_inference_flag
+
+	let (mut crop_x, mut crop_y) = (0, 0);
+	if r.bit()? == 1 {
+		let (left, right, top, bottom) = (r.ue()?, r.ue()?, r.ue()?, r.ue()?);
+		// Crop units depend on the chroma subsampling
+		let (unit_x, unit_y) = match (separate_colour_plane, chroma_format_idc) {
+			(0, 1) => (2, 2 * (2 - frame_mbs_only)),
+			(0, 2) => (2, 2 - frame_mbs_only),
+			_ => (1, 2 - frame_mbs_only),
+		};
+		crop_x = left.checked_add(right)?.checked_mul(unit_x)?;
+		crop_y = top.checked_add(bottom)?.checked_mul(unit_y)?;
+	}
+
+	let width = width_in_mbs.checked_mul(16)?.checked_sub(crop_x)?;
+	let height = height_in_map_units.checked_mul(16 * (2 - frame_mbs_only))?.checked_sub(crop_y)?;
+
+	Some((width, height))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+	use super::*;
+
+	#[test]
+	fn ftyp_brands_of_init_segment() {
+		let mut init = Vec::new();
+		init.extend_from_slice(&24u32.to_be_bytes());
+		init.extend_from_slice(b"ftypcmf2");
+		init.extend_from_slice(&0u32.to_be_bytes());
+		init.extend_from_slice(b"iso6cmfc");
+		init.extend_from_slice(b"\0\0\0\x08moov");
+		assert_eq!(ftyp_brands(&init), Some(vec![*b"cmf2", *b"iso6", *b"cmfc"]));
+
+		assert_eq!(ftyp_brands(b"\0\0\0\x08moov"), None);
+		assert_eq!(ftyp_brands(&init[..20]), None);
+	}
+
+	/// splice_info_section of a command
+	pub(crate) fn splice_info_section(command_type: u8, command: &[u8]) -> Vec<u8> {
+		let mut section = vec![0xfc, 0x30, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0xf0, command.len() as u8, command_type];
+		section.extend_from_slice(command);
+		section
+	}
+
+	#[test]
+	fn scte35_splice_points() {
+		// Out of network at a PTS, for 30 s
+		let splice_insert = [
+			0x00, 0x00, 0x00, 0x01, 0x7f, 0xef, 0xfe, 0x00, 0x00, 0x00, 0x00, 0xfe, 0x00, 0x29, 0x32, 0xe0, 0x00, 0x01, 0x00, 0x00,
+		];
+		assert_eq!(
+			scte35_splice_point(&splice_info_section(0x05, &splice_insert)),
+			Some(Some(gst::ClockTime::from_seconds(30)))
+		);
+
+		// Immediate, without a duration
+		let immediate = [0x00, 0x00, 0x00, 0x02, 0x7f, 0x5f, 0x00, 0x01, 0x00, 0x00];
+		assert_eq!(scte35_splice_point(&splice_info_section(0x05, &immediate)), Some(None));
+
+		// Cancelled events are no splice point
+		let cancelled = [0x00, 0x00, 0x00, 0x03, 0xff];
+		assert_eq!(scte35_splice_point(&splice_info_section(0x05, &cancelled)), None);
+
+		assert_eq!(scte35_splice_point(&splice_info_section(0x06, &[0xfe, 0x00, 0x00, 0x00, 0x00])), Some(None));
+		// splice_null
+		assert_eq!(scte35_splice_point(&splice_info_section(0x00, &[])), None);
+
+		let mut encrypted = splice_info_section(0x05, &splice_insert);
+		encrypted[4] |= 0x80;
+		assert_eq!(scte35_splice_point(&encrypted), Some(None));
+
+		assert_eq!(scte35_splice_point(&[0x00; 20]), None);
+		assert_eq!(scte35_splice_point(&splice_info_section(0x05, &splice_insert)[..10]), None);
+	}
+
+	#[test]
+	fn aac_configs() {
+		// LC at 48 kHz, HE-AAC and HE-AACv2 at 24 kHz decoding to 48 kHz, and
+		// USAC behind the escape
+		assert_eq!(aac_config(&[0x11, 0x90]), Some((2, None)));
+		assert_eq!(aac_config(&[0x2b, 0x11, 0x88]), Some((5, Some(48000))));
+		assert_eq!(aac_config(&[0xeb, 0x09, 0x88]), Some((29, Some(48000))));
+		assert_eq!(aac_config(&[0xf9, 0x46, 0x40]), Some((42, None)));
+		// Rates without an index, and a truncated extension
+		assert_eq!(aac_config(&[0x2f, 0x80, 0x2e, 0xe0, 0x17, 0x80, 0x5d, 0xc0, 0x08]), Some((5, Some(48000))));
+		assert_eq!(aac_config(&[0x2b, 0x11]), Some((5, None)));
+		assert_eq!(aac_config(&[0xf9]), None);
+		assert_eq!(aac_config(&[]), None);
+	}
+
+	// High profile SPS of 1280x720
+	pub(crate) const HIGH_AVCC: [u8; 25] = [
+		0x01, 0x64, 0x00, 0x28, 0xff, 0xe1, 0x00, 0x0a, 0x67, 0x64, 0x00, 0x28, 0xac, 0xe8, 0x05, 0x00, 0x5b, 0x90, 0x01, 0x00, 0x04,
+		0x68, 0xce, 0x38, 0x80,
+	];
+
+	#[test]
+	fn h264_sps_dimensions() {
+		// Baseline 1920x1088 cropped to 1080
+		let baseline = [
+			0x01, 0x42, 0x00, 0x28, 0xff, 0xe1, 0x00, 0x0a, 0x67, 0x42, 0x00, 0x28, 0xf4, 0x03, 0xc0, 0x11, 0x3f, 0x2a, 0x01, 0x00,
+			0x04, 0x68, 0xce, 0x38, 0x80,
+		];
+		assert_eq!(h264_dimensions(&baseline), Some((1920, 1080)));
+		assert_eq!(h264_dimensions(&HIGH_AVCC), Some((1280, 720)));
+
+		// No SPS, or one cut short
+		assert_eq!(h264_dimensions(&[0x01, 0x64, 0x00, 0x28, 0xff, 0xe0, 0x00, 0x00]), None);
+		assert_eq!(h264_dimensions(&HIGH_AVCC[..12]), None);
+	}
+}
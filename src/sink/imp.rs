@@ -1,608 +1,5089 @@
-// Copyright (C) 2025 Roberto Viola <rviola@vicomtech.org>
-//
-// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
-// If a copy of the MPL was not distributed with this file, You can obtain one at
-// <https://mozilla.org/MPL/2.0/>.
-//
-// SPDX-License-Identifier: MPL-2.0
-
-use gst::glib;
-use gst::prelude::*;
-use gst::subclass::prelude::*;
-use gst_base::subclass::prelude::*;
-use std::sync::LazyLock;
-use std::sync::Mutex;
-use std::io::Write;
-use std::fs::File;
-use std::path::Path;
-use std::collections::HashMap;
-
-const DEFAULT_TARGET_DURATION: u32 = 10;
-const DEFAULT_LATENCY: gst::ClockTime =
-    gst::ClockTime::from_mseconds((DEFAULT_TARGET_DURATION * 500) as u64);
-const DEFAULT_SYNC: bool = true;
-const DEFAULT_LOCATION: &str = "manifest.mpd";
-const DEFAULT_INIT_LOCATION: &str = "init.cmfi";
-const DEFAULT_SEGMENT_LOCATION: &str = "segment_%d.cmfv";
-
-struct DashCmafSinkSettings {
-    location: String,
-    init_location: String,
-	segment_location: String,
-    target_duration: u32,
-    sync: bool,
-	latency: gst::ClockTime,
-}
-
-struct DashCmafSinkStream {
-    segment_idx: usize,
-	start_time: Option<gst::ClockTime>,
-    end_time: Option<gst::ClockTime>,
-	bandwidth: u64,
-    cmafmux: gst::Element,
-    appsink: gst_app::AppSink,
-}
-
-#[derive(Default)]
-pub struct DashCmafSink {
-    settings: Mutex<DashCmafSinkSettings>,
-	streams: Mutex<HashMap<String, DashCmafSinkStream>>,
-}
-
-#[glib::object_subclass]
-impl ObjectSubclass for DashCmafSink {
-	const NAME: &'static str = "DashCmafSink";
-	type Type = super::DashCmafSink;
-	type ParentType = gst::Bin;
-}
-
-impl Default for DashCmafSinkSettings {
-    fn default() -> Self {
-        Self {
-			location: String::from(DEFAULT_LOCATION),
-            init_location: String::from(DEFAULT_INIT_LOCATION),
-            segment_location: String::from(DEFAULT_SEGMENT_LOCATION),
-            target_duration: DEFAULT_TARGET_DURATION,
-            sync: DEFAULT_SYNC,
-            latency: DEFAULT_LATENCY,
-        }
-    }
-}
-
-impl Default for DashCmafSinkStream {
-    fn default() -> Self {
-		let cmafmux = gst::ElementFactory::make("cmafmux")
-			.property(
-				"fragment-duration",
-				gst::ClockTime::from_seconds(DEFAULT_TARGET_DURATION as u64),
-			)
-			.property("latency", DEFAULT_LATENCY)
-			.build()
-			.expect("Could not create cmafmux");
-
-		let appsink = gst_app::AppSink::builder()
-			.buffer_list(true)
-			.sync(DEFAULT_SYNC)
-			.build();
-
-        Self {
-			segment_idx: 0,
-			start_time: Some(gst::ClockTime::from_seconds(0)),
-			end_time: Some(gst::ClockTime::from_seconds(0)),
-			bandwidth: 0,
-			cmafmux,
-			appsink,
-        }
-    }
-}
-
-impl BinImpl for DashCmafSink {}
-
-impl ObjectImpl for DashCmafSink {
-	fn properties() -> &'static [glib::ParamSpec] {
-        static PROPERTIES: LazyLock<Vec<glib::ParamSpec>> = LazyLock::new(|| {
-            vec![
-				glib::ParamSpecString::builder("location")
-                    .nick("MPD Location")
-                    .blurb("Path to write manifest (MPD)")
-                    .default_value(Some(DEFAULT_LOCATION))
-                    .build(),
-                glib::ParamSpecString::builder("init-location")
-                    .nick("Init Segment Location")
-                    .blurb("Path to write init segment")
-                    .default_value(Some(DEFAULT_INIT_LOCATION))
-                    .build(),
-				glib::ParamSpecString::builder("segment-location")
-                    .nick("Segment Location")
-                    .blurb("Template for CMAF segment files")
-                    .default_value(Some(DEFAULT_SEGMENT_LOCATION))
-                    .build(),
-                glib::ParamSpecUInt::builder("target-duration")
-                    .nick("Target Duration")
-                    .blurb("Target duration in seconds for each segment")
-                    .default_value(DEFAULT_TARGET_DURATION)
-                    .mutable_ready()
-                    .build(),
-                glib::ParamSpecBoolean::builder("sync")
-                    .nick("Sync")
-                    .blurb("Whether to sync appsink to the pipeline clock")
-                    .default_value(DEFAULT_SYNC)
-                    .build(),
-                glib::ParamSpecUInt64::builder("latency")
-                    .nick("Latency")
-                    .blurb("Latency in nanoseconds")
-                    .default_value(DEFAULT_LATENCY.nseconds())
-                    .build(),
-            ]
-        });
-        PROPERTIES.as_ref()
-    }
-
-	fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
-		let mut settings = self.settings.lock().unwrap();
-	
-		match pspec.name() {
-			"location" => {
-				settings.location = value
-					.get::<Option<String>>()
-					.expect("type checked upstream")
-					.unwrap_or_else(|| DEFAULT_LOCATION.into());
-			}
-			"init-location" => {
-				settings.init_location = value
-					.get::<Option<String>>()
-					.expect("type checked upstream")
-					.unwrap_or_else(|| DEFAULT_INIT_LOCATION.into());
-			}
-			"segment-location" => {
-				settings.segment_location = value
-					.get::<Option<String>>()
-					.expect("type checked upstream")
-					.unwrap_or_else(|| DEFAULT_SEGMENT_LOCATION.into());
-			}
-			"target-duration" => {
-				settings.target_duration = value.get().expect("type checked upstream");
-			}
-			"sync" => {
-				settings.sync = value.get().expect("type checked upstream");
-			}
-			"latency" => {
-				let latency_ns = value.get::<u64>().expect("type checked upstream");
-				settings.latency = gst::ClockTime::from_nseconds(latency_ns);
-			}
-			_ => unimplemented!(),
-		}
-	}
-
-	fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
-		let settings = self.settings.lock().unwrap();
-	
-		match pspec.name() {
-			"location" => settings.location.to_value(),
-			"init-location" => settings.init_location.to_value(),
-			"segment-location" => settings.segment_location.to_value(),
-			"target-duration" => settings.target_duration.to_value(),
-			"sync" => settings.sync.to_value(),
-			"latency" => settings.latency.nseconds().to_value(),
-			_ => unimplemented!("Property {} not implemented", pspec.name()),
-		}
-	}
-
-    fn constructed(&self) {
-        self.parent_constructed();
-    }
-}
-
-impl GstObjectImpl for DashCmafSink {}
-
-impl ElementImpl for DashCmafSink {
-	fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
-		static ELEMENT_METADATA: LazyLock<gst::subclass::ElementMetadata> = LazyLock::new(|| {
-			gst::subclass::ElementMetadata::new(
-				"DASH CMAF Sink",
-				"Sink/Network/Dash",
-				"Handles H264/AAC media buffers",
-				"Roberto Viola <rviola@vicomtech.org>",
-			)
-		});
-		Some(&*ELEMENT_METADATA)
-	}
-
-    fn pad_templates() -> &'static [gst::PadTemplate] {
-        static PAD_TEMPLATES: LazyLock<Vec<gst::PadTemplate>> = LazyLock::new(|| {
-            let pad_template = gst::PadTemplate::new(
-                "sink_%u",
-                gst::PadDirection::Sink,
-                gst::PadPresence::Request,
-                &[
-                    gst::Structure::builder("video/x-h264")
-                        .field("stream-format", gst::List::new(["avc", "avc3"]))
-                        .field("alignment", "au")
-                        .field("width", gst::IntRange::new(1, u16::MAX as i32))
-                        .field("height", gst::IntRange::new(1, u16::MAX as i32))
-                        .build(),
-                    gst::Structure::builder("audio/mpeg")
-                        .field("mpegversion", 4i32)
-                        .field("stream-format", "raw")
-                        .field("channels", gst::IntRange::new(1, u16::MAX as i32))
-                        .field("rate", gst::IntRange::new(1, i32::MAX))
-                        .build(),
-                ]
-                .into_iter()
-                .collect::<gst::Caps>(),
-            )
-            .unwrap();
-
-            vec![pad_template]
-        });
-
-        PAD_TEMPLATES.as_ref()
-    }
-
-	fn request_new_pad(
-		&self,
-		_template: &gst::PadTemplate,
-		_name: Option<&str>,
-		_caps: Option<&gst::Caps>,
-	) -> Option<gst::Pad> {
-		let pad_name = _name.map(|s| s.to_string()).unwrap_or_else(|| {
-			format!("sink_{}", self.streams.lock().unwrap().len())
-		});
-	
-		gst::info!(CAT, imp = self, "Requesting new pad: {pad_name}");
-	
-		// Create stream components
-		let stream = DashCmafSinkStream::default();
-		let settings = self.settings.lock().unwrap();
-		let obj = self.obj();
-
-		stream.cmafmux.set_property(
-			"fragment-duration",
-			gst::ClockTime::from_seconds(settings.target_duration as u64),
-		);
-		stream.cmafmux.set_property("latency", settings.latency);
-		stream.appsink.set_property("sync", settings.sync);
-	
-		// Add and link elements
-		obj.add_many([&stream.cmafmux, stream.appsink.upcast_ref()]).ok()?;
-		stream.cmafmux.link(&stream.appsink).ok()?;
-	
-		// Ghost pad
-		let target_pad = stream.cmafmux.static_pad("sink")?;
-		// let gpad = gst::GhostPad::with_target(&target_pad).ok()?;
-		let gpad = gst::GhostPad::builder(gst::PadDirection::Sink)
-			.name(&pad_name) 
-			.build();
-		gpad.set_target(Some(&target_pad)).expect("Failed to set target pad");
-		gpad.set_active(true).ok()?;
-		obj.add_pad(&gpad).ok()?;
-	
-		// Appsink callback
-		let stream_pad_name = pad_name.clone();
-		let self_weak = self.downgrade();
-		stream.appsink.set_callbacks(
-			gst_app::AppSinkCallbacks::builder()
-				.new_sample(move |sink| {
-					let Some(imp) = self_weak.upgrade() else {
-						return Err(gst::FlowError::Eos);
-					};
-	
-					let sample = sink.pull_sample().map_err(|_| gst::FlowError::Eos)?;
-					imp.on_new_sample(sample, &stream_pad_name) // you could pass pad_name if needed
-				})
-				.build(),
-		);
-	
-		// Store the stream context
-		let mut streams = self.streams.lock().unwrap();
-		streams.insert(pad_name.clone(), stream);
-	
-		Some(gpad.upcast())
-	}
-
-	fn release_pad(&self, _pad: &gst::Pad) {
-		let pad_name = _pad.name();
-		let mut streams = self.streams.lock().unwrap();
-		streams.remove(pad_name.as_str());
-	}
-}
-
-impl BaseSinkImpl for DashCmafSink {}
-
-impl DashCmafSink {
-
-    fn on_init_segment(&self, pad_name: &str) -> Result<File, std::io::Error> {
-        let settings = self.settings.lock().unwrap();
-		let location = format!("{}_{}", pad_name, &settings.init_location);
-        let path = Path::new(&location);
-
-        File::create(path)
-    }
-
-    fn on_new_segment(&self, pad_name: &str) -> Result<(File, String), std::io::Error> {
-        let mut streams = self.streams.lock().unwrap();
-		let stream = streams.get_mut(pad_name).unwrap(); 
-        let settings = self.settings.lock().unwrap();
-
-		let temp_location= sprintf::sprintf!(&settings.segment_location, stream.segment_idx).unwrap();
-		let location = format!("{}_{}", pad_name, temp_location);
-        stream.segment_idx += 1;
-		stream.start_time = Some(gst::ClockTime::from_seconds((0) as u64));
-		stream.end_time = Some(gst::ClockTime::from_seconds((settings.target_duration) as u64 * stream.segment_idx as u64));
-
-        let path = Path::new(&location);
-
-        let file = File::create(&path)?;
-        Ok((file, location))
-    }
-
-    fn add_segment(
-        &self,
-		_pad_name: &str
-    ) -> Result<gst::FlowSuccess, gst::FlowError> {
-		let mut streams = self.streams.lock().unwrap();
-		let settings = self.settings.lock().unwrap();
-		let path = settings.location.clone();
-
-		gst::info!(
-			CAT,
-			imp = self,
-			"writing manifest to {}",
-			path
-		);
-
-		let mut duration = 0;
-
-		let mut video_reps = Vec::new();
-		let mut audio_reps = Vec::new();
-		for (pad_name, stream) in streams.iter_mut() {
-
-			duration = stream
-				.end_time
-				.opt_checked_sub(stream.start_time)
-				.ok()
-				.flatten()
-				.unwrap()
-				.mseconds();
-
-			let obj = self.obj();
-			let sink_pad = obj.static_pad(pad_name).expect("Missing sink pad");
-			let caps = sink_pad.current_caps().unwrap();
-			let s = caps.structure(0);
-
-			let (media, codec) = if let Some(s) = s {
-				let media_type = s.name();
-			
-				let (media, codec) = match media_type.as_str() {
-					"video/x-h264" => ("video".to_string(), "avc1.64001e".to_string()),
-					"audio/mpeg" => ("audio".to_string(), "mp4a.40.2".to_string()),
-					_ => ("unknown".to_string(), "unknown".to_string()),
-				};
-			
-				(media, codec)
-			} else {
-				("unknown".to_string(), "unknown".to_string())
-			};
-
-			match media.as_str() {
-				"video" => {
-					let (width, height, framerate) = if let Some(s) = s {
-						let width = s.get::<i32>("width").unwrap_or(1280);
-						let height = s.get::<i32>("height").unwrap_or(720);
-						let fps = s.get::<gst::Fraction>("framerate").unwrap_or(gst::Fraction::new(30, 1));
-						let framerate = format!("{}/{}", fps.numer(), fps.denom());
-					
-						(width, height, framerate)
-					} else {
-						(1280, 720, "30/1".to_string())
-					};
-
-					gst::info!(
-						CAT,
-						imp = self,
-						"MPD info: media={} codec={} width={} height={} framerate={}",
-						media, codec, width, height, framerate
-					);
-
-					let segment_location= settings.segment_location.replace ("%d", "$Number$");
-					let segment_template = dash_mpd::SegmentTemplate {
-						timescale: Some(1000),
-						duration: Some(settings.target_duration as f64 * 1000.0),
-						startNumber: Some(0),
-						initialization: Some(format!("{}_{}", pad_name, &settings.init_location)),
-						media: Some(format!("{}_{}", pad_name, &segment_location)),
-						..Default::default()
-					};
-
-					let rep = dash_mpd::Representation {
-						id: Some(pad_name.to_string()),
-						codecs: Some(codec),
-						width: Some(width as u64),
-						height: Some(height as u64),
-						frameRate: Some(framerate),
-						bandwidth: Some(stream.bandwidth as u64),
-						SegmentTemplate: Some(segment_template),
-						..Default::default()
-					};
-					video_reps.push(rep)
-				},
-				"audio" => {
-					gst::info!(
-						CAT,
-						imp = self,
-						"MPD info: media={} codec={}",
-						media, codec
-					);
-
-					let segment_location= settings.segment_location.replace ("%d", "$Number$");
-					let segment_template = dash_mpd::SegmentTemplate {
-						timescale: Some(1000),
-						duration: Some(settings.target_duration as f64 * 1000.0),
-						startNumber: Some(0),
-						initialization: Some(format!("{}_{}", pad_name, &settings.init_location)),
-						media: Some(format!("{}_{}", pad_name, &segment_location)),
-						..Default::default()
-					};
-
-					let rep = dash_mpd::Representation {
-						id: Some(pad_name.to_string()),
-						codecs: Some(codec),
-						bandwidth: Some(stream.bandwidth as u64),
-						SegmentTemplate: Some(segment_template),
-						..Default::default()
-					};
-					audio_reps.push(rep)
-				},
-				_ => {}
-			};
-		}
-
-		let mut adaptations = Vec::new();
-
-		if !video_reps.is_empty() {
-			adaptations.push(dash_mpd::AdaptationSet {
-				contentType: Some("video".into()),
-				mimeType: Some("video/mp4".into()),
-				segmentAlignment: Some(true),
-				subsegmentStartsWithSAP: Some(1),
-				representations: video_reps,
-				..Default::default()
-			});
-		}
-
-		if !audio_reps.is_empty() {
-			adaptations.push(dash_mpd::AdaptationSet {
-				contentType: Some("audio".into()),
-				mimeType: Some("audio/mp4".into()),
-				segmentAlignment: Some(true),
-				subsegmentStartsWithSAP: Some(1),
-				representations: audio_reps,
-				..Default::default()
-			});
-		}
-
-		let period = dash_mpd::Period {
-			adaptations: adaptations,
-			..Default::default()
-		};
-
-		let mpd = dash_mpd::MPD {
-			mpdtype: Some("static".to_string()),
-			xmlns: Some("urn:mpeg:dash:schema:mpd:2011".to_string()),
-			schemaLocation: Some("urn:mpeg:dash:schema:mpd:2011 DASH-MPD.xsd".to_string()),
-			profiles: Some("urn:mpeg:dash:profile:isoff-on-demand:2011".to_string()),
-			periods: vec![period],
-			mediaPresentationDuration: Some(std::time::Duration::from_millis(duration)),
-			minBufferTime: Some(std::time::Duration::from_secs(settings.target_duration as u64)),
-			..Default::default()
-		};
-
-		use serde::ser::Serialize;
-
-		let mut xml = String::new();
-		let mut ser = quick_xml::se::Serializer::new(&mut xml);
-		ser.indent(' ', 4);
-		mpd.serialize(ser).unwrap();
-
-		let manifest = format!(
-			r###"<?xml version="1.0" encoding="UTF-8"?>
-{xml}
-"###
-		);
-
-		std::fs::write(path, manifest).expect("failed to write manifest");
-        Ok(gst::FlowSuccess::Ok)
-    }
-
-    fn on_new_sample(&self, sample: gst::Sample, pad_name: &str) -> Result<gst::FlowSuccess, gst::FlowError> {
-		let mut buffer_list = sample.buffer_list_owned().ok_or(gst::FlowError::Error)?;
-		let first = buffer_list.get(0).ok_or(gst::FlowError::Error)?;
-	
-		// Check for init segment (DISCONT or HEADER flags)
-		if first
-			.flags()
-			.contains(gst::BufferFlags::DISCONT | gst::BufferFlags::HEADER)
-		{
-			let mut stream = self.on_init_segment(pad_name).map_err(|err| {
-				gst::error!(
-					CAT,
-					imp = self,
-					"Couldn't get output stream for init segment: {err}",
-				);
-				gst::FlowError::Error
-			})?;
-	
-			let map = first.map_readable().map_err(|_| {
-				gst::error!(CAT, imp = self, "Failed to map init segment buffer");
-				gst::FlowError::Error
-			})?;
-	
-			stream.write_all(&map).map_err(|_| {
-				gst::error!(CAT, imp = self, "Couldn't write init segment to output stream");
-				gst::FlowError::Error
-			})?;
-	
-			stream.flush().map_err(|_| {
-				gst::error!(CAT, imp = self, "Couldn't flush init segment stream");
-				gst::FlowError::Error
-			})?;
-	
-			drop(map);
-	
-			// Remove init segment from buffer list
-			buffer_list.make_mut().remove(0..1);
-	
-			if buffer_list.is_empty() {
-				return Ok(gst::FlowSuccess::Ok);
-			}
-		}
-	
-		// Get output stream + location
-		let (mut stream, _location) = self.on_new_segment(pad_name).map_err(|err| {
-			gst::error!(
-				CAT,
-				imp = self,
-				"Couldn't get output stream for fragment: {err}",
-			);
-			gst::FlowError::Error
-		})?;
-	
-		let mut total_size = 0;
-		// Write all fragment buffers
-		for buffer in &*buffer_list {
-			let map = buffer.map_readable().map_err(|_| {
-				gst::error!(CAT, imp = self, "Failed to map fragment buffer");
-				gst::FlowError::Error
-			})?;
-	
-			stream.write_all(&map).map_err(|_| {
-				gst::error!(CAT, imp = self, "Couldn't write fragment to output stream");
-				gst::FlowError::Error
-			})?;
-			total_size += map.size();
-		}
-		{
-			let mut streams = self.streams.lock().unwrap();
-			let dash_stream = streams.get_mut(pad_name).unwrap(); 
-			let settings = self.settings.lock().unwrap();
-			dash_stream.bandwidth = total_size as u64 * 8 / settings.target_duration as u64;
-			gst::info!(CAT, imp = self, "total size: {} bandwidth: {}", total_size, dash_stream.bandwidth);
-		};
-		
-	
-		stream.flush().map_err(|_| {
-			gst::error!(CAT, imp = self, "Couldn't flush fragment stream");
-			gst::FlowError::Error
-		})?;
-	
-		self.add_segment(pad_name)
-	}	
-}
-
-static CAT: LazyLock<gst::DebugCategory> = LazyLock::new(|| {
-    gst::DebugCategory::new(
-        "dashcmafsink",
-        gst::DebugColorFlags::empty(),
-        Some("DASH CMAF Sink"),
-    )
-});
\ No newline at end of file
+// Copyright (C) 2025 Roberto Viola <rviola@vicomtech.org>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use gst::glib;
+use gst::prelude::*;
+use gst::subclass::prelude::*;
+use gst_base::subclass::prelude::*;
+use std::sync::{Arc, LazyLock};
+use std::sync::Mutex;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+
+use super::bitstream::{ftyp_brands, scte35_splice_point, styp_box};
+use super::json::json_string;
+use super::location::{
+	check_location_template, checksum_manifest_location, expand_time_tokens, location_pattern, pad_location,
+	period_manifest_location, printf_number, relative_url, INIT_PAD_PLACEHOLDERS, PAD_PLACEHOLDERS,
+};
+use super::mpd::{
+	build_mpd, decode_manifest, manifest_xml, media_and_codec, resume_points, timescale_ticks, AdaptationSetKey,
+	DashCmafSinkStreamInfo, TIMELINE_GAP_TOLERANCE,
+};
+use super::output::{
+	CallbackOutput, FileOutput, IngestOutput, MemoryOutput, OutputBackend, ResourceKind, WriteCallback,
+};
+use super::{
+	DashCmafSinkAddressing, DashCmafSinkBackend, DashCmafSinkContainer, DashCmafSinkDescriptorPlacement,
+	DashCmafSinkManifestEncoding, DashCmafSinkManifestWriteMode, DashCmafSinkOutputPolicy,
+	DashCmafSinkPlaylistType, DashCmafSinkTimescaleRounding, DashCmafSinkUrlMode,
+};
+
+const DEFAULT_TARGET_DURATION: u32 = 10;
+const DEFAULT_LATENCY: gst::ClockTime =
+    gst::ClockTime::from_mseconds((DEFAULT_TARGET_DURATION * 500) as u64);
+const DEFAULT_AUTO_LATENCY: bool = false;
+const DEFAULT_SYNC: bool = true;
+const DEFAULT_LOCATION: &str = "manifest.mpd";
+const DEFAULT_INIT_LOCATION: &str = "init.cmfi";
+const DEFAULT_SEGMENT_LOCATION: &str = "segment_%d.cmfv";
+pub(crate) const DEFAULT_START_NUMBER: u64 = 0;
+const DEFAULT_BACKEND: DashCmafSinkBackend = DashCmafSinkBackend::File;
+const DEFAULT_CUSTOM_WRITER: bool = false;
+const DEFAULT_OUTPUT_POLICY: DashCmafSinkOutputPolicy = DashCmafSinkOutputPolicy::Overwrite;
+const DEFAULT_WINDOW_SIZE: u32 = 5;
+const DEFAULT_PLAYLIST_TYPE: DashCmafSinkPlaylistType = DashCmafSinkPlaylistType::Static;
+// 0 means 3 times the target duration
+const DEFAULT_PRESENTATION_DELAY: u32 = 0;
+const DEFAULT_STRICT_ALIGNMENT: bool = false;
+const DEFAULT_ALIGN_AUDIO: bool = false;
+const DEFAULT_ALIGNMENT_TOLERANCE: gst::ClockTime = gst::ClockTime::from_mseconds(10);
+const DEFAULT_RESUME: bool = false;
+const DEFAULT_MANIFEST_FROZEN: bool = false;
+const DEFAULT_PER_PERIOD_MANIFEST: bool = false;
+const DEFAULT_CHECKSUM_MANIFEST: bool = false;
+const DEFAULT_MANIFEST_WRITE_MODE: DashCmafSinkManifestWriteMode = DashCmafSinkManifestWriteMode::EverySegment;
+const DEFAULT_MANIFEST_INTERVAL: u32 = 10;
+const DEFAULT_MUX_STREAMS: bool = false;
+const DEFAULT_CONTAINER: DashCmafSinkContainer = DashCmafSinkContainer::Cmaf;
+const DEFAULT_CLEANUP_ON_ERROR: bool = true;
+const DEFAULT_CMAF_PROFILE: bool = false;
+const DEFAULT_INLINE_INIT: bool = false;
+const DEFAULT_COMBINE_INIT_FIRST_SEGMENT: bool = false;
+const DEFAULT_ENSURE_STYP: bool = false;
+// Structural brand every CMAF track carries in its ftyp
+const CMAF_STRUCTURAL_BRAND: &[u8; 4] = b"cmfc";
+// SCTE 214-1 scheme of Events carrying a binary splice_info_section
+const SCTE35_SCHEME: &str = "urn:scte:scte35:2014:xml+bin";
+// EBML ID of the Cluster element, which starts every WebM media segment
+const WEBM_CLUSTER_ID: [u8; 4] = [0x1F, 0x43, 0xB6, 0x75];
+const DEFAULT_FORCE_KEYFRAME: bool = false;
+const DEFAULT_REQUIRE_KEYFRAME: bool = false;
+const DEFAULT_URL_MODE: DashCmafSinkUrlMode = DashCmafSinkUrlMode::Relative;
+// 0 means unlimited
+const DEFAULT_MAX_BUFFERS: u32 = 0;
+const DEFAULT_MAX_BYTES: u64 = 0;
+const DEFAULT_DROP: bool = false;
+const DEFAULT_MAX_BITRATE: u64 = 0;
+const DEFAULT_BANDWIDTH_WINDOW: u32 = 1;
+const DEFAULT_USER_AGENT: &str = concat!("GStreamer dashcmafsink ", env!("CARGO_PKG_VERSION"));
+const DEFAULT_ADDRESSING: DashCmafSinkAddressing = DashCmafSinkAddressing::Number;
+pub(crate) const DEFAULT_TIMESCALE: u32 = 1000;
+const DEFAULT_DESCRIPTOR_PLACEMENT: DashCmafSinkDescriptorPlacement = DashCmafSinkDescriptorPlacement::Representation;
+// Samples per AAC frame, the granularity of audio fragments
+const AAC_FRAME_SAMPLES: u64 = 1024;
+const DEFAULT_TIMESCALE_ROUNDING: DashCmafSinkTimescaleRounding = DashCmafSinkTimescaleRounding::Nearest;
+const DEFAULT_THUMBNAIL_LOCATION: &str = "thumbnail_%d.jpg";
+const DEFAULT_THUMBNAIL_TILES: (u32, u32) = (1, 1);
+// Spaces per nesting level of the manifest, 0 for a compact single line
+const DEFAULT_MANIFEST_INDENT: u32 = 4;
+const DEFAULT_SAP_TYPE: u32 = 1;
+const DEFAULT_REORDER_DELAY_OFFSET: bool = false;
+const DEFAULT_SEGMENT_ALIGNMENT: bool = true;
+const DEFAULT_MANIFEST_ENCODING: DashCmafSinkManifestEncoding = DashCmafSinkManifestEncoding::Utf8;
+const DEFAULT_MANIFEST_BOM: bool = false;
+// Namespace prefixes declared on the MPD element, some always with a
+// default URI
+const XML_NAMESPACE_PREFIXES: [&str; 8] = ["xsi", "cenc", "mspr", "xlink", "scte35", "dvb", "ext", "scte214"];
+
+#[derive(Clone)]
+pub(crate) struct DashCmafSinkSettings {
+    pub(crate) location: String,
+    pub(crate) init_location: String,
+	pub(crate) segment_location: String,
+    // Duration of the segments, target-duration being its whole seconds
+    pub(crate) segment_duration: gst::ClockTime,
+    pub(crate) sync: bool,
+	pub(crate) latency: gst::ClockTime,
+	pub(crate) auto_latency: bool,
+	pub(crate) mpd_id: Option<String>,
+	pub(crate) period_id: Option<String>,
+	pub(crate) title: Option<String>,
+	pub(crate) source: Option<String>,
+	pub(crate) copyright: Option<String>,
+	pub(crate) output_dir: Option<String>,
+	pub(crate) backend: DashCmafSinkBackend,
+	pub(crate) custom_writer: bool,
+	pub(crate) output_policy: DashCmafSinkOutputPolicy,
+	pub(crate) window_size: u32,
+	pub(crate) playlist_type: DashCmafSinkPlaylistType,
+	pub(crate) presentation_delay: u32,
+	pub(crate) strict_alignment: bool,
+	pub(crate) alignment_tolerance: gst::ClockTime,
+	pub(crate) align_audio: bool,
+	pub(crate) start_time: Option<chrono::DateTime<chrono::Utc>>,
+	pub(crate) start_segment_number: u64,
+	pub(crate) resume: bool,
+	// Segments keep being written, the manifest isn't updated
+	pub(crate) manifest_frozen: bool,
+	pub(crate) manifest_write_mode: DashCmafSinkManifestWriteMode,
+	pub(crate) manifest_interval: u32,
+	pub(crate) per_period_manifest: bool,
+	pub(crate) checksum_manifest: bool,
+	pub(crate) thumbnail_location: String,
+	// Columns and rows of thumbnails in each tile image
+	pub(crate) thumbnail_tiles: (u32, u32),
+	pub(crate) manifest_indent: u32,
+	pub(crate) sap_type: u32,
+	pub(crate) reorder_delay_offset: bool,
+	pub(crate) segment_alignment: bool,
+	pub(crate) manifest_encoding: DashCmafSinkManifestEncoding,
+	pub(crate) manifest_bom: bool,
+	pub(crate) ingest_url: Option<String>,
+	pub(crate) cmaf_brand: Option<String>,
+	pub(crate) addressing: DashCmafSinkAddressing,
+	pub(crate) timescale_rounding: DashCmafSinkTimescaleRounding,
+	pub(crate) descriptor_placement: DashCmafSinkDescriptorPlacement,
+	pub(crate) max_buffers: u32,
+	pub(crate) max_bytes: u64,
+	pub(crate) drop: bool,
+	pub(crate) url_mode: DashCmafSinkUrlMode,
+	pub(crate) base_url: Option<String>,
+	pub(crate) manifest_location_url: Option<String>,
+	pub(crate) mux_streams: bool,
+	pub(crate) force_keyframe: bool,
+	pub(crate) require_keyframe: bool,
+	pub(crate) container: DashCmafSinkContainer,
+	pub(crate) max_duration: Option<gst::ClockTime>,
+	pub(crate) negotiation_timeout: Option<gst::ClockTime>,
+	pub(crate) widevine_pssh: Option<String>,
+	pub(crate) playready_pssh: Option<String>,
+	pub(crate) playready_pro: Option<String>,
+	pub(crate) cleanup_on_error: bool,
+	pub(crate) cmaf_profile: bool,
+	pub(crate) inline_init: bool,
+	pub(crate) combine_init_first_segment: bool,
+	pub(crate) ensure_styp: bool,
+	pub(crate) max_bitrate: u64,
+	pub(crate) bandwidth_window: u32,
+	pub(crate) user_agent: String,
+	pub(crate) auth_token: Option<String>,
+	pub(crate) http_headers: Option<gst::Structure>,
+	pub(crate) xml_namespaces: Option<gst::Structure>,
+	// Wall-clock time the {strftime} tokens of the locations expand to,
+	// fixed for the whole presentation so files and manifest agree
+	pub(crate) template_time: Option<chrono::DateTime<chrono::Utc>>,
+	pub(crate) file_mode: Option<u32>,
+	pub(crate) max_segment_files: u32,
+}
+
+impl DashCmafSinkSettings {
+	/// Suggested presentation delay of live manifests, never below minBufferTime
+	pub(crate) fn presentation_delay(&self) -> std::time::Duration {
+		let min_buffer_time = self.segment_duration.mseconds();
+		let delay = match self.presentation_delay {
+			0 => 3 * min_buffer_time,
+			delay => (delay as u64).max(min_buffer_time),
+		};
+
+		std::time::Duration::from_millis(delay)
+	}
+
+	/// Duration of a tile image, each of its thumbnails covering one target
+	/// duration
+	pub(crate) fn thumbnail_duration(&self) -> gst::ClockTime {
+		let (columns, rows) = self.thumbnail_tiles;
+		self.segment_duration * (columns as u64 * rows as u64)
+	}
+
+	/// Headers sent with every request of the ingest backend
+	fn http_headers(&self) -> Vec<(String, String)> {
+		let mut headers = vec![("User-Agent".to_string(), self.user_agent.clone())];
+		if let Some(ref token) = self.auth_token {
+			headers.push(("Authorization".to_string(), format!("Bearer {token}")));
+		}
+		if let Some(ref http_headers) = self.http_headers {
+			headers.extend(http_headers.iter().filter_map(|(name, value)| {
+				Some((name.to_string(), value.get::<String>().ok()?))
+			}));
+		}
+		// A line break would end the header block of the request
+		headers.retain(|(_, value)| !value.contains(['\r', '\n']));
+
+		headers
+	}
+
+	/// Location of the init segment of a stream. The pad name replaces the
+	/// `$RepresentationID$`, `{pad}` or `%s` placeholders of init-location,
+	/// or prefixes it when there is none.
+	pub(crate) fn init_location(&self, pad_name: &str) -> String {
+		let init_location = self.expand_time(&self.init_location);
+		if !INIT_PAD_PLACEHOLDERS.iter().any(|placeholder| init_location.contains(placeholder)) {
+			return format!("{}_{}", pad_name, &init_location);
+		}
+
+		INIT_PAD_PLACEHOLDERS
+			.iter()
+			.fold(init_location, |location, placeholder| {
+				location.replace(placeholder, pad_name)
+			})
+	}
+
+	fn manifest_location(&self) -> String {
+		self.expand_time(&self.location)
+	}
+
+	/// Media segment location of a pad, a printf template of the number
+	pub(crate) fn segment_location(&self, pad_name: &str) -> String {
+		pad_location(&printf_number(&self.expand_time(&self.segment_location)), pad_name)
+	}
+
+	pub(crate) fn thumbnail_location(&self, pad_name: &str) -> String {
+		pad_location(&printf_number(&self.expand_time(&self.thumbnail_location)), pad_name)
+	}
+
+	/// Expands the `{strftime}` tokens of a location, like `{%Y-%m-%d}`
+	fn expand_time(&self, location: &str) -> String {
+		expand_time_tokens(location, self.template_time.unwrap_or_else(chrono::Utc::now))
+	}
+
+	/// URL of a resource as referenced from the manifest, `location` being
+	/// relative to the output directory like the manifest location
+	pub(crate) fn url(&self, location: &str) -> String {
+		if self.url_mode == DashCmafSinkUrlMode::Absolute {
+			if let Some(ref base_url) = self.base_url {
+				return format!("{}/{}", base_url.trim_end_matches('/'), location);
+			}
+		}
+
+		let manifest_path = self.output_path(&self.manifest_location());
+		let manifest_dir = manifest_path.parent().unwrap_or(Path::new(""));
+		relative_url(manifest_dir, &self.output_path(location)).unwrap_or_else(|| location.to_string())
+	}
+
+	/// Path on disk of a manifest-relative location
+	fn output_path(&self, location: &str) -> PathBuf {
+		match self.output_dir {
+			Some(ref output_dir) => Path::new(output_dir).join(location),
+			None => PathBuf::from(location),
+		}
+	}
+
+	/// Files of the output directory matching the manifest, init, media
+	/// segment or thumbnail locations, whatever the pad or number.
+	fn existing_outputs(&self) -> Vec<PathBuf> {
+		let has_placeholder = |template: &str, placeholders: &[&str]| placeholders.iter().any(|p| template.contains(p));
+		let templates = [
+			(self.location.as_str(), false),
+			(self.init_location.as_str(), !has_placeholder(&self.init_location, &INIT_PAD_PLACEHOLDERS)),
+			(self.segment_location.as_str(), !has_placeholder(&self.segment_location, &PAD_PLACEHOLDERS)),
+			(self.thumbnail_location.as_str(), !has_placeholder(&self.thumbnail_location, &PAD_PLACEHOLDERS)),
+		];
+		let patterns = templates
+			.iter()
+			.filter_map(|(template, pad_prefix)| location_pattern(&printf_number(template), *pad_prefix))
+			.collect::<Vec<_>>();
+		let depth = templates.iter().map(|(template, _)| template.matches('/').count() + 1).max().unwrap_or(1);
+
+		let root = self.output_dir.as_deref().map_or(PathBuf::from("."), PathBuf::from);
+		let mut files = Vec::new();
+		let mut dirs = vec![(root.clone(), 1)];
+		while let Some((dir, level)) = dirs.pop() {
+			let Ok(entries) = std::fs::read_dir(&dir) else {
+				continue;
+			};
+			for entry in entries.flatten() {
+				let path = entry.path();
+				if path.is_dir() {
+					if level < depth {
+						dirs.push((path, level + 1));
+					}
+					continue;
+				}
+				// Named pipes are where the output goes, not a previous one
+				if !entry.file_type().is_ok_and(|file_type| file_type.is_file()) {
+					continue;
+				}
+
+				let Ok(relative) = path.strip_prefix(&root) else {
+					continue;
+				};
+				let relative = relative.components().map(|c| c.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/");
+				if patterns.iter().any(|pattern| pattern.is_match(&relative)) {
+					files.push(path);
+				}
+			}
+		}
+
+		files
+	}
+}
+
+#[derive(Default)]
+struct DashCmafSinkState {
+	availability_start_time: Option<chrono::DateTime<chrono::Utc>>,
+	output: Option<Arc<dyn OutputBackend>>,
+	bytes_written: u64,
+	segments_written: u64,
+	// Last segment number checked for alignment across video streams
+	aligned_segment: Option<u64>,
+	// Incremented for every manifest generated, to order their writes
+	manifest_version: u64,
+	max_segment_duration: Option<gst::ClockTime>,
+	// Pushed with push-event, signalled in the EventStreams of the Period
+	events: Vec<DashCmafSinkEvent>,
+	// Period begun by the last reset or new-period
+	period: Option<DashCmafSinkPeriod>,
+	// Periods closed by new-period, listed before the current one
+	closed_periods: Vec<dash_mpd::Period>,
+	// Wall time the element last went to PLAYING, and the periodic check
+	// of the streams scheduled from then on
+	playing_since: Option<std::time::Instant>,
+	health_check: Option<gst::PeriodicClockId>,
+	// A stream did not write a segment for twice the segment duration
+	stalled: bool,
+	// Timeouts of the streams yet to produce a fragment, with
+	// negotiation-timeout
+	negotiation_timeouts: Vec<gst::SingleShotClockId>,
+	// Longest GOP observed on the video streams, with auto-latency
+	gop_duration: Option<gst::ClockTime>,
+}
+
+/// Period begun by a reset or new-period, the first one being implicit
+#[derive(Clone)]
+pub(crate) struct DashCmafSinkPeriod {
+	pub(crate) index: u32,
+	// Running time the Period starts at
+	pub(crate) start: gst::ClockTime,
+	// Given with new-period, derived otherwise
+	pub(crate) id: Option<String>,
+}
+
+/// Event of an EventStream, timed like the segments in running time
+#[derive(Clone)]
+pub(crate) struct DashCmafSinkEvent {
+	pub(crate) scheme_id_uri: String,
+	pub(crate) value: Option<String>,
+	pub(crate) presentation_time: gst::ClockTime,
+	pub(crate) duration: Option<gst::ClockTime>,
+	pub(crate) message_data: Option<String>,
+	// Base64 SCTE-35 splice_info_section, signalled as scte35:Signal
+	pub(crate) scte35_binary: Option<String>,
+}
+
+#[derive(Clone)]
+pub(crate) struct DashCmafSinkSegment {
+	pub(crate) number: u64,
+	// Running time of the first sample
+	pub(crate) start: gst::ClockTime,
+	pub(crate) duration: gst::ClockTime,
+	// Manifest-relative URL of the segment
+	pub(crate) location: String,
+	// Hex SHA-256 of the segment, with checksum-manifest
+	pub(crate) checksum: Option<String>,
+}
+
+struct DashCmafSinkCluster {
+	// Running time of the first frame
+	start: Option<gst::ClockTime>,
+	buffers: gst::BufferList,
+}
+
+struct DashCmafSinkStream {
+	// Number of the first segment, advertised as startNumber
+	start_number: u64,
+	// Number of the next segment to be written
+    segment_idx: u64,
+	start_time: Option<gst::ClockTime>,
+    end_time: Option<gst::ClockTime>,
+	// Duration of the segments written by a previous run, when resuming
+	resumed_duration: gst::ClockTime,
+	bandwidth: u64,
+	// Highest bandwidth signalled so far
+	peak_bandwidth: u64,
+	// Size and duration of the last bandwidth-window segments
+	recent_segments: VecDeque<(u64, gst::ClockTime)>,
+	bytes_written: u64,
+	segments_written: u64,
+	segments: Vec<DashCmafSinkSegment>,
+	// Fragments that reached the appsink and were pulled from it, the
+	// difference is what is queued
+	buffers_received: u64,
+	buffers_pulled: u64,
+	// WebM header and cluster being collected, webmmux not fragmenting its
+	// output by itself
+	webm_header: Vec<u8>,
+	webm_cluster: Option<DashCmafSinkCluster>,
+	// max-duration reached, the stream is being drained
+	finished: bool,
+	// Pad and last caps of a stream whose pad was released, which stays in
+	// the manifest until the Period ends
+	released: Option<(super::DashCmafSinkPad, gst::Caps)>,
+	// Further pads of a muxed stream, which carry it on when its own pad is
+	// released
+	track_pads: Vec<super::DashCmafSinkPad>,
+	// Last init segment, kept for the manifest with inline-init
+	init_segment: Option<Vec<u8>>,
+	// With combine-init-first-segment, the init segment is yet to be
+	// written ahead of the next media segment, and the location of the one
+	// it was last written with and its size
+	init_pending: bool,
+	combined_init: Option<(String, usize)>,
+	// Segments lacking a styp box were reported
+	styp_missing: bool,
+	// Video segments not starting with a keyframe were reported
+	keyframe_missing: bool,
+	// The appsink received EOS
+	eos: bool,
+	// Wall time the last segment was written at
+	last_segment_at: Option<std::time::Instant>,
+	// PTS minus DTS of the first sample of the first media segment
+	reorder_delay: Option<gst::ClockTime>,
+	// Wall time spent writing the last segment and the manifest, and the
+	// longest one so far
+	last_write_time: std::time::Duration,
+	max_write_time: std::time::Duration,
+	// End of the last audio segment minus the one of the video segment of
+	// the same number, in nanoseconds, with align-audio
+	alignment_offset: i64,
+	// None for thumbnail streams, whose tile images are written as they are
+    cmafmux: Option<gst::Element>,
+    appsink: gst_app::AppSink,
+}
+
+#[derive(Default)]
+pub struct DashCmafSink {
+    settings: Mutex<DashCmafSinkSettings>,
+	state: Mutex<DashCmafSinkState>,
+	streams: Mutex<HashMap<String, DashCmafSinkStream>>,
+	// Serializes the manifest writes, holding the version last written
+	manifest: Mutex<u64>,
+	// Index of the next pad requested without a name, never reused so that
+	// a Representation id names a single pad
+	pad_index: Mutex<u32>,
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for DashCmafSink {
+	const NAME: &'static str = "DashCmafSink";
+	type Type = super::DashCmafSink;
+	type ParentType = gst::Bin;
+}
+
+#[derive(Default)]
+struct DashCmafSinkPadSettings {
+	role: Option<String>,
+	accessibility: Option<String>,
+	lang: Option<String>,
+	// language-code tag received upstream, used when lang is unset
+	tag_lang: Option<String>,
+	// Overrides segment-duration for the muxer of the stream
+	fragment_duration: Option<gst::ClockTime>,
+	label: Option<String>,
+	group: Option<u32>,
+	preselection_id: Option<String>,
+	preselection_components: Option<String>,
+	quality_ranking: Option<u8>,
+	switching_group: Option<u32>,
+	selection_priority: Option<u32>,
+	trick_mode_for: Option<String>,
+	// Ticks per second of the segment timing in the manifest
+	timescale: Option<u32>,
+	// Overrides start-segment-number for the stream
+	start_number: Option<u64>,
+	// Declared instead of the measured bandwidth
+	bandwidth: Option<u64>,
+}
+
+#[derive(Default)]
+pub struct DashCmafSinkPad {
+	settings: Mutex<DashCmafSinkPadSettings>,
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for DashCmafSinkPad {
+	const NAME: &'static str = "DashCmafSinkPad";
+	type Type = super::DashCmafSinkPad;
+	type ParentType = gst::GhostPad;
+}
+
+impl Default for DashCmafSinkSettings {
+    fn default() -> Self {
+        Self {
+			location: String::from(DEFAULT_LOCATION),
+            init_location: String::from(DEFAULT_INIT_LOCATION),
+            segment_location: String::from(DEFAULT_SEGMENT_LOCATION),
+            segment_duration: gst::ClockTime::from_seconds(DEFAULT_TARGET_DURATION as u64),
+            sync: DEFAULT_SYNC,
+            latency: DEFAULT_LATENCY,
+            auto_latency: DEFAULT_AUTO_LATENCY,
+            mpd_id: None,
+            period_id: None,
+            title: None,
+            source: None,
+            copyright: None,
+            output_dir: None,
+            backend: DEFAULT_BACKEND,
+            custom_writer: DEFAULT_CUSTOM_WRITER,
+            output_policy: DEFAULT_OUTPUT_POLICY,
+            window_size: DEFAULT_WINDOW_SIZE,
+            playlist_type: DEFAULT_PLAYLIST_TYPE,
+            presentation_delay: DEFAULT_PRESENTATION_DELAY,
+            strict_alignment: DEFAULT_STRICT_ALIGNMENT,
+            align_audio: DEFAULT_ALIGN_AUDIO,
+            alignment_tolerance: DEFAULT_ALIGNMENT_TOLERANCE,
+            start_time: None,
+            start_segment_number: DEFAULT_START_NUMBER,
+            resume: DEFAULT_RESUME,
+            manifest_frozen: DEFAULT_MANIFEST_FROZEN,
+            manifest_write_mode: DEFAULT_MANIFEST_WRITE_MODE,
+            manifest_interval: DEFAULT_MANIFEST_INTERVAL,
+            per_period_manifest: DEFAULT_PER_PERIOD_MANIFEST,
+            checksum_manifest: DEFAULT_CHECKSUM_MANIFEST,
+            thumbnail_location: String::from(DEFAULT_THUMBNAIL_LOCATION),
+            thumbnail_tiles: DEFAULT_THUMBNAIL_TILES,
+            manifest_indent: DEFAULT_MANIFEST_INDENT,
+            sap_type: DEFAULT_SAP_TYPE,
+            reorder_delay_offset: DEFAULT_REORDER_DELAY_OFFSET,
+            segment_alignment: DEFAULT_SEGMENT_ALIGNMENT,
+            manifest_encoding: DEFAULT_MANIFEST_ENCODING,
+            manifest_bom: DEFAULT_MANIFEST_BOM,
+            ingest_url: None,
+            cmaf_brand: None,
+            addressing: DEFAULT_ADDRESSING,
+            timescale_rounding: DEFAULT_TIMESCALE_ROUNDING,
+            descriptor_placement: DEFAULT_DESCRIPTOR_PLACEMENT,
+            max_buffers: DEFAULT_MAX_BUFFERS,
+            max_bytes: DEFAULT_MAX_BYTES,
+            drop: DEFAULT_DROP,
+            url_mode: DEFAULT_URL_MODE,
+            base_url: None,
+            manifest_location_url: None,
+            mux_streams: DEFAULT_MUX_STREAMS,
+            force_keyframe: DEFAULT_FORCE_KEYFRAME,
+            require_keyframe: DEFAULT_REQUIRE_KEYFRAME,
+            container: DEFAULT_CONTAINER,
+            max_duration: None,
+            negotiation_timeout: None,
+            widevine_pssh: None,
+            playready_pssh: None,
+            playready_pro: None,
+            cleanup_on_error: DEFAULT_CLEANUP_ON_ERROR,
+            cmaf_profile: DEFAULT_CMAF_PROFILE,
+            inline_init: DEFAULT_INLINE_INIT,
+            combine_init_first_segment: DEFAULT_COMBINE_INIT_FIRST_SEGMENT,
+            ensure_styp: DEFAULT_ENSURE_STYP,
+            max_bitrate: DEFAULT_MAX_BITRATE,
+            bandwidth_window: DEFAULT_BANDWIDTH_WINDOW,
+            user_agent: String::from(DEFAULT_USER_AGENT),
+            auth_token: None,
+            http_headers: None,
+            xml_namespaces: None,
+            template_time: None,
+            file_mode: None,
+            max_segment_files: 0,
+        }
+    }
+}
+
+impl DashCmafSinkStream {
+    /// Streams without muxer are thumbnail streams. Fails when the muxer
+    /// isn't installed.
+    fn new(muxer: Option<&str>) -> Result<Self, glib::BoolError> {
+		let cmafmux = muxer
+			.map(|muxer| gst::ElementFactory::make(muxer).build())
+			.transpose()?;
+
+		let appsink = gst_app::AppSink::builder()
+			.buffer_list(true)
+			.sync(DEFAULT_SYNC)
+			// EOS only goes on once the final sample was handled
+			.wait_on_eos(true)
+			.build();
+
+        Ok(Self {
+			start_number: DEFAULT_START_NUMBER,
+			segment_idx: DEFAULT_START_NUMBER,
+			start_time: Some(gst::ClockTime::from_seconds(0)),
+			end_time: Some(gst::ClockTime::from_seconds(0)),
+			resumed_duration: gst::ClockTime::ZERO,
+			bandwidth: 0,
+			peak_bandwidth: 0,
+			recent_segments: VecDeque::new(),
+			bytes_written: 0,
+			segments_written: 0,
+			segments: Vec::new(),
+			buffers_received: 0,
+			buffers_pulled: 0,
+			webm_header: Vec::new(),
+			webm_cluster: None,
+			finished: false,
+			released: None,
+			track_pads: Vec::new(),
+			init_segment: None,
+			init_pending: false,
+			combined_init: None,
+			styp_missing: false,
+			keyframe_missing: false,
+			eos: false,
+			last_segment_at: None,
+			reorder_delay: None,
+			last_write_time: std::time::Duration::ZERO,
+			max_write_time: std::time::Duration::ZERO,
+			alignment_offset: 0,
+			cmafmux,
+			appsink,
+        })
+    }
+}
+
+impl BinImpl for DashCmafSink {}
+
+impl ObjectImpl for DashCmafSink {
+	fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: LazyLock<Vec<glib::ParamSpec>> = LazyLock::new(|| {
+            vec![
+				glib::ParamSpecString::builder("location")
+                    .nick("MPD Location")
+                    .blurb("Path to write manifest (MPD), {strftime} tokens like {%Y%m%d} expanding to the start of the presentation")
+                    .default_value(Some(DEFAULT_LOCATION))
+                    .build(),
+                glib::ParamSpecString::builder("init-location")
+                    .nick("Init Segment Location")
+                    .blurb("Path to write init segment, $RepresentationID$, {pad} or %s being replaced by the pad name, otherwise prefixed")
+                    .default_value(Some(DEFAULT_INIT_LOCATION))
+                    .build(),
+				glib::ParamSpecString::builder("segment-location")
+                    .nick("Segment Location")
+                    .blurb("Template for CMAF segment files, with a %d, %05d or $Number$ number, optional {strftime} tokens, and $RepresentationID$ or {pad} for the pad name, otherwise prefixed")
+                    .default_value(Some(DEFAULT_SEGMENT_LOCATION))
+                    .build(),
+                glib::ParamSpecUInt::builder("target-duration")
+                    .nick("Target Duration")
+                    .blurb("Target duration in seconds for each segment, deprecated for segment-duration")
+                    .default_value(DEFAULT_TARGET_DURATION)
+                    .mutable_ready()
+                    .deprecated()
+                    .build(),
+                glib::ParamSpecUInt64::builder("segment-duration")
+                    .nick("Segment Duration")
+                    .blurb("Target duration in nanoseconds for each segment, superseding target-duration")
+                    .minimum(gst::ClockTime::MSECOND.nseconds())
+                    .default_value(DEFAULT_TARGET_DURATION as u64 * gst::ClockTime::SECOND.nseconds())
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecBoolean::builder("sync")
+                    .nick("Sync")
+                    .blurb("Whether fragments are written in sync with the pipeline clock, disable to package offline faster than realtime")
+                    .default_value(DEFAULT_SYNC)
+                    .build(),
+                glib::ParamSpecUInt64::builder("latency")
+                    .nick("Latency")
+                    .blurb("Latency in nanoseconds")
+                    .default_value(DEFAULT_LATENCY.nseconds())
+                    .build(),
+                glib::ParamSpecBoolean::builder("auto-latency")
+                    .nick("Auto Latency")
+                    .blurb("Use the longest GOP observed on the video streams as muxer latency instead of latency, once one is complete")
+                    .default_value(DEFAULT_AUTO_LATENCY)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecString::builder("mpd-id")
+                    .nick("MPD Id")
+                    .blurb("Identifier of the MPD")
+                    .build(),
+                glib::ParamSpecString::builder("period-id")
+                    .nick("Period Id")
+                    .blurb("Identifier of the Period, derived from the availability start time if unset")
+                    .build(),
+                glib::ParamSpecString::builder("title")
+                    .nick("Title")
+                    .blurb("Title of the ProgramInformation of the MPD")
+                    .build(),
+                glib::ParamSpecString::builder("source")
+                    .nick("Source")
+                    .blurb("Source of the ProgramInformation of the MPD")
+                    .build(),
+                glib::ParamSpecString::builder("copyright")
+                    .nick("Copyright")
+                    .blurb("Copyright of the ProgramInformation of the MPD")
+                    .build(),
+                glib::ParamSpecString::builder("output-dir")
+                    .nick("Output Directory")
+                    .blurb("Directory prepended to the manifest, init and segment locations")
+                    .build(),
+                glib::ParamSpecEnum::builder_with_default("backend", DEFAULT_BACKEND)
+                    .nick("Backend")
+                    .blurb("Where the manifest, init and media segments are written")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecBoolean::builder("custom-writer")
+                    .nick("Custom Writer")
+                    .blurb("Hand the manifest, init and media segments to the write-* signals instead of the backend")
+                    .default_value(DEFAULT_CUSTOM_WRITER)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecEnum::builder_with_default("output-policy", DEFAULT_OUTPUT_POLICY)
+                    .nick("Output Policy")
+                    .blurb("What to do with files of a previous run matching the locations when starting with the file backend")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecUInt::builder("window-size")
+                    .nick("Window Size")
+                    .blurb("Number of media segments kept in memory by the memory backend")
+                    .minimum(1)
+                    .default_value(DEFAULT_WINDOW_SIZE)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecString::builder("ingest-url")
+                    .nick("Ingest URL")
+                    .blurb("HTTP or HTTPS base URL of the DASH-IF ingest endpoint used by the ingest backend, streams posting under {ingest-url}/{pad}/")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecUInt::builder("file-mode")
+                    .nick("File Mode")
+                    .blurb("Unix permissions of the files written by the file backend, e.g. 0644 (0 = from the umask)")
+                    .maximum(0o7777)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecUInt::builder("max-segment-files")
+                    .nick("Max Segment Files")
+                    .blurb("Files the file backend keeps open at once, others are reopened for every write (0 = unlimited)")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecString::builder("user-agent")
+                    .nick("User Agent")
+                    .blurb("User-Agent of the requests of the ingest backend")
+                    .default_value(Some(DEFAULT_USER_AGENT))
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecString::builder("auth-token")
+                    .nick("Auth Token")
+                    .blurb("Token sent as Authorization: Bearer with the requests of the ingest backend")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecBoxed::builder::<gst::Structure>("http-headers")
+                    .nick("HTTP Headers")
+                    .blurb("Extra headers of the requests of the ingest backend, as string fields of a structure")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecBoxed::builder::<gst::Structure>("xml-namespaces")
+                    .nick("XML Namespaces")
+                    .blurb("URIs of the namespace prefixes declared on the MPD element, as string fields of a structure named after the prefixes (xsi, cenc, mspr, xlink, scte35, dvb, ext, scte214)")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecEnum::builder_with_default("playlist-type", DEFAULT_PLAYLIST_TYPE)
+                    .nick("Playlist Type")
+                    .blurb("Whether the manifest is static (on-demand) or dynamic (live)")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecUInt::builder("presentation-delay")
+                    .nick("Presentation Delay")
+                    .blurb("Suggested presentation delay in milliseconds of dynamic manifests (0 = 3 x segment-duration)")
+                    .default_value(DEFAULT_PRESENTATION_DELAY)
+                    .build(),
+                glib::ParamSpecBoolean::builder("strict-alignment")
+                    .nick("Strict Alignment")
+                    .blurb("Warn when segment boundaries of the video streams are not aligned")
+                    .default_value(DEFAULT_STRICT_ALIGNMENT)
+                    .build(),
+                glib::ParamSpecBoolean::builder("align-audio")
+                    .nick("Align Audio")
+                    .blurb("Adjust the fragment-duration of audio streams so their segments end with the video ones")
+                    .default_value(DEFAULT_ALIGN_AUDIO)
+                    .build(),
+                glib::ParamSpecUInt64::builder("alignment-tolerance")
+                    .nick("Alignment Tolerance")
+                    .blurb("Maximum difference in nanoseconds between aligned segment boundaries")
+                    .default_value(DEFAULT_ALIGNMENT_TOLERANCE.nseconds())
+                    .build(),
+                glib::ParamSpecString::builder("start-time")
+                    .nick("Start Time")
+                    .blurb("RFC 3339 availabilityStartTime, derived from the pipeline clock if unset")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecUInt64::builder("start-segment-number")
+                    .nick("Start Segment Number")
+                    .blurb("Number of the first media segment of each stream")
+                    .default_value(DEFAULT_START_NUMBER)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecBoolean::builder("resume")
+                    .nick("Resume")
+                    .blurb("Continue the numbering and timeline of the manifest found at location")
+                    .default_value(DEFAULT_RESUME)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecBoolean::builder("manifest-frozen")
+                    .nick("Manifest Frozen")
+                    .blurb("Stop updating the manifest while segments keep being written, it catches up when unset")
+                    .default_value(DEFAULT_MANIFEST_FROZEN)
+                    .build(),
+                glib::ParamSpecBoolean::builder("checksum-manifest")
+                    .nick("Checksum Manifest")
+                    .blurb("Write the SHA-256 of every media segment in the manifest to a JSON file next to location, with the .checksums.json extension")
+                    .default_value(DEFAULT_CHECKSUM_MANIFEST)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecEnum::builder_with_default("manifest-write-mode", DEFAULT_MANIFEST_WRITE_MODE)
+                    .nick("Manifest Write Mode")
+                    .blurb("When the manifest is written, the timing of every segment being tracked either way")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecUInt::builder("manifest-interval")
+                    .nick("Manifest Interval")
+                    .blurb("Number of media segments, of all the streams, between manifest writes with manifest-write-mode=interval")
+                    .minimum(1)
+                    .default_value(DEFAULT_MANIFEST_INTERVAL)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecBoolean::builder("per-period-manifest")
+                    .nick("Per Period Manifest")
+                    .blurb("Write every Period closed by a reset or new-period as a standalone on-demand manifest next to location, suffixed with the Period id")
+                    .default_value(DEFAULT_PER_PERIOD_MANIFEST)
+                    .build(),
+                glib::ParamSpecEnum::builder_with_default("addressing", DEFAULT_ADDRESSING)
+                    .nick("Addressing")
+                    .blurb("How media segments are addressed, segment-location %d being the number or the start time in timescale units")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecEnum::builder_with_default("timescale-rounding", DEFAULT_TIMESCALE_ROUNDING)
+                    .nick("Timescale Rounding")
+                    .blurb("How segment boundaries are rounded to the timescale of their stream")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecEnum::builder_with_default("descriptor-placement", DEFAULT_DESCRIPTOR_PLACEMENT)
+                    .nick("Descriptor Placement")
+                    .blurb("Whether codecs, width, height and frameRate are signalled on the Representations, their AdaptationSet or both")
+                    .build(),
+                glib::ParamSpecString::builder("thumbnail-location")
+                    .nick("Thumbnail Location")
+                    .blurb("Template for the tile images of thumbnail streams")
+                    .default_value(Some(DEFAULT_THUMBNAIL_LOCATION))
+                    .build(),
+                glib::ParamSpecString::builder("thumbnail-tiles")
+                    .nick("Thumbnail Tiles")
+                    .blurb("Columns and rows of thumbnails in each tile image, as NxM, each thumbnail covering one target duration")
+                    .default_value(Some("1x1"))
+                    .build(),
+                glib::ParamSpecString::builder("cmaf-brand")
+                    .nick("CMAF Brand")
+                    .blurb("CMAF brand (e.g. cmfc) appended as profiles to the mimeType of the AdaptationSets")
+                    .build(),
+                glib::ParamSpecUInt::builder("manifest-indent")
+                    .nick("Manifest Indent")
+                    .blurb("Number of spaces per nesting level of the manifest (0 = compact)")
+                    .maximum(16)
+                    .default_value(DEFAULT_MANIFEST_INDENT)
+                    .build(),
+                glib::ParamSpecUInt::builder("sap-type")
+                    .nick("SAP Type")
+                    .blurb("Stream access point type every segment starts with, signalled as subsegmentStartsWithSAP, e.g. 1 for closed GOPs and 3 for open GOPs (0 = not signalled)")
+                    .maximum(6)
+                    .default_value(DEFAULT_SAP_TYPE)
+                    .build(),
+                glib::ParamSpecBoolean::builder("reorder-delay-offset")
+                    .nick("Reorder Delay Offset")
+                    .blurb("Add the composition offset of the first frame of each stream to its presentationTimeOffset, for muxers writing no edit list for B-frames")
+                    .default_value(DEFAULT_REORDER_DELAY_OFFSET)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecBoolean::builder("segment-alignment")
+                    .nick("Segment Alignment")
+                    .blurb("Signal that the segments of the Representations of an AdaptationSet are aligned")
+                    .default_value(DEFAULT_SEGMENT_ALIGNMENT)
+                    .build(),
+                glib::ParamSpecEnum::builder_with_default("manifest-encoding", DEFAULT_MANIFEST_ENCODING)
+                    .nick("Manifest Encoding")
+                    .blurb("Encoding of the manifest, declared in its XML declaration")
+                    .build(),
+                glib::ParamSpecBoolean::builder("manifest-bom")
+                    .nick("Manifest BOM")
+                    .blurb("Start the manifest with a byte order mark, UTF-16 without one being declared with its byte order")
+                    .default_value(DEFAULT_MANIFEST_BOM)
+                    .build(),
+                glib::ParamSpecEnum::builder_with_default("url-mode", DEFAULT_URL_MODE)
+                    .nick("URL Mode")
+                    .blurb("Whether the manifest references segments relative to its directory or with base-url")
+                    .build(),
+                glib::ParamSpecString::builder("base-url")
+                    .nick("Base URL")
+                    .blurb("URL prefixed to the init and media segment locations in absolute url-mode")
+                    .build(),
+                glib::ParamSpecString::builder("manifest-location-url")
+                    .nick("Manifest Location URL")
+                    .blurb("Canonical URL of the manifest, signalled as the MPD Location for fetching updates")
+                    .build(),
+                glib::ParamSpecBoolean::builder("mux-streams")
+                    .nick("Mux Streams")
+                    .blurb("Interleave the audio and video pads into a single Representation (uses isofmp4mux)")
+                    .default_value(DEFAULT_MUX_STREAMS)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecEnum::builder_with_default("container", DEFAULT_CONTAINER)
+                    .nick("Container")
+                    .blurb("Container of the init and media segments (cmaf uses cmafmux, webm uses webmmux)")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecUInt64::builder("max-duration")
+                    .nick("Max Duration")
+                    .blurb("Duration in nanoseconds after which the streams are ended and the manifest finalized (0 = unlimited)")
+                    .default_value(0)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecUInt64::builder("negotiation-timeout")
+                    .nick("Negotiation Timeout")
+                    .blurb("Time in nanoseconds after going to PLAYING within which every stream must have negotiated caps and produced a fragment, erroring out otherwise (0 = no timeout)")
+                    .default_value(0)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecString::builder("widevine-pssh")
+                    .nick("Widevine PSSH")
+                    .blurb("Base64 Widevine pssh box signalled in the ContentProtection of the AdaptationSets")
+                    .build(),
+                glib::ParamSpecString::builder("playready-pssh")
+                    .nick("PlayReady PSSH")
+                    .blurb("Base64 PlayReady pssh box signalled in the ContentProtection of the AdaptationSets")
+                    .build(),
+                glib::ParamSpecString::builder("playready-pro")
+                    .nick("PlayReady Object")
+                    .blurb("Base64 PlayReady Object signalled as mspr:pro in the ContentProtection of the AdaptationSets")
+                    .build(),
+                glib::ParamSpecBoolean::builder("cleanup-on-error")
+                    .nick("Cleanup On Error")
+                    .blurb("Discard media segments whose write failed instead of keeping the partial data")
+                    .default_value(DEFAULT_CLEANUP_ON_ERROR)
+                    .build(),
+                glib::ParamSpecBoolean::builder("cmaf-profile")
+                    .nick("CMAF Profile")
+                    .blurb("Signal the DASH CMAF profile in the manifest, init segments without the cmfc brand are an error")
+                    .default_value(DEFAULT_CMAF_PROFILE)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecBoolean::builder("inline-init")
+                    .nick("Inline Init")
+                    .blurb("Embed the init segments in the manifest as base64 data URIs instead of writing them")
+                    .default_value(DEFAULT_INLINE_INIT)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecBoolean::builder("combine-init-first-segment")
+                    .nick("Combine Init First Segment")
+                    .blurb("Write every init segment at the start of the next media segment instead of on its own, the manifest referencing it there, so startup takes a single request")
+                    .default_value(DEFAULT_COMBINE_INIT_FIRST_SEGMENT)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecBoolean::builder("ensure-styp")
+                    .nick("Ensure styp")
+                    .blurb("Start every CMAF media segment with a styp box, adding one when the muxer doesn't")
+                    .default_value(DEFAULT_ENSURE_STYP)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecBoolean::builder("force-keyframe")
+                    .nick("Force Keyframe")
+                    .blurb("Request upstream keyframes every segment-duration so video segments stay regular")
+                    .default_value(DEFAULT_FORCE_KEYFRAME)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecBoolean::builder("require-keyframe")
+                    .nick("Require Keyframe")
+                    .blurb("Fail on video segments not starting with a keyframe instead of warning")
+                    .default_value(DEFAULT_REQUIRE_KEYFRAME)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecUInt::builder("max-buffers")
+                    .nick("Max Buffers")
+                    .blurb("Maximum number of fragments queued in each appsink (0 = unlimited)")
+                    .default_value(DEFAULT_MAX_BUFFERS)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecUInt64::builder("max-bytes")
+                    .nick("Max Bytes")
+                    .blurb("Maximum number of bytes queued in each appsink (0 = unlimited)")
+                    .default_value(DEFAULT_MAX_BYTES)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecBoolean::builder("drop")
+                    .nick("Drop")
+                    .blurb("Drop the oldest queued fragments when the limits are hit instead of blocking")
+                    .default_value(DEFAULT_DROP)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecUInt64::builder("max-bitrate")
+                    .nick("Max Bitrate")
+                    .blurb("Maximum bits per second written by each stream, throttling unsynced output (0 = unlimited)")
+                    .default_value(DEFAULT_MAX_BITRATE)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecUInt::builder("bandwidth-window")
+                    .nick("Bandwidth Window")
+                    .blurb("Number of recent segments whose average bitrate is signalled as the bandwidth of the Representation")
+                    .minimum(1)
+                    .default_value(DEFAULT_BANDWIDTH_WINDOW)
+                    .build(),
+                glib::ParamSpecUInt64::builder("bytes-written")
+                    .nick("Bytes Written")
+                    .blurb("Total bytes written to media segments")
+                    .read_only()
+                    .build(),
+                glib::ParamSpecUInt64::builder("segments-written")
+                    .nick("Segments Written")
+                    .blurb("Total number of media segments written")
+                    .read_only()
+                    .build(),
+                glib::ParamSpecUInt64::builder("live-edge")
+                    .nick("Live Edge")
+                    .blurb("Running time in nanoseconds at the end of the latest media segment of all streams")
+                    .default_value(u64::MAX)
+                    .read_only()
+                    .build(),
+                glib::ParamSpecBoolean::builder("healthy")
+                    .nick("Healthy")
+                    .blurb("Whether every stream wrote a segment within twice the segment duration while PLAYING")
+                    .default_value(true)
+                    .read_only()
+                    .build(),
+            ]
+        });
+        PROPERTIES.as_ref()
+    }
+
+	fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+		let mut settings = self.settings.lock().unwrap();
+	
+		match pspec.name() {
+			"location" => {
+				settings.location = value
+					.get::<Option<String>>()
+					.expect("type checked upstream")
+					.unwrap_or_else(|| DEFAULT_LOCATION.into());
+			}
+			"init-location" => {
+				settings.init_location = value
+					.get::<Option<String>>()
+					.expect("type checked upstream")
+					.unwrap_or_else(|| DEFAULT_INIT_LOCATION.into());
+			}
+			"segment-location" => {
+				let segment_location = value
+					.get::<Option<String>>()
+					.expect("type checked upstream")
+					.unwrap_or_else(|| DEFAULT_SEGMENT_LOCATION.into());
+				match check_location_template(&segment_location) {
+					Ok(()) => settings.segment_location = segment_location,
+					Err(err) => {
+						// Posted without the lock, bus handlers may read the properties
+						drop(settings);
+						gst::element_imp_warning!(
+							self,
+							gst::LibraryError::Settings,
+							("Rejecting segment-location {:?}, the previous one is kept", segment_location),
+							["Expected a single %d: {}", err]
+						);
+					}
+				}
+			}
+			"target-duration" => {
+				let target_duration = value.get::<u32>().expect("type checked upstream");
+				settings.segment_duration = gst::ClockTime::from_seconds(target_duration as u64);
+			}
+			"segment-duration" => {
+				settings.segment_duration = gst::ClockTime::from_nseconds(value.get().expect("type checked upstream"));
+			}
+			"sync" => {
+				settings.sync = value.get().expect("type checked upstream");
+			}
+			"latency" => {
+				let latency_ns = value.get::<u64>().expect("type checked upstream");
+				settings.latency = gst::ClockTime::from_nseconds(latency_ns);
+			}
+			"auto-latency" => {
+				settings.auto_latency = value.get().expect("type checked upstream");
+			}
+			"mpd-id" => {
+				settings.mpd_id = value.get().expect("type checked upstream");
+			}
+			"period-id" => {
+				settings.period_id = value.get().expect("type checked upstream");
+			}
+			"title" => {
+				settings.title = value.get().expect("type checked upstream");
+			}
+			"source" => {
+				settings.source = value.get().expect("type checked upstream");
+			}
+			"copyright" => {
+				settings.copyright = value.get().expect("type checked upstream");
+			}
+			"output-dir" => {
+				settings.output_dir = value.get().expect("type checked upstream");
+			}
+			"backend" => {
+				settings.backend = value.get().expect("type checked upstream");
+			}
+			"custom-writer" => {
+				settings.custom_writer = value.get().expect("type checked upstream");
+			}
+			"output-policy" => {
+				settings.output_policy = value.get().expect("type checked upstream");
+			}
+			"window-size" => {
+				settings.window_size = value.get().expect("type checked upstream");
+			}
+			"ingest-url" => {
+				settings.ingest_url = value.get().expect("type checked upstream");
+			}
+			"file-mode" => {
+				let file_mode = value.get::<u32>().expect("type checked upstream");
+				settings.file_mode = (file_mode != 0).then_some(file_mode);
+			}
+			"max-segment-files" => {
+				settings.max_segment_files = value.get().expect("type checked upstream");
+			}
+			"user-agent" => {
+				settings.user_agent = value
+					.get::<Option<String>>()
+					.expect("type checked upstream")
+					.unwrap_or_else(|| DEFAULT_USER_AGENT.into());
+			}
+			"auth-token" => {
+				settings.auth_token = value.get().expect("type checked upstream");
+			}
+			"http-headers" => {
+				let http_headers = value.get::<Option<gst::Structure>>().expect("type checked upstream");
+				// Values are written as is into the requests
+				let invalid = http_headers.iter().flat_map(|s| s.iter()).find(|(_, value)| {
+					value.get::<&str>().map_or(true, |value| value.contains(['\r', '\n']))
+				});
+				match invalid {
+					Some((name, _)) => {
+						let name = name.to_string();
+						// Posted without the lock, bus handlers may read the properties
+						drop(settings);
+						gst::element_imp_warning!(
+							self,
+							gst::LibraryError::Settings,
+							("Rejecting http-headers {:?}, the previous ones are kept", http_headers),
+							["Expected single line strings, {} is not", name]
+						);
+					}
+					None => settings.http_headers = http_headers,
+				}
+			}
+			"xml-namespaces" => {
+				let xml_namespaces = value.get::<Option<gst::Structure>>().expect("type checked upstream");
+				let invalid = xml_namespaces.iter().flat_map(|s| s.iter()).find(|(prefix, uri)| {
+					!XML_NAMESPACE_PREFIXES.contains(&prefix.as_str()) || uri.get::<&str>().map_or(true, str::is_empty)
+				});
+				match invalid {
+					Some((prefix, _)) => {
+						let prefix = prefix.to_string();
+						// Posted without the lock, bus handlers may read the properties
+						drop(settings);
+						gst::element_imp_warning!(
+							self,
+							gst::LibraryError::Settings,
+							("Rejecting xml-namespaces {:?}, the previous ones are kept", xml_namespaces),
+							["Expected URI strings of the prefixes {:?}, {} is not", XML_NAMESPACE_PREFIXES, prefix]
+						);
+					}
+					None => settings.xml_namespaces = xml_namespaces,
+				}
+			}
+			"playlist-type" => {
+				settings.playlist_type = value.get().expect("type checked upstream");
+			}
+			"strict-alignment" => {
+				settings.strict_alignment = value.get().expect("type checked upstream");
+			}
+			"align-audio" => {
+				settings.align_audio = value.get().expect("type checked upstream");
+			}
+			"alignment-tolerance" => {
+				let tolerance_ns = value.get::<u64>().expect("type checked upstream");
+				settings.alignment_tolerance = gst::ClockTime::from_nseconds(tolerance_ns);
+			}
+			"start-time" => {
+				let start_time = value.get::<Option<String>>().expect("type checked upstream");
+				match start_time.as_deref().map(chrono::DateTime::parse_from_rfc3339) {
+					None => settings.start_time = None,
+					Some(Ok(start_time)) => settings.start_time = Some(start_time.to_utc()),
+					Some(Err(err)) => {
+						// Posted without the lock, bus handlers may read the properties
+						drop(settings);
+						gst::element_imp_warning!(
+							self,
+							gst::LibraryError::Settings,
+							("Rejecting start-time {:?}, the previous one is kept", start_time),
+							["Expected an RFC 3339 date and time: {}", err]
+						);
+					}
+				}
+			}
+			"start-segment-number" => {
+				settings.start_segment_number = value.get().expect("type checked upstream");
+			}
+			"resume" => {
+				settings.resume = value.get().expect("type checked upstream");
+			}
+			"per-period-manifest" => {
+				settings.per_period_manifest = value.get().expect("type checked upstream");
+			}
+			"manifest-frozen" => {
+				let frozen = value.get::<bool>().expect("type checked upstream");
+				let thawed = settings.manifest_frozen && !frozen;
+				settings.manifest_frozen = frozen;
+
+				// Catch up with the segments written while frozen
+				drop(settings);
+				if thawed && self.state.lock().unwrap().segments_written > 0 {
+					let _ = self.add_segment("");
+				}
+			}
+			"checksum-manifest" => {
+				settings.checksum_manifest = value.get().expect("type checked upstream");
+			}
+			"manifest-write-mode" => {
+				settings.manifest_write_mode = value.get().expect("type checked upstream");
+			}
+			"manifest-interval" => {
+				settings.manifest_interval = value.get().expect("type checked upstream");
+			}
+			"addressing" => {
+				settings.addressing = value.get().expect("type checked upstream");
+			}
+			"timescale-rounding" => {
+				settings.timescale_rounding = value.get().expect("type checked upstream");
+			}
+			"descriptor-placement" => {
+				settings.descriptor_placement = value.get().expect("type checked upstream");
+			}
+			"url-mode" => {
+				settings.url_mode = value.get().expect("type checked upstream");
+			}
+			"base-url" => {
+				settings.base_url = value.get().expect("type checked upstream");
+			}
+			"manifest-location-url" => {
+				settings.manifest_location_url = value.get().expect("type checked upstream");
+			}
+			"mux-streams" => {
+				settings.mux_streams = value.get().expect("type checked upstream");
+			}
+			"container" => {
+				settings.container = value.get().expect("type checked upstream");
+			}
+			"max-duration" => {
+				let max_duration = value.get::<u64>().expect("type checked upstream");
+				settings.max_duration =
+					(max_duration != 0).then(|| gst::ClockTime::from_nseconds(max_duration));
+			}
+			"negotiation-timeout" => {
+				let timeout = value.get::<u64>().expect("type checked upstream");
+				settings.negotiation_timeout = (timeout != 0).then(|| gst::ClockTime::from_nseconds(timeout));
+			}
+			name @ ("widevine-pssh" | "playready-pssh" | "playready-pro") => {
+				let data = value.get::<Option<String>>().expect("type checked upstream");
+				match data {
+					Some(ref data) if !is_base64(data) => {
+						// Posted without the lock, bus handlers may read the properties
+						drop(settings);
+						gst::element_imp_warning!(
+							self,
+							gst::LibraryError::Settings,
+							("Rejecting {} {:?}, the previous one is kept", name, data),
+							["Expected base64 initialization data"]
+						);
+					}
+					_ => match name {
+						"widevine-pssh" => settings.widevine_pssh = data,
+						"playready-pssh" => settings.playready_pssh = data,
+						_ => settings.playready_pro = data,
+					},
+				}
+			}
+			"cleanup-on-error" => {
+				settings.cleanup_on_error = value.get().expect("type checked upstream");
+			}
+			"cmaf-profile" => {
+				settings.cmaf_profile = value.get().expect("type checked upstream");
+			}
+			"inline-init" => {
+				settings.inline_init = value.get().expect("type checked upstream");
+			}
+			"combine-init-first-segment" => {
+				settings.combine_init_first_segment = value.get().expect("type checked upstream");
+			}
+			"ensure-styp" => {
+				settings.ensure_styp = value.get().expect("type checked upstream");
+			}
+			"force-keyframe" => {
+				settings.force_keyframe = value.get().expect("type checked upstream");
+			}
+			"require-keyframe" => {
+				settings.require_keyframe = value.get().expect("type checked upstream");
+			}
+			"max-bitrate" => {
+				settings.max_bitrate = value.get().expect("type checked upstream");
+			}
+			"bandwidth-window" => {
+				settings.bandwidth_window = value.get().expect("type checked upstream");
+			}
+			"max-buffers" => {
+				settings.max_buffers = value.get().expect("type checked upstream");
+			}
+			"max-bytes" => {
+				settings.max_bytes = value.get().expect("type checked upstream");
+			}
+			"drop" => {
+				settings.drop = value.get().expect("type checked upstream");
+			}
+			"thumbnail-location" => {
+				let thumbnail_location = value
+					.get::<Option<String>>()
+					.expect("type checked upstream")
+					.unwrap_or_else(|| DEFAULT_THUMBNAIL_LOCATION.into());
+				match check_location_template(&thumbnail_location) {
+					Ok(()) => settings.thumbnail_location = thumbnail_location,
+					Err(err) => {
+						// Posted without the lock, bus handlers may read the properties
+						drop(settings);
+						gst::element_imp_warning!(
+							self,
+							gst::LibraryError::Settings,
+							("Rejecting thumbnail-location {:?}, the previous one is kept", thumbnail_location),
+							["Expected a single %d: {}", err]
+						);
+					}
+				}
+			}
+			"cmaf-brand" => {
+				settings.cmaf_brand = value.get().expect("type checked upstream");
+			}
+			"sap-type" => {
+				settings.sap_type = value.get().expect("type checked upstream");
+			}
+			"reorder-delay-offset" => {
+				settings.reorder_delay_offset = value.get().expect("type checked upstream");
+			}
+			"segment-alignment" => {
+				settings.segment_alignment = value.get().expect("type checked upstream");
+			}
+			"manifest-encoding" => {
+				settings.manifest_encoding = value.get().expect("type checked upstream");
+			}
+			"manifest-bom" => {
+				settings.manifest_bom = value.get().expect("type checked upstream");
+			}
+			"manifest-indent" => {
+				settings.manifest_indent = value.get().expect("type checked upstream");
+			}
+			"thumbnail-tiles" => {
+				let tiles = value.get::<Option<String>>().expect("type checked upstream");
+				match tiles.as_deref().map(parse_tiles) {
+					None => settings.thumbnail_tiles = DEFAULT_THUMBNAIL_TILES,
+					Some(Some(tiles)) => settings.thumbnail_tiles = tiles,
+					Some(None) => {
+						// Posted without the lock, bus handlers may read the properties
+						drop(settings);
+						gst::element_imp_warning!(
+							self,
+							gst::LibraryError::Settings,
+							("Rejecting thumbnail-tiles {:?}, the previous one is kept", tiles),
+							["Expected NxM"]
+						);
+					}
+				}
+			}
+			"presentation-delay" => {
+				settings.presentation_delay = value.get().expect("type checked upstream");
+				if settings.presentation_delay != 0
+					&& (settings.presentation_delay as u64) < settings.segment_duration.mseconds()
+				{
+					gst::warning!(
+						CAT,
+						imp = self,
+						"presentation-delay {} ms is below minBufferTime, using {} ms instead",
+						settings.presentation_delay,
+						settings.segment_duration.mseconds()
+					);
+				}
+			}
+			_ => unimplemented!(),
+		}
+	}
+
+	fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+		let settings = self.settings.lock().unwrap();
+	
+		match pspec.name() {
+			"location" => settings.location.to_value(),
+			"init-location" => settings.init_location.to_value(),
+			"segment-location" => settings.segment_location.to_value(),
+			"target-duration" => (settings.segment_duration.nseconds().div_ceil(gst::ClockTime::SECOND.nseconds()) as u32).to_value(),
+			"segment-duration" => settings.segment_duration.nseconds().to_value(),
+			"sync" => settings.sync.to_value(),
+			"latency" => settings.latency.nseconds().to_value(),
+			"auto-latency" => settings.auto_latency.to_value(),
+			"mpd-id" => settings.mpd_id.to_value(),
+			"period-id" => settings.period_id.to_value(),
+			"title" => settings.title.to_value(),
+			"source" => settings.source.to_value(),
+			"copyright" => settings.copyright.to_value(),
+			"output-dir" => settings.output_dir.to_value(),
+			"backend" => settings.backend.to_value(),
+			"custom-writer" => settings.custom_writer.to_value(),
+			"output-policy" => settings.output_policy.to_value(),
+			"window-size" => settings.window_size.to_value(),
+			"ingest-url" => settings.ingest_url.to_value(),
+			"file-mode" => settings.file_mode.unwrap_or(0).to_value(),
+			"max-segment-files" => settings.max_segment_files.to_value(),
+			"user-agent" => settings.user_agent.to_value(),
+			"auth-token" => settings.auth_token.to_value(),
+			"http-headers" => settings.http_headers.to_value(),
+			"xml-namespaces" => settings.xml_namespaces.to_value(),
+			"playlist-type" => settings.playlist_type.to_value(),
+			"presentation-delay" => settings.presentation_delay.to_value(),
+			"strict-alignment" => settings.strict_alignment.to_value(),
+			"align-audio" => settings.align_audio.to_value(),
+			"alignment-tolerance" => settings.alignment_tolerance.nseconds().to_value(),
+			"start-time" => settings.start_time.map(|start_time| start_time.to_rfc3339()).to_value(),
+			"start-segment-number" => settings.start_segment_number.to_value(),
+			"resume" => settings.resume.to_value(),
+			"manifest-frozen" => settings.manifest_frozen.to_value(),
+			"checksum-manifest" => settings.checksum_manifest.to_value(),
+			"manifest-write-mode" => settings.manifest_write_mode.to_value(),
+			"manifest-interval" => settings.manifest_interval.to_value(),
+			"per-period-manifest" => settings.per_period_manifest.to_value(),
+			"addressing" => settings.addressing.to_value(),
+			"timescale-rounding" => settings.timescale_rounding.to_value(),
+			"descriptor-placement" => settings.descriptor_placement.to_value(),
+			"url-mode" => settings.url_mode.to_value(),
+			"base-url" => settings.base_url.to_value(),
+			"manifest-location-url" => settings.manifest_location_url.to_value(),
+			"mux-streams" => settings.mux_streams.to_value(),
+			"container" => settings.container.to_value(),
+			"max-duration" => settings.max_duration.map_or(0, |max| max.nseconds()).to_value(),
+			"negotiation-timeout" => settings.negotiation_timeout.map_or(0, gst::ClockTime::nseconds).to_value(),
+			"widevine-pssh" => settings.widevine_pssh.to_value(),
+			"playready-pssh" => settings.playready_pssh.to_value(),
+			"playready-pro" => settings.playready_pro.to_value(),
+			"cleanup-on-error" => settings.cleanup_on_error.to_value(),
+			"cmaf-profile" => settings.cmaf_profile.to_value(),
+			"inline-init" => settings.inline_init.to_value(),
+			"combine-init-first-segment" => settings.combine_init_first_segment.to_value(),
+			"ensure-styp" => settings.ensure_styp.to_value(),
+			"force-keyframe" => settings.force_keyframe.to_value(),
+			"require-keyframe" => settings.require_keyframe.to_value(),
+			"max-buffers" => settings.max_buffers.to_value(),
+			"max-bitrate" => settings.max_bitrate.to_value(),
+			"bandwidth-window" => settings.bandwidth_window.to_value(),
+			"max-bytes" => settings.max_bytes.to_value(),
+			"drop" => settings.drop.to_value(),
+			"thumbnail-location" => settings.thumbnail_location.to_value(),
+			"cmaf-brand" => settings.cmaf_brand.to_value(),
+			"manifest-indent" => settings.manifest_indent.to_value(),
+			"sap-type" => settings.sap_type.to_value(),
+			"reorder-delay-offset" => settings.reorder_delay_offset.to_value(),
+			"segment-alignment" => settings.segment_alignment.to_value(),
+			"manifest-encoding" => settings.manifest_encoding.to_value(),
+			"manifest-bom" => settings.manifest_bom.to_value(),
+			"thumbnail-tiles" => {
+				let (columns, rows) = settings.thumbnail_tiles;
+				format!("{columns}x{rows}").to_value()
+			}
+			"bytes-written" => self.state.lock().unwrap().bytes_written.to_value(),
+			"segments-written" => self.state.lock().unwrap().segments_written.to_value(),
+			"live-edge" => {
+				// streams are locked before settings
+				drop(settings);
+				let streams = self.streams.lock().unwrap();
+				let live_edge = streams.keys().filter_map(|pad_name| live_edge(&streams, pad_name)).max();
+				live_edge.map_or(u64::MAX, gst::ClockTime::nseconds).to_value()
+			}
+			"healthy" => (!self.state.lock().unwrap().stalled).to_value(),
+			_ => unimplemented!("Property {} not implemented", pspec.name()),
+		}
+	}
+
+	fn signals() -> &'static [glib::subclass::Signal] {
+		static SIGNALS: LazyLock<Vec<glib::subclass::Signal>> = LazyLock::new(|| {
+			vec![
+				glib::subclass::Signal::builder("get-stream-stats")
+					.param_types([String::static_type()])
+					.return_type::<Option<gst::Structure>>()
+					.action()
+					.class_handler(|_token, args| {
+						let element = args[0].get::<super::DashCmafSink>().expect("signal arg");
+						let pad_name = args[1].get::<String>().expect("signal arg");
+
+						Some(element.imp().stream_stats(&pad_name).to_value())
+					})
+					.build(),
+				// Muxer of a stream for tuning properties the element doesn't
+				// expose, segmentation still expects one buffer list per fragment
+				glib::subclass::Signal::builder("get-muxer")
+					.param_types([String::static_type()])
+					.return_type::<Option<gst::Element>>()
+					.action()
+					.class_handler(|_token, args| {
+						let element = args[0].get::<super::DashCmafSink>().expect("signal arg");
+						let pad_name = args[1].get::<String>().expect("signal arg");
+
+						let streams = element.imp().streams.lock().unwrap();
+						let muxer = streams.get(&pad_name).and_then(|stream| stream.cmafmux.clone());
+						Some(muxer.to_value())
+					})
+					.build(),
+				glib::subclass::Signal::builder("push-event")
+					.param_types([
+						String::static_type(),
+						String::static_type(),
+						u64::static_type(),
+						u64::static_type(),
+						String::static_type(),
+					])
+					.return_type::<bool>()
+					.action()
+					.class_handler(|_token, args| {
+						let element = args[0].get::<super::DashCmafSink>().expect("signal arg");
+						let scheme_id_uri = args[1].get::<Option<String>>().expect("signal arg");
+						let value = args[2].get::<Option<String>>().expect("signal arg");
+						let presentation_time = args[3].get::<u64>().expect("signal arg");
+						let duration = args[4].get::<u64>().expect("signal arg");
+						let message_data = args[5].get::<Option<String>>().expect("signal arg");
+
+						let Some(scheme_id_uri) = scheme_id_uri.filter(|uri| !uri.is_empty()) else {
+							gst::warning!(CAT, obj = element, "Ignoring event without scheme");
+							return Some(false.to_value());
+						};
+						let event = DashCmafSinkEvent {
+							scheme_id_uri,
+							value,
+							presentation_time: gst::ClockTime::from_nseconds(presentation_time),
+							duration: (duration != u64::MAX).then(|| gst::ClockTime::from_nseconds(duration)),
+							message_data,
+							scte35_binary: None,
+						};
+						element.imp().state.lock().unwrap().events.push(event);
+
+						Some(true.to_value())
+					})
+					.build(),
+				glib::subclass::Signal::builder("get-live-edge")
+					.param_types([String::static_type()])
+					.return_type::<u64>()
+					.action()
+					.class_handler(|_token, args| {
+						let element = args[0].get::<super::DashCmafSink>().expect("signal arg");
+						let pad_name = args[1].get::<String>().expect("signal arg");
+
+						let streams = element.imp().streams.lock().unwrap();
+						let live_edge = live_edge(&streams, &pad_name);
+						Some(live_edge.map_or(u64::MAX, gst::ClockTime::nseconds).to_value())
+					})
+					.build(),
+				glib::subclass::Signal::builder("generate-test-manifest")
+					.return_type::<Option<String>>()
+					.action()
+					.class_handler(|_token, args| {
+						let element = args[0].get::<super::DashCmafSink>().expect("signal arg");
+						Some(element.imp().test_manifest().to_value())
+					})
+					.build(),
+				// Starts a new Period, optionally writing the current manifest
+				// to the given location first
+				glib::subclass::Signal::builder("reset")
+					.param_types([String::static_type()])
+					.return_type::<bool>()
+					.action()
+					.class_handler(|_token, args| {
+						let element = args[0].get::<super::DashCmafSink>().expect("signal arg");
+						let archive_location = args[1].get::<Option<String>>().expect("signal arg");
+
+						let reset = element.imp().reset(archive_location.as_deref().filter(|location| !location.is_empty()));
+						Some(reset.to_value())
+					})
+					.build(),
+				// Emitted with custom-writer for every complete resource, with
+				// its location and data, returning whether it was written
+				glib::subclass::Signal::builder("write-init-segment")
+					.param_types([String::static_type(), glib::Bytes::static_type()])
+					.return_type::<bool>()
+					.build(),
+				glib::subclass::Signal::builder("write-media-segment")
+					.param_types([String::static_type(), glib::Bytes::static_type()])
+					.return_type::<bool>()
+					.build(),
+				glib::subclass::Signal::builder("write-manifest")
+					.param_types([String::static_type(), glib::Bytes::static_type()])
+					.return_type::<bool>()
+					.build(),
+				// Starts a new Period with the given id for the next segments,
+				// at the given running time or the end of the latest segments
+				glib::subclass::Signal::builder("new-period")
+					.param_types([String::static_type(), u64::static_type()])
+					.return_type::<bool>()
+					.action()
+					.class_handler(|_token, args| {
+						let element = args[0].get::<super::DashCmafSink>().expect("signal arg");
+						let id = args[1].get::<Option<String>>().expect("signal arg").filter(|id| !id.is_empty());
+						let start = args[2].get::<u64>().expect("signal arg");
+
+						let start = (start != u64::MAX).then(|| gst::ClockTime::from_nseconds(start));
+						Some(element.imp().start_period(id, start, None, true).to_value())
+					})
+					.build(),
+				glib::subclass::Signal::builder("pull-resource")
+					.param_types([String::static_type()])
+					.return_type::<Option<glib::Bytes>>()
+					.action()
+					.class_handler(|_token, args| {
+						let element = args[0].get::<super::DashCmafSink>().expect("signal arg");
+						let location = args[1].get::<String>().expect("signal arg");
+
+						let output = element.imp().state.lock().unwrap().output.clone();
+						Some(output.and_then(|output| output.pull(&location)).to_value())
+					})
+					.build(),
+			]
+		});
+
+		SIGNALS.as_ref()
+	}
+
+    fn constructed(&self) {
+        self.parent_constructed();
+    }
+}
+
+impl GstObjectImpl for DashCmafSink {}
+
+impl ObjectImpl for DashCmafSinkPad {
+	fn properties() -> &'static [glib::ParamSpec] {
+		static PROPERTIES: LazyLock<Vec<glib::ParamSpec>> = LazyLock::new(|| {
+			vec![
+				glib::ParamSpecString::builder("role")
+					.nick("Role")
+					.blurb("DASH Role of the stream (e.g. main, alternate, commentary, subtitle), default being signalled as main")
+					.build(),
+				glib::ParamSpecString::builder("accessibility")
+					.nick("Accessibility")
+					.blurb("DASH Accessibility value of the stream (e.g. description, caption)")
+					.build(),
+				glib::ParamSpecString::builder("lang")
+					.nick("Language")
+					.blurb("BCP-47 language of the stream, defaults to the language-code tag")
+					.build(),
+				glib::ParamSpecString::builder("label")
+					.nick("Label")
+					.blurb("Label of the AdaptationSet of the stream")
+					.build(),
+				glib::ParamSpecUInt::builder("group")
+					.nick("Group")
+					.blurb("Group of the AdaptationSet of the stream, alternative codecs of the same group being separate AdaptationSets (0 = none)")
+					.build(),
+				glib::ParamSpecString::builder("preselection-id")
+					.nick("Preselection Id")
+					.blurb("Id of a Preselection whose main component is this audio stream")
+					.build(),
+				glib::ParamSpecString::builder("preselection-components")
+					.nick("Preselection Components")
+					.blurb("Space separated names of the audio pads bundled in the Preselection after this one")
+					.build(),
+				glib::ParamSpecUInt::builder("switching-group")
+					.nick("Switching Group")
+					.blurb("AdaptationSets of the same switching group are signalled as seamlessly switchable (0 = none)")
+					.build(),
+				glib::ParamSpecString::builder("trick-mode-for")
+					.nick("Trick Mode For")
+					.blurb("Name of the video pad this I-frame-only stream is the trick-mode version of")
+					.build(),
+				glib::ParamSpecUInt::builder("selection-priority")
+					.nick("Selection Priority")
+					.blurb("selectionPriority of the AdaptationSet of the stream, clients picking the highest by default (0 = none)")
+					.build(),
+				glib::ParamSpecUInt::builder("quality-ranking")
+					.nick("Quality Ranking")
+					.blurb("qualityRanking of the Representation among the others of its AdaptationSet, lower is better (0 = none)")
+					.maximum(u8::MAX as u32)
+					.build(),
+				glib::ParamSpecUInt::builder("timescale")
+					.nick("Timescale")
+					.blurb("Timescale of the segment timing of the stream in the manifest, also the track timescale of muxers supporting it, the sample rate of audio if unset")
+					.minimum(1)
+					.default_value(DEFAULT_TIMESCALE)
+					.mutable_ready()
+					.build(),
+				glib::ParamSpecUInt64::builder("fragment-duration")
+					.nick("Fragment Duration")
+					.blurb("Duration in nanoseconds of the segments of the stream (0 = segment-duration)")
+					.mutable_ready()
+					.build(),
+				glib::ParamSpecUInt64::builder("bandwidth")
+					.nick("Bandwidth")
+					.blurb("Bandwidth in bits per second declared for the Representation of the stream instead of the measured one (0 = measured)")
+					.build(),
+				glib::ParamSpecUInt64::builder("start-number")
+					.nick("Start Number")
+					.blurb("Number of the first segment of the stream, in its filenames and startNumber (G_MAXUINT64 = start-segment-number)")
+					.default_value(u64::MAX)
+					.mutable_ready()
+					.build(),
+			]
+		});
+		PROPERTIES.as_ref()
+	}
+
+	fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+		let mut settings = self.settings.lock().unwrap();
+
+		match pspec.name() {
+			"role" => {
+				settings.role = value.get().expect("type checked upstream");
+			}
+			"accessibility" => {
+				settings.accessibility = value.get().expect("type checked upstream");
+			}
+			"lang" => {
+				settings.lang = value.get().expect("type checked upstream");
+			}
+			"label" => {
+				settings.label = value.get().expect("type checked upstream");
+			}
+			"group" => {
+				let group = value.get::<u32>().expect("type checked upstream");
+				settings.group = (group != 0).then_some(group);
+			}
+			"preselection-id" => {
+				settings.preselection_id = value.get().expect("type checked upstream");
+			}
+			"preselection-components" => {
+				settings.preselection_components = value.get().expect("type checked upstream");
+			}
+			"switching-group" => {
+				let switching_group = value.get::<u32>().expect("type checked upstream");
+				settings.switching_group = (switching_group != 0).then_some(switching_group);
+			}
+			"trick-mode-for" => {
+				settings.trick_mode_for = value.get::<Option<String>>().expect("type checked upstream").filter(|pad| !pad.is_empty());
+			}
+			"selection-priority" => {
+				let selection_priority = value.get::<u32>().expect("type checked upstream");
+				settings.selection_priority = (selection_priority != 0).then_some(selection_priority);
+			}
+			"quality-ranking" => {
+				let quality_ranking = value.get::<u32>().expect("type checked upstream") as u8;
+				settings.quality_ranking = (quality_ranking != 0).then_some(quality_ranking);
+			}
+			"timescale" => {
+				let timescale = value.get::<u32>().expect("type checked upstream");
+				settings.timescale = Some(timescale);
+
+				if let Some(target) = self.obj().target().filter(|target| target.has_property("trak-timescale", None)) {
+					target.set_property("trak-timescale", timescale);
+				}
+			}
+			"fragment-duration" => {
+				let duration_ns = value.get::<u64>().expect("type checked upstream");
+				settings.fragment_duration =
+					Some(gst::ClockTime::from_nseconds(duration_ns)).filter(|duration| !duration.is_zero());
+
+				// Thumbnail streams have no muxer to configure
+				let muxer = self.obj().target().and_then(|target| target.parent_element());
+				let aac_rate = self.obj().current_caps().and_then(|caps| aac_rate(caps.structure(0)));
+				let duration = settings.fragment_duration.map(|duration| match aac_rate {
+					Some(rate) => whole_aac_frames(duration, rate),
+					None => duration,
+				});
+				if let (Some(muxer), Some(duration)) = (muxer, duration) {
+					if muxer.has_property("fragment-duration", None) {
+						muxer.set_property("fragment-duration", duration);
+					}
+				}
+			}
+			"bandwidth" => {
+				let bandwidth = value.get::<u64>().expect("type checked upstream");
+				settings.bandwidth = (bandwidth != 0).then_some(bandwidth);
+			}
+			"start-number" => {
+				let start_number = value.get::<u64>().expect("type checked upstream");
+				settings.start_number = (start_number != u64::MAX).then_some(start_number);
+
+				// The stream was created with the number of the element
+				drop(settings);
+				if let Some(element) = self.obj().parent().and_downcast::<super::DashCmafSink>() {
+					element.imp().restart_numbering(&self.obj().name());
+				}
+			}
+			_ => unimplemented!(),
+		}
+	}
+
+	fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+		let settings = self.settings.lock().unwrap();
+
+		match pspec.name() {
+			"role" => settings.role.to_value(),
+			"accessibility" => settings.accessibility.to_value(),
+			"lang" => settings.lang.to_value(),
+			"label" => settings.label.to_value(),
+			"group" => settings.group.unwrap_or(0).to_value(),
+			"preselection-id" => settings.preselection_id.to_value(),
+			"preselection-components" => settings.preselection_components.to_value(),
+			"switching-group" => settings.switching_group.unwrap_or(0).to_value(),
+			"trick-mode-for" => settings.trick_mode_for.to_value(),
+			"selection-priority" => settings.selection_priority.unwrap_or(0).to_value(),
+			"quality-ranking" => (settings.quality_ranking.unwrap_or(0) as u32).to_value(),
+			"timescale" => settings.timescale.unwrap_or(DEFAULT_TIMESCALE).to_value(),
+			"fragment-duration" => settings.fragment_duration.map_or(0, gst::ClockTime::nseconds).to_value(),
+			"bandwidth" => settings.bandwidth.unwrap_or(0).to_value(),
+			"start-number" => settings.start_number.unwrap_or(u64::MAX).to_value(),
+			_ => unimplemented!("Property {} not implemented", pspec.name()),
+		}
+	}
+}
+
+impl GstObjectImpl for DashCmafSinkPad {}
+
+impl PadImpl for DashCmafSinkPad {}
+
+impl ProxyPadImpl for DashCmafSinkPad {}
+
+impl GhostPadImpl for DashCmafSinkPad {}
+
+impl DashCmafSinkPad {
+	/// Timescale of the segment timing, the sample rate of audio unless set
+	fn timescale(&self, caps: Option<&gst::CapsRef>) -> u32 {
+		let timescale = self.settings.lock().unwrap().timescale;
+
+		timescale
+			.or_else(|| {
+				let s = caps?.structure(0).filter(|s| s.name().starts_with("audio/"))?;
+				s.get::<i32>("rate").ok().filter(|rate| *rate > 0).map(|rate| rate as u32)
+			})
+			.unwrap_or(DEFAULT_TIMESCALE)
+	}
+
+	fn adaptation_set_key(&self) -> AdaptationSetKey {
+		let settings = self.settings.lock().unwrap();
+
+		AdaptationSetKey {
+			lang: settings.lang.clone().or_else(|| settings.tag_lang.clone()),
+			// The default track has the main role of its media
+			role: settings.role.clone().map(|role| match role.as_str() {
+				"default" => "main".to_string(),
+				_ => role,
+			}),
+			accessibility: settings.accessibility.clone(),
+			label: settings.label.clone(),
+			group: settings.group,
+			codec: None,
+			switching_group: settings.switching_group,
+			selection_priority: settings.selection_priority,
+			trick_mode_for: settings.trick_mode_for.clone(),
+		}
+	}
+
+	/// Id of the Preselection declared by the stream and the names of the
+	/// pads of its components, this one being the main component
+	fn preselection(&self) -> Option<(String, Vec<String>)> {
+		let settings = self.settings.lock().unwrap();
+		let id = settings.preselection_id.clone()?;
+
+		let pad_name = self.obj().name().to_string();
+		let mut components = vec![pad_name.clone()];
+		components.extend(
+			settings
+				.preselection_components
+				.iter()
+				.flat_map(|components| components.split_whitespace())
+				.filter(|component| *component != pad_name)
+				.map(String::from),
+		);
+
+		Some((id, components))
+	}
+}
+
+impl ElementImpl for DashCmafSink {
+	fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+		static ELEMENT_METADATA: LazyLock<gst::subclass::ElementMetadata> = LazyLock::new(|| {
+			gst::subclass::ElementMetadata::new(
+				"DASH CMAF Sink",
+				"Sink/Network/Dash",
+				"Writes H.264, VP8, VP9, AAC, MP3, AC-3, E-AC-3, Opus and Vorbis streams as CMAF or WebM segments, and JPEG thumbnails as tile images, described by a DASH manifest",
+				"Roberto Viola <rviola@vicomtech.org>",
+			)
+		});
+		Some(&*ELEMENT_METADATA)
+	}
+
+    fn pad_templates() -> &'static [gst::PadTemplate] {
+        static PAD_TEMPLATES: LazyLock<Vec<gst::PadTemplate>> = LazyLock::new(|| {
+            let pad_template = gst::PadTemplate::with_gtype(
+                "sink_%u",
+                gst::PadDirection::Sink,
+                gst::PadPresence::Request,
+                &[
+                    gst::Structure::builder("video/x-h264")
+                        .field("stream-format", gst::List::new(["avc", "avc3"]))
+                        .field("alignment", "au")
+                        .field("width", gst::IntRange::new(1, u16::MAX as i32))
+                        .field("height", gst::IntRange::new(1, u16::MAX as i32))
+                        .build(),
+                    gst::Structure::builder("audio/mpeg")
+                        .field("mpegversion", 4i32)
+                        .field("stream-format", "raw")
+                        .field("channels", gst::IntRange::new(1, u16::MAX as i32))
+                        .field("rate", gst::IntRange::new(1, i32::MAX))
+                        .build(),
+                    gst::Structure::builder("audio/x-ac3")
+                        .field("framed", true)
+                        .field("channels", gst::IntRange::new(1, 6))
+                        .field("rate", gst::IntRange::new(1, i32::MAX))
+                        .build(),
+                    gst::Structure::builder("audio/x-eac3")
+                        .field("framed", true)
+                        .field("channels", gst::IntRange::new(1, 16))
+                        .field("rate", gst::IntRange::new(1, i32::MAX))
+                        .build(),
+                    gst::Structure::builder("audio/mpeg")
+                        .field("mpegversion", 1i32)
+                        .field("layer", 3i32)
+                        .field("channels", gst::IntRange::new(1, 2))
+                        .field("rate", gst::IntRange::new(1, i32::MAX))
+                        .build(),
+                    gst::Structure::builder("video/x-vp8")
+                        .field("width", gst::IntRange::new(1, u16::MAX as i32))
+                        .field("height", gst::IntRange::new(1, u16::MAX as i32))
+                        .build(),
+                    gst::Structure::builder("video/x-vp9")
+                        .field("width", gst::IntRange::new(1, u16::MAX as i32))
+                        .field("height", gst::IntRange::new(1, u16::MAX as i32))
+                        .build(),
+                    gst::Structure::builder("audio/x-opus")
+                        .field("channels", gst::IntRange::new(1, 8))
+                        .field("rate", gst::IntRange::new(1, i32::MAX))
+                        .build(),
+                    gst::Structure::builder("audio/x-vorbis")
+                        .field("channels", gst::IntRange::new(1, 255))
+                        .field("rate", gst::IntRange::new(1, i32::MAX))
+                        .build(),
+                ]
+                .into_iter()
+                .collect::<gst::Caps>(),
+                super::DashCmafSinkPad::static_type(),
+            )
+            .unwrap();
+
+            let thumbnail_pad_template = gst::PadTemplate::with_gtype(
+                "thumbnail_%u",
+                gst::PadDirection::Sink,
+                gst::PadPresence::Request,
+                &gst::Caps::builder("image/jpeg")
+                    .field("width", gst::IntRange::new(1, u16::MAX as i32))
+                    .field("height", gst::IntRange::new(1, u16::MAX as i32))
+                    .build(),
+                super::DashCmafSinkPad::static_type(),
+            )
+            .unwrap();
+
+            vec![pad_template, thumbnail_pad_template]
+        });
+
+        PAD_TEMPLATES.as_ref()
+    }
+
+	fn request_new_pad(
+		&self,
+		templ: &gst::PadTemplate,
+		_name: Option<&str>,
+		_caps: Option<&gst::Caps>,
+	) -> Option<gst::Pad> {
+		let taken = |name: &str| self.obj().static_pad(name).is_some() || self.streams.lock().unwrap().contains_key(name);
+		let pad_name = match _name {
+			Some(name) if taken(name) => {
+				gst::error!(CAT, imp = self, "A pad named {name} already exists");
+				return None;
+			}
+			Some(name) => name.to_string(),
+			None => {
+				let mut pad_index = self.pad_index.lock().unwrap();
+				loop {
+					let name = templ.name_template().replace("%u", &pad_index.to_string());
+					*pad_index += 1;
+					if !taken(&name) {
+						break name;
+					}
+				}
+			}
+		};
+	
+		gst::info!(CAT, imp = self, "Requesting new pad: {pad_name}");
+	
+		let thumbnail = templ.name_template() == "thumbnail_%u";
+		let (mux_streams, keyframe_interval, container, auto_latency) = {
+			let settings = self.settings.lock().unwrap();
+			(
+				!thumbnail && settings.mux_streams,
+				(!thumbnail && settings.force_keyframe)
+					.then(|| settings.segment_duration),
+				settings.container,
+				!thumbnail && settings.auto_latency,
+			)
+		};
+		let caps = _caps.map(|caps| caps.as_ref());
+
+		// Further pads of a muxed stream only add a track to its muxer
+		if mux_streams {
+			let muxed = self
+				.streams
+				.lock()
+				.unwrap()
+				.iter()
+				.filter(|(_, stream)| stream.released.is_none())
+				.find_map(|(name, stream)| Some((name.clone(), stream.cmafmux.clone()?)));
+			if let Some((stream_name, muxer)) = muxed {
+				let target_pad = request_muxer_pad(&muxer, caps);
+				let Some(gpad) = self.add_sink_pad(templ, &pad_name, target_pad.as_ref(), Some(muxer.clone())) else {
+					if let Some(target_pad) = target_pad {
+						muxer.release_request_pad(&target_pad);
+					}
+					return None;
+				};
+				if let Some(interval) = keyframe_interval {
+					self.force_keyframes(&gpad, interval);
+				}
+				if let Some(stream) = self.streams.lock().unwrap().get_mut(&stream_name) {
+					stream.track_pads.push(gpad.clone());
+				}
+				if auto_latency {
+					self.detect_gop(&gpad);
+				}
+				return Some(gpad.upcast());
+			}
+		}
+
+		// Create stream components
+		let muxer = match (thumbnail, container, mux_streams) {
+			(true, _, _) => None,
+			(false, DashCmafSinkContainer::Webm, _) => Some("webmmux"),
+			(false, DashCmafSinkContainer::Cmaf, false) => Some("cmafmux"),
+			(false, DashCmafSinkContainer::Cmaf, true) => Some("isofmp4mux"),
+		};
+		let mut stream = match DashCmafSinkStream::new(muxer) {
+			Ok(stream) => stream,
+			Err(err) => {
+				gst::element_imp_error!(
+					self,
+					gst::CoreError::MissingPlugin,
+					("Couldn't create {} for pad {}", muxer.unwrap_or_default(), pad_name),
+					["cmafmux and isofmp4mux are in the fmp4 plugin of gst-plugins-rs, webmmux in gst-plugins-good: {}", err]
+				);
+				return None;
+			}
+		};
+		let settings = self.settings.lock().unwrap();
+		let obj = self.obj();
+
+		stream.start_number = settings.start_segment_number;
+		stream.segment_idx = settings.start_segment_number;
+		stream.appsink.set_property("sync", settings.sync);
+		stream.appsink.set_property("max-buffers", settings.max_buffers);
+		stream.appsink.set_property("drop", settings.drop);
+		stream.appsink.set_property("max-bitrate", settings.max_bitrate);
+		// max-bytes is only available since GStreamer 1.24
+		if stream.appsink.has_property("max-bytes", None) {
+			stream.appsink.set_property("max-bytes", settings.max_bytes);
+		} else if settings.max_bytes > 0 {
+			gst::warning!(CAT, imp = self, "max-bytes is not supported by the installed appsink");
+		}
+		obj.add(&stream.appsink).ok()?;
+		// Elements already added are taken out again when the pad can't be
+		// made
+		let discard = |stream: &DashCmafSinkStream| {
+			for element in stream.cmafmux.iter().chain([stream.appsink.upcast_ref()]) {
+				let _ = obj.remove(element);
+			}
+			None
+		};
+
+		let target_duration = settings.segment_duration;
+		let target_pad = match stream.cmafmux {
+			Some(ref cmafmux) => {
+				if cmafmux.has_property("fragment-duration", None) {
+					cmafmux.set_property("fragment-duration", target_duration);
+				}
+				// webmmux starts clusters at the first keyframe after this
+				if cmafmux.has_property("min-cluster-duration", None) {
+					cmafmux.set_property("streamable", true);
+					cmafmux.set_property("min-cluster-duration", target_duration.nseconds() as i64);
+				}
+				if cmafmux.has_property("latency", None) {
+					cmafmux.set_property("latency", self.muxer_latency(&settings));
+				}
+
+				// Add and link elements
+				if obj.add(cmafmux).is_err() || cmafmux.link(&stream.appsink).is_err() {
+					return discard(&stream);
+				}
+				request_muxer_pad(cmafmux, caps)
+			}
+			None => stream.appsink.static_pad("sink"),
+		};
+	
+		let Some(gpad) = self.add_sink_pad(templ, &pad_name, target_pad.as_ref(), stream.cmafmux.clone()) else {
+			return discard(&stream);
+		};
+		if let Some(interval) = keyframe_interval {
+			self.force_keyframes(&gpad, interval);
+		}
+		if auto_latency {
+			self.detect_gop(&gpad);
+		}
+	
+		// Queued fragments accounting, to report the ones dropped by appsink
+		let stream_pad_name = pad_name.clone();
+		let self_weak = self.downgrade();
+		stream.appsink.static_pad("sink")?.add_probe(
+			gst::PadProbeType::BUFFER | gst::PadProbeType::BUFFER_LIST,
+			move |_, _| {
+				if let Some(imp) = self_weak.upgrade() {
+					imp.on_appsink_buffer(&stream_pad_name);
+				}
+				gst::PadProbeReturn::Ok
+			},
+		);
+
+		// Appsink callback
+		let stream_pad_name = pad_name.clone();
+		let self_weak = self.downgrade();
+		stream.appsink.set_callbacks(
+			gst_app::AppSinkCallbacks::builder()
+				.new_sample(move |sink| {
+					let Some(imp) = self_weak.upgrade() else {
+						return Err(gst::FlowError::Eos);
+					};
+	
+					let sample = sink.pull_sample().map_err(|_| gst::FlowError::Eos)?;
+					imp.on_new_sample(sample, &stream_pad_name) // you could pass pad_name if needed
+				})
+				.eos({
+					let self_weak = self.downgrade();
+					let stream_pad_name = pad_name.clone();
+					move |_| {
+						if let Some(imp) = self_weak.upgrade() {
+							let _ = imp.flush_webm_cluster(&stream_pad_name, None);
+							imp.on_eos(&stream_pad_name);
+						}
+					}
+				})
+				.build(),
+		);
+	
+		// Store the stream context, streams are locked before settings
+		drop(settings);
+		let mut streams = self.streams.lock().unwrap();
+		streams.insert(pad_name.clone(), stream);
+	
+		Some(gpad.upcast())
+	}
+
+	fn change_state(
+		&self,
+		transition: gst::StateChange,
+	) -> Result<gst::StateChangeSuccess, gst::StateChangeError> {
+		if matches!(
+			transition,
+			gst::StateChange::ReadyToPaused | gst::StateChange::PausedToPlaying
+		) && self.streams.lock().unwrap().is_empty()
+		{
+			gst::element_imp_warning!(
+				self,
+				gst::CoreError::Pad,
+				("dashcmafsink has no sink pads"),
+				["Request at least one sink_%u pad, otherwise no output is produced"]
+			);
+		}
+
+		if transition == gst::StateChange::ReadyToPaused {
+			let (cmaf_profile, mux_streams) = {
+				let settings = self.settings.lock().unwrap();
+				(settings.cmaf_profile, settings.mux_streams)
+			};
+			if cmaf_profile && mux_streams {
+				gst::element_imp_error!(
+					self,
+					gst::LibraryError::Settings,
+					("cmaf-profile can't be used with mux-streams"),
+					["CMAF tracks are one per file, the muxed segments of isofmp4mux are not CMAF"]
+				);
+				return Err(gst::StateChangeError);
+			}
+
+			if self.settings.lock().unwrap().resume {
+				self.resume();
+			} else {
+				self.apply_output_policy()?;
+			}
+		}
+
+		if transition == gst::StateChange::PlayingToPaused {
+			self.stop_health_check();
+		}
+
+		let ret = self.parent_change_state(transition)?;
+
+		if transition == gst::StateChange::PausedToPlaying {
+			self.start_health_check();
+			self.start_negotiation_timeouts();
+		}
+
+		if transition == gst::StateChange::PausedToReady {
+			// The presentation of released pads is over
+			let mut streams = self.streams.lock().unwrap();
+			streams.retain(|_, stream| stream.released.is_none());
+			streams.values_mut().for_each(|stream| stream.eos = false);
+			drop(streams);
+			*self.state.lock().unwrap() = DashCmafSinkState::default();
+			*self.manifest.lock().unwrap() = 0;
+			self.settings.lock().unwrap().template_time = None;
+		}
+
+		Ok(ret)
+	}
+
+	/// Streams that wrote segments are ended and stay in the manifest until
+	/// the Period ends, the others are removed. Either way it happens under
+	/// the streams lock, so manifest updates see the stream before or after.
+	/// A muxed stream is handed over to its further pads, and only ends with
+	/// the last of them.
+	fn release_pad(&self, pad: &gst::Pad) {
+		let pad_name = pad.name();
+		let released = pad.clone().downcast::<super::DashCmafSinkPad>().ok().zip(pad.current_caps());
+		let target = pad.downcast_ref::<gst::GhostPad>().and_then(|pad| pad.target());
+		let obj = self.obj();
+
+		// The stream is only dropped once its elements stopped delivering
+		let ending = {
+			let mut streams = self.streams.lock().unwrap();
+			let owner = streams.iter_mut().find(|(name, stream)| {
+				name.as_str() == pad_name.as_str() || stream.track_pads.iter().any(|track| track.upcast_ref::<gst::Pad>() == pad)
+			});
+			match owner {
+				Some((name, stream)) => {
+					stream.track_pads.retain(|track| track.upcast_ref::<gst::Pad>() != pad);
+					let own_pad_gone = name.as_str() == pad_name.as_str() || obj.static_pad(name).is_none();
+					if own_pad_gone && stream.track_pads.is_empty() {
+						let ended = released.is_some() && !stream.segments.is_empty();
+						if ended {
+							gst::info!(CAT, imp = self, "{} released, ending {}", pad_name, name);
+							stream.released = released;
+							// Fragments still in flight are not written
+							stream.finished = true;
+						}
+						Some((name.clone(), stream.cmafmux.clone(), stream.appsink.clone(), !ended))
+					} else {
+						gst::info!(CAT, imp = self, "{} released, {} goes on with its other tracks", pad_name, name);
+						None
+					}
+				}
+				None => None,
+			}
+		};
+
+		let _ = pad.set_active(false);
+		let _ = obj.remove_pad(pad);
+
+		match ending {
+			Some((name, muxer, appsink, remove)) => {
+				for element in muxer.iter().map(|muxer| muxer.upcast_ref::<gst::Element>()).chain([appsink.upcast_ref()]) {
+					let _ = element.set_state(gst::State::Null);
+					let _ = obj.remove(element);
+				}
+				if remove {
+					self.streams.lock().unwrap().remove(&name);
+				}
+			}
+			// A track of a muxed stream going on with the others
+			None => {
+				let muxer = target.as_ref().and_then(|target| target.parent_element());
+				if let (Some(muxer), Some(target)) = (muxer, target) {
+					muxer.release_request_pad(&target);
+				}
+			}
+		}
+	}
+
+	fn query(&self, query: &mut gst::QueryRef) -> bool {
+		if let gst::QueryViewMut::Latency(q) = query.view_mut() {
+			let (live, min, max) = self.latency();
+			gst::debug!(CAT, imp = self, "Reporting latency live={} min={} max={}", live, min, max.display());
+			q.set(live, min, max);
+			return true;
+		}
+
+		ElementImplExt::parent_query(self, query)
+	}
+
+	fn send_event(&self, event: gst::Event) -> bool {
+		if event.type_() != gst::EventType::Eos {
+			return self.parent_send_event(event);
+		}
+
+		// GstBin sends downstream events to its source elements, a sink bin
+		// has none. Each sink pad is ended instead, from its own streaming
+		// thread on its next buffer so that EOS can't race upstream buffers
+		// into the muxer. The muxer then pushes its trailing fragment and the
+		// bin posts EOS once every appsink handled its final sample.
+		let pads = self
+			.obj()
+			.sink_pads()
+			.into_iter()
+			.filter(|pad| !pad.pad_flags().contains(gst::PadFlags::EOS))
+			.collect::<Vec<_>>();
+
+		gst::debug!(CAT, imp = self, "Draining {} streams on EOS", pads.len());
+		for pad in pads {
+			end_on_next_buffer(&pad);
+		}
+
+		true
+	}
+}
+
+impl BaseSinkImpl for DashCmafSink {}
+
+impl DashCmafSink {
+	/// Latency of the upstream of the sink pads, plus the muxer latency and
+	/// the longest segment, as a segment is only output once complete
+	fn latency(&self) -> (bool, gst::ClockTime, Option<gst::ClockTime>) {
+		let (mut live, mut min, mut max) = (false, gst::ClockTime::ZERO, None::<gst::ClockTime>);
+		let mut segment_duration = gst::ClockTime::ZERO;
+		// Peer queries can go through a whole pipeline, not under the lock
+		let (latency, target_duration) = {
+			let settings = self.settings.lock().unwrap();
+			(self.muxer_latency(&settings), settings.segment_duration)
+		};
+
+		for pad in self.obj().sink_pads() {
+			if let Ok(pad) = pad.clone().downcast::<super::DashCmafSinkPad>() {
+				let fragment_duration = pad.imp().settings.lock().unwrap().fragment_duration;
+				segment_duration = segment_duration.max(fragment_duration.unwrap_or(target_duration));
+			}
+
+			let mut peer_query = gst::query::Latency::new();
+			if !pad.peer_query(&mut peer_query) {
+				continue;
+			}
+			let (upstream_live, upstream_min, upstream_max) = peer_query.result();
+			if upstream_live {
+				live = true;
+				min = min.max(upstream_min);
+				max = match (max, upstream_max) {
+					(Some(max), Some(upstream_max)) => Some(max.min(upstream_max)),
+					(max, upstream_max) => max.or(upstream_max),
+				};
+			}
+		}
+
+		let own_latency = latency + segment_duration;
+		(live, min + own_latency, max.map(|max| max + own_latency))
+	}
+
+	/// Number of the first segment of a stream, start-number of its pad if set
+	fn start_number(&self, pad_name: &str, settings: &DashCmafSinkSettings) -> u64 {
+		self.obj()
+			.static_pad(pad_name)
+			.and_downcast::<super::DashCmafSinkPad>()
+			.and_then(|pad| pad.imp().settings.lock().unwrap().start_number)
+			.unwrap_or(settings.start_segment_number)
+	}
+
+	/// Numbers the segments of a stream that wrote none yet from its
+	/// start-number
+	fn restart_numbering(&self, pad_name: &str) {
+		let mut streams = self.streams.lock().unwrap();
+		let settings = self.settings.lock().unwrap();
+		let start_number = self.start_number(pad_name, &settings);
+
+		if let Some(stream) = streams.get_mut(pad_name).filter(|stream| stream.segments_written == 0) {
+			stream.start_number = start_number;
+			stream.segment_idx = start_number;
+		}
+	}
+
+	/// Checks every segment duration that the streams keep writing segments
+	fn start_health_check(&self) {
+		let clock = gst::SystemClock::obtain();
+		let interval = self.settings.lock().unwrap().segment_duration;
+		let health_check = clock.new_periodic_id(clock.time().unwrap_or_default() + interval, interval);
+
+		let element = self.obj().downgrade();
+		let res = health_check.wait_async(move |_clock, _time, _id| {
+			if let Some(element) = element.upgrade() {
+				element.imp().check_health();
+			}
+		});
+		if let Err(err) = res {
+			gst::warning!(CAT, imp = self, "Failed to schedule the health check: {:?}", err);
+			return;
+		}
+
+		let mut state = self.state.lock().unwrap();
+		state.playing_since = Some(std::time::Instant::now());
+		if let Some(previous) = state.health_check.replace(health_check) {
+			previous.unschedule();
+		}
+	}
+
+	fn stop_health_check(&self) {
+		let mut state = self.state.lock().unwrap();
+		if let Some(health_check) = state.health_check.take() {
+			health_check.unschedule();
+		}
+		for timeout in state.negotiation_timeouts.drain(..) {
+			timeout.unschedule();
+		}
+		state.playing_since = None;
+	}
+
+	/// Schedules a timeout for every stream that produced no fragment yet
+	fn start_negotiation_timeouts(&self) {
+		let Some(timeout) = self.settings.lock().unwrap().negotiation_timeout else {
+			return;
+		};
+		let pad_names = self
+			.streams
+			.lock()
+			.unwrap()
+			.iter()
+			.filter(|(_, stream)| stream.buffers_received == 0 && stream.released.is_none())
+			.map(|(pad_name, _)| pad_name.clone())
+			.collect::<Vec<_>>();
+
+		let clock = gst::SystemClock::obtain();
+		let deadline = clock.time().unwrap_or_default() + timeout;
+		let mut timeouts = Vec::new();
+		for pad_name in pad_names {
+			let id = clock.new_single_shot_id(deadline);
+			let element = self.obj().downgrade();
+			let res = id.wait_async(move |_clock, _time, _id| {
+				if let Some(element) = element.upgrade() {
+					element.imp().check_negotiation(&pad_name, timeout);
+				}
+			});
+			match res {
+				Ok(_) => timeouts.push(id),
+				Err(err) => gst::warning!(CAT, imp = self, "Failed to schedule the negotiation timeout: {:?}", err),
+			}
+		}
+
+		self.state.lock().unwrap().negotiation_timeouts.extend(timeouts);
+	}
+
+	/// Errors out when a stream still produced no fragment at its
+	/// negotiation timeout
+	fn check_negotiation(&self, pad_name: &str, timeout: gst::ClockTime) {
+		let stalled = self
+			.streams
+			.lock()
+			.unwrap()
+			.get(pad_name)
+			.is_some_and(|stream| stream.buffers_received == 0 && stream.released.is_none());
+		if !stalled {
+			return;
+		}
+
+		match self.obj().static_pad(pad_name).and_then(|pad| pad.current_caps()) {
+			None => gst::element_imp_error!(
+				self,
+				gst::CoreError::Negotiation,
+				("{} did not negotiate caps within {}", pad_name, timeout),
+				["Upstream of the pad never sent caps"]
+			),
+			Some(caps) => gst::element_imp_error!(
+				self,
+				gst::StreamError::Failed,
+				("{} produced no fragment within {}", pad_name, timeout),
+				["Negotiated caps: {}", caps]
+			),
+		}
+	}
+
+	/// Streams that neither finished nor were released are stalled when
+	/// they wrote no segment for twice the segment duration, counting from
+	/// the last time the element went to PLAYING
+	fn check_health(&self) {
+		let now = std::time::Instant::now();
+		let stalled = {
+			let streams = self.streams.lock().unwrap();
+			let settings = self.settings.lock().unwrap();
+			let Some(playing_since) = self.state.lock().unwrap().playing_since else {
+				return;
+			};
+			let timeout = std::time::Duration::from_nanos(settings.segment_duration.nseconds() * 2);
+
+			let mut stalled = streams
+				.iter()
+				.filter(|(_, stream)| !stream.finished && stream.released.is_none())
+				.filter(|(_, stream)| {
+					let since = stream.last_segment_at.map_or(playing_since, |at| at.max(playing_since));
+					now.duration_since(since) > timeout
+				})
+				.map(|(pad_name, _)| pad_name.clone())
+				.collect::<Vec<_>>();
+			stalled.sort();
+			stalled
+		};
+
+		let was_stalled = {
+			let mut state = self.state.lock().unwrap();
+			std::mem::replace(&mut state.stalled, !stalled.is_empty())
+		};
+		if stalled.is_empty() == was_stalled {
+			if was_stalled {
+				gst::info!(CAT, imp = self, "All streams are writing segments again");
+			} else {
+				gst::element_imp_warning!(
+					self,
+					gst::StreamError::Failed,
+					("Streams stalled"),
+					["No segment written for twice the segment duration by {}", stalled.join(", ")]
+				);
+			}
+			self.obj().notify("healthy");
+		}
+	}
+
+	/// Adds a sink pad of the element proxying `target_pad`, or a pad of
+	/// `muxer` requested once the caps are known
+	fn add_sink_pad(
+		&self,
+		templ: &gst::PadTemplate,
+		pad_name: &str,
+		target_pad: Option<&gst::Pad>,
+		muxer: Option<gst::Element>,
+	) -> Option<super::DashCmafSinkPad> {
+		let gpad = gst::PadBuilder::<super::DashCmafSinkPad>::from_template(templ)
+			.name(pad_name)
+			.build();
+		if let Some(target_pad) = target_pad {
+			gpad.set_target(Some(target_pad)).expect("Failed to set target pad");
+		}
+		gpad.set_active(true).ok()?;
+		self.obj().add_pad(&gpad).ok()?;
+
+		// Caps check, so unsupported formats fail with a clear error, and
+		// language tags used as default lang of the stream
+		let self_weak = self.downgrade();
+		gpad.add_probe(gst::PadProbeType::EVENT_DOWNSTREAM, move |pad, info| {
+			let Some(imp) = self_weak.upgrade() else {
+				return gst::PadProbeReturn::Ok;
+			};
+
+			match info.event().map(|event| event.view()) {
+				Some(gst::EventView::Caps(ev)) => {
+					if pad.target().is_none() {
+						let target_pad = muxer
+							.as_ref()
+							.and_then(|muxer| request_muxer_pad(muxer, Some(ev.caps())));
+						if let Some(target_pad) = target_pad {
+							let _ = pad.set_target(Some(&target_pad));
+						}
+					}
+
+					let mux_caps = pad
+						.target()
+						.map(|target_pad| target_pad.pad_template_caps())
+						.unwrap_or_else(gst::Caps::new_empty);
+					if imp.check_caps(pad.name().as_str(), ev.caps(), &mux_caps) {
+						imp.fit_aac_fragments(pad, ev.caps());
+						gst::PadProbeReturn::Ok
+					} else {
+						gst::PadProbeReturn::Drop
+					}
+				}
+				Some(gst::EventView::Tag(ev)) => {
+					if let Some(lang) = ev.tag().get::<gst::tags::LanguageCode>() {
+						pad.imp().settings.lock().unwrap().tag_lang = Some(lang.get().to_string());
+					}
+					gst::PadProbeReturn::Ok
+				}
+				Some(gst::EventView::CustomDownstream(ev)) => {
+					match ev.structure().filter(|s| s.name() == "scte-35") {
+						Some(s) => {
+							imp.on_scte35(pad.name().as_str(), s);
+							gst::PadProbeReturn::Drop
+						}
+						None => gst::PadProbeReturn::Ok,
+					}
+				}
+				_ => gst::PadProbeReturn::Ok,
+			}
+		});
+
+		Some(gpad)
+	}
+
+	/// Signals the SCTE-35 splice_info_section of a `scte-35` custom event,
+	/// held in its `section` buffer, as an Event of the SCTE 214 binary
+	/// scheme. The splice is at the `running-time` of the event if set, as
+	/// its PTS can't be mapped to running time here, or else at the end of
+	/// the last segment of the stream.
+	fn on_scte35(&self, pad_name: &str, s: &gst::StructureRef) {
+		let Ok(section) = s.get::<gst::Buffer>("section") else {
+			gst::warning!(CAT, imp = self, "Ignoring SCTE-35 event without section on pad {}", pad_name);
+			return;
+		};
+		let Ok(map) = section.map_readable() else {
+			return;
+		};
+		let Some(duration) = scte35_splice_point(&map) else {
+			gst::debug!(CAT, imp = self, "Ignoring SCTE-35 section without splice point on pad {}", pad_name);
+			return;
+		};
+
+		let presentation_time = s
+			.get::<gst::ClockTime>("running-time")
+			.ok()
+			.or_else(|| self.streams.lock().unwrap().get(pad_name).and_then(|stream| stream.end_time))
+			.unwrap_or(gst::ClockTime::ZERO);
+		gst::info!(
+			CAT,
+			imp = self,
+			"SCTE-35 splice on pad {} at {} lasting {:?}",
+			pad_name, presentation_time, duration
+		);
+
+		self.state.lock().unwrap().events.push(DashCmafSinkEvent {
+			scheme_id_uri: SCTE35_SCHEME.to_string(),
+			value: None,
+			presentation_time,
+			duration,
+			message_data: None,
+			scte35_binary: Some(glib::base64_encode(&map).to_string()),
+		});
+	}
+
+	/// Requests a keyframe upstream for every `interval` of running time on
+	/// video pads, so cmafmux can cut segments whatever the encoder's GOP is.
+	/// The request for the next boundary is sent as soon as the previous one
+	/// is passed, encoders keep it pending until that running time.
+	fn force_keyframes(&self, pad: &super::DashCmafSinkPad, interval: gst::ClockTime) {
+		let next_keyframe = Mutex::new((None::<gst::ClockTime>, 0u32));
+
+		pad.add_probe(gst::PadProbeType::BUFFER, move |pad, info| {
+			let is_video = pad
+				.current_caps()
+				.and_then(|caps| caps.structure(0).map(|s| s.name().starts_with("video/")))
+				.unwrap_or(false);
+			let running_time = info.buffer().and_then(|buffer| buffer.pts()).and_then(|pts| {
+				let segment = pad.sticky_event::<gst::event::Segment>(0)?;
+				segment.segment().downcast_ref::<gst::ClockTime>()?.to_running_time(pts)
+			});
+			let (true, Some(running_time)) = (is_video, running_time) else {
+				return gst::PadProbeReturn::Ok;
+			};
+
+			let mut next_keyframe = next_keyframe.lock().unwrap();
+			let (ref mut next, ref mut count) = *next_keyframe;
+			if next.is_some_and(|next| running_time < next) {
+				return gst::PadProbeReturn::Ok;
+			}
+
+			let mut keyframe = next.unwrap_or(running_time) + interval;
+			while keyframe <= running_time {
+				keyframe += interval;
+			}
+			*next = Some(keyframe);
+			*count += 1;
+
+			let event = gst::event::CustomUpstream::new(
+				gst::Structure::builder("GstForceKeyUnit")
+					.field("running-time", keyframe)
+					.field("all-headers", true)
+					.field("count", *count)
+					.build(),
+			);
+			pad.push_event(event);
+
+			gst::PadProbeReturn::Ok
+		});
+	}
+
+	/// Measures the running time between keyframes of video pads, raising
+	/// the muxer latency with auto-latency when a GOP is longer than the
+	/// previous ones
+	fn detect_gop(&self, pad: &super::DashCmafSinkPad) {
+		let last_keyframe = Mutex::new(None::<gst::ClockTime>);
+		let self_weak = self.downgrade();
+
+		pad.add_probe(gst::PadProbeType::BUFFER, move |pad, info| {
+			let is_video = pad
+				.current_caps()
+				.and_then(|caps| caps.structure(0).map(|s| s.name().starts_with("video/")))
+				.unwrap_or(false);
+			let Some(buffer) = info.buffer().filter(|_| is_video) else {
+				return gst::PadProbeReturn::Ok;
+			};
+			if buffer.flags().contains(gst::BufferFlags::DELTA_UNIT) {
+				return gst::PadProbeReturn::Ok;
+			}
+			let running_time = buffer.pts().and_then(|pts| {
+				let segment = pad.sticky_event::<gst::event::Segment>(0)?;
+				segment.segment().downcast_ref::<gst::ClockTime>()?.to_running_time(pts)
+			});
+
+			let mut last_keyframe = last_keyframe.lock().unwrap();
+			// No GOP spans a discontinuity
+			let previous = std::mem::replace(&mut *last_keyframe, running_time)
+				.filter(|_| !buffer.flags().contains(gst::BufferFlags::DISCONT));
+
+			let gop = running_time.opt_checked_sub(previous).ok().flatten().filter(|gop| !gop.is_zero());
+			if let (Some(gop), Some(imp)) = (gop, self_weak.upgrade()) {
+				imp.on_gop(gop);
+			}
+
+			gst::PadProbeReturn::Ok
+		});
+	}
+
+	fn on_gop(&self, gop: gst::ClockTime) {
+		let muxers = {
+			let streams = self.streams.lock().unwrap();
+			let mut state = self.state.lock().unwrap();
+			if state.gop_duration >= Some(gop) {
+				return;
+			}
+			state.gop_duration = Some(gop);
+			streams.values().filter_map(|stream| stream.cmafmux.clone()).collect::<Vec<_>>()
+		};
+
+		gst::info!(CAT, imp = self, "Longest GOP is now {}, using it as muxer latency", gop);
+		for muxer in muxers.iter().filter(|muxer| muxer.has_property("latency", None)) {
+			muxer.set_property("latency", gop);
+		}
+		let _ = self.obj().post_message(gst::message::Latency::builder().src(&*self.obj()).build());
+	}
+
+	/// Latency of the muxers, the longest GOP once one is complete with
+	/// auto-latency
+	fn muxer_latency(&self, settings: &DashCmafSinkSettings) -> gst::ClockTime {
+		if settings.auto_latency {
+			self.state.lock().unwrap().gop_duration.unwrap_or(settings.latency)
+		} else {
+			settings.latency
+		}
+	}
+
+	/// Emits the write-* signal of a resource with custom-writer
+	fn write_callback(&self) -> Arc<WriteCallback> {
+		let obj = self.obj().downgrade();
+
+		Arc::new(move |kind, location, data| {
+			let Some(obj) = obj.upgrade() else {
+				return false;
+			};
+			let signal = match kind {
+				ResourceKind::Init => "write-init-segment",
+				ResourceKind::Segment => "write-media-segment",
+				ResourceKind::Manifest => "write-manifest",
+			};
+
+			obj.emit_by_name::<bool>(signal, &[&location, &data])
+		})
+	}
+
+	fn output(&self, settings: &DashCmafSinkSettings) -> Arc<dyn OutputBackend> {
+		let mut state = self.state.lock().unwrap();
+
+		state
+			.output
+			.get_or_insert_with(|| match settings.backend {
+				_ if settings.custom_writer => Arc::new(CallbackOutput::new(self.write_callback())),
+				DashCmafSinkBackend::Memory => {
+					Arc::new(MemoryOutput::new(settings.window_size as usize))
+				}
+				DashCmafSinkBackend::File => {
+					Arc::new(FileOutput::new(settings.file_mode, settings.max_segment_files as usize))
+				}
+				DashCmafSinkBackend::Ingest => {
+					Arc::new(IngestOutput::new(settings.ingest_url.clone(), settings.http_headers()))
+				}
+			})
+			.clone()
+	}
+
+	/// Handles the files a previous run left in the output directory, unless
+	/// resuming from them.
+	fn apply_output_policy(&self) -> Result<(), gst::StateChangeError> {
+		let settings = self.settings.lock().unwrap();
+		if settings.backend != DashCmafSinkBackend::File
+			|| settings.custom_writer
+			|| settings.output_policy == DashCmafSinkOutputPolicy::Overwrite
+		{
+			return Ok(());
+		}
+
+		let existing = settings.existing_outputs();
+		if existing.is_empty() {
+			return Ok(());
+		}
+
+		if settings.output_policy == DashCmafSinkOutputPolicy::FailIfExists {
+			gst::element_imp_error!(
+				self,
+				gst::ResourceError::OpenWrite,
+				("Output of a previous run found, e.g. {}", existing[0].display()),
+				["{} existing files match the locations and output-policy is fail-if-exists", existing.len()]
+			);
+			return Err(gst::StateChangeError);
+		}
+
+		for path in existing {
+			gst::debug!(CAT, imp = self, "Removing {} of a previous run", path.display());
+			if let Err(err) = std::fs::remove_file(&path) {
+				gst::element_imp_error!(
+					self,
+					gst::ResourceError::Write,
+					("Couldn't remove {} of a previous run", path.display()),
+					["{}", err]
+				);
+				return Err(gst::StateChangeError);
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Continues the numbering and timeline of the manifest written by a
+	/// previous run, preserving its availabilityStartTime.
+	fn resume(&self) {
+		let mut streams = self.streams.lock().unwrap();
+		let settings = self.settings.lock().unwrap();
+		let path = settings.output_path(&settings.location);
+
+		let xml = match std::fs::read(&path) {
+			Ok(xml) => decode_manifest(&xml),
+			Err(err) => {
+				gst::info!(CAT, imp = self, "No manifest to resume at {}: {err}", path.display());
+				return;
+			}
+		};
+		let mpd = match dash_mpd::parse(&xml) {
+			Ok(mpd) => mpd,
+			Err(err) => {
+				gst::element_imp_warning!(
+					self,
+					gst::ResourceError::Read,
+					("Couldn't parse manifest {} to resume from", path.display()),
+					["{}", err]
+				);
+				return;
+			}
+		};
+
+		let mut state = self.state.lock().unwrap();
+		state.availability_start_time = mpd.availabilityStartTime;
+		state.max_segment_duration = mpd
+			.maxSegmentDuration
+			.map(|duration| gst::ClockTime::from_nseconds(duration.as_nanos() as u64));
+		drop(state);
+
+		for (pad_name, point) in resume_points(&mpd, settings.start_segment_number) {
+			let Some(stream) = streams.get_mut(&pad_name) else {
+				continue;
+			};
+
+			gst::info!(
+				CAT,
+				imp = self,
+				"Resuming {pad_name} at segment {} after {}",
+				point.next_number,
+				point.duration
+			);
+			stream.start_number = point.start_number;
+			stream.segment_idx = point.next_number;
+			stream.resumed_duration = point.duration;
+		}
+	}
+
+	/// Wall clock time at running time 0, unless overridden by start-time,
+	/// so that segments become available when their running time is reached.
+	fn availability_start_time(&self, settings: &DashCmafSinkSettings) -> chrono::DateTime<chrono::Utc> {
+		if let Some(start_time) = settings.start_time {
+			return start_time;
+		}
+
+		let obj = self.obj();
+		let now = chrono::Utc::now();
+		let running_time = obj
+			.clock()
+			.and_then(|clock| clock.time())
+			.zip(obj.base_time())
+			.and_then(|(time, base_time)| time.checked_sub(base_time));
+
+		match running_time {
+			Some(running_time) => now - chrono::Duration::nanoseconds(running_time.nseconds() as i64),
+			None => {
+				gst::debug!(CAT, imp = self, "No clock yet, starting availability now");
+				now
+			}
+		}
+	}
+
+	fn stream_stats(&self, pad_name: &str) -> Option<gst::Structure> {
+		let streams = self.streams.lock().unwrap();
+		let stream = streams.get(pad_name)?;
+
+		Some(
+			gst::Structure::builder("dashcmafsink-stream-stats")
+				.field("pad", pad_name)
+				.field("bytes-written", stream.bytes_written)
+				.field("segments-written", stream.segments_written)
+				.field("bandwidth", stream.bandwidth)
+				.field("peak-bandwidth", stream.peak_bandwidth)
+				.field("alignment-offset", stream.alignment_offset)
+				.field("segment-write-ms", stream.last_write_time.as_secs_f64() * 1000.0)
+				.field("max-segment-write-ms", stream.max_write_time.as_secs_f64() * 1000.0)
+				.build(),
+		)
+	}
+
+	fn check_caps(&self, pad_name: &str, caps: &gst::CapsRef, mux_caps: &gst::Caps) -> bool {
+		if caps.can_intersect(mux_caps) {
+			return true;
+		}
+
+		let is_mp3 = caps.structure(0).is_some_and(|s| {
+			s.name() == "audio/mpeg" && s.get::<i32>("mpegversion") == Ok(1)
+		});
+		// Annex B or NAL aligned H.264 has to be converted by a parser
+		let unparsed_h264 = caps.structure(0).filter(|s| s.name() == "video/x-h264").and_then(|s| {
+			let stream_format = s.get::<&str>("stream-format").ok();
+			let alignment = s.get::<&str>("alignment").ok();
+			match (stream_format, alignment) {
+				(Some("byte-stream"), _) | (_, Some("nal")) => Some((stream_format, alignment)),
+				_ => None,
+			}
+		});
+		if let Some((stream_format, alignment)) = unparsed_h264 {
+			gst::element_imp_error!(
+				self,
+				gst::StreamError::Format,
+				("H.264 on pad {} must be AVC with access unit alignment", pad_name),
+				[
+					"Got stream-format {} and alignment {}, insert h264parse before the sink",
+					stream_format.unwrap_or("unset"),
+					alignment.unwrap_or("unset")
+				]
+			);
+		} else if is_mp3 {
+			gst::element_imp_error!(
+				self,
+				gst::StreamError::Format,
+				("MP3 input on pad {} is not supported by the installed cmafmux", pad_name),
+				["Transcode to AAC (e.g. avenc_aac ! aacparse) or use a newer gst-plugins-rs"]
+			);
+		} else {
+			gst::element_imp_error!(
+				self,
+				gst::StreamError::Format,
+				("Caps on pad {} are not supported by cmafmux", pad_name),
+				["caps: {}", caps]
+			);
+		}
+
+		false
+	}
+
+    fn on_init_segment(&self, pad_name: &str) -> Result<Box<dyn Write + Send>, std::io::Error> {
+        let mut settings = self.settings.lock().unwrap();
+		self.fix_template_time(&mut settings);
+		let location = settings.init_location(pad_name);
+        let path = settings.output_path(&location);
+
+		self.output(&settings).create_for_stream(pad_name, ResourceKind::Init, &location, &path)
+    }
+
+	/// Timescale of the segment timing of a stream
+	fn pad_timescale(&self, pad_name: &str) -> u32 {
+		self.obj()
+			.static_pad(pad_name)
+			.and_then(|pad| pad.downcast::<super::DashCmafSinkPad>().ok())
+			.map_or(DEFAULT_TIMESCALE, |pad| pad.imp().timescale(pad.current_caps().as_deref()))
+	}
+
+	/// Sets the fragment-duration of the muxer of an AAC stream to whole
+	/// frames. It can only cut fragments between frames, so they would
+	/// otherwise drift from the duration signalled in the manifest.
+	fn fit_aac_fragments(&self, pad: &super::DashCmafSinkPad, caps: &gst::CapsRef) {
+		let Some(rate) = aac_rate(caps.structure(0)) else {
+			return;
+		};
+		let target_duration = {
+			let settings = self.settings.lock().unwrap();
+			// The fragments of muxed streams are cut for the video
+			if settings.mux_streams {
+				return;
+			}
+			settings.segment_duration
+		};
+		let duration = pad.imp().settings.lock().unwrap().fragment_duration.unwrap_or(target_duration);
+		let duration = whole_aac_frames(duration, rate);
+
+		let muxer = pad.target().and_then(|target| target.parent_element());
+		if let Some(muxer) = muxer.filter(|muxer| muxer.has_property("fragment-duration", None)) {
+			gst::debug!(CAT, imp = self, "Fragments of AAC pad {} last {} at {} Hz", pad.name(), duration, rate);
+			muxer.set_property("fragment-duration", duration);
+		}
+	}
+
+	/// Fixes the time the {strftime} tokens of the locations expand to on
+	/// the first write, as the availabilityStartTime of the presentation
+	fn fix_template_time(&self, settings: &mut DashCmafSinkSettings) {
+		if settings.template_time.is_none() {
+			let mut state = self.state.lock().unwrap();
+			let availability_start_time = *state
+				.availability_start_time
+				.get_or_insert_with(|| self.availability_start_time(settings));
+			settings.template_time = Some(availability_start_time);
+		}
+	}
+
+    /// Opens the next media segment of the stream, falling back to a segment
+    /// contiguous with the previous one when timing is not signalled.
+    fn on_new_segment(
+		&self,
+		pad_name: &str,
+		start: Option<gst::ClockTime>,
+		duration: Option<gst::ClockTime>,
+	) -> Result<(Box<dyn Write + Send>, DashCmafSinkSegment), std::io::Error> {
+        let mut streams = self.streams.lock().unwrap();
+		let stream = streams
+			.get_mut(pad_name)
+			.ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, format!("{pad_name} was released")))?;
+        let mut settings = self.settings.lock().unwrap();
+		self.fix_template_time(&mut settings);
+
+		if let Some(gap) = start
+			.zip(stream.end_time.filter(|_| stream.segments_written > 0))
+			.and_then(|(start, end)| start.checked_sub(end))
+			.filter(|gap| *gap > TIMELINE_GAP_TOLERANCE)
+		{
+			gst::warning!(
+				CAT,
+				imp = self,
+				"Gap of {} before segment {} of {}",
+				gap,
+				stream.segment_idx,
+				pad_name
+			);
+		}
+
+		let mut segment = DashCmafSinkSegment {
+			number: stream.segment_idx,
+			start: start
+				.or(stream.end_time.filter(|_| stream.segments_written > 0))
+				.unwrap_or(gst::ClockTime::ZERO),
+			duration: duration.unwrap_or(match stream.cmafmux {
+				Some(_) => settings.segment_duration,
+				None => settings.thumbnail_duration(),
+			}),
+			location: String::new(),
+			checksum: None,
+		};
+		let (template, value) = match (&stream.cmafmux, settings.addressing) {
+			(None, _) => (settings.thumbnail_location(pad_name), segment.number),
+			(Some(_), DashCmafSinkAddressing::Number | DashCmafSinkAddressing::List) => {
+				(settings.segment_location(pad_name), segment.number)
+			}
+			(Some(_), DashCmafSinkAddressing::Time) => {
+				let timescale = self.pad_timescale(pad_name);
+				(
+					settings.segment_location(pad_name),
+					timescale_ticks(segment.start, timescale, settings.timescale_rounding),
+				)
+			}
+		};
+		segment.location = sprintf::sprintf!(&template, value).map_err(|err| {
+			std::io::Error::new(
+				std::io::ErrorKind::InvalidInput,
+				format!("Invalid location template {template:?}: {err}"),
+			)
+		})?;
+        stream.segment_idx += 1;
+
+        let path = settings.output_path(&segment.location);
+
+        let file = self.output(&settings).create_for_stream(pad_name, ResourceKind::Segment, &segment.location, &path)?;
+        Ok((file, segment))
+    }
+
+	/// Warns once per segment number when the segment boundaries of the video
+	/// streams differ by more than the configured tolerance.
+	fn check_alignment(
+		&self,
+		streams: &HashMap<String, DashCmafSinkStream>,
+		settings: &DashCmafSinkSettings,
+	) {
+		let obj = self.obj();
+		let video_streams = streams
+			.iter()
+			.filter(|(pad_name, _)| {
+				obj.static_pad(pad_name)
+					.and_then(|pad| pad.current_caps())
+					.and_then(|caps| caps.structure(0).map(|s| s.name().starts_with("video/")))
+					.unwrap_or(false)
+			})
+			.collect::<Vec<_>>();
+		if video_streams.len() < 2 {
+			return;
+		}
+
+		// Latest segment number that all video streams have written
+		let Some(number) = video_streams
+			.iter()
+			.map(|(_, stream)| stream.segments.last().map(|segment| segment.number))
+			.min()
+			.flatten()
+		else {
+			return;
+		};
+
+		let mut state = self.state.lock().unwrap();
+		if state.aligned_segment.is_some_and(|aligned| aligned >= number) {
+			return;
+		}
+		state.aligned_segment = Some(number);
+		drop(state);
+
+		let starts = video_streams
+			.iter()
+			.filter_map(|(pad_name, stream)| {
+				stream
+					.segments
+					.iter()
+					.rev()
+					.find(|segment| segment.number == number)
+					.map(|segment| (pad_name.as_str(), segment.start))
+			})
+			.collect::<Vec<_>>();
+		let (Some(min), Some(max)) = (
+			starts.iter().map(|(_, start)| *start).min(),
+			starts.iter().map(|(_, start)| *start).max(),
+		) else {
+			return;
+		};
+
+		if max - min > settings.alignment_tolerance {
+			gst::element_imp_warning!(
+				self,
+				gst::StreamError::Failed,
+				("Segment {} is not aligned across video streams", number),
+				["Segment start times: {:?}", starts]
+			);
+		}
+	}
+
+    fn add_segment(
+        &self,
+		_pad_name: &str
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+		let streams = self.streams.lock().unwrap();
+		let mut settings = self.settings.lock().unwrap();
+		if settings.manifest_frozen {
+			gst::debug!(CAT, imp = self, "Manifest frozen, not updating it");
+			return Ok(gst::FlowSuccess::Ok);
+		}
+		// Catching up is always written
+		let due = match settings.manifest_write_mode {
+			DashCmafSinkManifestWriteMode::EverySegment => true,
+			DashCmafSinkManifestWriteMode::OnEos => false,
+			// Counting the segments of all the streams, so that the
+			// manifest doesn't follow the stream written last
+			DashCmafSinkManifestWriteMode::Interval => {
+				self.state.lock().unwrap().segments_written % settings.manifest_interval as u64 == 0
+			}
+		};
+		if !_pad_name.is_empty() && !due {
+			gst::trace!(CAT, imp = self, "Manifest not due after this segment of {}", _pad_name);
+			return Ok(gst::FlowSuccess::Ok);
+		}
+		self.fix_template_time(&mut settings);
+		let location = settings.manifest_location();
+		let path = settings.output_path(&location);
+
+		gst::info!(
+			CAT,
+			imp = self,
+			"writing manifest to {}",
+			path.display()
+		);
+
+		if settings.strict_alignment {
+			self.check_alignment(&streams, &settings);
+		}
+
+		let Some((mpd, version)) = self.manifest_snapshot(&streams, &settings) else {
+			// A manifest without AdaptationSets is not valid DASH
+			gst::warning!(CAT, imp = self, "No stream ready, not writing manifest");
+			return Ok(gst::FlowSuccess::Ok);
+		};
+		let manifest = settings.manifest_bytes(&mpd);
+		let checksums = settings
+			.checksum_manifest
+			.then(|| (checksum_manifest_location(&location), checksum_manifest(&streams)));
+		let output = self.output(&settings);
+
+		// The snapshot is taken, other streams can go on during the write
+		drop(settings);
+		drop(streams);
+
+		let mut written_version = self.manifest.lock().unwrap();
+		// A thread that took a later snapshot got here first
+		if *written_version > version {
+			gst::debug!(CAT, imp = self, "Manifest {} superseded, not writing it", version);
+			return Ok(gst::FlowSuccess::Ok);
+		}
+		*written_version = version;
+
+		output
+			.create(ResourceKind::Manifest, &location, &path)
+			.and_then(|mut file| {
+				file.write_all(&manifest)?;
+				file.flush()
+			})
+			.map_err(|err| {
+				if err.kind() == std::io::ErrorKind::BrokenPipe {
+					gst::info!(CAT, imp = self, "Reader of {} closed it, ending", path.display());
+					return gst::FlowError::Eos;
+				}
+				gst::error!(CAT, imp = self, "Couldn't write manifest to {}: {err}", path.display());
+				gst::FlowError::Error
+			})?;
+
+		if let Some((location, checksums)) = checksums {
+			let path = self.settings.lock().unwrap().output_path(&location);
+			let res = output.create(ResourceKind::Manifest, &location, &path).and_then(|mut file| {
+				file.write_all(checksums.as_bytes())?;
+				file.flush()
+			});
+			if let Err(err) = res {
+				gst::element_imp_warning!(
+					self,
+					gst::ResourceError::Write,
+					("Couldn't write checksums to {}", path.display()),
+					["{}", err]
+				);
+			}
+		}
+
+        Ok(gst::FlowSuccess::Ok)
+    }
+
+	/// Manifest of the segments written so far and its version, None when no
+	/// stream has any
+	fn manifest_snapshot(
+		&self,
+		streams: &HashMap<String, DashCmafSinkStream>,
+		settings: &DashCmafSinkSettings,
+	) -> Option<(dash_mpd::MPD, u64)> {
+		let infos = streams
+			.iter()
+			.filter_map(|(pad_name, stream)| {
+				// Streams that only delivered their init segment so far have no
+				// media segment to reference yet
+				if stream.segments.is_empty() {
+					gst::debug!(CAT, imp = self, "{pad_name} has no media segment yet, skipping");
+					return None;
+				}
+
+				let duration = stream
+					.end_time
+					.opt_checked_sub(stream.start_time)
+					.ok()
+					.flatten()
+					.unwrap_or_default()
+					+ stream.resumed_duration;
+				self.stream_info(pad_name, stream, Cow::Borrowed(&stream.segments), duration, settings)
+			})
+			.collect::<Vec<_>>();
+
+		let (availability_start_time, max_segment_duration, events, period, version) = {
+			let mut state = self.state.lock().unwrap();
+			state.manifest_version += 1;
+			(
+				*state
+					.availability_start_time
+					.get_or_insert_with(|| self.availability_start_time(settings)),
+				state.max_segment_duration,
+				state.events.clone(),
+				state.period.clone(),
+				state.manifest_version,
+			)
+		};
+		let mpd = build_mpd(
+			&infos,
+			settings,
+			availability_start_time,
+			max_segment_duration,
+			&events,
+			period.as_ref(),
+			chrono::Utc::now(),
+		)?;
+		let mut mpd = mpd;
+		let closed_periods = self.state.lock().unwrap().closed_periods.clone();
+		mpd.periods.splice(0..0, closed_periods);
+
+		Some((mpd, version))
+	}
+
+	/// Writes a manifest other than the main one, only warning on failure
+	fn write_side_manifest(&self, settings: &DashCmafSinkSettings, location: &str, mpd: &dash_mpd::MPD) {
+		let path = settings.output_path(location);
+		let manifest = settings.manifest_bytes(mpd);
+
+		let res = self
+			.output(settings)
+			.create(ResourceKind::Manifest, location, &path)
+			.and_then(|mut file| {
+				file.write_all(&manifest)?;
+				file.flush()
+			});
+		if let Err(err) = res {
+			gst::element_imp_warning!(
+				self,
+				gst::ResourceError::Write,
+				("Couldn't write manifest to {}", path.display()),
+				["{}", err]
+			);
+		}
+	}
+
+	/// Writes the current Period as a standalone on-demand manifest, before a
+	/// reset closes it
+	fn write_period_manifest(
+		&self,
+		streams: &HashMap<String, DashCmafSinkStream>,
+		settings: &DashCmafSinkSettings,
+	) {
+		let mut period_settings = settings.clone();
+		period_settings.playlist_type = DashCmafSinkPlaylistType::Static;
+		let Some((mut mpd, _)) = self.manifest_snapshot(streams, &period_settings) else {
+			return;
+		};
+		let current = mpd.periods.len() - 1;
+		mpd.periods.drain(..current);
+
+		// The Period is the whole presentation, and not updated anywhere
+		if let Some(start) = mpd.periods[0].start.take() {
+			mpd.mediaPresentationDuration = mpd.mediaPresentationDuration.map(|duration| duration.saturating_sub(start));
+		}
+		mpd.locations.clear();
+
+		let period_id = mpd.periods[0].id.clone().unwrap_or_default();
+		let location = period_manifest_location(&settings.manifest_location(), &period_id);
+		gst::info!(CAT, imp = self, "Writing manifest of period {} to {}", period_id, location);
+		self.write_side_manifest(settings, &location, &mpd);
+	}
+
+	/// Starts a new Period at the end of the latest segments, after writing
+	/// the current manifest to `archive_location` if given. The previous
+	/// Periods are dropped, and numbering starts over unless archived.
+	fn reset(&self, archive_location: Option<&str>) -> bool {
+		self.start_period(None, None, archive_location, false)
+	}
+
+	/// Starts a new Period for the next segments, at `start` or else the end
+	/// of the latest segments, the previous Periods staying in the manifest
+	/// with `keep_previous`. Numbering goes on while a manifest still refers
+	/// to the segments of the closed Period, which would be overwritten
+	/// otherwise.
+	fn start_period(
+		&self,
+		id: Option<String>,
+		start: Option<gst::ClockTime>,
+		archive_location: Option<&str>,
+		keep_previous: bool,
+	) -> bool {
+		let mut streams = self.streams.lock().unwrap();
+		let mut settings = self.settings.lock().unwrap();
+
+		let Some(period_start) = streams
+			.values()
+			.filter(|stream| !stream.segments.is_empty() && stream.released.is_none())
+			.filter_map(|stream| stream.end_time)
+			.min()
+		else {
+			gst::debug!(CAT, imp = self, "No segment written, no Period to close");
+			return false;
+		};
+		let period_start = start.unwrap_or(period_start);
+
+		self.fix_template_time(&mut settings);
+		if let Some(archive_location) = archive_location {
+			if let Some((mpd, _)) = self.manifest_snapshot(&streams, &settings) {
+				self.write_side_manifest(&settings, archive_location, &mpd);
+			}
+		}
+		if settings.per_period_manifest {
+			self.write_period_manifest(&streams, &settings);
+		}
+		let closed_period = keep_previous
+			.then(|| self.manifest_snapshot(&streams, &settings))
+			.flatten()
+			.and_then(|(mut mpd, _)| mpd.periods.pop())
+			.map(|mut period| {
+				let start = period.start.map_or(0, |start| start.as_nanos() as u64);
+				period.duration = Some(std::time::Duration::from_nanos(period_start.nseconds().saturating_sub(start)));
+				period
+			});
+
+		// Released streams end with their Period
+		streams.retain(|_, stream| stream.released.is_none());
+		let continuous = keep_previous || archive_location.is_some() || settings.per_period_manifest;
+		for (pad_name, stream) in streams.iter_mut() {
+			let start_number = if continuous {
+				stream.segment_idx
+			} else {
+				self.start_number(pad_name, &settings)
+			};
+			stream.segments.clear();
+			stream.start_number = start_number;
+			stream.segment_idx = start_number;
+			stream.resumed_duration = gst::ClockTime::ZERO;
+		}
+
+		let mut state = self.state.lock().unwrap();
+		let index = state.period.as_ref().map_or(1, |period| period.index + 1);
+		gst::info!(CAT, imp = self, "Starting period {} at {}", index, period_start);
+		match closed_period {
+			Some(closed_period) => state.closed_periods.push(closed_period),
+			None => state.closed_periods.clear(),
+		}
+		state.period = Some(DashCmafSinkPeriod {
+			index,
+			start: period_start,
+			id,
+		});
+		state.aligned_segment = None;
+		state.events.retain(|event| event.presentation_time >= period_start);
+
+		true
+	}
+
+	/// Manifest of the current pads with a single dummy segment per stream,
+	/// for checking the configuration without writing anything
+	fn test_manifest(&self) -> Option<String> {
+		let streams = self.streams.lock().unwrap();
+		let settings = self.settings.lock().unwrap();
+
+		let infos = streams
+			.iter()
+			.filter_map(|(pad_name, stream)| {
+				let duration = self
+					.obj()
+					.static_pad(pad_name)
+					.and_then(|pad| pad.downcast::<super::DashCmafSinkPad>().ok())
+					.and_then(|pad| pad.imp().settings.lock().unwrap().fragment_duration)
+					.unwrap_or(settings.segment_duration);
+				let segment = DashCmafSinkSegment {
+					number: stream.start_number,
+					start: gst::ClockTime::ZERO,
+					duration,
+					location: sprintf::sprintf!(&settings.segment_location(pad_name), stream.start_number).ok()?,
+					checksum: None,
+				};
+				self.stream_info(pad_name, stream, Cow::Owned(vec![segment]), duration, &settings)
+			})
+			.collect::<Vec<_>>();
+
+		let mpd = build_mpd(
+			&infos,
+			&settings,
+			self.availability_start_time(&settings),
+			infos.iter().map(|info| info.segment_duration).max(),
+			&self.state.lock().unwrap().events,
+			None,
+			chrono::Utc::now(),
+		)?;
+
+		Some(manifest_xml(&mpd, settings.manifest_indent, "UTF-8"))
+	}
+
+	/// What the manifest describes of a stream with the given segments, None
+	/// until its caps are known
+	fn stream_info<'a>(
+		&self,
+		pad_name: &str,
+		stream: &DashCmafSinkStream,
+		segments: Cow<'a, [DashCmafSinkSegment]>,
+		duration: gst::ClockTime,
+		settings: &DashCmafSinkSettings,
+	) -> Option<DashCmafSinkStreamInfo<'a>> {
+		// A released pad has no caps anymore, they were kept with it
+		let (sink_pad, caps) = match stream.released {
+			Some((ref sink_pad, ref caps)) => (sink_pad.clone(), caps.clone()),
+			None => {
+				let sink_pad = self
+					.obj()
+					.static_pad(pad_name)?
+					.downcast::<super::DashCmafSinkPad>()
+					.ok()?;
+				let caps = sink_pad.current_caps()?;
+				(sink_pad, caps)
+			}
+		};
+		let init_data_uri = stream.init_segment.as_ref().filter(|_| settings.inline_init).map(|init| {
+			let media = media_and_codec(caps.structure(0)).0;
+			let mime_type = match settings.container {
+				DashCmafSinkContainer::Cmaf => format!("{media}/mp4"),
+				DashCmafSinkContainer::Webm => format!("{media}/webm"),
+			};
+			format!("data:{mime_type};base64,{}", glib::base64_encode(init))
+		});
+		let (segment_duration, quality_ranking, bandwidth) = {
+			let pad_settings = sink_pad.imp().settings.lock().unwrap();
+			let segment_duration = pad_settings
+				.fragment_duration
+				.unwrap_or(settings.segment_duration);
+
+			(segment_duration, pad_settings.quality_ranking, pad_settings.bandwidth)
+		};
+		// As cut by the muxer, see fit_aac_fragments()
+		let segment_duration = match aac_rate(caps.structure(0)).filter(|_| !settings.mux_streams) {
+			Some(rate) => whole_aac_frames(segment_duration, rate),
+			None => segment_duration,
+		};
+		let timescale = sink_pad.imp().timescale(Some(&caps));
+
+		Some(DashCmafSinkStreamInfo {
+			pad_name: pad_name.to_string(),
+			caps,
+			tracks: stream
+				.cmafmux
+				.iter()
+				.flat_map(|muxer| muxer.sink_pads())
+				.filter_map(|pad| pad.current_caps())
+				.collect(),
+			key: sink_pad.imp().adaptation_set_key(),
+			preselection: sink_pad.imp().preselection(),
+			segment_duration,
+			start_number: stream.start_number,
+			segments,
+			duration,
+			bandwidth: bandwidth.unwrap_or(stream.bandwidth),
+			quality_ranking,
+			timescale,
+			ended: stream.released.is_some(),
+			period_start: self.state.lock().unwrap().period.as_ref().map_or(gst::ClockTime::ZERO, |period| period.start),
+			reorder_delay: stream
+				.reorder_delay
+				.filter(|_| settings.reorder_delay_offset)
+				.unwrap_or(gst::ClockTime::ZERO),
+			init_data_uri,
+			combined_init: stream.combined_init.clone(),
+		})
+	}
+
+	/// Called for every fragment reaching the appsink, warns when a full
+	/// queue is going to make it drop the oldest one.
+	fn on_appsink_buffer(&self, pad_name: &str) {
+		let mut streams = self.streams.lock().unwrap();
+		let Some(stream) = streams.get_mut(pad_name) else {
+			return;
+		};
+		let settings = self.settings.lock().unwrap();
+
+		let queued = stream.buffers_received - stream.buffers_pulled;
+		let max_buffers = settings.max_buffers;
+		let dropping = settings.drop && max_buffers > 0 && queued >= max_buffers as u64;
+		if dropping {
+			stream.buffers_pulled += 1;
+		}
+		stream.buffers_received += 1;
+		drop(settings);
+		drop(streams);
+
+		if dropping {
+			// Posted without the lock, bus handlers may read the properties
+			gst::element_imp_warning!(
+				self,
+				gst::ResourceError::Write,
+				("Output of {} is too slow, dropping the oldest fragment", pad_name),
+				["{} fragments queued, max-buffers {}", queued, max_buffers]
+			);
+		}
+	}
+
+    fn on_new_sample(&self, sample: gst::Sample, pad_name: &str) -> Result<gst::FlowSuccess, gst::FlowError> {
+		let (webm, ensure_styp) = {
+			let mut streams = self.streams.lock().unwrap();
+			let settings = self.settings.lock().unwrap();
+			match streams.get_mut(pad_name) {
+				Some(stream) => {
+					stream.buffers_pulled += 1;
+					let muxed = stream.cmafmux.is_some();
+					(
+						muxed && settings.container == DashCmafSinkContainer::Webm,
+						muxed && settings.ensure_styp,
+					)
+				}
+				None => (false, false),
+			}
+		};
+		if webm {
+			return self.on_webm_sample(sample, pad_name);
+		}
+
+		let mut buffer_list = match sample.buffer_list_owned() {
+			Some(buffer_list) => buffer_list,
+			// Tile images of thumbnail streams come as single buffers
+			None => {
+				let buffer = sample.buffer_owned().ok_or(gst::FlowError::Error)?;
+				let mut buffer_list = gst::BufferList::new();
+				buffer_list.get_mut().unwrap().add(buffer);
+				buffer_list
+			}
+		};
+		let first = buffer_list.get(0).ok_or(gst::FlowError::Error)?;
+	
+		if is_init_segment(first) {
+			let map = first.map_readable().map_err(|_| {
+				gst::error!(CAT, imp = self, "Failed to map init segment buffer");
+				gst::FlowError::Error
+			})?;
+			self.check_cmaf_brands(pad_name, &map)?;
+			self.write_init_segment(pad_name, &map)?;
+			drop(map);
+	
+			// Remove init segment from buffer list
+			buffer_list.make_mut().remove(0..1);
+	
+			if buffer_list.is_empty() {
+				return Ok(gst::FlowSuccess::Ok);
+			}
+		}
+	
+		self.check_keyframe_start(pad_name, &buffer_list)?;
+		self.detect_reorder_delay(pad_name, &buffer_list);
+		if ensure_styp {
+			self.ensure_styp(pad_name, &mut buffer_list)?;
+		}
+
+		// Segment timing as signalled by cmafmux on the first buffer of the fragment
+		let first = buffer_list.get(0).ok_or(gst::FlowError::Error)?;
+		let segment = sample
+			.segment()
+			.and_then(|segment| segment.downcast_ref::<gst::ClockTime>());
+		let start = first
+			.pts()
+			.and_then(|pts| segment.and_then(|segment| segment.to_running_time(pts)));
+		let duration = first.duration();
+
+		self.write_media_segment(pad_name, start, duration, &buffer_list)
+	}
+
+	/// webmmux output is not fragmented, so the header is collected until the
+	/// first Cluster and every Cluster becomes a media segment once the next
+	/// one starts, or on EOS.
+	fn on_webm_sample(&self, sample: gst::Sample, pad_name: &str) -> Result<gst::FlowSuccess, gst::FlowError> {
+		let buffer = sample.buffer_owned().ok_or(gst::FlowError::Error)?;
+		let start = buffer.pts().and_then(|pts| {
+			sample
+				.segment()
+				.and_then(|segment| segment.downcast_ref::<gst::ClockTime>())
+				.and_then(|segment| segment.to_running_time(pts))
+		});
+		let cluster_start = {
+			let map = buffer.map_readable().map_err(|_| {
+				gst::error!(CAT, imp = self, "Failed to map WebM buffer");
+				gst::FlowError::Error
+			})?;
+			let cluster_start = map.starts_with(&WEBM_CLUSTER_ID);
+
+			if buffer.flags().contains(gst::BufferFlags::HEADER) && !cluster_start {
+				let mut streams = self.streams.lock().unwrap();
+				let stream = streams.get_mut(pad_name).ok_or(gst::FlowError::Error)?;
+				stream.webm_header.extend_from_slice(&map);
+				return Ok(gst::FlowSuccess::Ok);
+			}
+
+			cluster_start
+		};
+
+		if !cluster_start {
+			let mut streams = self.streams.lock().unwrap();
+			let stream = streams.get_mut(pad_name).ok_or(gst::FlowError::Error)?;
+			if let Some(ref mut cluster) = stream.webm_cluster {
+				cluster.buffers.get_mut().unwrap().add(buffer);
+			}
+			return Ok(gst::FlowSuccess::Ok);
+		}
+
+		let header = std::mem::take(
+			&mut self
+				.streams
+				.lock()
+				.unwrap()
+				.get_mut(pad_name)
+				.ok_or(gst::FlowError::Error)?
+				.webm_header,
+		);
+		if !header.is_empty() {
+			self.write_init_segment(pad_name, &header)?;
+		}
+		self.flush_webm_cluster(pad_name, start)?;
+
+		let mut buffers = gst::BufferList::new();
+		buffers.get_mut().unwrap().add(buffer);
+		if let Some(stream) = self.streams.lock().unwrap().get_mut(pad_name) {
+			stream.webm_cluster = Some(DashCmafSinkCluster { start, buffers });
+		}
+
+		Ok(gst::FlowSuccess::Ok)
+	}
+
+	/// Writes the pending WebM Cluster of the stream as a media segment
+	/// lasting until `end`, the start of the next one.
+	fn flush_webm_cluster(
+		&self,
+		pad_name: &str,
+		end: Option<gst::ClockTime>,
+	) -> Result<gst::FlowSuccess, gst::FlowError> {
+		let cluster = self
+			.streams
+			.lock()
+			.unwrap()
+			.get_mut(pad_name)
+			.and_then(|stream| stream.webm_cluster.take());
+		let Some(cluster) = cluster else {
+			return Ok(gst::FlowSuccess::Ok);
+		};
+
+		let duration = cluster.start.zip(end).and_then(|(start, end)| end.checked_sub(start));
+		self.write_media_segment(pad_name, cluster.start, duration, &cluster.buffers)
+	}
+
+	/// Checks the init segment carries the CMAF structural brand, so players
+	/// treat the output as CMAF. Only an error with cmaf-profile, which
+	/// promises it in the manifest.
+	fn check_cmaf_brands(&self, pad_name: &str, init: &[u8]) -> Result<(), gst::FlowError> {
+		let brands = ftyp_brands(init).unwrap_or_default();
+		// isofmp4mux output of mux-streams is not meant to be CMAF
+		let (mux_streams, cmaf_profile) = {
+			let settings = self.settings.lock().unwrap();
+			(settings.mux_streams, settings.cmaf_profile)
+		};
+		if mux_streams || brands.contains(CMAF_STRUCTURAL_BRAND) {
+			return Ok(());
+		}
+
+		let brands = brands.iter().map(|brand| String::from_utf8_lossy(brand)).collect::<Vec<_>>().join(",");
+		if cmaf_profile {
+			gst::element_imp_error!(
+				self,
+				gst::StreamError::Format,
+				("Init segment of pad {} is not CMAF", pad_name),
+				["ftyp brands [{}] lack cmfc, required by cmaf-profile", brands]
+			);
+			return Err(gst::FlowError::Error);
+		}
+
+		gst::element_imp_warning!(
+			self,
+			gst::StreamError::Format,
+			("Init segment of pad {} is not CMAF", pad_name),
+			["ftyp brands [{}] lack cmfc, players may not treat the stream as CMAF", brands]
+		);
+
+		Ok(())
+	}
+
+	/// Keeps the composition offset of the first sample of a stream, the
+	/// delay B-frames add before the first frame is presented when the muxer
+	/// writes no edit list for it
+	fn detect_reorder_delay(&self, pad_name: &str, buffer_list: &gst::BufferListRef) {
+		let mut streams = self.streams.lock().unwrap();
+		let Some(stream) = streams.get_mut(pad_name).filter(|stream| stream.reorder_delay.is_none()) else {
+			return;
+		};
+		let Some(first_sample) = first_sample(buffer_list) else {
+			return;
+		};
+
+		let delay = first_sample.pts().opt_checked_sub(first_sample.dts()).ok().flatten().unwrap_or_default();
+		if !delay.is_zero() {
+			gst::info!(CAT, imp = self, "{} starts with a reorder delay of {}", pad_name, delay);
+		}
+		stream.reorder_delay = Some(delay);
+	}
+
+	/// Checks that a video fragment starts with a keyframe, so segments are
+	/// independently decodable. cmafmux outputs the fragment header followed
+	/// by the samples, which keep their flags.
+	fn check_keyframe_start(&self, pad_name: &str, buffer_list: &gst::BufferListRef) -> Result<(), gst::FlowError> {
+		let is_video = self
+			.obj()
+			.static_pad(pad_name)
+			.and_then(|pad| pad.current_caps())
+			.and_then(|caps| caps.structure(0).map(|s| s.name().starts_with("video/")))
+			.unwrap_or(false);
+		let (require_keyframe, force_keyframe) = {
+			let settings = self.settings.lock().unwrap();
+			// Muxed fragments may start with any track
+			if !is_video || settings.mux_streams {
+				return Ok(());
+			}
+			(settings.require_keyframe, settings.force_keyframe)
+		};
+
+		let Some(first_sample) = first_sample(buffer_list) else {
+			return Ok(());
+		};
+		if !first_sample.flags().contains(gst::BufferFlags::DELTA_UNIT) {
+			return Ok(());
+		}
+
+		let hint = if force_keyframe {
+			"The encoder doesn't honour the keyframe requests"
+		} else {
+			"Set force-keyframe or align the encoder GOP with the segment duration"
+		};
+		if require_keyframe {
+			gst::element_imp_error!(
+				self,
+				gst::StreamError::Format,
+				("Segment of pad {} doesn't start with a keyframe", pad_name),
+				["{}", hint]
+			);
+			return Err(gst::FlowError::Error);
+		}
+
+		gst::debug!(CAT, imp = self, "Segment of pad {} doesn't start with a keyframe", pad_name);
+		let mut streams = self.streams.lock().unwrap();
+		if let Some(stream) = streams.get_mut(pad_name).filter(|stream| !stream.keyframe_missing) {
+			stream.keyframe_missing = true;
+			drop(streams);
+			gst::element_imp_warning!(
+				self,
+				gst::StreamError::Format,
+				("Segments of pad {} don't start with a keyframe", pad_name),
+				["{}", hint]
+			);
+		}
+
+		Ok(())
+	}
+
+	/// Adds a styp box in front of a fragment lacking one, warning the first
+	/// time for the stream as the muxer doesn't emit them.
+	fn ensure_styp(&self, pad_name: &str, buffer_list: &mut gst::BufferList) -> Result<(), gst::FlowError> {
+		let first = buffer_list.get(0).ok_or(gst::FlowError::Error)?;
+		let has_styp = first
+			.map_readable()
+			.map(|map| map.get(4..8) == Some(b"styp".as_slice()))
+			.unwrap_or(false);
+		if has_styp {
+			return Ok(());
+		}
+
+		let mut buffer = gst::Buffer::from_mut_slice(styp_box());
+		{
+			// Timing stays signalled on the first buffer of the fragment
+			let buffer = buffer.get_mut().unwrap();
+			buffer.set_pts(first.pts());
+			buffer.set_dts(first.dts());
+			buffer.set_duration(first.duration());
+		}
+		buffer_list.make_mut().insert(0, buffer);
+
+		let mut streams = self.streams.lock().unwrap();
+		if let Some(stream) = streams.get_mut(pad_name).filter(|stream| !stream.styp_missing) {
+			stream.styp_missing = true;
+			drop(streams);
+			gst::element_imp_warning!(
+				self,
+				gst::StreamError::Format,
+				("Media segments of pad {} lack a styp box", pad_name),
+				["Adding one to every segment as ensure-styp is set"]
+			);
+		}
+
+		Ok(())
+	}
+
+	fn write_init_segment(&self, pad_name: &str, data: &[u8]) -> Result<(), gst::FlowError> {
+		// Signalled in the manifest with the next segment instead, or
+		// written at its start
+		let (inline_init, combine_init) = {
+			let settings = self.settings.lock().unwrap();
+			(settings.inline_init, settings.combine_init_first_segment)
+		};
+		if inline_init || combine_init {
+			if let Some(stream) = self.streams.lock().unwrap().get_mut(pad_name) {
+				stream.init_segment = Some(data.to_vec());
+				stream.init_pending = combine_init && !inline_init;
+			}
+			return Ok(());
+		}
+
+		let mut stream = self.on_init_segment(pad_name).map_err(|err| {
+			gst::error!(
+				CAT,
+				imp = self,
+				"Couldn't get output stream for init segment: {err}",
+			);
+			gst::FlowError::Error
+		})?;
+
+		stream.write_all(data).map_err(|_| {
+			gst::error!(CAT, imp = self, "Couldn't write init segment to output stream");
+			gst::FlowError::Error
+		})?;
+
+		stream.flush().map_err(|_| {
+			gst::error!(CAT, imp = self, "Couldn't flush init segment stream");
+			gst::FlowError::Error
+		})
+	}
+
+	/// Writes a media segment made of `buffer_list` and updates the manifest
+	fn write_media_segment(
+		&self,
+		pad_name: &str,
+		start: Option<gst::ClockTime>,
+		duration: Option<gst::ClockTime>,
+		buffer_list: &gst::BufferListRef,
+	) -> Result<gst::FlowSuccess, gst::FlowError> {
+		// Fragments drained after max-duration are not part of the recording
+		let past_max_duration = {
+			let mut streams = self.streams.lock().unwrap();
+			let Some(dash_stream) = streams.get_mut(pad_name) else {
+				return Err(gst::FlowError::Flushing);
+			};
+			if dash_stream.finished {
+				return Ok(gst::FlowSuccess::Ok);
+			}
+
+			// Presentation time before this segment, a resumed run or a gap
+			// possibly having covered max-duration already
+			let elapsed = if dash_stream.segments.is_empty() {
+				Some(gst::ClockTime::ZERO)
+			} else {
+				start.opt_checked_sub(dash_stream.start_time).ok().flatten()
+			}
+			.map(|elapsed| elapsed + dash_stream.resumed_duration);
+			let max_duration = self.settings.lock().unwrap().max_duration;
+			dash_stream.finished = max_duration.is_some() && elapsed >= max_duration;
+			dash_stream.finished
+		};
+		if past_max_duration {
+			self.end_stream(pad_name);
+			return Ok(gst::FlowSuccess::Ok);
+		}
+		let write_start = std::time::Instant::now();
+
+		// Get output stream + location
+		let (mut stream, mut segment) = self.on_new_segment(pad_name, start, duration).map_err(|err| {
+			gst::error!(
+				CAT,
+				imp = self,
+				"Couldn't get output stream for fragment: {err}",
+			);
+			gst::FlowError::Error
+		})?;
+	
+		let mut checksum = self
+			.settings
+			.lock()
+			.unwrap()
+			.checksum_manifest
+			.then(|| glib::Checksum::new(glib::ChecksumType::Sha256))
+			.flatten();
+		let init = self
+			.streams
+			.lock()
+			.unwrap()
+			.get(pad_name)
+			.filter(|stream| stream.init_pending)
+			.and_then(|stream| stream.init_segment.clone());
+		let combined = init.as_ref().map(|init| {
+			let mut combined = buffer_list.to_owned();
+			combined.make_mut().insert(0, gst::Buffer::from_slice(init.clone()));
+			combined
+		});
+		let buffer_list = combined.as_deref().unwrap_or(buffer_list);
+		let init_size = init.as_ref().map_or(0, Vec::len);
+		let total_size = match self.write_fragment(&mut stream, buffer_list, checksum.as_mut()) {
+			Ok(total_size) => total_size - init_size,
+			Err(err) => {
+				if self.settings.lock().unwrap().cleanup_on_error {
+					// The backends discard resources dropped without being
+					// flushed, and the segment number is reused
+					drop(stream);
+					if let Some(dash_stream) = self.streams.lock().unwrap().get_mut(pad_name) {
+						dash_stream.segment_idx -= 1;
+					}
+				} else {
+					let _ = stream.flush();
+				}
+				return Err(err);
+			}
+		};
+		segment.checksum = checksum.and_then(|checksum| checksum.string()).map(|checksum| checksum.to_string());
+
+		let finished = {
+			let mut streams = self.streams.lock().unwrap();
+			let dash_stream = streams.get_mut(pad_name).ok_or(gst::FlowError::Flushing)?;
+			let settings = self.settings.lock().unwrap();
+			if init.is_some() {
+				dash_stream.init_pending = false;
+				dash_stream.combined_init = Some((segment.location.clone(), init_size));
+			}
+			dash_stream.recent_segments.push_back((total_size as u64, segment.duration));
+			while dash_stream.recent_segments.len() > settings.bandwidth_window as usize {
+				dash_stream.recent_segments.pop_front();
+			}
+			dash_stream.bandwidth = windowed_bandwidth(&dash_stream.recent_segments, settings.segment_duration);
+			dash_stream.peak_bandwidth = dash_stream.peak_bandwidth.max(dash_stream.bandwidth);
+			dash_stream.bytes_written += total_size as u64;
+			dash_stream.segments_written += 1;
+
+			if dash_stream.segments.is_empty() {
+				dash_stream.start_time = Some(segment.start);
+			}
+			dash_stream.end_time = Some(segment.start + segment.duration);
+			dash_stream.last_segment_at = Some(std::time::Instant::now());
+			let duration = segment.duration;
+			dash_stream.segments.push(segment);
+
+			let mut state = self.state.lock().unwrap();
+			state.bytes_written += total_size as u64;
+			state.segments_written += 1;
+			state.max_segment_duration = state.max_segment_duration.max(Some(duration));
+			gst::info!(CAT, imp = self, "total size: {} bandwidth: {}", total_size, dash_stream.bandwidth);
+
+			let elapsed = dash_stream
+				.end_time
+				.opt_checked_sub(dash_stream.start_time)
+				.ok()
+				.flatten()
+				.map(|elapsed| elapsed + dash_stream.resumed_duration);
+			dash_stream.finished = settings.max_duration.is_some() && elapsed >= settings.max_duration;
+			dash_stream.finished
+		};
+	
+		self.align_audio_boundary(pad_name);
+		let res = self.add_segment(pad_name);
+
+		let write_time = write_start.elapsed();
+		if let Some(dash_stream) = self.streams.lock().unwrap().get_mut(pad_name) {
+			dash_stream.last_write_time = write_time;
+			dash_stream.max_write_time = dash_stream.max_write_time.max(write_time);
+		}
+		gst::trace!(CAT, imp = self, "Segment of pad {} written in {:?}", pad_name, write_time);
+
+		if finished {
+			self.end_stream(pad_name);
+		}
+
+		res
+	}
+
+	/// With align-audio, shortens or lengthens the next fragment of an audio
+	/// stream by the offset between its last segment and the video segment
+	/// of the same number, so audio boundaries stay within a frame of the
+	/// video ones instead of drifting. cmafmux has usually started the next
+	/// fragment already, the correction applies to the one after.
+	fn align_audio_boundary(&self, pad_name: &str) {
+		let obj = self.obj();
+		let media = |pad_name: &str| {
+			obj.static_pad(pad_name)
+				.and_then(|pad| pad.current_caps())
+				.and_then(|caps| caps.structure(0).map(|s| s.name().split('/').next().unwrap_or_default().to_string()))
+		};
+		if media(pad_name).as_deref() != Some("audio") {
+			return;
+		}
+		let Some(sink_pad) = obj.static_pad(pad_name).and_then(|pad| pad.downcast::<super::DashCmafSinkPad>().ok()) else {
+			return;
+		};
+		let fragment_duration = sink_pad.imp().settings.lock().unwrap().fragment_duration;
+		let aac_rate = sink_pad.current_caps().and_then(|caps| aac_rate(caps.structure(0)));
+
+		let mut streams = self.streams.lock().unwrap();
+		let settings = self.settings.lock().unwrap();
+		if !settings.align_audio {
+			return;
+		}
+		let Some(segment) = streams.get(pad_name).and_then(|stream| stream.segments.last()) else {
+			return;
+		};
+		let number = segment.number;
+		let audio_end = segment.start + segment.duration;
+
+		let video_end = streams
+			.iter()
+			.filter(|(other, _)| media(other).as_deref() == Some("video"))
+			.find_map(|(_, stream)| stream.segments.iter().rev().find(|segment| segment.number == number))
+			.map(|segment| segment.start + segment.duration);
+		// The video segment isn't written yet, it's corrected on the next one
+		let Some(video_end) = video_end else {
+			return;
+		};
+
+		let duration = fragment_duration.unwrap_or(settings.segment_duration);
+		// Never more than half a segment, a late video segment isn't drift
+		let max_offset = (duration.nseconds() / 2) as i64;
+		let offset = (audio_end.nseconds() as i64 - video_end.nseconds() as i64).clamp(-max_offset, max_offset);
+		gst::debug!(CAT, imp = self, "Audio segment {} of pad {} ends {} ns after the video one", number, pad_name, offset);
+
+		let Some(stream) = streams.get_mut(pad_name) else {
+			return;
+		};
+		stream.alignment_offset = offset;
+		if let Some(muxer) = stream.cmafmux.as_ref().filter(|muxer| muxer.has_property("fragment-duration", None)) {
+			let next_duration = gst::ClockTime::from_nseconds((duration.nseconds() as i64 - offset) as u64);
+			let next_duration = match aac_rate {
+				Some(rate) => whole_aac_frames(next_duration, rate),
+				None => next_duration,
+			};
+			muxer.set_property("fragment-duration", next_duration);
+		}
+	}
+
+	/// Writes all buffers of a fragment and flushes them, returning the size
+	fn write_fragment(
+		&self,
+		stream: &mut Box<dyn Write + Send>,
+		buffer_list: &gst::BufferListRef,
+		mut checksum: Option<&mut glib::Checksum>,
+	) -> Result<usize, gst::FlowError> {
+		let mut total_size = 0;
+		for buffer in buffer_list {
+			let map = buffer.map_readable().map_err(|_| {
+				gst::error!(CAT, imp = self, "Failed to map fragment buffer");
+				gst::FlowError::Error
+			})?;
+	
+			stream.write_all(&map).map_err(|err| self.write_error(&err))?;
+			if let Some(ref mut checksum) = checksum {
+				checksum.update(&map);
+			}
+			total_size += map.size();
+		}
+
+		stream.flush().map_err(|err| self.write_error(&err))?;
+
+		Ok(total_size)
+	}
+
+	/// Flow return of a failed write, EOS when the reader of a named pipe
+	/// went away
+	fn write_error(&self, err: &std::io::Error) -> gst::FlowError {
+		if err.kind() == std::io::ErrorKind::BrokenPipe {
+			gst::info!(CAT, imp = self, "Reader of the output closed it, ending");
+			return gst::FlowError::Eos;
+		}
+
+		gst::error!(CAT, imp = self, "Couldn't write fragment to output stream: {err}");
+		gst::FlowError::Error
+	}
+
+	/// Writes the manifest deferred by manifest-write-mode once all streams
+	/// reached EOS
+	fn on_eos(&self, pad_name: &str) {
+		let all_eos = {
+			let mut streams = self.streams.lock().unwrap();
+			if let Some(stream) = streams.get_mut(pad_name) {
+				stream.eos = true;
+			}
+			streams.values().all(|stream| stream.eos || stream.released.is_some())
+		};
+		let deferred = self.settings.lock().unwrap().manifest_write_mode != DashCmafSinkManifestWriteMode::EverySegment;
+
+		if all_eos && deferred && self.state.lock().unwrap().segments_written > 0 {
+			gst::info!(CAT, imp = self, "All streams reached EOS, writing the manifest");
+			let _ = self.add_segment("");
+		}
+	}
+
+	/// Sends EOS into the muxer of a stream that reached max-duration, so
+	/// upstream gets EOS flow returns and the pipeline ends once every stream
+	/// is done.
+	fn end_stream(&self, pad_name: &str) {
+		let elements = match self.streams.lock().unwrap().get(pad_name) {
+			Some(stream) => [stream.cmafmux.clone(), Some(stream.appsink.clone().upcast())],
+			None => return,
+		};
+
+		gst::info!(CAT, imp = self, "{} reached max-duration, ending it", pad_name);
+		// Every sink pad feeding the muxer, all the tracks of a muxed stream
+		for pad in self.obj().sink_pads() {
+			let target = pad
+				.downcast_ref::<gst::GhostPad>()
+				.and_then(|pad| pad.target())
+				.and_then(|target| target.parent_element());
+			if target.is_some_and(|target| elements.contains(&Some(target))) {
+				end_on_next_buffer(&pad);
+			}
+		}
+	}
+}
+
+/// Running time at the end of the latest media segment of a stream.
+fn live_edge(streams: &HashMap<String, DashCmafSinkStream>, pad_name: &str) -> Option<gst::ClockTime> {
+	streams
+		.get(pad_name)
+		.and_then(|stream| stream.segments.last())
+		.map(|segment| segment.start + segment.duration)
+}
+
+/// Ends a sink pad with EOS on its next buffer, sent from the streaming
+/// thread pushing it so that it can't race upstream buffers into the muxer.
+/// Upstream then gets EOS flow returns.
+fn end_on_next_buffer(pad: &gst::Pad) {
+	pad.add_probe(
+		gst::PadProbeType::BUFFER | gst::PadProbeType::BUFFER_LIST | gst::PadProbeType::EVENT_DOWNSTREAM,
+		|pad, info| match info.data {
+			Some(gst::PadProbeData::Event(ref ev)) => match ev.type_() {
+				// Ended by upstream meanwhile, or restarted
+				gst::EventType::Eos | gst::EventType::StreamStart => gst::PadProbeReturn::Remove,
+				_ => gst::PadProbeReturn::Ok,
+			},
+			_ => {
+				if let Some(id) = info.id.take() {
+					pad.remove_probe(id);
+				}
+				pad.send_event(gst::event::Eos::new());
+				gst::PadProbeReturn::Drop
+			}
+		},
+	);
+}
+
+/// Sink pad of a muxer for a stream. webmmux only has per media type
+/// request pads, so it needs the caps of the stream.
+fn request_muxer_pad(muxer: &gst::Element, caps: Option<&gst::CapsRef>) -> Option<gst::Pad> {
+	if let Some(pad) = muxer.static_pad("sink") {
+		return Some(pad);
+	}
+	if muxer.pad_template("sink_%u").is_some() {
+		return muxer.request_pad_simple("sink_%u");
+	}
+
+	let media = caps?.structure(0)?.name();
+	if media.starts_with("video/") {
+		muxer.request_pad_simple("video_%u")
+	} else if media.starts_with("audio/") {
+		muxer.request_pad_simple("audio_%u")
+	} else {
+		None
+	}
+}
+
+/// Whether the first buffer of a fragment is an init segment. cmafmux flags
+/// it DISCONT and HEADER, but some muxer configurations leave DISCONT out,
+/// so a HEADER buffer starting with an ftyp box is one as well, unlike the
+/// HEADER buffers of the moof boxes.
+fn is_init_segment(buffer: &gst::BufferRef) -> bool {
+	let flags = buffer.flags();
+	if !flags.contains(gst::BufferFlags::HEADER) {
+		return false;
+	}
+	if flags.contains(gst::BufferFlags::DISCONT) {
+		return true;
+	}
+
+	let mut ftyp = [0u8; 8];
+	buffer.copy_to_slice(0, &mut ftyp).is_ok() && &ftyp[4..] == b"ftyp"
+}
+
+/// First sample of a fragment, after the HEADER buffers of its header.
+fn first_sample(buffer_list: &gst::BufferListRef) -> Option<&gst::BufferRef> {
+	buffer_list.iter().find(|buffer| !buffer.flags().contains(gst::BufferFlags::HEADER))
+}
+
+/// Average bitrate of recent segments, from their real durations. Segments
+/// without a duration count as lasting `target_duration`.
+fn windowed_bandwidth(segments: &VecDeque<(u64, gst::ClockTime)>, target_duration: gst::ClockTime) -> u64 {
+	let (bytes, duration) = segments.iter().fold((0u64, gst::ClockTime::ZERO), |(bytes, total), (size, duration)| {
+		let duration = Some(*duration)
+			.filter(|duration| !duration.is_zero())
+			.unwrap_or(target_duration);
+		(bytes + size, total + duration)
+	});
+	if duration.is_zero() {
+		return 0;
+	}
+
+	(bytes as u128 * 8 * gst::ClockTime::SECOND.nseconds() as u128 / duration.nseconds() as u128) as u64
+}
+
+/// JSON object listing the number, location and SHA-256 of the segments of
+/// every stream, by pad name.
+fn checksum_manifest(streams: &HashMap<String, DashCmafSinkStream>) -> String {
+	let mut pad_names = streams.keys().collect::<Vec<_>>();
+	pad_names.sort();
+
+	let streams = pad_names
+		.into_iter()
+		.map(|pad_name| {
+			let segments = streams[pad_name]
+				.segments
+				.iter()
+				.filter_map(|segment| {
+					Some(format!(
+						"    {{ \"number\": {}, \"location\": {}, \"sha256\": \"{}\" }}",
+						segment.number,
+						json_string(&segment.location),
+						segment.checksum.as_ref()?
+					))
+				})
+				.collect::<Vec<_>>();
+			format!("  {}: [\n{}\n  ]", json_string(pad_name), segments.join(",\n"))
+		})
+		.collect::<Vec<_>>();
+
+	format!("{{\n{}\n}}\n", streams.join(",\n"))
+}
+
+/// Sample rate of AAC caps.
+fn aac_rate(s: Option<&gst::StructureRef>) -> Option<u32> {
+	let s = s.filter(|s| s.name() == "audio/mpeg" && matches!(s.get::<i32>("mpegversion"), Ok(2 | 4)))?;
+	s.get::<i32>("rate").ok().filter(|rate| *rate > 0).map(|rate| rate as u32)
+}
+
+/// `duration` rounded to the nearest whole number of AAC frames at `rate`,
+/// at least one. Exact in ticks of a `rate` timescale.
+fn whole_aac_frames(duration: gst::ClockTime, rate: u32) -> gst::ClockTime {
+	let frame = AAC_FRAME_SAMPLES as u128 * gst::ClockTime::SECOND.nseconds() as u128;
+	let scaled = duration.nseconds() as u128 * rate as u128;
+	let frames = ((scaled + frame / 2) / frame).max(1);
+
+	gst::ClockTime::from_nseconds((frames * frame).div_ceil(rate as u128) as u64)
+}
+
+/// Whether `data` only holds standard base64 characters with the right padding.
+fn is_base64(data: &str) -> bool {
+	let payload = data.trim_end_matches('=');
+	data.len() % 4 == 0
+		&& data.len() - payload.len() <= 2
+		&& payload
+			.bytes()
+			.all(|c| c.is_ascii_alphanumeric() || c == b'+' || c == b'/')
+}
+
+/// Parses tile dimensions given as `NxM`, both greater than zero.
+fn parse_tiles(tiles: &str) -> Option<(u32, u32)> {
+	let (columns, rows) = tiles.split_once('x')?;
+	let columns = columns.trim().parse::<u32>().ok().filter(|columns| *columns > 0)?;
+	let rows = rows.trim().parse::<u32>().ok().filter(|rows| *rows > 0)?;
+
+	Some((columns, rows))
+}
+
+pub(crate) static CAT: LazyLock<gst::DebugCategory> = LazyLock::new(|| {
+    gst::DebugCategory::new(
+        "dashcmafsink",
+        gst::DebugColorFlags::empty(),
+        Some("DASH CMAF Sink"),
+    )
+});
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use super::super::bitstream::tests::splice_info_section;
+	use super::super::mpd::tests::{mp3_caps, segment, stream_info, video_caps};
+
+	fn init() {
+		gst::init().unwrap();
+	}
+
+	#[test]
+	fn mp3_needs_a_muxer_accepting_it() {
+		init();
+		let element = glib::Object::new::<super::super::DashCmafSink>();
+		let imp = element.imp();
+
+		let aac_only = gst::Caps::builder("audio/mpeg").field("mpegversion", 4i32).build();
+		assert!(!imp.check_caps("sink_0", &mp3_caps(), &aac_only));
+
+		let with_mp3 = gst::Caps::builder("audio/mpeg")
+			.field("mpegversion", gst::List::new([1i32, 4i32]))
+			.build();
+		assert!(imp.check_caps("sink_0", &mp3_caps(), &with_mp3));
+	}
+
+	#[test]
+	fn lang_property_overrides_tag() {
+		init();
+		let pad = glib::Object::builder::<super::super::DashCmafSinkPad>()
+			.property("direction", gst::PadDirection::Sink)
+			.build();
+		pad.imp().settings.lock().unwrap().tag_lang = Some("fr".to_string());
+		assert_eq!(pad.imp().adaptation_set_key().lang.as_deref(), Some("fr"));
+
+		pad.set_property("lang", "en");
+		assert_eq!(pad.imp().adaptation_set_key().lang.as_deref(), Some("en"));
+	}
+
+	#[test]
+	fn no_stats_for_unknown_pads() {
+		init();
+		let element = glib::Object::new::<super::super::DashCmafSink>();
+		let stats = element.emit_by_name::<Option<gst::Structure>>("get-stream-stats", &[&"sink_7"]);
+		assert!(stats.is_none());
+		assert_eq!(element.property::<u64>("segments-written"), 0);
+	}
+
+	#[test]
+	fn output_dir_prefixes_locations() {
+		let mut settings = DashCmafSinkSettings::default();
+		assert_eq!(settings.output_path("manifest.mpd"), PathBuf::from("manifest.mpd"));
+
+		settings.output_dir = Some("/srv/dash".to_string());
+		assert_eq!(settings.output_path("sink_0_init.cmfi"), PathBuf::from("/srv/dash/sink_0_init.cmfi"));
+	}
+
+	#[test]
+	fn presentation_delay_above_min_buffer_time() {
+		let mut settings = DashCmafSinkSettings {
+			segment_duration: gst::ClockTime::from_seconds(2),
+			..Default::default()
+		};
+		assert_eq!(settings.presentation_delay(), std::time::Duration::from_secs(6));
+
+		settings.presentation_delay = 500;
+		assert_eq!(settings.presentation_delay(), std::time::Duration::from_secs(2));
+
+		settings.presentation_delay = 4500;
+		assert_eq!(settings.presentation_delay(), std::time::Duration::from_millis(4500));
+	}
+
+	#[test]
+	fn warn_without_sink_pads() {
+		init();
+		let pipeline = gst::Pipeline::new();
+		let element = glib::Object::new::<super::super::DashCmafSink>();
+		pipeline.add(&element).unwrap();
+
+		pipeline.set_state(gst::State::Paused).unwrap();
+		let msg = pipeline
+			.bus()
+			.unwrap()
+			.timed_pop_filtered(gst::ClockTime::from_seconds(5), &[gst::MessageType::Warning]);
+		pipeline.set_state(gst::State::Null).unwrap();
+
+		let Some(msg) = msg else {
+			panic!("no warning posted");
+		};
+		let gst::MessageView::Warning(warning) = msg.view() else {
+			unreachable!();
+		};
+		assert!(warning.error().matches(gst::CoreError::Pad));
+	}
+
+	#[test]
+	fn tiles_parsed() {
+		assert_eq!(parse_tiles("5x2"), Some((5, 2)));
+		assert_eq!(parse_tiles(" 3 x 3 "), Some((3, 3)));
+		assert_eq!(parse_tiles("0x2"), None);
+		assert_eq!(parse_tiles("5"), None);
+	}
+
+	#[test]
+	fn tile_images_cover_every_tile() {
+		let settings = DashCmafSinkSettings {
+			segment_duration: gst::ClockTime::from_seconds(2),
+			thumbnail_tiles: (5, 2),
+			..Default::default()
+		};
+		assert_eq!(settings.thumbnail_duration(), gst::ClockTime::from_seconds(20));
+	}
+
+	#[test]
+	fn invalid_tiles_rejected() {
+		init();
+		let element = glib::Object::new::<super::super::DashCmafSink>();
+		element.set_property("thumbnail-tiles", "4x4");
+		element.set_property("thumbnail-tiles", "4 by 4");
+		assert_eq!(element.property::<String>("thumbnail-tiles"), "4x4");
+	}
+
+	#[test]
+	fn full_queue_drops_the_oldest_fragment() {
+		init();
+		let element = glib::Object::new::<super::super::DashCmafSink>();
+		let pipeline = gst::Pipeline::new();
+		pipeline.add(&element).unwrap();
+		let bus = pipeline.bus().unwrap();
+		element.set_property("max-buffers", 2u32);
+		element.set_property("drop", true);
+		let imp = element.imp();
+		imp.streams.lock().unwrap().insert("sink_0".to_string(), DashCmafSinkStream::new(None).unwrap());
+
+		imp.on_appsink_buffer("sink_0");
+		imp.on_appsink_buffer("sink_0");
+		assert!(bus.pop_filtered(&[gst::MessageType::Warning]).is_none());
+
+		// The third one makes appsink drop the first
+		imp.on_appsink_buffer("sink_0");
+		assert!(bus.pop_filtered(&[gst::MessageType::Warning]).is_some());
+		let streams = imp.streams.lock().unwrap();
+		assert_eq!(streams["sink_0"].buffers_received, 3);
+		assert_eq!(streams["sink_0"].buffers_received - streams["sink_0"].buffers_pulled, 2);
+	}
+
+	#[test]
+	fn invalid_location_rejected() {
+		init();
+		let element = glib::Object::new::<super::super::DashCmafSink>();
+		let pipeline = gst::Pipeline::new();
+		pipeline.add(&element).unwrap();
+		let bus = pipeline.bus().unwrap();
+
+		element.set_property("segment-location", "segment_%d_%d.cmfv");
+		assert_eq!(element.property::<String>("segment-location"), DEFAULT_SEGMENT_LOCATION);
+		let message = bus.pop_filtered(&[gst::MessageType::Warning]).unwrap();
+		let gst::MessageView::Warning(warning) = message.view() else {
+			unreachable!();
+		};
+		assert!(warning.error().matches(gst::LibraryError::Settings));
+
+		element.set_property("segment-location", "chunk_%05d.m4s");
+		assert_eq!(element.property::<String>("segment-location"), "chunk_%05d.m4s");
+		assert!(bus.pop_filtered(&[gst::MessageType::Warning]).is_none());
+	}
+
+	#[test]
+	fn absolute_urls_need_a_base_url() {
+		let settings = DashCmafSinkSettings {
+			url_mode: DashCmafSinkUrlMode::Absolute,
+			..Default::default()
+		};
+		assert_eq!(settings.url("sink_0_init.cmfi"), "sink_0_init.cmfi");
+
+		let settings = DashCmafSinkSettings {
+			base_url: Some("https://cdn.example.com/live/".into()),
+			..settings
+		};
+		assert_eq!(settings.url("sink_0_init.cmfi"), "https://cdn.example.com/live/sink_0_init.cmfi");
+	}
+
+	#[test]
+	fn live_edge_of_the_latest_segment() {
+		init();
+		let element = glib::Object::new::<super::super::DashCmafSink>();
+		assert_eq!(element.property::<u64>("live-edge"), u64::MAX);
+
+		let mut video = DashCmafSinkStream::new(None).unwrap();
+		video.segments = vec![segment(0, 0, 2), segment(1, 2, 2)];
+		let mut audio = DashCmafSinkStream::new(None).unwrap();
+		audio.segments = vec![segment(0, 0, 3)];
+		let mut streams = element.imp().streams.lock().unwrap();
+		streams.insert("sink_0".to_string(), video);
+		streams.insert("sink_1".to_string(), audio);
+		drop(streams);
+
+		let live_edge = |pad_name: &str| element.emit_by_name::<u64>("get-live-edge", &[&pad_name]);
+		assert_eq!(live_edge("sink_0"), gst::ClockTime::from_seconds(4).nseconds());
+		assert_eq!(live_edge("sink_1"), gst::ClockTime::from_seconds(3).nseconds());
+		assert_eq!(live_edge("sink_2"), u64::MAX);
+		assert_eq!(element.property::<u64>("live-edge"), gst::ClockTime::from_seconds(4).nseconds());
+	}
+
+	#[test]
+	fn init_location_placeholders() {
+		let location = |init_location: &str| {
+			DashCmafSinkSettings {
+				init_location: init_location.to_string(),
+				..Default::default()
+			}
+			.init_location("sink_1")
+		};
+
+		assert_eq!(location("init.cmfi"), "sink_1_init.cmfi");
+		assert_eq!(location("$RepresentationID$/init.cmfi"), "sink_1/init.cmfi");
+		assert_eq!(location("{pad}-init.mp4"), "sink_1-init.mp4");
+		assert_eq!(location("init_%s.cmfi"), "init_sink_1.cmfi");
+	}
+
+	#[test]
+	fn start_time_parsed() {
+		init();
+		let element = glib::Object::new::<super::super::DashCmafSink>();
+		let pipeline = gst::Pipeline::new();
+		pipeline.add(&element).unwrap();
+		let bus = pipeline.bus().unwrap();
+
+		element.set_property("start-time", "2025-01-02T03:04:05+01:00");
+		assert_eq!(element.property::<Option<String>>("start-time").as_deref(), Some("2025-01-02T02:04:05+00:00"));
+		let settings = element.imp().settings.lock().unwrap();
+		assert_eq!(
+			element.imp().availability_start_time(&settings),
+			chrono::DateTime::parse_from_rfc3339("2025-01-02T02:04:05Z").unwrap()
+		);
+		drop(settings);
+
+		element.set_property("start-time", "yesterday");
+		assert!(bus.pop_filtered(&[gst::MessageType::Warning]).is_some());
+		assert_eq!(element.property::<Option<String>>("start-time").as_deref(), Some("2025-01-02T02:04:05+00:00"));
+
+		element.set_property("start-time", None::<String>);
+		assert!(element.property::<Option<String>>("start-time").is_none());
+	}
+
+	#[test]
+	fn webm_pads_requested_per_media_type() {
+		init();
+		let Ok(muxer) = gst::ElementFactory::make("webmmux").build() else {
+			return;
+		};
+
+		assert!(request_muxer_pad(&muxer, None).is_none());
+		let video = request_muxer_pad(&muxer, Some(&gst::Caps::new_empty_simple("video/x-vp8"))).unwrap();
+		assert!(video.name().starts_with("video_"));
+		let audio = request_muxer_pad(&muxer, Some(&gst::Caps::new_empty_simple("audio/x-opus"))).unwrap();
+		assert!(audio.name().starts_with("audio_"));
+	}
+
+	#[test]
+	fn base64_checked() {
+		assert!(is_base64("AAAAQHBzc2g="));
+		assert!(is_base64("AAAA"));
+		assert!(!is_base64("AAAAQHBzc2g"));
+		assert!(!is_base64("AAA==="));
+		assert!(!is_base64("AA-_"));
+	}
+
+	#[test]
+	fn invalid_pssh_rejected() {
+		init();
+		let element = glib::Object::new::<super::super::DashCmafSink>();
+		let pipeline = gst::Pipeline::new();
+		pipeline.add(&element).unwrap();
+		let bus = pipeline.bus().unwrap();
+
+		element.set_property("widevine-pssh", "AAAA");
+		element.set_property("widevine-pssh", "not base64");
+		assert!(bus.pop_filtered(&[gst::MessageType::Warning]).is_some());
+		assert_eq!(element.property::<Option<String>>("widevine-pssh").as_deref(), Some("AAAA"));
+
+		element.set_property("widevine-pssh", None::<String>);
+		assert!(element.property::<Option<String>>("widevine-pssh").is_none());
+	}
+
+	/// Backend whose writes all fail, counting the resources flushed anyway
+	#[derive(Default)]
+	struct FailingOutput {
+		flushed: Arc<Mutex<u32>>,
+	}
+
+	struct FailingWriter {
+		flushed: Arc<Mutex<u32>>,
+	}
+
+	impl Write for FailingWriter {
+		fn write(&mut self, _buf: &[u8]) -> Result<usize, std::io::Error> {
+			Err(std::io::Error::other("disk full"))
+		}
+
+		fn flush(&mut self) -> Result<(), std::io::Error> {
+			*self.flushed.lock().unwrap() += 1;
+			Ok(())
+		}
+	}
+
+	impl OutputBackend for FailingOutput {
+		fn create(
+			&self,
+			_kind: ResourceKind,
+			_location: &str,
+			_path: &Path,
+		) -> Result<Box<dyn Write + Send>, std::io::Error> {
+			Ok(Box::new(FailingWriter { flushed: self.flushed.clone() }))
+		}
+	}
+
+	#[test]
+	fn failed_segments_discarded() {
+		init();
+		for cleanup_on_error in [true, false] {
+			let element = glib::Object::new::<super::super::DashCmafSink>();
+			element.set_property("cleanup-on-error", cleanup_on_error);
+			let imp = element.imp();
+			let output = Arc::new(FailingOutput::default());
+			imp.state.lock().unwrap().output = Some(output.clone());
+			imp.streams.lock().unwrap().insert("sink_0".to_string(), DashCmafSinkStream::new(None).unwrap());
+
+			let mut buffers = gst::BufferList::new();
+			buffers.get_mut().unwrap().add(gst::Buffer::from_slice([0u8; 16]));
+			let res = imp.write_media_segment("sink_0", Some(gst::ClockTime::ZERO), None, &buffers);
+			assert!(res.is_err());
+
+			let streams = imp.streams.lock().unwrap();
+			assert!(streams["sink_0"].segments.is_empty());
+			// The number of a discarded segment is reused
+			let next_number = if cleanup_on_error { DEFAULT_START_NUMBER } else { DEFAULT_START_NUMBER + 1 };
+			assert_eq!(streams["sink_0"].segment_idx, next_number);
+			assert_eq!(*output.flushed.lock().unwrap(), if cleanup_on_error { 0 } else { 1 });
+		}
+	}
+
+	#[test]
+	fn ingest_request_headers() {
+		init();
+		let element = glib::Object::new::<super::super::DashCmafSink>();
+		let pipeline = gst::Pipeline::new();
+		pipeline.add(&element).unwrap();
+		let bus = pipeline.bus().unwrap();
+
+		element.set_property("user-agent", "packager/1.0");
+		element.set_property("auth-token", "secret");
+		let headers = gst::Structure::builder("headers").field("X-Route", "edge-1").build();
+		element.set_property("http-headers", &headers);
+		assert_eq!(
+			element.imp().settings.lock().unwrap().http_headers(),
+			[
+				("User-Agent".to_string(), "packager/1.0".to_string()),
+				("Authorization".to_string(), "Bearer secret".to_string()),
+				("X-Route".to_string(), "edge-1".to_string()),
+			]
+		);
+
+		// Values that would break the request out of its header are rejected
+		let injected = gst::Structure::builder("headers").field("X-Route", "edge\r\nHost: evil").build();
+		element.set_property("http-headers", &injected);
+		assert!(bus.pop_filtered(&[gst::MessageType::Warning]).is_some());
+		assert_eq!(element.property::<Option<gst::Structure>>("http-headers"), Some(headers));
+	}
+
+	#[test]
+	fn unparsed_h264_rejected() {
+		init();
+		let element = glib::Object::new::<super::super::DashCmafSink>();
+		let pipeline = gst::Pipeline::new();
+		pipeline.add(&element).unwrap();
+		let bus = pipeline.bus().unwrap();
+		let imp = element.imp();
+		let avc = gst::Caps::builder("video/x-h264")
+			.field("stream-format", gst::List::new(["avc", "avc3"]))
+			.field("alignment", "au")
+			.build();
+
+		let byte_stream = gst::Caps::builder("video/x-h264")
+			.field("stream-format", "byte-stream")
+			.field("alignment", "au")
+			.build();
+		assert!(!imp.check_caps("sink_0", &byte_stream, &avc));
+		let msg = bus.pop_filtered(&[gst::MessageType::Error]).unwrap();
+		let gst::MessageView::Error(err) = msg.view() else {
+			unreachable!();
+		};
+		assert!(err.error().to_string().contains("access unit alignment"), "{}", err.error());
+		let debug = err.debug().unwrap();
+		assert!(debug.contains("byte-stream") && debug.contains("h264parse"), "{debug}");
+
+		let nal = gst::Caps::builder("video/x-h264")
+			.field("stream-format", "avc")
+			.field("alignment", "nal")
+			.build();
+		assert!(!imp.check_caps("sink_0", &nal, &avc));
+		assert!(bus.pop_filtered(&[gst::MessageType::Error]).is_some());
+
+		let parsed = gst::Caps::builder("video/x-h264")
+			.field("stream-format", "avc")
+			.field("alignment", "au")
+			.build();
+		assert!(imp.check_caps("sink_0", &parsed, &avc));
+	}
+
+	#[test]
+	fn events_in_event_streams() {
+		init();
+		let element = glib::Object::new::<super::super::DashCmafSink>();
+		let push = |scheme: &str, value: &str, time: u64, duration: u64, data: &str| {
+			element.emit_by_name::<bool>("push-event", &[&scheme, &value, &time, &duration, &data])
+		};
+		assert!(push("urn:scte:scte35:2013:xml", "1", 4_000_000_000, 30_000_000_000, "splice"));
+		assert!(push("urn:scte:scte35:2013:xml", "1", 10_000_000_000, u64::MAX, "return"));
+		assert!(push("urn:example:chapters", "", 2_500_000_000, u64::MAX, "chapter"));
+		assert!(!push("", "", 0, u64::MAX, ""));
+
+		let time = chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+		let events = element.imp().state.lock().unwrap().events.clone();
+		let settings = DashCmafSinkSettings::default();
+		let mpd = build_mpd(&[stream_info("sink_0", video_caps(), 3)], &settings, time, None, &events, None, time).unwrap();
+
+		let event_streams = &mpd.periods[0].event_streams;
+		assert_eq!(event_streams.len(), 2);
+		assert_eq!(event_streams[0].schemeIdUri, "urn:scte:scte35:2013:xml");
+		assert_eq!(event_streams[0].timescale, Some(1000));
+		let events = event_streams[0]
+			.event
+			.iter()
+			.map(|event| (event.presentationTime, event.duration, event.content.as_deref()))
+			.collect::<Vec<_>>();
+		assert_eq!(events, [(Some(4000), Some(30000), Some("splice")), (Some(10000), None, Some("return"))]);
+		assert_eq!(event_streams[1].event[0].presentationTime, Some(2500));
+
+		// The serialized manifest keeps the timing
+		let xml = manifest_xml(&mpd, 0, "UTF-8");
+		let parsed = dash_mpd::parse(&xml).unwrap();
+		assert_eq!(parsed.periods[0].event_streams[0].event[1].presentationTime, Some(10000));
+	}
+
+	#[test]
+	fn scte35_splice_insert_event() {
+		init();
+		let element = glib::Object::new::<super::super::DashCmafSink>();
+		let splice_insert = [
+			0x00, 0x00, 0x00, 0x01, 0x7f, 0xef, 0xfe, 0x00, 0x00, 0x00, 0x00, 0xfe, 0x00, 0x29, 0x32, 0xe0, 0x00, 0x01, 0x00, 0x00,
+		];
+		let section = splice_info_section(0x05, &splice_insert);
+		let s = gst::Structure::builder("scte-35")
+			.field("section", gst::Buffer::from_slice(section.clone()))
+			.field("running-time", gst::ClockTime::from_seconds(4))
+			.build();
+		element.imp().on_scte35("sink_0", &s);
+
+		let time = chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+		let events = element.imp().state.lock().unwrap().events.clone();
+		let mpd = build_mpd(&[stream_info("sink_0", video_caps(), 3)], &DashCmafSinkSettings::default(), time, None, &events, None, time)
+			.unwrap();
+
+		let event_stream = &mpd.periods[0].event_streams[0];
+		assert_eq!(event_stream.schemeIdUri, SCTE35_SCHEME);
+		let event = &event_stream.event[0];
+		assert_eq!((event.presentationTime, event.duration), (Some(4000), Some(30000)));
+		let binary = event.signal[0].content.as_ref().unwrap();
+		assert_eq!(binary.content, glib::base64_encode(&section).as_str());
+	}
+
+	#[test]
+	fn bandwidth_over_real_durations() {
+		let window = |segments: &[(u64, u64)]| {
+			segments
+				.iter()
+				.map(|(size, duration)| (*size, gst::ClockTime::from_mseconds(*duration)))
+				.collect::<VecDeque<_>>()
+		};
+		let target = gst::ClockTime::from_seconds(2);
+
+		assert_eq!(windowed_bandwidth(&window(&[]), target), 0);
+		// 250 kB over 2 s and 1 s
+		assert_eq!(windowed_bandwidth(&window(&[(250_000, 2000)]), target), 1_000_000);
+		assert_eq!(windowed_bandwidth(&window(&[(250_000, 2000), (125_000, 1000)]), target), 1_000_000);
+		assert_eq!(windowed_bandwidth(&window(&[(500_000, 2000), (0, 2000)]), target), 1_000_000);
+		// Unknown durations count as the target duration
+		assert_eq!(windowed_bandwidth(&window(&[(250_000, 0)]), target), 1_000_000);
+	}
+
+	#[test]
+	fn missing_muxer_is_an_error() {
+		init();
+		assert!(DashCmafSinkStream::new(Some("dashcmafsink-missing-mux")).is_err());
+		assert!(DashCmafSinkStream::new(None).is_ok());
+	}
+
+	#[test]
+	fn latency_query() {
+		init();
+		let element = glib::Object::new::<super::super::DashCmafSink>();
+		element.set_property("latency", 300_000_000u64);
+		element.set_property("target-duration", 2u32);
+
+		let mut query = gst::query::Latency::new();
+		assert!(element.query(&mut query));
+		let (live, min, max) = query.result();
+		assert!(!live);
+		assert_eq!(min, gst::ClockTime::from_mseconds(300));
+		assert_eq!(max, None);
+
+		// A pad without upstream still waits for whole segments
+		if gst::ElementFactory::find("cmafmux").is_none() {
+			return;
+		}
+		let pad = element.request_pad_simple("sink_%u").unwrap();
+		pad.set_property("fragment-duration", 4_000_000_000u64);
+		let mut query = gst::query::Latency::new();
+		assert!(element.query(&mut query));
+		assert_eq!(query.result().1, gst::ClockTime::from_mseconds(4300));
+	}
+
+	#[test]
+	fn aac_fragments_of_whole_frames() {
+		// 2 s are 93.75 frames at 48 kHz, 86.13 at 44.1 kHz
+		assert_eq!(whole_aac_frames(gst::ClockTime::from_seconds(2), 48_000), gst::ClockTime::from_nseconds(2_005_333_334));
+		assert_eq!(whole_aac_frames(gst::ClockTime::from_seconds(2), 44_100), gst::ClockTime::from_nseconds(1_996_916_100));
+		// Exact in ticks of the sample rate
+		let duration = whole_aac_frames(gst::ClockTime::from_seconds(2), 48_000);
+		assert_eq!(timescale_ticks(duration, 48_000, DashCmafSinkTimescaleRounding::Down), 94 * 1024);
+		// At least one frame
+		assert_eq!(whole_aac_frames(gst::ClockTime::ZERO, 48_000), gst::ClockTime::from_nseconds(21_333_334));
+	}
+
+	/// Element with a stream of `count` segments of 2 s written
+	fn element_with_segments(count: u64) -> super::super::DashCmafSink {
+		let element = glib::Object::new::<super::super::DashCmafSink>();
+		let mut stream = DashCmafSinkStream::new(None).unwrap();
+		stream.segments = (0..count).map(|idx| segment(DEFAULT_START_NUMBER + idx, 2 * idx, 2)).collect();
+		stream.segment_idx = DEFAULT_START_NUMBER + count;
+		stream.end_time = Some(gst::ClockTime::from_seconds(2 * count));
+		element.imp().streams.lock().unwrap().insert("sink_0".to_string(), stream);
+		element
+	}
+
+	#[test]
+	fn kept_periods_keep_numbering() {
+		init();
+		let element = element_with_segments(3);
+		let imp = element.imp();
+
+		assert!(element.emit_by_name::<bool>("new-period", &[&"p1", &u64::MAX]));
+		{
+			let streams = imp.streams.lock().unwrap();
+			let stream = &streams["sink_0"];
+			assert!(stream.segments.is_empty());
+			assert_eq!(stream.start_number, DEFAULT_START_NUMBER + 3);
+			assert_eq!(stream.segment_idx, DEFAULT_START_NUMBER + 3);
+		}
+
+		let state = imp.state.lock().unwrap();
+		let period = state.period.as_ref().unwrap();
+		assert_eq!((period.index, period.id.as_deref()), (1, Some("p1")));
+		assert_eq!(period.start, gst::ClockTime::from_seconds(6));
+	}
+
+	#[test]
+	fn archived_reset_keeps_numbering() {
+		init();
+		let element = element_with_segments(2);
+		let imp = element.imp();
+		imp.settings.lock().unwrap().per_period_manifest = true;
+
+		assert!(imp.reset(None));
+		assert_eq!(imp.streams.lock().unwrap()["sink_0"].start_number, DEFAULT_START_NUMBER + 2);
+	}
+
+	#[test]
+	fn reset_restarts_numbering() {
+		init();
+		let element = element_with_segments(2);
+		let imp = element.imp();
+
+		assert!(imp.reset(None));
+		let streams = imp.streams.lock().unwrap();
+		assert_eq!(streams["sink_0"].start_number, DEFAULT_START_NUMBER);
+		assert_eq!(streams["sink_0"].segment_idx, DEFAULT_START_NUMBER);
+	}
+
+	#[test]
+	fn unknown_namespace_rejected() {
+		init();
+		let element = glib::Object::new::<super::super::DashCmafSink>();
+		let pipeline = gst::Pipeline::new();
+		pipeline.add(&element).unwrap();
+		let bus = pipeline.bus().unwrap();
+
+		let valid = gst::Structure::builder("namespaces").field("cenc", "urn:mpeg:cenc:2013").build();
+		element.set_property("xml-namespaces", &valid);
+		element.set_property("xml-namespaces", gst::Structure::builder("namespaces").field("drm", "urn:example").build());
+		assert!(bus.pop_filtered(&[gst::MessageType::Warning]).is_some());
+		assert_eq!(element.property::<Option<gst::Structure>>("xml-namespaces"), Some(valid));
+	}
+
+	#[test]
+	fn init_segment_detection() {
+		init();
+		let buffer = |data: &'static [u8], flags: gst::BufferFlags| {
+			let mut buffer = gst::Buffer::from_slice(data);
+			buffer.get_mut().unwrap().set_flags(flags);
+			buffer
+		};
+		let ftyp = b"\0\0\0\x10ftypcmf2\0\0\0\0";
+		let moof = b"\0\0\0\x08moof";
+
+		assert!(is_init_segment(&buffer(ftyp, gst::BufferFlags::HEADER | gst::BufferFlags::DISCONT)));
+		assert!(is_init_segment(&buffer(ftyp, gst::BufferFlags::HEADER)));
+		assert!(!is_init_segment(&buffer(moof, gst::BufferFlags::HEADER)));
+		assert!(!is_init_segment(&buffer(ftyp, gst::BufferFlags::DISCONT)));
+	}
+
+	#[test]
+	fn checksum_manifest_by_pad() {
+		init();
+		let stream = |segments: Vec<DashCmafSinkSegment>| {
+			let mut stream = DashCmafSinkStream::new(None).unwrap();
+			stream.segments = segments;
+			stream
+		};
+		let checked = |number: u64, checksum: &str| DashCmafSinkSegment {
+			checksum: Some(checksum.to_string()),
+			..segment(number, 2 * number, 2)
+		};
+
+		let mut streams = HashMap::new();
+		streams.insert("sink_1".to_string(), stream(vec![checked(0, "ab"), segment(1, 2, 2)]));
+		streams.insert("sink_0".to_string(), stream(vec![checked(0, "cd"), checked(1, "ef")]));
+
+		assert_eq!(
+			checksum_manifest(&streams),
+			concat!(
+				"{\n",
+				"  \"sink_0\": [\n",
+				"    { \"number\": 0, \"location\": \"segment_0.cmfv\", \"sha256\": \"cd\" },\n",
+				"    { \"number\": 1, \"location\": \"segment_1.cmfv\", \"sha256\": \"ef\" }\n",
+				"  ],\n",
+				"  \"sink_1\": [\n",
+				"    { \"number\": 0, \"location\": \"segment_0.cmfv\", \"sha256\": \"ab\" }\n",
+				"  ]\n",
+				"}\n",
+			)
+		);
+	}
+
+	#[test]
+	fn broken_pipe_ends_the_stream() {
+		init();
+		let element = glib::Object::new::<super::super::DashCmafSink>();
+		let imp = element.imp();
+
+		let broken_pipe = std::io::Error::from(std::io::ErrorKind::BrokenPipe);
+		assert_eq!(imp.write_error(&broken_pipe), gst::FlowError::Eos);
+		let denied = std::io::Error::from(std::io::ErrorKind::PermissionDenied);
+		assert_eq!(imp.write_error(&denied), gst::FlowError::Error);
+	}
+
+	#[test]
+	fn first_sample_after_header() {
+		let mut list = gst::BufferList::new();
+		{
+			let list = list.get_mut().unwrap();
+			let mut header = gst::Buffer::from_slice(b"moof");
+			header.get_mut().unwrap().set_flags(gst::BufferFlags::HEADER);
+			list.add(header);
+			let mut sample = gst::Buffer::from_slice(b"mdat");
+			sample.get_mut().unwrap().set_pts(gst::ClockTime::from_mseconds(80));
+			list.add(sample);
+		}
+		assert_eq!(first_sample(&list).and_then(|sample| sample.pts()), Some(gst::ClockTime::from_mseconds(80)));
+
+		let headers_only = gst::BufferList::from_iter([list.get(0).unwrap().to_owned()]);
+		assert!(first_sample(&headers_only).is_none());
+	}
+
+}
@@ -0,0 +1,37 @@
+// Copyright (C) 2025 Roberto Viola <rviola@vicomtech.org>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+/// JSON string literal of `s`.
+pub(crate) fn json_string(s: &str) -> String {
+	let mut json = String::with_capacity(s.len() + 2);
+	json.push('"');
+	for c in s.chars() {
+		match c {
+			'"' => json.push_str("\\\""),
+			'\\' => json.push_str("\\\\"),
+			c if c < ' ' => json.push_str(&format!("\\u{:04x}", c as u32)),
+			c => json.push(c),
+		}
+	}
+	json.push('"');
+
+	json
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn json_strings() {
+		assert_eq!(json_string("sink_0/segment_1.cmfv"), r#""sink_0/segment_1.cmfv""#);
+		assert_eq!(json_string(r#"a "b" \c"#), r#""a \"b\" \\c""#);
+		assert_eq!(json_string("tab\there\n"), r#""tab\u0009here\u000a""#);
+		assert_eq!(json_string("vídeo"), "\"vídeo\"");
+	}
+}
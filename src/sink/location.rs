@@ -0,0 +1,218 @@
+// Copyright (C) 2025 Roberto Viola <rviola@vicomtech.org>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::path::Path;
+use std::sync::LazyLock;
+
+// Replaced by the pad name in init-location, prefixed to it otherwise
+pub(crate) const INIT_PAD_PLACEHOLDERS: [&str; 3] = ["$RepresentationID$", "{pad}", "%s"];
+// The same for segment and thumbnail locations, where %s isn't printf safe
+pub(crate) const PAD_PLACEHOLDERS: [&str; 2] = ["$RepresentationID$", "{pad}"];
+
+/// Location of the standalone manifest of a Period, the main location with
+/// the Period id appended to its file stem.
+pub(crate) fn period_manifest_location(location: &str, period_id: &str) -> String {
+	match location.rsplit_once('.').filter(|(_, extension)| !extension.contains('/')) {
+		Some((stem, extension)) => format!("{stem}_{period_id}.{extension}"),
+		None => format!("{location}_{period_id}"),
+	}
+}
+
+/// Location of the checksums of the segments of a manifest.
+pub(crate) fn checksum_manifest_location(location: &str) -> String {
+	match location.rsplit_once('.').filter(|(_, extension)| !extension.contains('/')) {
+		Some((stem, _)) => format!("{stem}.checksums.json"),
+		None => format!("{location}.checksums.json"),
+	}
+}
+
+/// Path of `path` relative to the directory `dir` as a URL, both being
+/// either absolute or relative to the same directory.
+pub(crate) fn relative_url(dir: &Path, path: &Path) -> Option<String> {
+	use std::path::Component;
+
+	if dir.is_absolute() != path.is_absolute() {
+		return None;
+	}
+
+	let dir = dir.components().filter(|c| *c != Component::CurDir).collect::<Vec<_>>();
+	let path = path.components().filter(|c| *c != Component::CurDir).collect::<Vec<_>>();
+	let common = dir.iter().zip(&path).take_while(|(a, b)| a == b).count();
+	if dir[common..].contains(&Component::ParentDir) {
+		return None;
+	}
+
+	let parents = std::iter::repeat_n("..".to_string(), dir.len() - common);
+	let rest = path[common..].iter().map(|c| c.as_os_str().to_string_lossy().into_owned());
+
+	Some(parents.chain(rest).collect::<Vec<_>>().join("/"))
+}
+
+/// Checks that a segment location template formats exactly one number.
+pub(crate) fn check_location_template(template: &str) -> Result<(), sprintf::PrintfError> {
+	sprintf::sprintf!(&printf_number(&expand_time_tokens(template, chrono::Utc::now())), 0u64).map(|_| ())
+}
+
+/// Location of a pad, its name replacing `$RepresentationID$` or `{pad}`,
+/// or prefixed to the location without them.
+pub(crate) fn pad_location(location: &str, pad_name: &str) -> String {
+	if !PAD_PLACEHOLDERS.iter().any(|placeholder| location.contains(placeholder)) {
+		return format!("{pad_name}_{location}");
+	}
+
+	PAD_PLACEHOLDERS
+		.iter()
+		.fold(location.to_string(), |location, placeholder| location.replace(placeholder, pad_name))
+}
+
+/// Rewrites the DASH `$Number$` and `$Time$` identifiers of a location,
+/// with their `%0Nd` width if any, as the printf `%d` the locations use.
+pub(crate) fn printf_number(location: &str) -> String {
+	static IDENTIFIER: LazyLock<regex::Regex> =
+		LazyLock::new(|| regex::Regex::new(r"\$(?:Number|Time)(%0\d+d)?\$").unwrap());
+
+	IDENTIFIER
+		.replace_all(location, |caps: &regex::Captures| {
+			caps.get(1).map_or("%d", |width| width.as_str()).to_string()
+		})
+		.into_owned()
+}
+
+/// Replaces every `{...}` holding a strftime format with `time` formatted
+/// with it, invalid formats being left untouched.
+pub(crate) fn expand_time_tokens(location: &str, time: chrono::DateTime<chrono::Utc>) -> String {
+	use chrono::format::{Item, StrftimeItems};
+
+	static TOKEN: LazyLock<regex::Regex> =
+		LazyLock::new(|| regex::Regex::new(r"\{([^{}]*%[^{}]*)\}").unwrap());
+
+	TOKEN
+		.replace_all(location, |caps: &regex::Captures| {
+			let format = &caps[1];
+			if StrftimeItems::new(format).any(|item| matches!(item, Item::Error)) {
+				caps[0].to_string()
+			} else {
+				time.format(format).to_string()
+			}
+		})
+		.into_owned()
+}
+
+/// DASH template identifier for the printf style number of a location,
+/// `%0Nd` keeping its zero padding as `$Identifier%0Nd$`.
+pub(crate) fn location_template(location: &str, identifier: &str) -> String {
+	static NUMBER: LazyLock<regex::Regex> =
+		LazyLock::new(|| regex::Regex::new(r"%(0\d+)?d").unwrap());
+
+	NUMBER
+		.replace(location, |caps: &regex::Captures| match caps.get(1) {
+			Some(width) => format!("${identifier}%{}d$", width.as_str()),
+			None => format!("${identifier}$"),
+		})
+		.into_owned()
+}
+
+/// Anchored pattern of the paths a location template expands to, relative
+/// to the output directory, `pad_prefix` for the `{pad}_` the sink adds.
+pub(crate) fn location_pattern(template: &str, pad_prefix: bool) -> Option<regex::Regex> {
+	static TOKENS: LazyLock<regex::Regex> = LazyLock::new(|| {
+		regex::Regex::new(r"\{[^{}]*%[^{}]*\}|%(0\d+)?d|%s|\$RepresentationID\$|\{pad\}").unwrap()
+	});
+
+	let mut pattern = String::from(if pad_prefix { "^[^/]+_" } else { "^" });
+	let mut last = 0;
+	for token in TOKENS.find_iter(template) {
+		pattern.push_str(&regex::escape(&template[last..token.start()]));
+		pattern.push_str(match token.as_str() {
+			token if token.starts_with('{') && token != "{pad}" => ".+?",
+			token if token.ends_with('d') => "\\d+",
+			_ => "[^/]+",
+		});
+		last = token.end();
+	}
+	pattern.push_str(&regex::escape(&template[last..]));
+	pattern.push('$');
+
+	regex::Regex::new(&pattern).ok()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn location_templates_format_one_number() {
+		assert!(check_location_template("segment_%d.cmfv").is_ok());
+		assert!(check_location_template("segment_%05d.cmfv").is_ok());
+		assert!(check_location_template("segment.cmfv").is_err());
+		assert!(check_location_template("segment_%d_%d.cmfv").is_err());
+		assert!(check_location_template("segment_%s.cmfv").is_err());
+	}
+
+	#[test]
+	fn location_templates_keep_padding() {
+		assert_eq!(location_template("segment_%d.cmfv", "Number"), "segment_$Number$.cmfv");
+		assert_eq!(location_template("segment_%05d.cmfv", "Number"), "segment_$Number%05d$.cmfv");
+		assert_eq!(location_template("sink_0/%010d.cmfv", "Time"), "sink_0/$Time%010d$.cmfv");
+		assert_eq!(location_template("segment.cmfv", "Number"), "segment.cmfv");
+	}
+
+	#[test]
+	fn time_tokens_expanded() {
+		let time = chrono::DateTime::parse_from_rfc3339("2024-05-01T10:30:00Z").unwrap().to_utc();
+		assert_eq!(expand_time_tokens("{%Y/%m/%d}/manifest.mpd", time), "2024/05/01/manifest.mpd");
+		assert_eq!(expand_time_tokens("segment_{%H%M}_%d.cmfv", time), "segment_1030_%d.cmfv");
+		// Braces without a conversion, or with an invalid one, are kept
+		assert_eq!(expand_time_tokens("{pad}_init.cmfi", time), "{pad}_init.cmfi");
+		assert_eq!(expand_time_tokens("{%Q}.mpd", time), "{%Q}.mpd");
+	}
+
+	#[test]
+	fn location_patterns() {
+		let pattern = location_pattern("segment_%05d.cmfv", true).unwrap();
+		assert!(pattern.is_match("sink_0_segment_00042.cmfv"));
+		assert!(!pattern.is_match("segment_00042.cmfv"));
+		assert!(!pattern.is_match("sink_0_segment_00042.cmfv.tmp"));
+		assert!(!pattern.is_match("dir/sink_0_segment_1.cmfv"));
+
+		let pattern = location_pattern("{%Y-%m-%d}/$RepresentationID$/%d.m4s", false).unwrap();
+		assert!(pattern.is_match("2025-06-01/sink_1/7.m4s"));
+		assert!(!pattern.is_match("2025-06-01/sink_1/seven.m4s"));
+
+		// Regex characters of the template are literal
+		let pattern = location_pattern("manifest.mpd", false).unwrap();
+		assert!(pattern.is_match("manifest.mpd"));
+		assert!(!pattern.is_match("manifestsmpd"));
+	}
+
+	#[test]
+	fn pad_locations() {
+		assert_eq!(pad_location("segment_%d.cmfv", "sink_0"), "sink_0_segment_%d.cmfv");
+		assert_eq!(pad_location("$RepresentationID$/%d.m4s", "sink_1"), "sink_1/%d.m4s");
+		assert_eq!(pad_location("{pad}/{pad}_%d.m4s", "sink_1"), "sink_1/sink_1_%d.m4s");
+
+		assert_eq!(printf_number("seg_$Number$.cmfv"), "seg_%d.cmfv");
+		assert_eq!(printf_number("seg_$Number%05d$.cmfv"), "seg_%05d.cmfv");
+		assert_eq!(printf_number("seg_$Time$.cmfv"), "seg_%d.cmfv");
+		assert_eq!(printf_number("seg_%d.cmfv"), "seg_%d.cmfv");
+	}
+
+	#[test]
+	fn relative_urls() {
+		let url = |dir: &str, path: &str| relative_url(Path::new(dir), Path::new(path));
+
+		assert_eq!(url("", "segment_0.cmfv").as_deref(), Some("segment_0.cmfv"));
+		assert_eq!(url("out", "out/video/segment_0.cmfv").as_deref(), Some("video/segment_0.cmfv"));
+		assert_eq!(url("out/manifests", "out/media/init.cmfi").as_deref(), Some("../media/init.cmfi"));
+		assert_eq!(url("./out", "out/init.cmfi").as_deref(), Some("init.cmfi"));
+		assert_eq!(url("/srv/dash", "/srv/dash/init.cmfi").as_deref(), Some("init.cmfi"));
+		// No relative URL from below a parent or between relative and absolute paths
+		assert_eq!(url("../out", "init.cmfi"), None);
+		assert_eq!(url("/srv/dash", "init.cmfi"), None);
+	}
+}
@@ -1,20 +1,143 @@
-// Copyright (C) 2025 Roberto Viola <rviola@vicomtech.org>
-//
-// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
-// If a copy of the MPL was not distributed with this file, You can obtain one at
-// <https://mozilla.org/MPL/2.0/>.
-//
-// SPDX-License-Identifier: MPL-2.0
-
-use gst::glib;
-use gst::prelude::*;
-
-mod imp;
-
-glib::wrapper! {
-    pub struct DashCmafSink(ObjectSubclass<imp::DashCmafSink>) @extends gst::Bin, gst::Element, gst::Object;
-}
-
-pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
-	gst::Element::register(Some(plugin), "dashcmafsink", gst::Rank::NONE, DashCmafSink::static_type())
+// Copyright (C) 2025 Roberto Viola <rviola@vicomtech.org>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use gst::glib;
+use gst::prelude::*;
+
+mod bitstream;
+mod imp;
+mod json;
+mod location;
+mod mpd;
+mod output;
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstDashCmafSinkBackend")]
+pub enum DashCmafSinkBackend {
+    #[enum_value(name = "File: Write resources to disk", nick = "file")]
+    File = 0,
+    #[enum_value(name = "Memory: Keep resources in memory for the pull-resource signal", nick = "memory")]
+    Memory = 1,
+    #[enum_value(name = "Ingest: Push resources to a DASH-IF live media ingest endpoint", nick = "ingest")]
+    Ingest = 2,
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstDashCmafSinkPlaylistType")]
+pub enum DashCmafSinkPlaylistType {
+    #[enum_value(name = "Static: On-demand manifest", nick = "static")]
+    Static = 0,
+    #[enum_value(name = "Dynamic: Live manifest updated with every segment", nick = "dynamic")]
+    Dynamic = 1,
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstDashCmafSinkAddressing")]
+pub enum DashCmafSinkAddressing {
+    #[enum_value(name = "Number: SegmentTemplate with $Number$ and a fixed duration", nick = "number")]
+    Number = 0,
+    #[enum_value(name = "Time: SegmentTemplate with $Time$ and a SegmentTimeline", nick = "time")]
+    Time = 1,
+    #[enum_value(name = "List: SegmentList with a SegmentURL per media segment", nick = "segment-list")]
+    List = 2,
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstDashCmafSinkUrlMode")]
+pub enum DashCmafSinkUrlMode {
+    #[enum_value(name = "Relative: URLs relative to the directory of the manifest", nick = "relative")]
+    Relative = 0,
+    #[enum_value(name = "Absolute: URLs prefixed with base-url", nick = "absolute")]
+    Absolute = 1,
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstDashCmafSinkContainer")]
+pub enum DashCmafSinkContainer {
+    #[enum_value(name = "CMAF: Fragmented MP4 segments", nick = "cmaf")]
+    Cmaf = 0,
+    #[enum_value(name = "WebM: WebM clusters as segments", nick = "webm")]
+    Webm = 1,
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstDashCmafSinkOutputPolicy")]
+pub enum DashCmafSinkOutputPolicy {
+    #[enum_value(name = "Overwrite: Write over the resources of a previous run", nick = "overwrite")]
+    Overwrite = 0,
+    #[enum_value(name = "Fail if exists: Error out when resources of a previous run are found", nick = "fail-if-exists")]
+    FailIfExists = 1,
+    #[enum_value(name = "Clean: Remove the resources of a previous run before starting", nick = "clean")]
+    Clean = 2,
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstDashCmafSinkTimescaleRounding")]
+pub enum DashCmafSinkTimescaleRounding {
+    #[enum_value(name = "Nearest: Round segment boundaries to the nearest tick", nick = "nearest")]
+    Nearest = 0,
+    #[enum_value(name = "Down: Round segment boundaries down", nick = "down")]
+    Down = 1,
+    #[enum_value(name = "Up: Round segment boundaries up", nick = "up")]
+    Up = 2,
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstDashCmafSinkDescriptorPlacement")]
+pub enum DashCmafSinkDescriptorPlacement {
+    #[enum_value(name = "Representation: codecs, width, height and frameRate on the Representations", nick = "representation")]
+    Representation = 0,
+    #[enum_value(name = "Adaptation: Values shared by all Representations on the AdaptationSet", nick = "adaptation")]
+    Adaptation = 1,
+    #[enum_value(name = "Both: Shared values on the AdaptationSet and every value on the Representations", nick = "both")]
+    Both = 2,
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstDashCmafSinkManifestWriteMode")]
+pub enum DashCmafSinkManifestWriteMode {
+    #[enum_value(name = "Every segment: Write the manifest after every media segment", nick = "every-segment")]
+    EverySegment = 0,
+    #[enum_value(name = "On EOS: Write the manifest once all streams reached EOS", nick = "on-eos")]
+    OnEos = 1,
+    #[enum_value(name = "Interval: Write the manifest every manifest-interval media segments of a stream", nick = "interval")]
+    Interval = 2,
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstDashCmafSinkManifestEncoding")]
+pub enum DashCmafSinkManifestEncoding {
+    #[enum_value(name = "UTF-8", nick = "utf-8")]
+    Utf8 = 0,
+    #[enum_value(name = "UTF-16LE: Little-endian UTF-16", nick = "utf-16le")]
+    Utf16Le = 1,
+    #[enum_value(name = "UTF-16BE: Big-endian UTF-16", nick = "utf-16be")]
+    Utf16Be = 2,
+}
+
+glib::wrapper! {
+    pub struct DashCmafSink(ObjectSubclass<imp::DashCmafSink>) @extends gst::Bin, gst::Element, gst::Object;
+}
+
+glib::wrapper! {
+    pub struct DashCmafSinkPad(ObjectSubclass<imp::DashCmafSinkPad>) @extends gst::GhostPad, gst::ProxyPad, gst::Pad, gst::Object;
+}
+
+pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+	gst::Element::register(Some(plugin), "dashcmafsink", gst::Rank::NONE, DashCmafSink::static_type())
 }
\ No newline at end of file
@@ -0,0 +1,1984 @@
+// Copyright (C) 2025 Roberto Viola <rviola@vicomtech.org>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use gst::prelude::*;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+
+use super::bitstream::{aac_config, h264_dimensions};
+use super::imp::{
+	DashCmafSinkEvent, DashCmafSinkPeriod, DashCmafSinkSegment, DashCmafSinkSettings, CAT, DEFAULT_START_NUMBER,
+};
+use super::location::location_template;
+use super::{
+	DashCmafSinkAddressing, DashCmafSinkContainer, DashCmafSinkDescriptorPlacement, DashCmafSinkManifestEncoding,
+	DashCmafSinkPlaylistType, DashCmafSinkTimescaleRounding,
+};
+
+const CMAF_DASH_PROFILE: &str = "urn:mpeg:dash:profile:cmaf:2019";
+// Discontinuities up to this are rounding of the millisecond timescale
+pub(crate) const TIMELINE_GAP_TOLERANCE: gst::ClockTime = gst::ClockTime::from_mseconds(1);
+const THUMBNAIL_TILE_SCHEME: &str = "http://dashif.org/thumbnail_tile";
+const DOLBY_CHANNEL_CONFIGURATION_SCHEME: &str =
+    "tag:dolby.com,2014:dash:audio_channel_configuration:2011";
+const ROLE_SCHEME: &str = "urn:mpeg:dash:role:2011";
+const ADAPTATION_SET_SWITCHING_SCHEME: &str = "urn:mpeg:dash:adaptation-set-switching:2016";
+const TRICK_MODE_SCHEME: &str = "http://dashif.org/guidelines/trickmode";
+const MP4_PROTECTION_SCHEME: &str = "urn:mpeg:dash:mp4protection:2011";
+const WIDEVINE_SYSTEM_ID: &str = "edef8ba9-79d6-4ace-a3c8-27dcd51d21ed";
+const PLAYREADY_SYSTEM_ID: &str = "9a04f079-9840-4286-ab92-e65be0885f95";
+
+impl DashCmafSinkSettings {
+	/// ContentProtection descriptors of the audio and video AdaptationSets,
+	/// the common encryption one followed by one per DRM system with
+	/// initialization data
+	fn content_protection(&self) -> Vec<dash_mpd::ContentProtection> {
+		let mut descriptors = Vec::new();
+
+		if let Some(ref pssh) = self.widevine_pssh {
+			descriptors.push(dash_mpd::ContentProtection {
+				schemeIdUri: format!("urn:uuid:{WIDEVINE_SYSTEM_ID}"),
+				value: Some("Widevine".to_string()),
+				cenc_pssh: vec![dash_mpd::CencPssh { content: Some(pssh.clone()) }],
+				..Default::default()
+			});
+		}
+
+		if self.playready_pssh.is_some() || self.playready_pro.is_some() {
+			descriptors.push(dash_mpd::ContentProtection {
+				schemeIdUri: format!("urn:uuid:{PLAYREADY_SYSTEM_ID}"),
+				value: Some("MSPR 2.0".to_string()),
+				cenc_pssh: self
+					.playready_pssh
+					.iter()
+					.map(|pssh| dash_mpd::CencPssh { content: Some(pssh.clone()) })
+					.collect(),
+				msprpro: self.playready_pro.as_ref().map(|pro| dash_mpd::MsprPro {
+					content: Some(pro.clone()),
+					..Default::default()
+				}),
+				..Default::default()
+			});
+		}
+
+		if !descriptors.is_empty() {
+			descriptors.insert(
+				0,
+				dash_mpd::ContentProtection {
+					schemeIdUri: MP4_PROTECTION_SCHEME.to_string(),
+					value: Some("cenc".to_string()),
+					..Default::default()
+				},
+			);
+		}
+
+		descriptors
+	}
+
+	/// Declares the namespaces of xml-namespaces on the MPD element, the
+	/// other prefixes keeping the URIs of dash_mpd
+	fn declare_namespaces(&self, mpd: &mut dash_mpd::MPD) {
+		let Some(ref namespaces) = self.xml_namespaces else {
+			return;
+		};
+
+		for (prefix, uri) in namespaces.iter() {
+			let uri = uri.get::<String>().ok();
+			match prefix.as_str() {
+				"xsi" => mpd.xsi = uri,
+				"cenc" => mpd.cenc = uri,
+				"mspr" => mpd.mspr = uri,
+				"xlink" => mpd.xlink = uri,
+				"scte35" => mpd.scte35 = uri,
+				"dvb" => mpd.dvb = uri,
+				"ext" => mpd.ext = uri,
+				"scte214" => mpd.scte214 = uri,
+				_ => (),
+			}
+		}
+	}
+
+	/// Bytes of a manifest in manifest-encoding, after a BOM with manifest-bom
+	pub(crate) fn manifest_bytes(&self, mpd: &dash_mpd::MPD) -> Vec<u8> {
+		// The byte order of UTF-16 is declared unless the BOM tells it
+		let declared = match (self.manifest_encoding, self.manifest_bom) {
+			(DashCmafSinkManifestEncoding::Utf8, _) => "UTF-8",
+			(_, true) => "UTF-16",
+			(DashCmafSinkManifestEncoding::Utf16Le, false) => "UTF-16LE",
+			(DashCmafSinkManifestEncoding::Utf16Be, false) => "UTF-16BE",
+		};
+		let xml = manifest_xml(mpd, self.manifest_indent, declared);
+		let xml = if self.manifest_bom { format!("\u{feff}{xml}") } else { xml };
+
+		match self.manifest_encoding {
+			DashCmafSinkManifestEncoding::Utf8 => xml.into_bytes(),
+			DashCmafSinkManifestEncoding::Utf16Le => xml.encode_utf16().flat_map(u16::to_le_bytes).collect(),
+			DashCmafSinkManifestEncoding::Utf16Be => xml.encode_utf16().flat_map(u16::to_be_bytes).collect(),
+		}
+	}
+
+	/// mimeType of the AdaptationSets of a media type, with the CMAF brand
+	/// as profiles if set
+	pub(crate) fn mime_type(&self, media: &str) -> String {
+		match (self.container, &self.cmaf_brand) {
+			(DashCmafSinkContainer::Webm, _) => format!("{media}/webm"),
+			(DashCmafSinkContainer::Cmaf, Some(brand)) => format!("{media}/mp4; profiles=\"{brand}\""),
+			(DashCmafSinkContainer::Cmaf, None) => format!("{media}/mp4"),
+		}
+	}
+
+	/// ProgramInformation of the MPD, if any of its fields is set
+	fn program_information(&self) -> Vec<dash_mpd::ProgramInformation> {
+		if self.title.is_none() && self.source.is_none() && self.copyright.is_none() {
+			return Vec::new();
+		}
+
+		vec![dash_mpd::ProgramInformation {
+			Title: self.title.clone().map(|title| dash_mpd::Title { content: Some(title) }),
+			Source: self.source.clone().map(|source| dash_mpd::Source { content: Some(source) }),
+			Copyright: self
+				.copyright
+				.clone()
+				.map(|copyright| dash_mpd::Copyright { content: Some(copyright) }),
+			..Default::default()
+		}]
+	}
+}
+
+/// What the manifest describes of a stream, gathered from its pad and its
+/// written segments
+pub(crate) struct DashCmafSinkStreamInfo<'a> {
+	pub(crate) pad_name: String,
+	pub(crate) caps: gst::Caps,
+	// Caps of every track of a muxed stream
+	pub(crate) tracks: Vec<gst::Caps>,
+	pub(crate) key: AdaptationSetKey,
+	pub(crate) preselection: Option<(String, Vec<String>)>,
+	pub(crate) segment_duration: gst::ClockTime,
+	pub(crate) start_number: u64,
+	pub(crate) segments: Cow<'a, [DashCmafSinkSegment]>,
+	// Presentation time covered, including the one of a resumed manifest
+	pub(crate) duration: gst::ClockTime,
+	pub(crate) bandwidth: u64,
+	pub(crate) quality_ranking: Option<u8>,
+	pub(crate) timescale: u32,
+	// The pad was released, no segment follows the last one
+	pub(crate) ended: bool,
+	// Running time the Period starts at, the presentationTimeOffset
+	pub(crate) period_start: gst::ClockTime,
+	// Composition offset of the first frame, added to the
+	// presentationTimeOffset with reorder-delay-offset
+	pub(crate) reorder_delay: gst::ClockTime,
+	// data URI of the init segment with inline-init
+	pub(crate) init_data_uri: Option<String>,
+	// Media segment starting with the init segment and the size of the
+	// latter, with combine-init-first-segment
+	pub(crate) combined_init: Option<(String, usize)>,
+}
+
+impl DashCmafSinkStreamInfo<'_> {
+	/// `time` in the timescale of the stream
+	fn ticks(&self, time: gst::ClockTime, settings: &DashCmafSinkSettings) -> u64 {
+		timescale_ticks(time, self.timescale, settings.timescale_rounding)
+	}
+
+	/// Start of the Period in the timescale of the stream, after a reset,
+	/// plus the reorder delay of B-frames the muxer left in the media
+	fn presentation_time_offset(&self, settings: &DashCmafSinkSettings) -> Option<u64> {
+		let offset = self.period_start + self.reorder_delay;
+		(!offset.is_zero()).then(|| self.ticks(offset, settings))
+	}
+
+	/// URL of the init segment, or its data itself with inline-init
+	fn init_url(&self, settings: &DashCmafSinkSettings) -> String {
+		self.init_data_uri
+			.clone()
+			.unwrap_or_else(|| settings.url(&settings.init_location(&self.pad_name)))
+	}
+
+	/// Initialization of the media segment starting with the init segment,
+	/// with combine-init-first-segment, its range being the init segment
+	fn combined_initialization(&self, settings: &DashCmafSinkSettings) -> Option<dash_mpd::Initialization> {
+		let (location, size) = self.combined_init.as_ref().filter(|_| self.init_data_uri.is_none())?;
+		Some(dash_mpd::Initialization {
+			sourceURL: Some(settings.url(location)),
+			range: Some(format!("0-{}", size.saturating_sub(1))),
+		})
+	}
+
+	/// SegmentTemplate of the audio and video Representations, unless they
+	/// use a SegmentList
+	fn segment_template(
+		&self,
+		settings: &DashCmafSinkSettings,
+	) -> Option<dash_mpd::SegmentTemplate> {
+		// @initialization can't carry a range, unlike the element
+		let combined_initialization = self.combined_initialization(settings);
+		let initialization = combined_initialization.is_none().then(|| self.init_url(settings));
+
+		match settings.addressing {
+			DashCmafSinkAddressing::Number => {
+				let segment_location = location_template(&settings.segment_location(&self.pad_name), "Number");
+				// @duration can't signal a gap, the segments are listed in a
+				// timeline once there is one. Ended streams list them too,
+				// clients would otherwise expect more from the duration
+				let gap = self
+					.segments
+					.windows(2)
+					.any(|pair| pair[1].start > pair[0].start + pair[0].duration + TIMELINE_GAP_TOLERANCE);
+				let timeline = gap || self.ended;
+				Some(dash_mpd::SegmentTemplate {
+					timescale: Some(self.timescale as u64),
+					presentationTimeOffset: self.presentation_time_offset(settings),
+					duration: (!timeline).then(|| self.ticks(self.segment_duration, settings) as f64),
+					startNumber: Some(self.start_number),
+					initialization,
+					Initialization: combined_initialization,
+					media: Some(settings.url(&segment_location)),
+					SegmentTimeline: timeline.then(|| segment_timeline(&self.segments, |time| self.ticks(time, settings))),
+					..Default::default()
+				})
+			}
+			DashCmafSinkAddressing::Time => {
+				let segment_location = location_template(&settings.segment_location(&self.pad_name), "Time");
+				Some(dash_mpd::SegmentTemplate {
+					timescale: Some(self.timescale as u64),
+					presentationTimeOffset: self.presentation_time_offset(settings),
+					initialization,
+					Initialization: combined_initialization,
+					media: Some(settings.url(&segment_location)),
+					SegmentTimeline: Some(segment_timeline(&self.segments, |time| self.ticks(time, settings))),
+					..Default::default()
+				})
+			}
+			DashCmafSinkAddressing::List => None,
+		}
+	}
+
+	/// SegmentList enumerating every written segment, in segment-list addressing
+	fn segment_list(
+		&self,
+		settings: &DashCmafSinkSettings,
+	) -> Option<dash_mpd::SegmentList> {
+		if settings.addressing != DashCmafSinkAddressing::List {
+			return None;
+		}
+
+		Some(dash_mpd::SegmentList {
+			timescale: Some(self.timescale as u64),
+			duration: Some(self.ticks(self.segment_duration, settings)),
+			Initialization: Some(self.combined_initialization(settings).unwrap_or_else(|| dash_mpd::Initialization {
+				sourceURL: Some(self.init_url(settings)),
+				range: None,
+			})),
+			segment_urls: self
+				.segments
+				.iter()
+				.map(|segment| dash_mpd::SegmentURL {
+					media: Some(settings.url(&segment.location)),
+					..Default::default()
+				})
+				.collect(),
+			..Default::default()
+		})
+	}
+}
+
+/// Position of a Representation in the manifest left by a previous run
+pub(crate) struct DashCmafSinkResumePoint {
+	pub(crate) start_number: u64,
+	pub(crate) next_number: u64,
+	pub(crate) duration: gst::ClockTime,
+}
+
+/// Streams sharing the same key are grouped into the same AdaptationSet.
+#[derive(Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct AdaptationSetKey {
+	pub(crate) lang: Option<String>,
+	pub(crate) role: Option<String>,
+	pub(crate) accessibility: Option<String>,
+	pub(crate) label: Option<String>,
+	pub(crate) group: Option<u32>,
+	// Codec family of grouped audio, each alternative of a group being its
+	// own AdaptationSet
+	pub(crate) codec: Option<String>,
+	pub(crate) switching_group: Option<u32>,
+	pub(crate) selection_priority: Option<u32>,
+	// Pad name of the main stream of an I-frame-only trick-mode stream
+	pub(crate) trick_mode_for: Option<String>,
+}
+
+impl AdaptationSetKey {
+	fn labels(&self) -> Vec<dash_mpd::Label> {
+		self.label
+			.iter()
+			.map(|label| dash_mpd::Label {
+				content: label.clone(),
+				..Default::default()
+			})
+			.collect()
+	}
+
+	fn roles(&self) -> Vec<dash_mpd::Role> {
+		self.role
+			.iter()
+			.map(|role| dash_mpd::Role {
+				schemeIdUri: ROLE_SCHEME.to_string(),
+				value: Some(role.clone()),
+				..Default::default()
+			})
+			.collect()
+	}
+
+	fn accessibilities(&self) -> Vec<dash_mpd::Accessibility> {
+		self.accessibility
+			.iter()
+			.map(|accessibility| dash_mpd::Accessibility {
+				schemeIdUri: ROLE_SCHEME.to_string(),
+				value: Some(accessibility.clone()),
+				..Default::default()
+			})
+			.collect()
+	}
+}
+
+/// Manifest describing `streams`, None when none of them can be described
+/// yet. Only depends on its arguments, `publish_time` being the time of the
+/// update of dynamic manifests.
+pub(crate) fn build_mpd(
+	streams: &[DashCmafSinkStreamInfo],
+	settings: &DashCmafSinkSettings,
+	availability_start_time: chrono::DateTime<chrono::Utc>,
+	max_segment_duration: Option<gst::ClockTime>,
+	events: &[DashCmafSinkEvent],
+	period: Option<&DashCmafSinkPeriod>,
+	publish_time: chrono::DateTime<chrono::Utc>,
+) -> Option<dash_mpd::MPD> {
+	let mut duration = 0;
+
+	let mut video_reps = BTreeMap::<AdaptationSetKey, Vec<dash_mpd::Representation>>::new();
+	let mut audio_reps = BTreeMap::<AdaptationSetKey, Vec<dash_mpd::Representation>>::new();
+	let mut image_reps = BTreeMap::<AdaptationSetKey, Vec<dash_mpd::Representation>>::new();
+	let mut muxed_reps = BTreeMap::<AdaptationSetKey, Vec<dash_mpd::Representation>>::new();
+	// Track layout of the muxed streams of each AdaptationSet
+	let mut muxed_components = BTreeMap::<AdaptationSetKey, Vec<dash_mpd::ContentComponent>>::new();
+	// AdaptationSet key and codecs of each audio pad, for the Preselections
+	let mut audio_pads = HashMap::<String, (AdaptationSetKey, String)>::new();
+	// AdaptationSet key of each video pad, for the trick-mode streams
+	let mut video_pads = HashMap::<String, AdaptationSetKey>::new();
+	let mut preselections = Vec::new();
+	for stream in streams {
+		let pad_name = &stream.pad_name;
+
+		// The longest stream, up to max-duration
+		let mut stream_duration = stream.duration.mseconds();
+		if let Some(max_duration) = settings.max_duration {
+			stream_duration = stream_duration.min(max_duration.mseconds());
+		}
+		duration = duration.max(stream_duration);
+
+		let key = stream.key.clone();
+		preselections.extend(stream.preselection.clone());
+		let s = stream.caps.structure(0);
+
+		let (media, codec) = media_and_codec(s);
+
+		// The interleaved tracks of a muxed stream make a single Representation
+		let tracks = &stream.tracks;
+		if tracks.len() > 1 {
+			let codecs = tracks
+				.iter()
+				.map(|caps| media_and_codec(caps.structure(0)).1)
+				.collect::<Vec<_>>()
+				.join(",");
+			let video = tracks
+				.iter()
+				.filter_map(|caps| caps.structure(0))
+				.find(|s| s.name().starts_with("video/"));
+
+			let dimensions = video.and_then(video_dimensions);
+
+			gst::info!(CAT, "MPD info: media=muxed codecs={}", codecs);
+
+			// Tracks are identified by their order in the muxer
+			muxed_components.entry(key.clone()).or_insert_with(|| {
+				tracks
+					.iter()
+					.enumerate()
+					.map(|(idx, caps)| dash_mpd::ContentComponent {
+						id: Some((idx + 1).to_string()),
+						contentType: Some(media_and_codec(caps.structure(0)).0).filter(|media| media != "unknown"),
+						..Default::default()
+					})
+					.collect()
+			});
+
+			let rep = dash_mpd::Representation {
+				id: Some(pad_name.to_string()),
+				codecs: Some(codecs),
+				width: dimensions.map(|(width, _)| width as u64),
+				height: dimensions.map(|(_, height)| height as u64),
+				bandwidth: Some(stream.bandwidth),
+				qualityRanking: stream.quality_ranking,
+				SegmentTemplate: stream.segment_template(settings),
+				SegmentList: stream.segment_list(settings),
+				..Default::default()
+			};
+			muxed_reps.entry(key).or_default().push(rep);
+			continue;
+		}
+
+		match media.as_str() {
+			"video" => {
+				let dimensions = s.and_then(video_dimensions);
+				let framerate = s
+					.and_then(|s| s.get::<gst::Fraction>("framerate").ok())
+					.filter(|fps| fps.numer() > 0 && fps.denom() > 0)
+					.map(frame_rate);
+
+				gst::info!(
+					CAT,
+					"MPD info: media={} codec={} dimensions={:?} framerate={:?}",
+					media,
+					codec,
+					dimensions,
+					framerate
+				);
+
+				let rep = dash_mpd::Representation {
+					id: Some(pad_name.to_string()),
+					codecs: Some(codec),
+					width: dimensions.map(|(width, _)| width as u64),
+					height: dimensions.map(|(_, height)| height as u64),
+					frameRate: framerate,
+					bandwidth: Some(stream.bandwidth),
+					qualityRanking: stream.quality_ranking,
+					// I-frame-only, every frame decodable on its own
+					codingDependency: key.trick_mode_for.as_ref().map(|_| false),
+					SegmentTemplate: stream.segment_template(settings),
+					SegmentList: stream.segment_list(settings),
+					..Default::default()
+				};
+				// Switchable alternatives typically differ in codec, each
+				// in its own AdaptationSet
+				let key = AdaptationSetKey {
+					codec: key.switching_group.map(|_| s.map_or("", |s| s.name().as_str()).to_string()),
+					..key
+				};
+				video_pads.insert(pad_name.clone(), key.clone());
+				video_reps.entry(key).or_default().push(rep)
+			},
+			"audio" => {
+				gst::info!(CAT, "MPD info: media={} codec={}", media, codec);
+
+				// The dac3/dec3 boxes travel in the init segment, which is
+				// written untouched, so only the manifest needs the layout
+				let channels = s.and_then(|s| s.get::<i32>("channels").ok());
+				let audio_channel_configuration = match (codec.as_str(), channels) {
+					("ac-3" | "ec-3", Some(channels)) => dolby_channel_mask(channels)
+						.map(|mask| dash_mpd::AudioChannelConfiguration {
+							schemeIdUri: DOLBY_CHANNEL_CONFIGURATION_SCHEME.to_string(),
+							value: Some(format!("{:04X}", mask)),
+							..Default::default()
+						})
+						.into_iter()
+						.collect(),
+					_ => Vec::new(),
+				};
+				// The mp4a.40.5 and mp4a.40.29 codecs signal the SBR tool,
+				// which decodes to twice the rate of the AAC core
+				let audio_sampling_rate = match codec.as_str() {
+					"mp4a.40.5" | "mp4a.40.29" => s
+						.and_then(|s| s.get::<gst::Buffer>("codec_data").ok())
+						.and_then(|codec_data| {
+							let map = codec_data.map_readable().ok()?;
+							aac_config(&map)?.1
+						})
+						.map(|rate| rate.to_string()),
+					_ => None,
+				};
+
+				let rep = dash_mpd::Representation {
+					id: Some(pad_name.to_string()),
+					codecs: Some(codec),
+					bandwidth: Some(stream.bandwidth),
+					audioSamplingRate: audio_sampling_rate,
+					qualityRanking: stream.quality_ranking,
+					AudioChannelConfiguration: audio_channel_configuration,
+					SegmentTemplate: stream.segment_template(settings),
+					SegmentList: stream.segment_list(settings),
+					..Default::default()
+				};
+				let key = AdaptationSetKey {
+					codec: key.group.or(key.switching_group).map(|_| s.map_or("", |s| s.name().as_str()).to_string()),
+					..key
+				};
+				audio_pads.insert(pad_name.clone(), (key.clone(), rep.codecs.clone().unwrap_or_default()));
+				audio_reps.entry(key).or_default().push(rep)
+			},
+			"image" => {
+				let width = s.and_then(|s| s.get::<i32>("width").ok());
+				let height = s.and_then(|s| s.get::<i32>("height").ok());
+				let (columns, rows) = settings.thumbnail_tiles;
+
+				let thumbnail_location = location_template(&settings.thumbnail_location(pad_name), "Number");
+				let segment_template = dash_mpd::SegmentTemplate {
+					timescale: Some(1000),
+					duration: Some(settings.thumbnail_duration().mseconds() as f64),
+					startNumber: Some(stream.start_number),
+					media: Some(settings.url(&thumbnail_location)),
+					..Default::default()
+				};
+
+				let rep = dash_mpd::Representation {
+					id: Some(pad_name.to_string()),
+					width: width.map(|width| width as u64),
+					height: height.map(|height| height as u64),
+					bandwidth: Some(stream.bandwidth),
+					qualityRanking: stream.quality_ranking,
+					SegmentTemplate: Some(segment_template),
+					essential_property: vec![dash_mpd::EssentialProperty {
+						schemeIdUri: THUMBNAIL_TILE_SCHEME.to_string(),
+						value: Some(format!("{columns}x{rows}")),
+						..Default::default()
+					}],
+					..Default::default()
+				};
+				image_reps.entry(key).or_default().push(rep)
+			},
+			_ => {}
+		};
+	}
+
+	let mut adaptations = Vec::new();
+	// Index, content type and switching group of the switchable sets
+	let mut switchable_sets = Vec::<(usize, &str, u32)>::new();
+
+	// Trick-mode sets reference the set of their main stream by id
+	let trick_mode_mains = video_reps
+		.keys()
+		.filter_map(|key| video_pads.get(key.trick_mode_for.as_ref()?))
+		.cloned()
+		.collect::<Vec<_>>();
+	let mut video_set_ids = BTreeMap::<AdaptationSetKey, String>::new();
+	let mut trick_mode_sets = Vec::<(usize, AdaptationSetKey)>::new();
+	for (key, representations) in video_reps {
+		// Ranges of the alternatives, for capability filtering by clients
+		let multiple = representations.len() > 1;
+		let widths = representations.iter().filter_map(|rep| rep.width);
+		let heights = representations.iter().filter_map(|rep| rep.height);
+		let frame_rates = representations
+			.iter()
+			.filter_map(|rep| rep.frameRate.as_ref())
+			.filter_map(|rate| frame_rate_value(rate).map(|value| (value, rate)));
+		let min_frame_rate = frame_rates.clone().min_by(|a, b| a.0.total_cmp(&b.0)).map(|(_, rate)| rate.clone());
+		let max_frame_rate = frame_rates.max_by(|a, b| a.0.total_cmp(&b.0)).map(|(_, rate)| rate.clone());
+
+		if let Some(switching_group) = key.switching_group {
+			switchable_sets.push((adaptations.len(), "video", switching_group));
+		}
+		let id = (key.switching_group.is_some() || key.trick_mode_for.is_some() || trick_mode_mains.contains(&key))
+			.then(|| adaptations.len().to_string());
+		if let Some(ref id) = id {
+			video_set_ids.insert(key.clone(), id.clone());
+		}
+		if let Some(ref main) = key.trick_mode_for {
+			match video_pads.get(main) {
+				Some(main_key) => trick_mode_sets.push((adaptations.len(), main_key.clone())),
+				None => gst::warning!(CAT, "Trick-mode main stream {} is not a video stream with segments", main),
+			}
+		}
+		adaptations.push(dash_mpd::AdaptationSet {
+			id,
+			contentType: Some("video".into()),
+			minWidth: widths.clone().min().filter(|_| multiple),
+			maxWidth: widths.max().filter(|_| multiple),
+			minHeight: heights.clone().min().filter(|_| multiple),
+			maxHeight: heights.max().filter(|_| multiple),
+			minFrameRate: min_frame_rate.filter(|_| multiple),
+			maxFrameRate: max_frame_rate.filter(|_| multiple),
+			mimeType: Some(settings.mime_type("video")),
+			segmentAlignment: settings.segment_alignment.then_some(true),
+			subsegmentStartsWithSAP: (settings.sap_type != 0).then_some(settings.sap_type as u64),
+			group: key.group.map(i64::from),
+			lang: key.lang.clone(),
+			selectionPriority: key.selection_priority.map(u64::from),
+			Role: key.roles(),
+			Accessibility: key.accessibilities(),
+			Label: key.labels(),
+			ContentProtection: settings.content_protection(),
+			representations,
+			..Default::default()
+		});
+	}
+
+	for (index, main_key) in trick_mode_sets {
+		let Some(main_id) = video_set_ids.get(&main_key) else {
+			continue;
+		};
+		adaptations[index].essential_property.push(dash_mpd::EssentialProperty {
+			schemeIdUri: TRICK_MODE_SCHEME.to_string(),
+			value: Some(main_id.clone()),
+			..Default::default()
+		});
+	}
+
+	// Preselections reference the audio AdaptationSets by id
+	let mut audio_set_ids = BTreeMap::<AdaptationSetKey, String>::new();
+	for (key, representations) in audio_reps {
+		let id = (!preselections.is_empty() || key.switching_group.is_some()).then(|| adaptations.len().to_string());
+		if let Some(ref id) = id {
+			audio_set_ids.insert(key.clone(), id.clone());
+		}
+		if let Some(switching_group) = key.switching_group {
+			switchable_sets.push((adaptations.len(), "audio", switching_group));
+		}
+		adaptations.push(dash_mpd::AdaptationSet {
+			id,
+			contentType: Some("audio".into()),
+			mimeType: Some(settings.mime_type("audio")),
+			segmentAlignment: settings.segment_alignment.then_some(true),
+			subsegmentStartsWithSAP: (settings.sap_type != 0).then_some(settings.sap_type as u64),
+			group: key.group.map(i64::from),
+			lang: key.lang.clone(),
+			selectionPriority: key.selection_priority.map(u64::from),
+			Role: key.roles(),
+			Accessibility: key.accessibilities(),
+			Label: key.labels(),
+			ContentProtection: settings.content_protection(),
+			representations,
+			..Default::default()
+		});
+	}
+
+	for (key, representations) in muxed_reps {
+		adaptations.push(dash_mpd::AdaptationSet {
+			mimeType: Some(settings.mime_type("video")),
+			ContentComponent: muxed_components.remove(&key).unwrap_or_default(),
+			segmentAlignment: settings.segment_alignment.then_some(true),
+			subsegmentStartsWithSAP: (settings.sap_type != 0).then_some(settings.sap_type as u64),
+			group: key.group.map(i64::from),
+			lang: key.lang.clone(),
+			selectionPriority: key.selection_priority.map(u64::from),
+			Role: key.roles(),
+			Accessibility: key.accessibilities(),
+			Label: key.labels(),
+			ContentProtection: settings.content_protection(),
+			representations,
+			..Default::default()
+		});
+	}
+
+	for (key, representations) in image_reps {
+		adaptations.push(dash_mpd::AdaptationSet {
+			contentType: Some("image".into()),
+			mimeType: Some("image/jpeg".into()),
+			group: key.group.map(i64::from),
+			lang: key.lang.clone(),
+			selectionPriority: key.selection_priority.map(u64::from),
+			Role: key.roles(),
+			Accessibility: key.accessibilities(),
+			Label: key.labels(),
+			representations,
+			..Default::default()
+		});
+	}
+
+	// Every switchable set lists the other sets of its group
+	for &(index, media, switching_group) in &switchable_sets {
+		let others = switchable_sets
+			.iter()
+			.filter(|(other, other_media, other_group)| {
+				*other != index && *other_media == media && *other_group == switching_group
+			})
+			.filter_map(|(other, _, _)| adaptations[*other].id.clone())
+			.collect::<Vec<_>>();
+		if others.is_empty() {
+			continue;
+		}
+
+		adaptations[index].supplemental_property.push(dash_mpd::SupplementalProperty {
+			schemeIdUri: ADAPTATION_SET_SWITCHING_SCHEME.to_string(),
+			value: Some(others.join(",")),
+			..Default::default()
+		});
+	}
+
+	// A manifest without AdaptationSets is not valid DASH
+	if adaptations.is_empty() {
+		return None;
+	}
+	for adaptation in &mut adaptations {
+		place_descriptors(adaptation, settings.descriptor_placement);
+	}
+
+	let period_start = period.map_or(gst::ClockTime::ZERO, |period| period.start);
+	let period_id = match (settings.period_id.clone(), period) {
+		(_, Some(DashCmafSinkPeriod { id: Some(id), .. })) => id.clone(),
+		(Some(period_id), None) => period_id,
+		(Some(period_id), Some(period)) => format!("{period_id}-{}", period.index),
+		(None, _) => {
+			let start_time = availability_start_time + chrono::Duration::nanoseconds(period_start.nseconds() as i64);
+			format!("p{}", start_time.format("%Y%m%dT%H%M%SZ"))
+		}
+	};
+
+	let pre_selections = preselections
+		.into_iter()
+		.filter_map(|(id, components)| {
+			let (main_key, codecs) = audio_pads.get(components.first()?)?;
+			let mut set_ids = Vec::<&str>::new();
+			for component in &components {
+				let set_id = audio_pads.get(component).and_then(|(key, _)| audio_set_ids.get(key));
+				match set_id {
+					Some(set_id) if !set_ids.contains(&set_id.as_str()) => set_ids.push(set_id),
+					Some(_) => (),
+					None => gst::warning!(
+						CAT,
+						"Preselection {} component {} is not an audio stream with segments",
+						id,
+						component
+					),
+				}
+			}
+
+			Some(dash_mpd::Preselection {
+				id: Some(id.clone()),
+				tag: id,
+				preselectionComponents: set_ids.join(" "),
+				codecs: codecs.clone(),
+				lang: main_key.lang.clone(),
+				..Default::default()
+			})
+		})
+		.collect();
+
+	// Events of the same scheme and value share an EventStream
+	let mut event_streams = Vec::<dash_mpd::EventStream>::new();
+	for (id, event) in events.iter().enumerate() {
+		let dash_event = dash_mpd::Event {
+			id: Some(id.to_string()),
+			presentationTime: Some(event.presentation_time.mseconds()),
+			duration: event.duration.map(gst::ClockTime::mseconds),
+			content: event.message_data.clone(),
+			signal: event
+				.scte35_binary
+				.iter()
+				.map(|binary| dash_mpd::scte35::Signal {
+					content: Some(dash_mpd::scte35::Binary {
+						content: binary.clone(),
+						..Default::default()
+					}),
+					..Default::default()
+				})
+				.collect(),
+			..Default::default()
+		};
+		let event_stream = event_streams.iter_mut().find(|event_stream| {
+			event_stream.schemeIdUri == event.scheme_id_uri && event_stream.value == event.value
+		});
+		match event_stream {
+			Some(event_stream) => event_stream.event.push(dash_event),
+			None => event_streams.push(dash_mpd::EventStream {
+				schemeIdUri: event.scheme_id_uri.clone(),
+				value: event.value.clone(),
+				timescale: Some(1000),
+				presentationTimeOffset: period.map(|_| period_start.mseconds()),
+				event: vec![dash_event],
+				..Default::default()
+			}),
+		}
+	}
+
+	let period = dash_mpd::Period {
+		id: Some(period_id),
+		start: period.map(|_| std::time::Duration::from_nanos(period_start.nseconds())),
+		adaptations,
+		pre_selections,
+		event_streams,
+		..Default::default()
+	};
+
+	let mut mpd = dash_mpd::MPD {
+		id: settings.mpd_id.clone(),
+		mpdtype: Some("static".to_string()),
+		ProgramInformation: settings.program_information(),
+		locations: settings
+			.manifest_location_url
+			.iter()
+			.map(|url| dash_mpd::Location { url: url.clone() })
+			.collect(),
+		availabilityStartTime: Some(availability_start_time),
+		xmlns: Some("urn:mpeg:dash:schema:mpd:2011".to_string()),
+		schemaLocation: Some("urn:mpeg:dash:schema:mpd:2011 DASH-MPD.xsd".to_string()),
+		profiles: Some("urn:mpeg:dash:profile:isoff-on-demand:2011".to_string()),
+		periods: vec![period],
+		mediaPresentationDuration: Some(std::time::Duration::from_millis(period_start.mseconds() + duration)),
+		minBufferTime: Some(std::time::Duration::from_nanos(settings.segment_duration.nseconds())),
+		maxSegmentDuration: max_segment_duration
+			.map(|duration| std::time::Duration::from_nanos(duration.nseconds())),
+		..Default::default()
+	};
+
+	if settings.playlist_type == DashCmafSinkPlaylistType::Dynamic {
+		mpd.mpdtype = Some("dynamic".to_string());
+		mpd.profiles = Some("urn:mpeg:dash:profile:isoff-live:2011".to_string());
+		mpd.mediaPresentationDuration = None;
+		mpd.publishTime = Some(publish_time);
+		mpd.minimumUpdatePeriod = Some(std::time::Duration::from_nanos(settings.segment_duration.nseconds()));
+		mpd.suggestedPresentationDelay = Some(settings.presentation_delay());
+	}
+	if settings.cmaf_profile {
+		mpd.profiles = mpd.profiles.map(|profiles| format!("{profiles},{CMAF_DASH_PROFILE}"));
+	}
+	settings.declare_namespaces(&mut mpd);
+
+	Some(mpd)
+}
+
+/// XML document of a manifest, indented by `indent` spaces per level and
+/// declared in `encoding`
+pub(crate) fn manifest_xml(mpd: &dash_mpd::MPD, indent: u32, encoding: &str) -> String {
+	use serde::ser::Serialize;
+
+	let mut xml = String::new();
+	let mut ser = quick_xml::se::Serializer::new(&mut xml);
+	if indent > 0 {
+		ser.indent(' ', indent as usize);
+	}
+	mpd.serialize(ser).unwrap();
+
+	format!(
+		r###"<?xml version="1.0" encoding="{encoding}"?>
+{xml}
+"###
+	)
+}
+
+/// Text of a manifest written in any manifest-encoding, telling UTF-16 by
+/// its BOM or the zero bytes of its leading `<`.
+pub(crate) fn decode_manifest(data: &[u8]) -> String {
+	let utf16 = |data: &[u8], from_bytes: fn([u8; 2]) -> u16| {
+		let units = data.chunks_exact(2).map(|unit| from_bytes([unit[0], unit[1]])).collect::<Vec<_>>();
+		String::from_utf16_lossy(&units)
+	};
+
+	let xml = match data {
+		[0xff, 0xfe, ..] | [b'<', 0, ..] => utf16(data, u16::from_le_bytes),
+		[0xfe, 0xff, ..] | [0, b'<', ..] => utf16(data, u16::from_be_bytes),
+		_ => String::from_utf8_lossy(data).into_owned(),
+	};
+
+	xml.trim_start_matches('\u{feff}').to_string()
+}
+
+/// Where each Representation of a previously written manifest has to
+/// continue, after the last segment of its SegmentTimeline or SegmentList.
+/// Without either, segments are assumed every @duration over the elapsed
+/// time, the presentation duration of static manifests or the time since
+/// availabilityStartTime of dynamic ones.
+pub(crate) fn resume_points(mpd: &dash_mpd::MPD, list_start_number: u64) -> HashMap<String, DashCmafSinkResumePoint> {
+	let elapsed = mpd
+		.mediaPresentationDuration
+		.or_else(|| (chrono::Utc::now() - mpd.availabilityStartTime?).to_std().ok())
+		.unwrap_or_default();
+	let elapsed = elapsed.as_nanos() as u64;
+	let nanos = |ticks: u64, timescale: Option<u64>| {
+		let ticks = ticks.mul_div_floor(*gst::ClockTime::SECOND, timescale.unwrap_or(1).max(1));
+		gst::ClockTime::from_nseconds(ticks.unwrap_or(0))
+	};
+
+	// The Representations of the last Period a stream is in continue
+	let mut points = HashMap::new();
+	for period in &mpd.periods {
+		let period_start = period
+			.start
+			.map_or(gst::ClockTime::ZERO, |start| gst::ClockTime::from_nseconds(start.as_nanos() as u64));
+		for adaptation in &period.adaptations {
+			for rep in &adaptation.representations {
+				let Some(ref id) = rep.id else {
+					continue;
+				};
+				let template = rep.SegmentTemplate.as_ref().or(adaptation.SegmentTemplate.as_ref());
+				let list = rep.SegmentList.as_ref().or(adaptation.SegmentList.as_ref());
+
+				let point = match (template, list) {
+					(Some(template), _) => {
+						let start_number = template.startNumber.unwrap_or(DEFAULT_START_NUMBER);
+						let offset = template.presentationTimeOffset.unwrap_or(0);
+						match (&template.SegmentTimeline, template.duration) {
+							(Some(timeline), _) => {
+								let (count, end) = timeline_end(timeline);
+								Some(DashCmafSinkResumePoint {
+									start_number,
+									next_number: start_number + count,
+									duration: period_start + nanos(end.saturating_sub(offset), template.timescale),
+								})
+							}
+							// Live manifests of number addressing have no
+							// timeline, segments following every @duration
+							(None, Some(duration)) => {
+								let segment_duration =
+									(duration * 1e9 / template.timescale.unwrap_or(1) as f64) as u64;
+								let elapsed_in_period = elapsed.saturating_sub(*period_start);
+								(segment_duration > 0).then(|| DashCmafSinkResumePoint {
+									start_number,
+									next_number: start_number + elapsed_in_period.div_ceil(segment_duration),
+									duration: gst::ClockTime::from_nseconds(elapsed),
+								})
+							}
+							(None, None) => None,
+						}
+					}
+					(None, Some(list)) => {
+						let count = list.segment_urls.len() as u64;
+						Some(DashCmafSinkResumePoint {
+							start_number: list_start_number,
+							next_number: list_start_number + count,
+							duration: period_start + nanos(count * list.duration.unwrap_or(0), list.timescale),
+						})
+					}
+					(None, None) => None,
+				};
+				if let Some(point) = point {
+					points.insert(id.clone(), point);
+				}
+			}
+		}
+	}
+
+	points
+}
+
+/// Number of segments of a SegmentTimeline and the end of the last one, in
+/// timescale units.
+fn timeline_end(timeline: &dash_mpd::SegmentTimeline) -> (u64, u64) {
+	timeline.segments.iter().fold((0, 0), |(count, end), s| {
+		let repeat = s.r.unwrap_or(0).max(0) as u64 + 1;
+		(count + repeat, s.t.unwrap_or(end) + s.d * repeat)
+	})
+}
+
+/// Media type and codecs string of the stream with the given caps.
+pub(crate) fn media_and_codec(s: Option<&gst::StructureRef>) -> (String, String) {
+	let Some(s) = s else {
+		return ("unknown".to_string(), "unknown".to_string());
+	};
+
+	match s.name().as_str() {
+		"video/x-h264" => ("video".to_string(), "avc1.64001e".to_string()),
+		"audio/mpeg" if s.get::<i32>("mpegversion") == Ok(1) => {
+			("audio".to_string(), "mp4a.6B".to_string())
+		}
+		// LC unless the AudioSpecificConfig signals another object type,
+		// like 5 for HE-AAC or 29 for HE-AACv2
+		"audio/mpeg" => {
+			let object_type = s
+				.get::<gst::Buffer>("codec_data")
+				.ok()
+				.and_then(|codec_data| {
+					let map = codec_data.map_readable().ok()?;
+					aac_config(&map).map(|(object_type, _)| object_type)
+				})
+				.unwrap_or(2);
+			("audio".to_string(), format!("mp4a.40.{object_type}"))
+		}
+		"audio/x-ac3" => ("audio".to_string(), "ac-3".to_string()),
+		"audio/x-eac3" => ("audio".to_string(), "ec-3".to_string()),
+		"image/jpeg" => ("image".to_string(), "jpeg".to_string()),
+		"video/x-vp8" => ("video".to_string(), "vp8".to_string()),
+		"video/x-vp9" => ("video".to_string(), "vp9".to_string()),
+		"audio/x-opus" => ("audio".to_string(), "opus".to_string()),
+		"audio/x-vorbis" => ("audio".to_string(), "vorbis".to_string()),
+		_ => ("unknown".to_string(), "unknown".to_string()),
+	}
+}
+
+/// Resolution of a video stream from its caps, or from the SPS when the caps
+/// lack it. `None` if unknown, so the manifest doesn't advertise a made up one.
+fn video_dimensions(s: &gst::StructureRef) -> Option<(u32, u32)> {
+	if let (Ok(width), Ok(height)) = (s.get::<i32>("width"), s.get::<i32>("height")) {
+		return Some((width as u32, height as u32));
+	}
+	if s.name() != "video/x-h264" {
+		return None;
+	}
+
+	let codec_data = s.get::<gst::Buffer>("codec_data").ok()?;
+	let map = codec_data.map_readable().ok()?;
+	h264_dimensions(&map)
+}
+
+/// frameRate attribute of a positive framerate, the reduced fraction or an
+/// integer for whole rates.
+fn frame_rate(fps: gst::Fraction) -> String {
+	let (mut a, mut b) = (fps.numer(), fps.denom());
+	while b != 0 {
+		(a, b) = (b, a % b);
+	}
+	let (numer, denom) = (fps.numer() / a, fps.denom() / a);
+
+	match denom {
+		1 => numer.to_string(),
+		_ => format!("{numer}/{denom}"),
+	}
+}
+
+/// Frames per second of a frameRate attribute, a fraction or an integer.
+fn frame_rate_value(frame_rate: &str) -> Option<f64> {
+	let (numer, denom) = frame_rate.split_once('/').unwrap_or((frame_rate, "1"));
+	let (numer, denom) = (numer.parse::<f64>().ok()?, denom.parse::<f64>().ok()?);
+
+	(denom > 0.0).then(|| numer / denom)
+}
+
+/// SegmentTimeline of the written segments in milliseconds, contiguous
+/// segments of the same duration are merged with a repeat count. Only the
+/// first entry and those following a gap carry an explicit `t`.
+fn segment_timeline(
+	segments: &[DashCmafSinkSegment],
+	ticks: impl Fn(gst::ClockTime) -> u64,
+) -> dash_mpd::SegmentTimeline {
+	let mut timeline = Vec::<dash_mpd::S>::new();
+	let mut end = None;
+
+	for segment in segments {
+		// Both boundaries are rounded rather than the duration, so the
+		// rounding errors never add up over the timeline
+		let t = ticks(segment.start);
+		let d = ticks(segment.start + segment.duration) - t;
+
+		match timeline.last_mut() {
+			Some(last) if last.d == d && end == Some(t) => {
+				last.r = Some(last.r.unwrap_or(0) + 1);
+			}
+			_ => timeline.push(dash_mpd::S {
+				t: (end != Some(t)).then_some(t),
+				d,
+				..Default::default()
+			}),
+		}
+		end = Some(t + d);
+	}
+
+	dash_mpd::SegmentTimeline { segments: timeline }
+}
+
+/// Signals the codecs, width, height and frameRate shared by all the
+/// Representations of an AdaptationSet on it, moved with adaptation and
+/// copied with both. Values differing between Representations stay on them.
+/// With both, the mimeType is copied to the Representations as well.
+fn place_descriptors(adaptation: &mut dash_mpd::AdaptationSet, placement: DashCmafSinkDescriptorPlacement) {
+	if placement == DashCmafSinkDescriptorPlacement::Representation {
+		return;
+	}
+
+	let reps = &adaptation.representations;
+	let codecs = common_value(reps, |rep| &rep.codecs);
+	let width = common_value(reps, |rep| &rep.width);
+	let height = common_value(reps, |rep| &rep.height);
+	let frame_rate = common_value(reps, |rep| &rep.frameRate);
+
+	for rep in &mut adaptation.representations {
+		if placement == DashCmafSinkDescriptorPlacement::Both {
+			rep.mimeType = rep.mimeType.take().or_else(|| adaptation.mimeType.clone());
+			continue;
+		}
+		if codecs.is_some() {
+			rep.codecs = None;
+		}
+		if width.is_some() {
+			rep.width = None;
+		}
+		if height.is_some() {
+			rep.height = None;
+		}
+		if frame_rate.is_some() {
+			rep.frameRate = None;
+		}
+	}
+
+	adaptation.codecs = adaptation.codecs.take().or(codecs);
+	adaptation.width = adaptation.width.or(width);
+	adaptation.height = adaptation.height.or(height);
+	adaptation.frameRate = adaptation.frameRate.take().or(frame_rate);
+}
+
+/// Value of a Representation attribute, if all of them have the same.
+fn common_value<T: Clone + PartialEq>(
+	reps: &[dash_mpd::Representation],
+	attribute: impl Fn(&dash_mpd::Representation) -> &Option<T>,
+) -> Option<T> {
+	let value = attribute(reps.first()?).clone()?;
+	reps.iter().all(|rep| attribute(rep).as_ref() == Some(&value)).then_some(value)
+}
+
+/// `time` in ticks of `timescale`, rounded as requested.
+pub(crate) fn timescale_ticks(time: gst::ClockTime, timescale: u32, rounding: DashCmafSinkTimescaleRounding) -> u64 {
+	let scaled = time.nseconds() as u128 * timescale as u128;
+	let second = gst::ClockTime::SECOND.nseconds() as u128;
+	let ticks = match rounding {
+		DashCmafSinkTimescaleRounding::Nearest => (scaled + second / 2) / second,
+		DashCmafSinkTimescaleRounding::Down => scaled / second,
+		DashCmafSinkTimescaleRounding::Up => scaled.div_ceil(second),
+	};
+
+	ticks as u64
+}
+
+/// Channel mask for the Dolby audio channel configuration scheme, assuming
+/// the usual speaker layout for the given number of channels.
+fn dolby_channel_mask(channels: i32) -> Option<u16> {
+	match channels {
+		1 => Some(0x4000), // C
+		2 => Some(0xA000), // L R
+		3 => Some(0xE000), // L C R
+		4 => Some(0xB800), // L R Ls Rs
+		5 => Some(0xF800), // L C R Ls Rs
+		6 => Some(0xF801), // L C R Ls Rs LFE
+		8 => Some(0xFA01), // L C R Ls Rs Lrs Rrs LFE
+		_ => None,
+	}
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+	use super::*;
+	use super::super::bitstream::tests::HIGH_AVCC;
+	use super::super::imp::DEFAULT_TIMESCALE;
+
+	fn init() {
+		gst::init().unwrap();
+	}
+
+	pub(crate) fn segment(number: u64, start: u64, duration: u64) -> DashCmafSinkSegment {
+		DashCmafSinkSegment {
+			number,
+			start: gst::ClockTime::from_seconds(start),
+			duration: gst::ClockTime::from_seconds(duration),
+			location: format!("segment_{number}.cmfv"),
+			checksum: None,
+		}
+	}
+
+	pub(crate) fn mp3_caps() -> gst::Caps {
+		gst::Caps::builder("audio/mpeg")
+			.field("mpegversion", 1i32)
+			.field("layer", 3i32)
+			.field("channels", 2i32)
+			.field("rate", 44100i32)
+			.build()
+	}
+
+	pub(crate) fn video_caps() -> gst::Caps {
+		gst::Caps::builder("video/x-h264")
+			.field("width", 1280i32)
+			.field("height", 720i32)
+			.field("framerate", gst::Fraction::new(25, 1))
+			.build()
+	}
+
+	fn audio_caps() -> gst::Caps {
+		gst::Caps::builder("audio/mpeg")
+			.field("mpegversion", 4i32)
+			.field("rate", 48000i32)
+			.field("channels", 2i32)
+			.build()
+	}
+
+	/// Info of a stream with `segments` of 2 s
+	pub(crate) fn stream_info(pad_name: &str, caps: gst::Caps, segments: u64) -> DashCmafSinkStreamInfo<'static> {
+		let segments = (0..segments).map(|idx| segment(idx, 2 * idx, 2)).collect::<Vec<_>>();
+		DashCmafSinkStreamInfo {
+			pad_name: pad_name.to_string(),
+			caps,
+			tracks: Vec::new(),
+			key: AdaptationSetKey::default(),
+			preselection: None,
+			segment_duration: gst::ClockTime::from_seconds(2),
+			start_number: 0,
+			duration: gst::ClockTime::from_seconds(2 * segments.len() as u64),
+			segments: Cow::Owned(segments),
+			bandwidth: 1_000_000,
+			quality_ranking: None,
+			init_data_uri: None,
+			timescale: DEFAULT_TIMESCALE,
+			ended: false,
+			period_start: gst::ClockTime::ZERO,
+			reorder_delay: gst::ClockTime::ZERO,
+			combined_init: None,
+		}
+	}
+
+	pub(crate) fn mpd(streams: &[DashCmafSinkStreamInfo], settings: &DashCmafSinkSettings) -> dash_mpd::MPD {
+		let time = chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+		build_mpd(streams, settings, time, None, &[], None, time).unwrap()
+	}
+
+	#[test]
+	fn dolby_channel_masks() {
+		assert_eq!(dolby_channel_mask(2), Some(0xA000));
+		assert_eq!(dolby_channel_mask(6), Some(0xF801));
+		assert_eq!(dolby_channel_mask(7), None);
+	}
+
+	#[test]
+	fn adaptation_set_key_descriptors() {
+		let key = AdaptationSetKey {
+			role: Some("commentary".to_string()),
+			..Default::default()
+		};
+
+		let roles = key.roles();
+		assert_eq!(roles.len(), 1);
+		assert_eq!(roles[0].schemeIdUri, ROLE_SCHEME);
+		assert_eq!(roles[0].value.as_deref(), Some("commentary"));
+		assert!(key.accessibilities().is_empty());
+	}
+
+	#[test]
+	fn resume_after_elapsed_duration() {
+		let mpd = dash_mpd::parse(
+			r#"<?xml version="1.0"?>
+<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" type="static" mediaPresentationDuration="PT5S" profiles="urn:mpeg:dash:profile:isoff-on-demand:2011">
+  <Period>
+    <AdaptationSet>
+      <Representation id="sink_0" bandwidth="1000">
+        <SegmentTemplate timescale="1000" duration="2000" startNumber="3" media="segment_$Number$.cmfv"/>
+      </Representation>
+    </AdaptationSet>
+  </Period>
+</MPD>"#,
+		)
+		.unwrap();
+
+		let point = &resume_points(&mpd, 0)["sink_0"];
+		assert_eq!(point.start_number, 3);
+		assert_eq!(point.next_number, 6);
+		assert_eq!(point.duration, gst::ClockTime::from_seconds(5));
+	}
+
+	#[test]
+	fn resume_after_timeline() {
+		let mpd = dash_mpd::parse(
+			r#"<?xml version="1.0"?>
+<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" type="static" mediaPresentationDuration="PT100S" profiles="urn:mpeg:dash:profile:isoff-live:2011">
+  <Period>
+    <AdaptationSet>
+      <Representation id="sink_0" bandwidth="1000">
+        <SegmentTemplate timescale="1000" startNumber="5" media="segment_$Number$.cmfv">
+          <SegmentTimeline>
+            <S t="0" d="2000" r="2"/>
+            <S d="1000"/>
+          </SegmentTimeline>
+        </SegmentTemplate>
+      </Representation>
+      <Representation id="sink_1" bandwidth="1000">
+        <SegmentList timescale="1000" duration="2000">
+          <SegmentURL media="a.cmfv"/>
+          <SegmentURL media="b.cmfv"/>
+        </SegmentList>
+      </Representation>
+    </AdaptationSet>
+  </Period>
+</MPD>"#,
+		)
+		.unwrap();
+
+		let points = resume_points(&mpd, 1);
+		let point = &points["sink_0"];
+		assert_eq!(point.start_number, 5);
+		assert_eq!(point.next_number, 9);
+		assert_eq!(point.duration, gst::ClockTime::from_seconds(7));
+
+		let point = &points["sink_1"];
+		assert_eq!(point.start_number, 1);
+		assert_eq!(point.next_number, 3);
+		assert_eq!(point.duration, gst::ClockTime::from_seconds(4));
+	}
+
+	#[test]
+	fn timeline_end_counts_repeats() {
+		let s = |t: Option<u64>, d: u64, r: Option<i64>| dash_mpd::S {
+			t,
+			d,
+			r,
+			..Default::default()
+		};
+		let timeline = dash_mpd::SegmentTimeline {
+			segments: vec![s(Some(0), 2000, Some(1)), s(Some(5000), 1000, None)],
+		};
+		assert_eq!(timeline_end(&timeline), (3, 6000));
+	}
+
+	#[test]
+	fn timeline_merges_contiguous_segments() {
+		let timeline = segment_timeline(&[segment(0, 0, 2), segment(1, 2, 2), segment(2, 5, 1)], |time| time.mseconds());
+		let s = timeline.segments.iter().map(|s| (s.t, s.d, s.r)).collect::<Vec<_>>();
+		// The gap before the last segment starts a new S with its own @t
+		assert_eq!(s, [(Some(0), 2000, Some(1)), (Some(5000), 1000, None)]);
+	}
+
+	#[test]
+	fn segment_list_enumerates_segments() {
+		init();
+		let settings = DashCmafSinkSettings {
+			addressing: DashCmafSinkAddressing::List,
+			..Default::default()
+		};
+		let stream = stream_info("sink_0", video_caps(), 2);
+
+		assert!(stream.segment_template(&settings).is_none());
+		let list = stream.segment_list(&settings).unwrap();
+		let media = list.segment_urls.iter().map(|url| url.media.as_deref().unwrap()).collect::<Vec<_>>();
+		assert_eq!(media, ["segment_0.cmfv", "segment_1.cmfv"]);
+		assert_eq!(list.Initialization.unwrap().sourceURL.as_deref(), Some("sink_0_init.cmfi"));
+
+		assert!(stream.segment_list(&DashCmafSinkSettings::default()).is_none());
+	}
+
+	#[test]
+	fn media_and_codec_of_caps() {
+		init();
+		let codec = |caps: gst::Caps| media_and_codec(caps.structure(0));
+
+		assert_eq!(codec(gst::Caps::new_empty_simple("video/x-h264")), ("video".into(), "avc1.64001e".into()));
+		assert_eq!(codec(mp3_caps()), ("audio".into(), "mp4a.6B".into()));
+		assert_eq!(
+			codec(gst::Caps::builder("audio/mpeg").field("mpegversion", 4i32).build()),
+			("audio".into(), "mp4a.40.2".into())
+		);
+		assert_eq!(codec(gst::Caps::new_empty_simple("audio/x-eac3")), ("audio".into(), "ec-3".into()));
+		assert_eq!(media_and_codec(None), ("unknown".into(), "unknown".into()));
+	}
+
+	#[test]
+	fn fragment_duration_in_templates() {
+		init();
+		let settings = DashCmafSinkSettings::default();
+		let mut stream = stream_info("sink_0", video_caps(), 1);
+		stream.segment_duration = gst::ClockTime::from_mseconds(1500);
+
+		let template = stream.segment_template(&settings).unwrap();
+		assert_eq!(template.duration, Some(1500.0));
+		let settings = DashCmafSinkSettings {
+			addressing: DashCmafSinkAddressing::List,
+			..settings
+		};
+		let list = stream.segment_list(&settings).unwrap();
+		assert_eq!(list.duration, Some(1500));
+	}
+
+	#[test]
+	fn timeline_restarts_after_gaps() {
+		let segments = [segment(0, 0, 2), segment(1, 2, 2), segment(2, 4, 2), segment(3, 10, 2), segment(4, 12, 1)];
+		let timeline = segment_timeline(&segments, |time| time.mseconds());
+		let s = timeline.segments.iter().map(|s| (s.t, s.d, s.r)).collect::<Vec<_>>();
+		// Contiguous entries follow the previous one without a @t
+		assert_eq!(s, [(Some(0), 2000, Some(2)), (Some(10000), 2000, None), (None, 1000, None)]);
+	}
+
+	#[test]
+	fn number_addressing_signals_gaps() {
+		init();
+		let settings = DashCmafSinkSettings::default();
+		let mut stream = stream_info("sink_0", video_caps(), 3);
+
+		let template = stream.segment_template(&settings).unwrap();
+		assert_eq!(template.duration, Some(2000.0));
+		assert!(template.SegmentTimeline.is_none());
+
+		stream.segments.to_mut()[2].start = gst::ClockTime::from_seconds(6);
+		let template = stream.segment_template(&settings).unwrap();
+		assert!(template.duration.is_none());
+		assert_eq!(template.startNumber, Some(stream.start_number));
+		assert_eq!(template.SegmentTimeline.map(|timeline| timeline.segments.len()), Some(2));
+	}
+
+	#[test]
+	fn aac_codecs_of_codec_data() {
+		init();
+		let codec = |asc: &[u8]| {
+			let caps = gst::Caps::builder("audio/mpeg")
+				.field("mpegversion", 4i32)
+				.field("codec_data", gst::Buffer::from_slice(asc.to_vec()))
+				.build();
+			media_and_codec(caps.structure(0)).1
+		};
+
+		assert_eq!(codec(&[0x11, 0x90]), "mp4a.40.2");
+		assert_eq!(codec(&[0x2b, 0x11, 0x88]), "mp4a.40.5");
+		assert_eq!(codec(&[0xeb, 0x09, 0x88]), "mp4a.40.29");
+	}
+
+	#[test]
+	fn manifest_of_a_video_stream() {
+		init();
+		let settings = DashCmafSinkSettings::default();
+		let mpd = mpd(&[stream_info("sink_0", video_caps(), 3)], &settings);
+
+		assert_eq!(mpd.mpdtype.as_deref(), Some("static"));
+		assert_eq!(mpd.mediaPresentationDuration, Some(std::time::Duration::from_secs(6)));
+		assert_eq!(mpd.periods.len(), 1);
+		let adaptations = &mpd.periods[0].adaptations;
+		assert_eq!(adaptations.len(), 1);
+		assert_eq!(adaptations[0].contentType.as_deref(), Some("video"));
+
+		let rep = &adaptations[0].representations[0];
+		assert_eq!(rep.id.as_deref(), Some("sink_0"));
+		assert_eq!(rep.codecs.as_deref(), Some("avc1.64001e"));
+		assert_eq!((rep.width, rep.height), (Some(1280), Some(720)));
+		assert_eq!(rep.frameRate.as_deref(), Some("25"));
+		assert_eq!(rep.bandwidth, Some(1_000_000));
+	}
+
+	#[test]
+	fn manifest_of_audio_and_video() {
+		init();
+		let settings = DashCmafSinkSettings::default();
+		let mpd = mpd(&[stream_info("sink_0", video_caps(), 3), stream_info("sink_1", audio_caps(), 3)], &settings);
+
+		let adaptations = &mpd.periods[0].adaptations;
+		let content_types = adaptations.iter().map(|adaptation| adaptation.contentType.as_deref()).collect::<Vec<_>>();
+		assert_eq!(content_types, [Some("video"), Some("audio")]);
+		assert_eq!(adaptations[1].representations[0].id.as_deref(), Some("sink_1"));
+		assert_eq!(adaptations[1].representations[0].codecs.as_deref(), Some("mp4a.40.2"));
+	}
+
+	#[test]
+	fn manifest_of_a_live_stream() {
+		init();
+		let live = DashCmafSinkSettings {
+			playlist_type: DashCmafSinkPlaylistType::Dynamic,
+			..Default::default()
+		};
+		let time = chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+		let publish_time = time + chrono::Duration::seconds(6);
+
+		let mpd = build_mpd(&[stream_info("sink_0", video_caps(), 3)], &live, time, None, &[], None, publish_time).unwrap();
+		assert_eq!(mpd.mpdtype.as_deref(), Some("dynamic"));
+		assert!(mpd.mediaPresentationDuration.is_none());
+		assert_eq!(mpd.availabilityStartTime, Some(time));
+		assert_eq!(mpd.publishTime, Some(publish_time));
+		let template = mpd.periods[0].adaptations[0].representations[0].SegmentTemplate.clone().unwrap();
+		assert_eq!(template.duration, Some(2000.0));
+	}
+
+	#[test]
+	fn manifest_of_a_muxed_stream() {
+		init();
+		let settings = DashCmafSinkSettings {
+			mux_streams: true,
+			..Default::default()
+		};
+		let mut info = stream_info("sink_0", video_caps(), 2);
+		info.tracks = vec![video_caps(), audio_caps()];
+		let mpd = mpd(&[info], &settings);
+
+		let adaptations = &mpd.periods[0].adaptations;
+		assert_eq!(adaptations.len(), 1);
+		let reps = &adaptations[0].representations;
+		assert_eq!(reps.len(), 1);
+		assert_eq!(reps[0].codecs.as_deref(), Some("avc1.64001e,mp4a.40.2"));
+		assert_eq!((reps[0].width, reps[0].height), (Some(1280), Some(720)));
+	}
+
+	#[test]
+	fn duration_of_the_longest_stream() {
+		init();
+		let mut settings = DashCmafSinkSettings::default();
+		let streams = [stream_info("sink_0", video_caps(), 5), stream_info("sink_1", audio_caps(), 3)];
+		let duration = |settings: &DashCmafSinkSettings, streams: &[DashCmafSinkStreamInfo]| mpd(streams, settings).mediaPresentationDuration;
+
+		assert_eq!(duration(&settings, &streams), Some(std::time::Duration::from_secs(10)));
+		let reversed = [stream_info("sink_1", audio_caps(), 3), stream_info("sink_0", video_caps(), 5)];
+		assert_eq!(duration(&settings, &reversed), Some(std::time::Duration::from_secs(10)));
+
+		settings.max_duration = Some(gst::ClockTime::from_seconds(8));
+		assert_eq!(duration(&settings, &streams), Some(std::time::Duration::from_secs(8)));
+	}
+
+	#[test]
+	fn dimensions_from_caps_first() {
+		init();
+		let caps = gst::Caps::builder("video/x-h264").field("width", 640i32).field("height", 360i32).build();
+		assert_eq!(video_dimensions(caps.structure(0).unwrap()), Some((640, 360)));
+
+		let caps = gst::Caps::builder("video/x-h264").field("codec_data", gst::Buffer::from_slice(HIGH_AVCC)).build();
+		assert_eq!(video_dimensions(caps.structure(0).unwrap()), Some((1280, 720)));
+
+		let caps = gst::Caps::builder("video/x-h264").build();
+		assert_eq!(video_dimensions(caps.structure(0).unwrap()), None);
+	}
+
+	#[test]
+	fn manifest_resolution_from_the_sps() {
+		init();
+		let settings = DashCmafSinkSettings::default();
+		let caps = gst::Caps::builder("video/x-h264").field("codec_data", gst::Buffer::from_slice(HIGH_AVCC)).build();
+		let sps = mpd(&[stream_info("sink_0", caps, 1)], &settings);
+		let rep = &sps.periods[0].adaptations[0].representations[0];
+		assert_eq!((rep.width, rep.height), (Some(1280), Some(720)));
+		// Without a framerate none is made up either
+		assert_eq!(rep.frameRate, None);
+
+		let unknown = mpd(&[stream_info("sink_0", gst::Caps::new_empty_simple("video/x-h264"), 1)], &settings);
+		let rep = &unknown.periods[0].adaptations[0].representations[0];
+		assert_eq!((rep.width, rep.height), (None, None));
+	}
+
+	#[test]
+	fn cmaf_profile_signalled() {
+		init();
+		let settings = DashCmafSinkSettings {
+			cmaf_profile: true,
+			..Default::default()
+		};
+		let profiles = mpd(&[stream_info("sink_0", video_caps(), 1)], &settings).profiles.unwrap();
+		assert!(profiles.ends_with(&format!(",{CMAF_DASH_PROFILE}")));
+	}
+
+	#[test]
+	fn quality_ranking_of_representations() {
+		init();
+		let mut high = stream_info("sink_0", video_caps(), 1);
+		high.quality_ranking = Some(1);
+		let mut low = stream_info("sink_1", video_caps(), 1);
+		low.quality_ranking = Some(2);
+
+		let mpd = mpd(&[high, low], &DashCmafSinkSettings::default());
+		let reps = &mpd.periods[0].adaptations[0].representations;
+		assert_eq!(reps.iter().map(|rep| rep.qualityRanking).collect::<Vec<_>>(), [Some(1), Some(2)]);
+
+		let parsed = dash_mpd::parse(&manifest_xml(&mpd, 0, "UTF-8")).unwrap();
+		let reps = &parsed.periods[0].adaptations[0].representations;
+		assert_eq!(reps.iter().map(|rep| rep.qualityRanking).collect::<Vec<_>>(), [Some(1), Some(2)]);
+	}
+
+	#[test]
+	fn video_adaptation_set_ranges() {
+		init();
+		let caps = |width: i32, height: i32, fps: i32| {
+			gst::Caps::builder("video/x-h264")
+				.field("width", width)
+				.field("height", height)
+				.field("framerate", gst::Fraction::new(fps, 1))
+				.build()
+		};
+		let settings = DashCmafSinkSettings::default();
+
+		let ladder = mpd(&[stream_info("sink_0", caps(1920, 1080, 50), 1), stream_info("sink_1", caps(640, 360, 25), 1)], &settings);
+		let adaptation = &ladder.periods[0].adaptations[0];
+		assert_eq!(adaptation.representations.len(), 2);
+		assert_eq!((adaptation.maxWidth, adaptation.maxHeight), (Some(1920), Some(1080)));
+		assert_eq!((adaptation.minWidth, adaptation.minHeight), (Some(640), Some(360)));
+		assert_eq!(adaptation.maxFrameRate.as_deref(), Some("50"));
+		assert_eq!(adaptation.minFrameRate.as_deref(), Some("25"));
+
+		// A single Representation has nothing to filter
+		let single = mpd(&[stream_info("sink_0", caps(1920, 1080, 50), 1)], &settings);
+		assert_eq!(single.periods[0].adaptations[0].maxWidth, None);
+	}
+
+	#[test]
+	fn switchable_video_sets_linked() {
+		init();
+		let switchable = |pad_name: &str, caps: gst::Caps| DashCmafSinkStreamInfo {
+			key: AdaptationSetKey {
+				switching_group: Some(1),
+				..Default::default()
+			},
+			..stream_info(pad_name, caps, 1)
+		};
+		let hevc = gst::Caps::builder("video/x-h265").field("width", 1920i32).field("height", 1080i32).build();
+
+		let mpd = mpd(&[switchable("sink_0", video_caps()), switchable("sink_1", hevc)], &DashCmafSinkSettings::default());
+		let adaptations = &mpd.periods[0].adaptations;
+		assert_eq!(adaptations.len(), 2);
+		for (adaptation, other) in adaptations.iter().zip(adaptations.iter().rev()) {
+			let switching = &adaptation.supplemental_property[0];
+			assert_eq!(switching.schemeIdUri, ADAPTATION_SET_SWITCHING_SCHEME);
+			assert_eq!(switching.value, other.id);
+		}
+	}
+
+	#[test]
+	fn timescale_rounding() {
+		use DashCmafSinkTimescaleRounding::*;
+
+		// A 25 fps frame in 90 kHz, then a third of a second
+		let frame = gst::ClockTime::from_mseconds(40);
+		assert_eq!(timescale_ticks(frame, 90_000, Nearest), 3600);
+		assert_eq!(timescale_ticks(frame, 90_000, Down), 3600);
+		assert_eq!(timescale_ticks(frame, 90_000, Up), 3600);
+
+		let third = gst::ClockTime::from_nseconds(333_333_333);
+		assert_eq!(timescale_ticks(third, 1000, Nearest), 333);
+		assert_eq!(timescale_ticks(third, 1000, Down), 333);
+		assert_eq!(timescale_ticks(third, 1000, Up), 334);
+		assert_eq!(timescale_ticks(gst::ClockTime::from_nseconds(1_999_600), 1000, Nearest), 2);
+
+		assert_eq!(timescale_ticks(gst::ClockTime::from_seconds(24 * 3600), 90_000, Down), 7_776_000_000);
+	}
+
+	#[test]
+	fn long_timeline_doesnt_drift() {
+		use DashCmafSinkTimescaleRounding::*;
+
+		// A day of segments that are no whole number of ticks in any timescale
+		let duration = gst::ClockTime::from_nseconds(2_002_002_002);
+		let segments = (0..43_156)
+			.map(|number| DashCmafSinkSegment {
+				number,
+				start: duration * number,
+				duration,
+				location: String::new(),
+				checksum: None,
+			})
+			.collect::<Vec<_>>();
+		let media_duration = duration * segments.len() as u64;
+
+		for (timescale, rounding) in [(90_000, Nearest), (48_000, Down), (1000, Up)] {
+			let timeline = segment_timeline(&segments, |time| timescale_ticks(time, timescale, rounding));
+			let total = timeline
+				.segments
+				.iter()
+				.map(|s| s.d * (s.r.unwrap_or(0) as u64 + 1))
+				.sum::<u64>();
+			let expected = media_duration.nseconds() as f64 * timescale as f64 / 1e9;
+			assert!((total as f64 - expected).abs() <= 1.0, "{timescale}: {total} vs {expected}");
+		}
+	}
+
+	#[test]
+	fn selection_priority_of_audio_sets() {
+		init();
+		let audio = |pad_name: &str, lang: &str, selection_priority: u32| DashCmafSinkStreamInfo {
+			key: AdaptationSetKey {
+				lang: Some(lang.to_string()),
+				selection_priority: Some(selection_priority),
+				..Default::default()
+			},
+			..stream_info(pad_name, audio_caps(), 1)
+		};
+
+		let mpd = mpd(&[audio("sink_0", "en", 2), audio("sink_1", "fr", 1)], &DashCmafSinkSettings::default());
+		let parsed = dash_mpd::parse(&manifest_xml(&mpd, 0, "UTF-8")).unwrap();
+		let priorities = parsed.periods[0]
+			.adaptations
+			.iter()
+			.map(|adaptation| (adaptation.lang.as_deref(), adaptation.selectionPriority))
+			.collect::<Vec<_>>();
+		assert_eq!(priorities, [(Some("en"), Some(2)), (Some("fr"), Some(1))]);
+	}
+
+	#[test]
+	fn trick_mode_references_main_set() {
+		init();
+		let trick_mode = DashCmafSinkStreamInfo {
+			key: AdaptationSetKey {
+				trick_mode_for: Some("sink_0".to_string()),
+				..Default::default()
+			},
+			..stream_info("sink_1", video_caps(), 1)
+		};
+
+		let mpd = mpd(&[stream_info("sink_0", video_caps(), 1), trick_mode], &DashCmafSinkSettings::default());
+		let parsed = dash_mpd::parse(&manifest_xml(&mpd, 0, "UTF-8")).unwrap();
+		let adaptations = &parsed.periods[0].adaptations;
+		assert_eq!(adaptations.len(), 2);
+		let (main, trick) = (&adaptations[0], &adaptations[1]);
+		assert!(main.essential_property.is_empty());
+		assert_eq!(main.representations[0].id.as_deref(), Some("sink_0"));
+
+		let trickmode = &trick.essential_property[0];
+		assert_eq!(trickmode.schemeIdUri, TRICK_MODE_SCHEME);
+		assert!(main.id.is_some());
+		assert_eq!(trickmode.value, main.id);
+		assert_eq!(trick.representations[0].codingDependency, Some(false));
+	}
+
+	#[test]
+	fn descriptor_placements() {
+		init();
+		let small = gst::Caps::builder("video/x-h264")
+			.field("width", 640i32)
+			.field("height", 360i32)
+			.field("framerate", gst::Fraction::new(25, 1))
+			.build();
+		let streams = [stream_info("sink_0", video_caps(), 1), stream_info("sink_1", small, 1)];
+		let placed = |descriptor_placement| {
+			let settings = DashCmafSinkSettings {
+				descriptor_placement,
+				..Default::default()
+			};
+			mpd(&streams, &settings).periods[0].adaptations[0].clone()
+		};
+
+		let adaptation = placed(DashCmafSinkDescriptorPlacement::Representation);
+		assert_eq!(adaptation.codecs, None);
+		assert_eq!(adaptation.frameRate, None);
+		assert_eq!(adaptation.mimeType.as_deref(), Some("video/mp4"));
+		let rep = &adaptation.representations[0];
+		assert_eq!(rep.codecs.as_deref(), Some("avc1.64001e"));
+		assert_eq!((rep.width, rep.height), (Some(1280), Some(720)));
+		assert!(rep.frameRate.is_some());
+		assert_eq!(rep.mimeType, None);
+
+		// The differing sizes stay on the Representations
+		let adaptation = placed(DashCmafSinkDescriptorPlacement::Adaptation);
+		assert_eq!(adaptation.codecs.as_deref(), Some("avc1.64001e"));
+		assert!(adaptation.frameRate.is_some());
+		assert_eq!((adaptation.width, adaptation.height), (None, None));
+		for (rep, width) in adaptation.representations.iter().zip([1280, 640]) {
+			assert_eq!((rep.codecs.as_ref(), rep.frameRate.as_ref()), (None, None));
+			assert_eq!(rep.width, Some(width));
+			assert_eq!(rep.mimeType, None);
+		}
+
+		let adaptation = placed(DashCmafSinkDescriptorPlacement::Both);
+		assert_eq!(adaptation.codecs.as_deref(), Some("avc1.64001e"));
+		assert!(adaptation.frameRate.is_some());
+		for rep in &adaptation.representations {
+			assert_eq!(rep.codecs, adaptation.codecs);
+			assert_eq!(rep.frameRate, adaptation.frameRate);
+			assert_eq!(rep.mimeType.as_deref(), Some("video/mp4"));
+		}
+	}
+
+	#[test]
+	fn cenc_namespace_declared() {
+		init();
+		let settings = DashCmafSinkSettings {
+			widevine_pssh: Some("AAAA".into()),
+			xml_namespaces: Some(
+				gst::Structure::builder("namespaces")
+					.field("cenc", "urn:mpeg:cenc:2013")
+					.field("xsi", "http://www.w3.org/2001/XMLSchema-instance")
+					.build(),
+			),
+			..Default::default()
+		};
+
+		let xml = manifest_xml(&mpd(&[stream_info("sink_0", video_caps(), 1)], &settings), 0, "UTF-8");
+		assert!(xml.contains(r#"xmlns:cenc="urn:mpeg:cenc:2013""#));
+		assert!(xml.contains(r#"xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance""#));
+		assert!(xml.contains("<cenc:pssh"));
+	}
+
+	#[test]
+	fn muxed_content_components() {
+		init();
+		let muxed = DashCmafSinkStreamInfo {
+			tracks: vec![video_caps(), audio_caps()],
+			..stream_info("sink_0", video_caps(), 1)
+		};
+
+		let parsed = mpd(&[muxed], &DashCmafSinkSettings::default());
+		let adaptation = &parsed.periods[0].adaptations[0];
+		let components = adaptation
+			.ContentComponent
+			.iter()
+			.map(|component| (component.id.as_deref(), component.contentType.as_deref()))
+			.collect::<Vec<_>>();
+		assert_eq!(components, [(Some("1"), Some("video")), (Some("2"), Some("audio"))]);
+
+		let xml = manifest_xml(&parsed, 0, "UTF-8");
+		assert_eq!(xml.matches("<ContentComponent").count(), 2);
+	}
+
+	#[test]
+	fn reduced_frame_rates() {
+		assert_eq!(frame_rate(gst::Fraction::new(25, 1)), "25");
+		assert_eq!(frame_rate(gst::Fraction::new(60, 2)), "30");
+		assert_eq!(frame_rate(gst::Fraction::new(30000, 1001)), "30000/1001");
+		assert_eq!(frame_rate(gst::Fraction::new(60000, 2002)), "30000/1001");
+		assert_eq!(frame_rate(gst::Fraction::new(15, 2)), "15/2");
+
+		assert_eq!(frame_rate_value("25"), Some(25.0));
+		assert_eq!(frame_rate_value("15/2"), Some(7.5));
+		assert_eq!(frame_rate_value("25/0"), None);
+		assert_eq!(frame_rate_value("fast"), None);
+	}
+
+	#[test]
+	fn frame_rates_in_manifest() {
+		init();
+		let caps = |numer, denom| {
+			gst::Caps::builder("video/x-h264")
+				.field("width", 1280i32)
+				.field("height", 720i32)
+				.field("framerate", gst::Fraction::new(numer, denom))
+				.build()
+		};
+
+		for ((numer, denom), expected) in [((30000, 1001), "30000/1001"), ((60, 2), "30")] {
+			let xml = manifest_xml(&mpd(&[stream_info("sink_0", caps(numer, denom), 1)], &DashCmafSinkSettings::default()), 0, "UTF-8");
+			assert!(xml.contains(&format!(r#"frameRate="{expected}""#)), "{xml}");
+		}
+	}
+
+	#[test]
+	fn manifest_encodings_round_trip() {
+		init();
+		let mpd = mpd(&[stream_info("sink_0", video_caps(), 2)], &DashCmafSinkSettings::default());
+		let encoded = |manifest_encoding, manifest_bom| {
+			DashCmafSinkSettings {
+				manifest_encoding,
+				manifest_bom,
+				..Default::default()
+			}
+			.manifest_bytes(&mpd)
+		};
+
+		let utf8 = encoded(DashCmafSinkManifestEncoding::Utf8, false);
+		assert!(utf8.starts_with(br#"<?xml version="1.0" encoding="UTF-8"?>"#));
+		let xml = decode_manifest(&utf8);
+
+		let utf8_bom = encoded(DashCmafSinkManifestEncoding::Utf8, true);
+		assert!(utf8_bom.starts_with(&[0xef, 0xbb, 0xbf]));
+		assert_eq!(decode_manifest(&utf8_bom), xml);
+
+		// The byte order is declared without a BOM
+		let utf16le = encoded(DashCmafSinkManifestEncoding::Utf16Le, false);
+		assert!(utf16le.starts_with(&[b'<', 0, b'?', 0]));
+		let decoded = decode_manifest(&utf16le);
+		assert!(decoded.starts_with(r#"<?xml version="1.0" encoding="UTF-16LE"?>"#));
+		assert_eq!(decoded.replace("UTF-16LE", "UTF-8"), xml);
+
+		let utf16be = encoded(DashCmafSinkManifestEncoding::Utf16Be, true);
+		assert!(utf16be.starts_with(&[0xfe, 0xff, 0, b'<']));
+		let decoded = decode_manifest(&utf16be);
+		assert!(decoded.starts_with(r#"<?xml version="1.0" encoding="UTF-16"?>"#));
+		assert_eq!(decoded.replace("UTF-16", "UTF-8"), xml);
+	}
+
+	#[test]
+	fn sap_type_and_alignment() {
+		init();
+		let streams = [stream_info("sink_0", video_caps(), 1), stream_info("sink_1", audio_caps(), 1)];
+		let settings = DashCmafSinkSettings {
+			sap_type: 2,
+			..Default::default()
+		};
+		let xml = manifest_xml(&mpd(&streams, &settings), 0, "UTF-8");
+		assert_eq!(xml.matches(r#"subsegmentStartsWithSAP="2""#).count(), 2);
+		assert_eq!(xml.matches(r#"segmentAlignment="true""#).count(), 2);
+
+		let settings = DashCmafSinkSettings {
+			sap_type: 0,
+			segment_alignment: false,
+			..Default::default()
+		};
+		let xml = manifest_xml(&mpd(&streams, &settings), 0, "UTF-8");
+		assert!(!xml.contains("subsegmentStartsWithSAP"));
+		assert!(!xml.contains("segmentAlignment"));
+	}
+
+	#[test]
+	fn reorder_delay_offsets_presentation_time() {
+		init();
+		let video = DashCmafSinkStreamInfo {
+			reorder_delay: gst::ClockTime::from_mseconds(80),
+			..stream_info("sink_0", video_caps(), 2)
+		};
+		let mpd = mpd(&[video, stream_info("sink_1", audio_caps(), 2)], &DashCmafSinkSettings::default());
+		let offset = |id: &str| {
+			mpd.periods[0]
+				.adaptations
+				.iter()
+				.flat_map(|adaptation| &adaptation.representations)
+				.find(|rep| rep.id.as_deref() == Some(id))
+				.and_then(|rep| rep.SegmentTemplate.as_ref()?.presentationTimeOffset)
+		};
+		// Only the video is shifted, audio keeps its timing
+		assert_eq!(offset("sink_0"), Some(80));
+		assert_eq!(offset("sink_1"), None);
+	}
+
+	#[test]
+	fn combined_init_has_a_range() {
+		init();
+		let settings = DashCmafSinkSettings::default();
+		let mut info = stream_info("sink_0", video_caps(), 2);
+		info.combined_init = Some(("sink_0_segment_0.cmfv".to_string(), 1000));
+
+		let template = info.segment_template(&settings).unwrap();
+		assert_eq!(template.initialization, None);
+		let initialization = template.Initialization.unwrap();
+		assert_eq!(initialization.sourceURL.as_deref(), Some("sink_0_segment_0.cmfv"));
+		assert_eq!(initialization.range.as_deref(), Some("0-999"));
+
+		info.combined_init = None;
+		let template = info.segment_template(&settings).unwrap();
+		assert!(template.initialization.is_some());
+		assert!(template.Initialization.is_none());
+	}
+
+	#[test]
+	fn cmaf_brand_in_mime_type() {
+		let settings = DashCmafSinkSettings::default();
+		assert_eq!(settings.mime_type("video"), "video/mp4");
+
+		let settings = DashCmafSinkSettings {
+			cmaf_brand: Some("cmfc".into()),
+			..Default::default()
+		};
+		assert_eq!(settings.mime_type("audio"), "audio/mp4; profiles=\"cmfc\"");
+	}
+
+	#[test]
+	fn program_information_when_set() {
+		assert!(DashCmafSinkSettings::default().program_information().is_empty());
+
+		let settings = DashCmafSinkSettings {
+			title: Some("News".into()),
+			..Default::default()
+		};
+		let info = settings.program_information();
+		assert_eq!(info[0].Title.as_ref().and_then(|title| title.content.as_deref()), Some("News"));
+		assert!(info[0].Source.is_none());
+		assert!(info[0].Copyright.is_none());
+	}
+
+	#[test]
+	fn webm_mime_types() {
+		let settings = DashCmafSinkSettings {
+			container: DashCmafSinkContainer::Webm,
+			cmaf_brand: Some("cmfc".into()),
+			..Default::default()
+		};
+		assert_eq!(settings.mime_type("video"), "video/webm");
+		assert_eq!(settings.mime_type("audio"), "audio/webm");
+	}
+
+	#[test]
+	fn content_protection_descriptors() {
+		assert!(DashCmafSinkSettings::default().content_protection().is_empty());
+
+		let settings = DashCmafSinkSettings {
+			widevine_pssh: Some("AAAA".into()),
+			playready_pro: Some("BBBB".into()),
+			..Default::default()
+		};
+		let descriptors = settings.content_protection();
+		let schemes = descriptors.iter().map(|cp| cp.schemeIdUri.as_str()).collect::<Vec<_>>();
+		assert_eq!(
+			schemes,
+			[
+				MP4_PROTECTION_SCHEME.to_string(),
+				format!("urn:uuid:{WIDEVINE_SYSTEM_ID}"),
+				format!("urn:uuid:{PLAYREADY_SYSTEM_ID}"),
+			]
+		);
+		assert_eq!(descriptors[1].cenc_pssh[0].content.as_deref(), Some("AAAA"));
+		assert!(descriptors[2].cenc_pssh.is_empty());
+		assert_eq!(descriptors[2].msprpro.as_ref().and_then(|pro| pro.content.as_deref()), Some("BBBB"));
+	}
+}
@@ -0,0 +1,1114 @@
+// Copyright (C) 2025 Roberto Viola <rviola@vicomtech.org>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use gst::glib;
+use gio::prelude::*;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
+
+const INGEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ResourceKind {
+	Init,
+	Segment,
+	Manifest,
+}
+
+/// Destination of the init segments, media segments and manifest.
+///
+/// Resources are identified by their `location`, the URL relative to the
+/// manifest, and `path`, where the file would be stored on disk.
+pub(crate) trait OutputBackend: Send + Sync {
+	fn create(
+		&self,
+		kind: ResourceKind,
+		location: &str,
+		path: &Path,
+	) -> Result<Box<dyn Write + Send>, std::io::Error>;
+
+	/// Creates a resource of the stream of `pad_name`, for backends keeping
+	/// the resources of each stream apart
+	fn create_for_stream(
+		&self,
+		_pad_name: &str,
+		kind: ResourceKind,
+		location: &str,
+		path: &Path,
+	) -> Result<Box<dyn Write + Send>, std::io::Error> {
+		self.create(kind, location, path)
+	}
+
+	/// Returns the content of a resource previously written
+	fn pull(&self, _location: &str) -> Option<glib::Bytes> {
+		None
+	}
+}
+
+#[derive(Default)]
+pub(crate) struct FileOutput {
+	created_dirs: Mutex<HashSet<PathBuf>>,
+	// Permissions of the created files on Unix, the umask applies otherwise
+	file_mode: Option<u32>,
+	// Files kept open, never more than max_open_files unless it's 0
+	open_files: Arc<AtomicUsize>,
+	max_open_files: usize,
+	// Named pipes written to, kept open so their reader sees one stream
+	fifos: Mutex<HashMap<PathBuf, Arc<File>>>,
+}
+
+impl FileOutput {
+	pub(crate) fn new(file_mode: Option<u32>, max_open_files: usize) -> Self {
+		Self {
+			file_mode,
+			max_open_files,
+			..Default::default()
+		}
+	}
+
+	/// Takes one of the `max_open_files` slots, if any is left.
+	fn acquire_slot(&self) -> Option<Arc<AtomicUsize>> {
+		self.open_files
+			.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |open| {
+				(self.max_open_files == 0 || open < self.max_open_files).then_some(open + 1)
+			})
+			.ok()
+			.map(|_| self.open_files.clone())
+	}
+
+	/// Creates the parent directory of `path`, once per directory.
+	fn create_parent_dir(&self, path: &Path) -> Result<(), std::io::Error> {
+		let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) else {
+			return Ok(());
+		};
+
+		let mut created_dirs = self.created_dirs.lock().unwrap();
+		if !created_dirs.contains(dir) {
+			std::fs::create_dir_all(dir)?;
+			created_dirs.insert(dir.to_path_buf());
+		}
+
+		Ok(())
+	}
+
+	/// Opens the named pipe at `path` for writing the first time, blocking
+	/// until it has a reader. That happens without the lock, so writes to
+	/// the named pipes already open go on meanwhile.
+	fn open_fifo(&self, path: &Path) -> Result<FifoWriter, std::io::Error> {
+		if let Some(fifo) = self.fifos.lock().unwrap().get(path) {
+			return Ok(FifoWriter(fifo.clone()));
+		}
+
+		let fifo = Arc::new(std::fs::OpenOptions::new().write(true).open(path)?);
+		// Another stream may have opened it meanwhile, a single one is kept
+		let fifo = self.fifos.lock().unwrap().entry(path.to_path_buf()).or_insert(fifo).clone();
+
+		Ok(FifoWriter(fifo))
+	}
+}
+
+impl OutputBackend for FileOutput {
+	fn create(
+		&self,
+		_kind: ResourceKind,
+		_location: &str,
+		path: &Path,
+	) -> Result<Box<dyn Write + Send>, std::io::Error> {
+		// Named pipes are written in place, a rename would replace them
+		if is_fifo(path) {
+			return self.open_fifo(path).map(|fifo| Box::new(fifo) as Box<dyn Write + Send>);
+		}
+		self.create_parent_dir(path)?;
+
+		AtomicFile::create(path, self.file_mode, self.acquire_slot())
+			.map(|file| Box::new(file) as Box<dyn Write + Send>)
+	}
+}
+
+/// Writes to a temporary file next to `path`, renamed over it on flush so
+/// readers never see a partially written resource.
+///
+/// The file stays open while it holds a slot of the open files counter, and
+/// is otherwise reopened in append mode for every write.
+struct AtomicFile {
+	file: Option<File>,
+	slot: Option<Arc<AtomicUsize>>,
+	tmp_path: PathBuf,
+	path: PathBuf,
+	renamed: bool,
+}
+
+impl AtomicFile {
+	fn create(
+		path: &Path,
+		mode: Option<u32>,
+		slot: Option<Arc<AtomicUsize>>,
+	) -> Result<Self, std::io::Error> {
+		let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+		let tmp_path = path.with_file_name(format!(".{file_name}.tmp"));
+
+		let file = File::create(&tmp_path)?;
+		#[cfg(unix)]
+		if let Some(mode) = mode {
+			use std::os::unix::fs::PermissionsExt;
+			file.set_permissions(std::fs::Permissions::from_mode(mode))?;
+		}
+		#[cfg(not(unix))]
+		let _ = mode;
+
+		Ok(Self {
+			file: slot.is_some().then_some(file),
+			slot,
+			tmp_path,
+			path: path.to_path_buf(),
+			renamed: false,
+		})
+	}
+
+	/// Closes the file, giving its slot back.
+	fn close(&mut self) -> Result<(), std::io::Error> {
+		let res = self.file.take().map_or(Ok(()), |mut file| file.flush());
+		if let Some(slot) = self.slot.take() {
+			slot.fetch_sub(1, Ordering::SeqCst);
+		}
+
+		res
+	}
+}
+
+impl Write for AtomicFile {
+	fn write(&mut self, buf: &[u8]) -> Result<usize, std::io::Error> {
+		match self.file {
+			Some(ref mut file) => file.write(buf),
+			None => std::fs::OpenOptions::new().append(true).open(&self.tmp_path)?.write(buf),
+		}
+	}
+
+	fn flush(&mut self) -> Result<(), std::io::Error> {
+		// Nothing is written after the flush, the descriptor can go
+		self.close()?;
+		if !self.renamed {
+			std::fs::rename(&self.tmp_path, &self.path)?;
+			self.renamed = true;
+		}
+
+		Ok(())
+	}
+}
+
+impl Drop for AtomicFile {
+	fn drop(&mut self) {
+		let _ = self.close();
+		// Never flushed, the resource is incomplete
+		if !self.renamed {
+			let _ = std::fs::remove_file(&self.tmp_path);
+		}
+	}
+}
+
+/// Whether `path` is a named pipe.
+fn is_fifo(path: &Path) -> bool {
+	#[cfg(unix)]
+	{
+		use std::os::unix::fs::FileTypeExt;
+		std::fs::metadata(path).is_ok_and(|metadata| metadata.file_type().is_fifo())
+	}
+	#[cfg(not(unix))]
+	{
+		let _ = path;
+		false
+	}
+}
+
+/// Appends to a named pipe shared by all the resources written to it.
+struct FifoWriter(Arc<File>);
+
+impl Write for FifoWriter {
+	fn write(&mut self, buf: &[u8]) -> Result<usize, std::io::Error> {
+		(&*self.0).write(buf)
+	}
+
+	fn flush(&mut self) -> Result<(), std::io::Error> {
+		(&*self.0).flush()
+	}
+}
+
+/// Pushes the resources to a DASH-IF live media ingest endpoint over HTTP or
+/// HTTPS. The init and media segments of each stream are POSTed to
+/// `{ingest_url}/{pad}/{location}` over a connection kept open for that
+/// stream, the manifest is PUT to `{ingest_url}/{location}`. Bodies use
+/// chunked transfer encoding, every write being sent as a chunk right away.
+/// `headers` are added to every request.
+pub(crate) struct IngestOutput {
+	ingest_url: Option<String>,
+	headers: Vec<(String, String)>,
+	// Idle connection of each stream, the manifest's under ""
+	connections: Arc<Mutex<HashMap<String, IngestConnection>>>,
+}
+
+impl IngestOutput {
+	pub(crate) fn new(ingest_url: Option<String>, headers: Vec<(String, String)>) -> Self {
+		Self {
+			ingest_url,
+			headers,
+			connections: Arc::default(),
+		}
+	}
+
+	fn request(
+		&self,
+		key: &str,
+		kind: ResourceKind,
+		location: &str,
+	) -> Result<Box<dyn Write + Send>, std::io::Error> {
+		let ingest_url = self.ingest_url.as_deref().ok_or_else(|| {
+			std::io::Error::new(std::io::ErrorKind::InvalidInput, "ingest-url is not set")
+		})?;
+		let url = match key {
+			"" => format!("{}/{}", ingest_url.trim_end_matches('/'), location),
+			pad_name => format!("{}/{}/{}", ingest_url.trim_end_matches('/'), pad_name, location),
+		};
+		let url = url::Url::parse(&url)
+			.map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))?;
+		if !matches!(url.scheme(), "http" | "https") {
+			return Err(std::io::Error::new(
+				std::io::ErrorKind::Unsupported,
+				format!("Unsupported ingest scheme {}", url.scheme()),
+			));
+		}
+
+		let (method, content_type) = match kind {
+			ResourceKind::Manifest => ("PUT", "application/dash+xml"),
+			ResourceKind::Init | ResourceKind::Segment => ("POST", "application/mp4"),
+		};
+		let host = url.host_str().unwrap_or_default();
+		let host_header = match url.port() {
+			Some(port) => format!("{host}:{port}"),
+			None => host.to_string(),
+		};
+		let mut head = format!(
+			"{method} {} HTTP/1.1\r\n\
+			Host: {host_header}\r\n\
+			Content-Type: {content_type}\r\n\
+			Transfer-Encoding: chunked\r\n\
+			DASH-IF-Ingest: 1.1\r\n",
+			&url[url::Position::BeforePath..],
+		);
+		for (name, value) in &self.headers {
+			head.push_str(&format!("{name}: {value}\r\n"));
+		}
+		head.push_str("\r\n");
+
+		// An idle connection may have been closed by the server meanwhile,
+		// in which case the request is sent over a new one
+		let idle = self.connections.lock().unwrap().remove(key);
+		let connection = match idle.map(|connection| connection.start(&head).map(|_| connection)) {
+			Some(Ok(connection)) => connection,
+			_ => {
+				let connection = IngestConnection::open(&url)?;
+				connection.start(&head)?;
+				connection
+			}
+		};
+
+		Ok(Box::new(IngestWriter {
+			connection: Some(connection),
+			key: key.to_string(),
+			url: url.to_string(),
+			connections: self.connections.clone(),
+		}))
+	}
+}
+
+impl OutputBackend for IngestOutput {
+	fn create(
+		&self,
+		kind: ResourceKind,
+		location: &str,
+		_path: &Path,
+	) -> Result<Box<dyn Write + Send>, std::io::Error> {
+		self.request("", kind, location)
+	}
+
+	fn create_for_stream(
+		&self,
+		pad_name: &str,
+		kind: ResourceKind,
+		location: &str,
+		_path: &Path,
+	) -> Result<Box<dyn Write + Send>, std::io::Error> {
+		self.request(pad_name, kind, location)
+	}
+}
+
+enum IngestMessage {
+	// Request line and headers of the next request
+	Head(String),
+	Chunk(Vec<u8>),
+	// End of the request body, answered with the response
+	End,
+}
+
+/// Connection to the ingest server, owned by its own thread as GIO
+/// connections can't move between streaming threads. Every message is
+/// answered, with whether the connection can be kept for the next request
+/// once the response is read.
+struct IngestConnection {
+	messages: mpsc::Sender<IngestMessage>,
+	replies: Mutex<mpsc::Receiver<Result<bool, std::io::Error>>>,
+}
+
+impl IngestConnection {
+	fn open(url: &url::Url) -> Result<Self, std::io::Error> {
+		let (messages, messages_rx) = mpsc::channel();
+		let (replies_tx, replies) = mpsc::channel();
+		let uri = url.to_string();
+		let tls = url.scheme() == "https";
+		let default_port = url.port_or_known_default().unwrap_or(80);
+
+		std::thread::Builder::new()
+			.name("dashcmafsink-ingest".into())
+			.spawn(move || {
+				let client = gio::SocketClient::new();
+				client.set_tls(tls);
+				client.set_timeout(INGEST_TIMEOUT.as_secs() as u32);
+				let connection = match client.connect_to_uri(&uri, default_port, gio::Cancellable::NONE) {
+					Ok(connection) => connection,
+					Err(err) => {
+						let _ = replies_tx.send(Err(std::io::Error::new(
+							std::io::ErrorKind::ConnectionRefused,
+							format!("Couldn't connect to {uri}: {err}"),
+						)));
+						return;
+					}
+				};
+				let _ = replies_tx.send(Ok(true));
+
+				let mut writer = connection.output_stream().into_write();
+				let mut reader = BufReader::new(connection.input_stream().into_read());
+				let mut failed = None;
+				for message in messages_rx {
+					let reply = match message {
+						IngestMessage::Head(head) => writer.write_all(head.as_bytes()).map(|_| true),
+						IngestMessage::Chunk(data) => {
+							if failed.is_none() {
+								failed = write_chunk(&mut writer, &data).err();
+							}
+							continue;
+						}
+						IngestMessage::End => match failed.take() {
+							Some(err) => Err(err),
+							None => write_chunk(&mut writer, &[])
+								.and_then(|_| writer.flush())
+								.and_then(|_| read_response(&mut reader)),
+						},
+					};
+
+					let keep = matches!(reply, Ok(true));
+					if replies_tx.send(reply).is_err() || !keep {
+						break;
+					}
+				}
+			})?;
+
+		let connection = Self {
+			messages,
+			replies: Mutex::new(replies),
+		};
+		connection.reply()?;
+
+		Ok(connection)
+	}
+
+	fn reply(&self) -> Result<bool, std::io::Error> {
+		self.replies.lock().unwrap().recv().unwrap_or_else(|_| {
+			Err(std::io::Error::new(
+				std::io::ErrorKind::ConnectionAborted,
+				"ingest connection closed",
+			))
+		})
+	}
+
+	fn send(&self, message: IngestMessage) -> Result<(), std::io::Error> {
+		self.messages.send(message).map_err(|_| {
+			std::io::Error::new(std::io::ErrorKind::ConnectionAborted, "ingest connection closed")
+		})
+	}
+
+	fn start(&self, head: &str) -> Result<(), std::io::Error> {
+		self.send(IngestMessage::Head(head.to_string()))?;
+		self.reply().map(|_| ())
+	}
+}
+
+fn write_chunk(writer: &mut impl Write, data: &[u8]) -> Result<(), std::io::Error> {
+	write!(writer, "{:X}\r\n", data.len())?;
+	writer.write_all(data)?;
+	writer.write_all(b"\r\n")
+}
+
+/// Reads the response to a request, failing unless its status is 2xx.
+/// Returns whether the connection stays open for the next request.
+fn read_response(reader: &mut impl BufRead) -> Result<bool, std::io::Error> {
+	let invalid = |what: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, what.to_string());
+
+	loop {
+		let mut status_line = String::new();
+		if reader.read_line(&mut status_line)? == 0 {
+			return Err(std::io::Error::new(
+				std::io::ErrorKind::UnexpectedEof,
+				"connection closed without a response",
+			));
+		}
+		let mut parts = status_line.trim_end().splitn(3, ' ');
+		let version = parts.next().unwrap_or_default().to_string();
+		let status = parts
+			.next()
+			.and_then(|status| status.parse::<u16>().ok())
+			.ok_or_else(|| invalid("invalid status line"))?;
+		let reason = parts.next().unwrap_or_default().to_string();
+
+		let mut headers = Vec::new();
+		loop {
+			let mut line = String::new();
+			if reader.read_line(&mut line)? == 0 {
+				return Err(invalid("truncated response headers"));
+			}
+			let line = line.trim_end();
+			if line.is_empty() {
+				break;
+			}
+			let (name, value) = line.split_once(':').ok_or_else(|| invalid("invalid response header"))?;
+			headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+		}
+		let header = |name: &str| {
+			headers
+				.iter()
+				.find(|(header, _)| header == name)
+				.map(|(_, value)| value.as_str())
+		};
+
+		// Interim responses precede the final one
+		if (100..200).contains(&status) {
+			continue;
+		}
+
+		let mut keep_alive = version == "HTTP/1.1"
+			&& !header("connection").is_some_and(|value| value.eq_ignore_ascii_case("close"));
+		let mut body = Vec::new();
+		if header("transfer-encoding").is_some_and(|value| value.eq_ignore_ascii_case("chunked")) {
+			read_chunked_body(reader, &mut body)?;
+		} else if let Some(length) = header("content-length") {
+			let length = length.parse::<u64>().map_err(|_| invalid("invalid Content-Length"))?;
+			reader.take(length).read_to_end(&mut body)?;
+			if body.len() as u64 != length {
+				return Err(invalid("truncated response body"));
+			}
+		} else if status != 204 && status != 304 {
+			// The body ends with the connection
+			keep_alive = false;
+			reader.read_to_end(&mut body)?;
+		}
+
+		if !(200..300).contains(&status) {
+			let body = String::from_utf8_lossy(&body);
+			return Err(std::io::Error::other(format!("{status} {reason}: {}", body.trim())));
+		}
+
+		return Ok(keep_alive);
+	}
+}
+
+fn read_chunked_body(reader: &mut impl BufRead, body: &mut Vec<u8>) -> Result<(), std::io::Error> {
+	let invalid = || std::io::Error::new(std::io::ErrorKind::InvalidData, "invalid chunked response body");
+
+	loop {
+		let mut line = String::new();
+		reader.read_line(&mut line)?;
+		let size = line.split(';').next().unwrap_or_default().trim();
+		let size = usize::from_str_radix(size, 16).map_err(|_| invalid())?;
+		if size == 0 {
+			// Trailers, up to the empty line
+			loop {
+				line.clear();
+				if reader.read_line(&mut line)? == 0 || line.trim_end().is_empty() {
+					return Ok(());
+				}
+			}
+		}
+
+		let start = body.len();
+		body.resize(start + size, 0);
+		reader.read_exact(&mut body[start..])?;
+		line.clear();
+		reader.read_line(&mut line)?;
+	}
+}
+
+/// Sends every write as a chunk of the request body, the request is
+/// completed on flush. Dropped before, the connection is closed, so the
+/// server discards the incomplete resource.
+struct IngestWriter {
+	connection: Option<IngestConnection>,
+	key: String,
+	url: String,
+	connections: Arc<Mutex<HashMap<String, IngestConnection>>>,
+}
+
+impl Write for IngestWriter {
+	fn write(&mut self, buf: &[u8]) -> Result<usize, std::io::Error> {
+		if buf.is_empty() {
+			return Ok(0);
+		}
+
+		let connection = self.connection.as_ref().ok_or_else(|| {
+			std::io::Error::other(format!("{} already sent", self.url))
+		})?;
+		connection.send(IngestMessage::Chunk(buf.to_vec()))?;
+
+		Ok(buf.len())
+	}
+
+	fn flush(&mut self) -> Result<(), std::io::Error> {
+		let Some(connection) = self.connection.take() else {
+			return Ok(());
+		};
+
+		connection.send(IngestMessage::End)?;
+		match connection.reply() {
+			Ok(keep_alive) => {
+				if keep_alive {
+					self.connections.lock().unwrap().insert(self.key.clone(), connection);
+				}
+				Ok(())
+			}
+			Err(err) => Err(std::io::Error::new(
+				err.kind(),
+				format!("{} rejected by the ingest server: {err}", self.url),
+			)),
+		}
+	}
+}
+
+#[derive(Default)]
+struct MemoryStore {
+	resources: HashMap<String, glib::Bytes>,
+	// Media segments in the order they were written, oldest first
+	segments: VecDeque<String>,
+}
+
+/// Keeps the resources in memory so they can be served by the application,
+/// only the last `max_segments` media segments are kept.
+pub(crate) struct MemoryOutput {
+	max_segments: usize,
+	store: Arc<Mutex<MemoryStore>>,
+}
+
+impl MemoryOutput {
+	pub(crate) fn new(max_segments: usize) -> Self {
+		Self {
+			max_segments,
+			store: Arc::default(),
+		}
+	}
+}
+
+impl OutputBackend for MemoryOutput {
+	fn create(
+		&self,
+		kind: ResourceKind,
+		location: &str,
+		_path: &Path,
+	) -> Result<Box<dyn Write + Send>, std::io::Error> {
+		Ok(Box::new(MemoryWriter {
+			kind,
+			location: location.to_string(),
+			data: Vec::new(),
+			max_segments: self.max_segments,
+			store: self.store.clone(),
+		}))
+	}
+
+	fn pull(&self, location: &str) -> Option<glib::Bytes> {
+		self.store.lock().unwrap().resources.get(location).cloned()
+	}
+}
+
+/// Hands every resource to the application, `write` being called with its
+/// kind, location and data once complete. It returns whether the resource
+/// was stored.
+pub(crate) type WriteCallback = dyn Fn(ResourceKind, &str, glib::Bytes) -> bool + Send + Sync;
+
+pub(crate) struct CallbackOutput {
+	write: Arc<WriteCallback>,
+}
+
+impl CallbackOutput {
+	pub(crate) fn new(write: Arc<WriteCallback>) -> Self {
+		Self { write }
+	}
+}
+
+impl OutputBackend for CallbackOutput {
+	fn create(
+		&self,
+		kind: ResourceKind,
+		location: &str,
+		_path: &Path,
+	) -> Result<Box<dyn Write + Send>, std::io::Error> {
+		Ok(Box::new(CallbackWriter {
+			kind,
+			location: location.to_string(),
+			data: Vec::new(),
+			write: self.write.clone(),
+			written: false,
+		}))
+	}
+}
+
+/// Buffers the written data, handed to the callback on flush.
+struct CallbackWriter {
+	kind: ResourceKind,
+	location: String,
+	data: Vec<u8>,
+	write: Arc<WriteCallback>,
+	written: bool,
+}
+
+impl Write for CallbackWriter {
+	fn write(&mut self, buf: &[u8]) -> Result<usize, std::io::Error> {
+		self.data.extend_from_slice(buf);
+		Ok(buf.len())
+	}
+
+	fn flush(&mut self) -> Result<(), std::io::Error> {
+		if self.written {
+			return Ok(());
+		}
+		self.written = true;
+
+		let data = glib::Bytes::from_owned(std::mem::take(&mut self.data));
+		if !(self.write)(self.kind, &self.location, data) {
+			return Err(std::io::Error::other(format!("{} was not written by the application", self.location)));
+		}
+
+		Ok(())
+	}
+}
+
+/// Buffers the written data, which is published to the store on flush.
+struct MemoryWriter {
+	kind: ResourceKind,
+	location: String,
+	data: Vec<u8>,
+	max_segments: usize,
+	store: Arc<Mutex<MemoryStore>>,
+}
+
+impl Write for MemoryWriter {
+	fn write(&mut self, buf: &[u8]) -> Result<usize, std::io::Error> {
+		self.data.extend_from_slice(buf);
+		Ok(buf.len())
+	}
+
+	fn flush(&mut self) -> Result<(), std::io::Error> {
+		let mut store = self.store.lock().unwrap();
+
+		let bytes = glib::Bytes::from(&self.data[..]);
+		if store.resources.insert(self.location.clone(), bytes).is_none()
+			&& self.kind == ResourceKind::Segment
+		{
+			store.segments.push_back(self.location.clone());
+			while store.segments.len() > self.max_segments {
+				if let Some(oldest) = store.segments.pop_front() {
+					store.resources.remove(&oldest);
+				}
+			}
+		}
+
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn parent_dirs_created_once() {
+		let root = std::env::temp_dir().join(format!("dashcmafsink-parent-{}", std::process::id()));
+		let _ = std::fs::remove_dir_all(&root);
+		let output = FileOutput::default();
+
+		output.create_parent_dir(&root.join("a/b/segment_0.cmfv")).unwrap();
+		assert!(root.join("a/b").is_dir());
+		assert!(output.created_dirs.lock().unwrap().contains(&root.join("a/b")));
+
+		// Bare file names have no directory to create
+		output.create_parent_dir(Path::new("segment_0.cmfv")).unwrap();
+		assert_eq!(output.created_dirs.lock().unwrap().len(), 1);
+
+		std::fs::remove_dir_all(&root).unwrap();
+	}
+
+	fn write(output: &dyn OutputBackend, kind: ResourceKind, location: &str, data: &[u8]) {
+		let mut writer = output.create(kind, location, Path::new(location)).unwrap();
+		writer.write_all(data).unwrap();
+		writer.flush().unwrap();
+	}
+
+	#[test]
+	fn memory_keeps_a_window_of_segments() {
+		let output = MemoryOutput::new(2);
+		write(&output, ResourceKind::Init, "init.cmfi", b"init");
+		for idx in 0..3 {
+			write(&output, ResourceKind::Segment, &format!("segment_{idx}.cmfv"), &[idx]);
+		}
+		write(&output, ResourceKind::Manifest, "manifest.mpd", b"<MPD/>");
+
+		assert!(output.pull("segment_0.cmfv").is_none());
+		assert_eq!(&*output.pull("segment_2.cmfv").unwrap(), &[2]);
+		assert_eq!(&*output.pull("init.cmfi").unwrap(), b"init");
+		assert_eq!(&*output.pull("manifest.mpd").unwrap(), b"<MPD/>");
+	}
+
+	#[test]
+	fn memory_publishes_on_flush() {
+		let output = MemoryOutput::new(2);
+		let mut writer = output.create(ResourceKind::Manifest, "manifest.mpd", Path::new("manifest.mpd")).unwrap();
+		writer.write_all(b"<MPD/>").unwrap();
+		assert!(output.pull("manifest.mpd").is_none());
+		writer.flush().unwrap();
+		assert!(output.pull("manifest.mpd").is_some());
+	}
+
+	#[test]
+	fn atomic_file_renamed_on_flush() {
+		let root = std::env::temp_dir().join(format!("dashcmafsink-atomic-{}", std::process::id()));
+		std::fs::create_dir_all(&root).unwrap();
+		let path = root.join("manifest.mpd");
+		std::fs::write(&path, "old").unwrap();
+
+		let mut file = AtomicFile::create(&path, None, None).unwrap();
+		file.write_all(b"new").unwrap();
+		// Readers keep seeing the previous content until the flush
+		assert_eq!(std::fs::read_to_string(&path).unwrap(), "old");
+		file.flush().unwrap();
+		assert_eq!(std::fs::read_to_string(&path).unwrap(), "new");
+		drop(file);
+		assert!(!root.join(".manifest.mpd.tmp").exists());
+
+		// Dropped without a flush, the temporary file is discarded
+		let mut file = AtomicFile::create(&path, None, None).unwrap();
+		file.write_all(b"partial").unwrap();
+		drop(file);
+		assert_eq!(std::fs::read_to_string(&path).unwrap(), "new");
+		assert!(!root.join(".manifest.mpd.tmp").exists());
+
+		std::fs::remove_dir_all(&root).unwrap();
+	}
+
+	/// Request received by [`ingest_server`], its body as the chunks sent
+	struct IngestRequest {
+		connection: usize,
+		method: String,
+		path: String,
+		headers: Vec<(String, String)>,
+		chunks: Vec<Vec<u8>>,
+	}
+
+	/// Ingest server accepting every request on a path without "denied",
+	/// reporting every request and, as soon as they arrive, the body chunks
+	fn ingest_server() -> (String, mpsc::Receiver<IngestRequest>, mpsc::Receiver<Vec<u8>>) {
+		let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+		let url = format!("http://{}/ingest", listener.local_addr().unwrap());
+		let (requests_tx, requests) = mpsc::channel();
+		let (chunks_tx, chunks) = mpsc::channel();
+
+		std::thread::spawn(move || {
+			for (connection, stream) in listener.incoming().enumerate() {
+				let mut stream = stream.unwrap();
+				let mut reader = BufReader::new(stream.try_clone().unwrap());
+				let requests_tx = requests_tx.clone();
+				let chunks_tx = chunks_tx.clone();
+				std::thread::spawn(move || loop {
+					let mut line = String::new();
+					if reader.read_line(&mut line).unwrap_or(0) == 0 {
+						return;
+					}
+					let mut parts = line.split_whitespace();
+					let method = parts.next().unwrap().to_string();
+					let path = parts.next().unwrap().to_string();
+
+					let mut headers = Vec::new();
+					loop {
+						line.clear();
+						reader.read_line(&mut line).unwrap();
+						match line.trim_end().split_once(':') {
+							Some((name, value)) => headers.push((name.to_string(), value.trim().to_string())),
+							None => break,
+						}
+					}
+
+					let mut chunks = Vec::new();
+					loop {
+						line.clear();
+						if reader.read_line(&mut line).unwrap_or(0) == 0 {
+							return;
+						}
+						let size = usize::from_str_radix(line.trim_end(), 16).unwrap();
+						let mut chunk = vec![0; size + 2];
+						reader.read_exact(&mut chunk).unwrap();
+						chunk.truncate(size);
+						if size == 0 {
+							break;
+						}
+						let _ = chunks_tx.send(chunk.clone());
+						chunks.push(chunk);
+					}
+
+					let response: &[u8] = match path.contains("denied") {
+						true => b"HTTP/1.1 403 Forbidden\r\nContent-Length: 6\r\n\r\ndenied",
+						false => b"HTTP/1.1 201 Created\r\nContent-Length: 0\r\n\r\n",
+					};
+					stream.write_all(response).unwrap();
+					let _ = requests_tx.send(IngestRequest {
+						connection,
+						method,
+						path,
+						headers,
+						chunks,
+					});
+				});
+			}
+		});
+
+		(url, requests, chunks)
+	}
+
+	#[test]
+	fn ingest_requests() {
+		let (url, requests, chunks) = ingest_server();
+		let output = IngestOutput::new(Some(url), Vec::new());
+
+		let mut writer = output
+			.create_for_stream("sink_0", ResourceKind::Init, "sink_0_init.cmfi", Path::new("sink_0_init.cmfi"))
+			.unwrap();
+		writer.write_all(b"init").unwrap();
+		writer.flush().unwrap();
+
+		// Each CMAF chunk is sent as it is written, before the segment ends
+		let mut writer = output
+			.create_for_stream("sink_0", ResourceKind::Segment, "sink_0_segment_1.cmfv", Path::new("sink_0_segment_1.cmfv"))
+			.unwrap();
+		writer.write_all(b"moof1").unwrap();
+		assert_eq!(chunks.recv_timeout(INGEST_TIMEOUT).unwrap(), b"init");
+		assert_eq!(chunks.recv_timeout(INGEST_TIMEOUT).unwrap(), b"moof1");
+		writer.write_all(b"moof2").unwrap();
+		writer.flush().unwrap();
+
+		let mut writer = output
+			.create(ResourceKind::Manifest, "manifest.mpd", Path::new("manifest.mpd"))
+			.unwrap();
+		writer.write_all(b"<MPD/>").unwrap();
+		writer.flush().unwrap();
+
+		let requests: Vec<_> = (0..3).map(|_| requests.recv_timeout(INGEST_TIMEOUT).unwrap()).collect();
+		let summary: Vec<_> = requests.iter().map(|request| (request.method.as_str(), request.path.as_str())).collect();
+		assert_eq!(
+			summary,
+			[
+				("POST", "/ingest/sink_0/sink_0_init.cmfi"),
+				("POST", "/ingest/sink_0/sink_0_segment_1.cmfv"),
+				("PUT", "/ingest/manifest.mpd"),
+			]
+		);
+		assert_eq!(requests[1].chunks, [b"moof1".to_vec(), b"moof2".to_vec()]);
+		assert_eq!(requests[2].chunks, [b"<MPD/>".to_vec()]);
+
+		// The stream keeps its connection, the manifest has its own
+		assert_eq!(requests[0].connection, requests[1].connection);
+		assert_ne!(requests[0].connection, requests[2].connection);
+
+		for request in &requests {
+			let header = |name: &str| {
+				request
+					.headers
+					.iter()
+					.find(|(header, _)| header.eq_ignore_ascii_case(name))
+					.map(|(_, value)| value.as_str())
+			};
+			assert_eq!(header("DASH-IF-Ingest"), Some("1.1"));
+			assert_eq!(header("Transfer-Encoding"), Some("chunked"));
+		}
+		assert_eq!(requests[1].headers.iter().find(|(name, _)| name == "Content-Type").unwrap().1, "application/mp4");
+		assert_eq!(requests[2].headers.iter().find(|(name, _)| name == "Content-Type").unwrap().1, "application/dash+xml");
+	}
+
+	#[test]
+	fn ingest_errors() {
+		let (url, _requests, _chunks) = ingest_server();
+		let output = IngestOutput::new(Some(url), Vec::new());
+
+		// The status and body of a rejection are reported
+		let mut writer = output
+			.create(ResourceKind::Manifest, "denied.mpd", Path::new("denied.mpd"))
+			.unwrap();
+		writer.write_all(b"<MPD/>").unwrap();
+		let err = writer.flush().unwrap_err().to_string();
+		assert!(err.contains("403 Forbidden"), "{err}");
+		assert!(err.contains("denied"), "{err}");
+
+		let output = IngestOutput::new(Some("ftp://127.0.0.1/ingest".into()), Vec::new());
+		let err = output
+			.create(ResourceKind::Manifest, "manifest.mpd", Path::new("manifest.mpd"))
+			.err()
+			.unwrap();
+		assert_eq!(err.kind(), std::io::ErrorKind::Unsupported);
+
+		let output = IngestOutput::new(None, Vec::new());
+		assert!(output.create(ResourceKind::Manifest, "manifest.mpd", Path::new("manifest.mpd")).is_err());
+	}
+
+	#[test]
+	fn ingest_headers() {
+		let (url, requests, _chunks) = ingest_server();
+		let headers = vec![
+			("User-Agent".to_string(), "packager/1.0".to_string()),
+			("Authorization".to_string(), "Bearer secret".to_string()),
+			("X-Route".to_string(), "edge-1".to_string()),
+		];
+		let output = IngestOutput::new(Some(url), headers.clone());
+
+		let mut writer = output
+			.create_for_stream("sink_0", ResourceKind::Segment, "sink_0_segment_1.cmfv", Path::new("sink_0_segment_1.cmfv"))
+			.unwrap();
+		writer.write_all(b"moof").unwrap();
+		writer.flush().unwrap();
+
+		let request = requests.recv_timeout(INGEST_TIMEOUT).unwrap();
+		for (name, value) in &headers {
+			assert!(request.headers.contains(&(name.clone(), value.clone())), "{name} missing");
+		}
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn file_mode_applied() {
+		use std::os::unix::fs::PermissionsExt;
+
+		let root = std::env::temp_dir().join(format!("dashcmafsink-mode-{}", std::process::id()));
+		let _ = std::fs::remove_dir_all(&root);
+		let output = FileOutput::new(Some(0o640), 0);
+
+		for (kind, location) in [
+			(ResourceKind::Init, "init.cmfi"),
+			(ResourceKind::Segment, "segment_0.cmfv"),
+			(ResourceKind::Manifest, "manifest.mpd"),
+		] {
+			let path = root.join(location);
+			let mut file = output.create(kind, location, &path).unwrap();
+			file.write_all(b"data").unwrap();
+			file.flush().unwrap();
+			drop(file);
+			let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+			assert_eq!(mode & 0o7777, 0o640, "{location}");
+		}
+		let _ = std::fs::remove_dir_all(&root);
+	}
+
+	#[test]
+	fn open_files_bounded() {
+		let root = std::env::temp_dir().join(format!("dashcmafsink-open-files-{}", std::process::id()));
+		let output = FileOutput::new(None, 2);
+
+		// Segments of many pads written at once, past the first two they are
+		// reopened in append mode for every write
+		let paths = (0..8).map(|pad| root.join(format!("segment_{pad}.cmfv"))).collect::<Vec<_>>();
+		let mut writers = paths
+			.iter()
+			.map(|path| output.create(ResourceKind::Segment, "", path).unwrap())
+			.collect::<Vec<_>>();
+		for chunk in [b"moof".as_slice(), b"mdat"] {
+			for writer in &mut writers {
+				writer.write_all(chunk).unwrap();
+			}
+			assert_eq!(output.open_files.load(Ordering::SeqCst), 2);
+		}
+		for writer in &mut writers {
+			writer.flush().unwrap();
+		}
+		assert_eq!(output.open_files.load(Ordering::SeqCst), 0);
+		for path in &paths {
+			assert_eq!(std::fs::read(path).unwrap(), b"moofmdat");
+		}
+
+		std::fs::remove_dir_all(&root).unwrap();
+	}
+
+	/// Named pipe in a fresh directory, made by mkfifo
+	#[cfg(unix)]
+	fn fifo(name: &str) -> (PathBuf, PathBuf) {
+		let root = std::env::temp_dir().join(format!("dashcmafsink-{name}-{}", std::process::id()));
+		let _ = std::fs::remove_dir_all(&root);
+		std::fs::create_dir_all(&root).unwrap();
+		let path = root.join("relay.cmfv");
+		assert!(std::process::Command::new("mkfifo").arg(&path).status().unwrap().success());
+		(root, path)
+	}
+
+	#[test]
+	#[cfg(unix)]
+	fn fifo_written_in_place() {
+		let (root, path) = fifo("fifo");
+		let reader = {
+			let path = path.clone();
+			std::thread::spawn(move || std::fs::read(path).unwrap())
+		};
+
+		// Every resource goes to the same stream, without truncating it
+		let output = FileOutput::default();
+		for data in [b"init".as_slice(), b"moof", b"mdat"] {
+			let mut writer = output.create(ResourceKind::Segment, "relay.cmfv", &path).unwrap();
+			writer.write_all(data).unwrap();
+			writer.flush().unwrap();
+		}
+		assert_eq!(output.fifos.lock().unwrap().len(), 1);
+		drop(output);
+
+		assert_eq!(reader.join().unwrap(), b"initmoofmdat");
+		assert!(is_fifo(&path));
+		std::fs::remove_dir_all(&root).unwrap();
+	}
+
+	#[test]
+	#[cfg(unix)]
+	fn fifo_reader_gone() {
+		let (root, path) = fifo("fifo-closed");
+		let reader = {
+			let path = path.clone();
+			std::thread::spawn(move || drop(File::open(path).unwrap()))
+		};
+
+		let output = FileOutput::default();
+		let mut writer = output.create(ResourceKind::Segment, "relay.cmfv", &path).unwrap();
+		reader.join().unwrap();
+		// The reader closed its end, the pipe is broken
+		let err = writer.write_all(&[0; 65536]).unwrap_err();
+		assert_eq!(err.kind(), std::io::ErrorKind::BrokenPipe);
+
+		std::fs::remove_dir_all(&root).unwrap();
+	}
+}
@@ -0,0 +1,2028 @@
+// Copyright (C) 2025 Roberto Viola <rviola@vicomtech.org>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use gst::glib;
+use gst::prelude::*;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard, Once};
+
+/// Pipelines write relative to the working directory, so they run one at a time
+static SERIAL: Mutex<()> = Mutex::new(());
+
+fn init() {
+	static INIT: Once = Once::new();
+	INIT.call_once(|| {
+		gst::init().unwrap();
+		// Like an application with its own logger, which the plugin keeps
+		let _ = env_logger::builder().is_test(true).try_init();
+		gstdashcmafsink::plugin_register_static().expect("Failed to register dashcmafsink");
+	});
+}
+
+/// Whether any of `elements` isn't installed, in which case the test is skipped
+fn missing(elements: &[&str]) -> bool {
+	init();
+	let missing = elements
+		.iter()
+		.filter(|name| gst::ElementFactory::find(name).is_none())
+		.collect::<Vec<_>>();
+	if !missing.is_empty() {
+		eprintln!("skipping, missing elements: {missing:?}");
+	}
+	!missing.is_empty()
+}
+
+/// Empty directory the test runs in, removed when dropped
+struct Workdir {
+	path: PathBuf,
+	previous: PathBuf,
+	_serial: MutexGuard<'static, ()>,
+}
+
+impl Workdir {
+	fn new(name: &str) -> Self {
+		let serial = SERIAL.lock().unwrap_or_else(|err| err.into_inner());
+		let path = std::env::temp_dir().join(format!("dashcmafsink-{}-{name}", std::process::id()));
+		let _ = std::fs::remove_dir_all(&path);
+		std::fs::create_dir_all(&path).unwrap();
+		let previous = std::env::current_dir().unwrap();
+		std::env::set_current_dir(&path).unwrap();
+
+		Self {
+			path,
+			previous,
+			_serial: serial,
+		}
+	}
+
+	fn path(&self, location: &str) -> PathBuf {
+		self.path.join(location)
+	}
+
+	fn manifest(&self, location: &str) -> dash_mpd::MPD {
+		let xml = std::fs::read_to_string(self.path(location)).expect("Missing manifest");
+		dash_mpd::parse(&xml).expect("Invalid manifest")
+	}
+}
+
+impl Drop for Workdir {
+	fn drop(&mut self) {
+		let _ = std::env::set_current_dir(&self.previous);
+		let _ = std::fs::remove_dir_all(&self.path);
+	}
+}
+
+/// First installed AAC encoder
+fn aac_encoder() -> Option<&'static str> {
+	["avenc_aac", "fdkaacenc", "voaacenc"]
+		.into_iter()
+		.find(|name| gst::ElementFactory::find(name).is_some())
+}
+
+fn launch(description: &str) -> gst::Pipeline {
+	gst::parse::launch(description)
+		.expect("Invalid pipeline")
+		.downcast::<gst::Pipeline>()
+		.unwrap()
+}
+
+/// Plays `pipeline` until EOS, or the error posted on the bus, leaving it
+/// playing so the element can still be inspected
+fn wait(pipeline: &gst::Pipeline) -> Result<(), String> {
+	let bus = pipeline.bus().unwrap();
+	pipeline.set_state(gst::State::Playing).map_err(|err| err.to_string())?;
+
+	match bus.timed_pop_filtered(
+		gst::ClockTime::from_seconds(60),
+		&[gst::MessageType::Eos, gst::MessageType::Error],
+	) {
+		Some(msg) => match msg.view() {
+			gst::MessageView::Error(err) => Err(format!("{} ({:?})", err.error(), err.debug())),
+			_ => Ok(()),
+		},
+		None => Err("timed out".to_string()),
+	}
+}
+
+/// Plays `pipeline` until EOS, or the error posted on the bus
+fn play(pipeline: &gst::Pipeline) -> Result<(), String> {
+	let result = wait(pipeline);
+	pipeline.set_state(gst::State::Null).unwrap();
+	result
+}
+
+/// Collects the warnings posted on the bus of `pipeline`
+fn warnings(pipeline: &gst::Pipeline) -> Arc<Mutex<Vec<String>>> {
+	let warnings = Arc::new(Mutex::new(Vec::new()));
+	let collected = warnings.clone();
+	pipeline.bus().unwrap().set_sync_handler(move |_bus, msg| {
+		if let gst::MessageView::Warning(warning) = msg.view() {
+			collected.lock().unwrap().push(warning.error().to_string());
+		}
+		gst::BusSyncReply::Pass
+	});
+	warnings
+}
+
+fn run(description: &str) -> Result<(), String> {
+	play(&launch(description))
+}
+
+fn representations(mpd: &dash_mpd::MPD) -> Vec<&dash_mpd::Representation> {
+	mpd.periods
+		.iter()
+		.flat_map(|period| &period.adaptations)
+		.flat_map(|adaptation| &adaptation.representations)
+		.collect()
+}
+
+#[test]
+fn mp3_representation() {
+	if missing(&["cmafmux", "audiotestsrc", "lamemp3enc", "mpegaudioparse"]) {
+		return;
+	}
+	let dir = Workdir::new("mp3");
+
+	let result = run(
+		"audiotestsrc num-buffers=200 ! lamemp3enc ! mpegaudioparse ! dashcmafsink sync=false target-duration=1",
+	);
+
+	let cmafmux = gst::ElementFactory::make("cmafmux").build().unwrap();
+	let mp3 = gst::Caps::builder("audio/mpeg").field("mpegversion", 1i32).build();
+	let template = cmafmux.static_pad("sink").unwrap().pad_template_caps();
+	if template.can_intersect(&mp3) {
+		result.unwrap();
+		let mpd = dir.manifest("manifest.mpd");
+		assert_eq!(representations(&mpd)[0].codecs.as_deref(), Some("mp4a.6B"));
+	} else {
+		let err = result.unwrap_err();
+		assert!(err.contains("MP3"), "{err}");
+	}
+}
+
+#[test]
+fn ac3_channel_configuration() {
+	if missing(&["cmafmux", "audiotestsrc", "avenc_ac3", "ac3parse"]) {
+		return;
+	}
+	let dir = Workdir::new("ac3");
+
+	run(
+		"audiotestsrc num-buffers=200 ! audio/x-raw,channels=2 ! avenc_ac3 ! ac3parse ! dashcmafsink sync=false target-duration=1",
+	)
+	.unwrap();
+
+	let mpd = dir.manifest("manifest.mpd");
+	let rep = representations(&mpd)[0];
+	assert_eq!(rep.codecs.as_deref(), Some("ac-3"));
+	let configuration = &rep.AudioChannelConfiguration[0];
+	assert_eq!(configuration.schemeIdUri, "tag:dolby.com,2014:dash:audio_channel_configuration:2011");
+	assert_eq!(configuration.value.as_deref(), Some("A000"));
+}
+
+#[test]
+fn roles_split_adaptation_sets() {
+	if missing(&["cmafmux", "audiotestsrc", "aacparse"]) {
+		return;
+	}
+	let Some(aac) = aac_encoder() else {
+		return;
+	};
+	let dir = Workdir::new("roles");
+
+	let pipeline = launch(&format!(
+		"audiotestsrc num-buffers=100 ! {aac} ! aacparse ! s.sink_0 \
+		 audiotestsrc num-buffers=100 ! {aac} ! aacparse ! s.sink_1 \
+		 dashcmafsink name=s sync=false target-duration=1"
+	));
+	let sink = pipeline.by_name("s").unwrap();
+	sink.static_pad("sink_0").unwrap().set_property("role", "main");
+	let commentary = sink.static_pad("sink_1").unwrap();
+	commentary.set_property("role", "commentary");
+	commentary.set_property("accessibility", "description");
+	play(&pipeline).unwrap();
+
+	let mpd = dir.manifest("manifest.mpd");
+	let adaptations = &mpd.periods[0].adaptations;
+	assert_eq!(adaptations.len(), 2);
+	let main = adaptations
+		.iter()
+		.find(|adaptation| adaptation.Role[0].value.as_deref() == Some("main"))
+		.unwrap();
+	assert!(main.Accessibility.is_empty());
+	let commentary = adaptations
+		.iter()
+		.find(|adaptation| adaptation.Role[0].value.as_deref() == Some("commentary"))
+		.unwrap();
+	assert_eq!(commentary.Accessibility[0].value.as_deref(), Some("description"));
+}
+
+#[test]
+fn lang_from_property_and_tags() {
+	if missing(&["cmafmux", "audiotestsrc", "aacparse", "taginject"]) {
+		return;
+	}
+	let Some(aac) = aac_encoder() else {
+		return;
+	};
+	let dir = Workdir::new("lang");
+
+	let pipeline = launch(&format!(
+		"audiotestsrc num-buffers=100 ! {aac} ! aacparse ! s.sink_0 \
+		 audiotestsrc num-buffers=100 ! {aac} ! aacparse ! taginject tags=\"language-code=fr\" ! s.sink_1 \
+		 dashcmafsink name=s sync=false target-duration=1"
+	));
+	let sink = pipeline.by_name("s").unwrap();
+	sink.static_pad("sink_0").unwrap().set_property("lang", "en");
+	play(&pipeline).unwrap();
+
+	let mpd = dir.manifest("manifest.mpd");
+	let mut langs = mpd.periods[0]
+		.adaptations
+		.iter()
+		.map(|adaptation| adaptation.lang.clone())
+		.collect::<Vec<_>>();
+	langs.sort();
+	assert_eq!(langs, [Some("en".to_string()), Some("fr".to_string())]);
+}
+
+/// Launch line of a short H.264 stream into `sink`
+fn h264(frames: u32, sink: &str) -> String {
+	format!(
+		"videotestsrc num-buffers={frames} ! video/x-raw,width=320,height=240,framerate=30/1 \
+		 ! x264enc key-int-max=30 bframes=0 ! h264parse ! {sink}"
+	)
+}
+
+#[test]
+fn mpd_and_period_ids() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse"]) {
+		return;
+	}
+	let dir = Workdir::new("ids");
+
+	run(&h264(60, "dashcmafsink sync=false target-duration=1")).unwrap();
+	let mpd = dir.manifest("manifest.mpd");
+	assert_eq!(mpd.id, None);
+	let period_id = mpd.periods[0].id.clone().unwrap();
+	assert!(regex::Regex::new(r"^p\d{8}T\d{6}Z$").unwrap().is_match(&period_id), "{period_id}");
+
+	run(&h264(60, "dashcmafsink sync=false target-duration=1 mpd-id=live period-id=first")).unwrap();
+	let mpd = dir.manifest("manifest.mpd");
+	assert_eq!(mpd.id.as_deref(), Some("live"));
+	assert_eq!(mpd.periods[0].id.as_deref(), Some("first"));
+}
+
+#[test]
+fn parent_dirs_created() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse"]) {
+		return;
+	}
+	let dir = Workdir::new("dirs");
+
+	run(&h264(
+		60,
+		"dashcmafsink sync=false target-duration=1 location=dash/live/manifest.mpd \
+		 init-location=init/init.cmfi segment-location=media/segment_%d.cmfv",
+	))
+	.unwrap();
+
+	assert!(dir.path("dash/live/manifest.mpd").is_file());
+	assert!(dir.path("sink_0_init/init.cmfi").is_file());
+	assert!(dir.path("sink_0_media/segment_0.cmfv").is_file());
+}
+
+#[test]
+fn stream_stats() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse"]) {
+		return;
+	}
+	let dir = Workdir::new("stats");
+
+	let pipeline = launch(&h264(90, "dashcmafsink name=s sync=false target-duration=1"));
+	wait(&pipeline).unwrap();
+	let sink = pipeline.by_name("s").unwrap();
+	let stats = sink
+		.emit_by_name::<Option<gst::Structure>>("get-stream-stats", &[&"sink_0"])
+		.unwrap();
+	let segments = sink.property::<u64>("segments-written");
+	let bytes = sink.property::<u64>("bytes-written");
+	pipeline.set_state(gst::State::Null).unwrap();
+
+	assert_eq!(stats.get::<u64>("segments-written").unwrap(), segments);
+	assert_eq!(stats.get::<u64>("bytes-written").unwrap(), bytes);
+	let written = (0..segments)
+		.map(|idx| std::fs::metadata(dir.path(&format!("sink_0_segment_{idx}.cmfv"))).unwrap().len())
+		.sum::<u64>();
+	assert!(segments >= 2);
+	assert_eq!(written, bytes);
+}
+
+#[test]
+fn start_number_is_first_segment() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse"]) {
+		return;
+	}
+	let dir = Workdir::new("start-number");
+
+	run(&h264(60, "dashcmafsink sync=false target-duration=1")).unwrap();
+
+	let mpd = dir.manifest("manifest.mpd");
+	let template = representations(&mpd)[0].SegmentTemplate.clone().unwrap();
+	let start_number = template.startNumber.unwrap();
+	let first = template.media.unwrap().replace("$Number$", &start_number.to_string());
+	assert!(dir.path(&first).is_file(), "{first}");
+}
+
+#[test]
+fn output_dir() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse"]) {
+		return;
+	}
+	let dir = Workdir::new("output-dir");
+
+	run(&h264(60, "dashcmafsink sync=false target-duration=1 output-dir=out")).unwrap();
+
+	// Locations in the manifest stay relative to it
+	let mpd = dir.manifest("out/manifest.mpd");
+	let template = representations(&mpd)[0].SegmentTemplate.clone().unwrap();
+	assert_eq!(template.initialization.as_deref(), Some("sink_0_init.cmfi"));
+	assert!(dir.path("out/sink_0_init.cmfi").is_file());
+	assert!(dir.path("out/sink_0_segment_0.cmfv").is_file());
+	assert!(!dir.path("manifest.mpd").exists());
+}
+
+#[test]
+fn memory_backend() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse"]) {
+		return;
+	}
+	let dir = Workdir::new("memory");
+
+	let pipeline = launch(&h264(90, "dashcmafsink name=s sync=false target-duration=1 backend=memory window-size=1"));
+	wait(&pipeline).unwrap();
+	let sink = pipeline.by_name("s").unwrap();
+	let pull = |location: &str| sink.emit_by_name::<Option<glib::Bytes>>("pull-resource", &[&location]);
+	let manifest = pull("manifest.mpd").unwrap();
+	let init = pull("sink_0_init.cmfi");
+	let first = pull("sink_0_segment_0.cmfv");
+	let segments = sink.property::<u64>("segments-written");
+	let last = pull(&format!("sink_0_segment_{}.cmfv", segments - 1));
+	pipeline.set_state(gst::State::Null).unwrap();
+
+	dash_mpd::parse(std::str::from_utf8(&manifest).unwrap()).unwrap();
+	assert!(init.is_some());
+	assert!(segments >= 2);
+	assert!(first.is_none());
+	assert!(last.is_some());
+	assert!(std::fs::read_dir(&dir.path).unwrap().next().is_none());
+}
+
+#[test]
+fn dynamic_manifest() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse"]) {
+		return;
+	}
+	let dir = Workdir::new("dynamic");
+
+	run(&h264(
+		60,
+		"dashcmafsink sync=false target-duration=1 playlist-type=dynamic presentation-delay=4000",
+	))
+	.unwrap();
+
+	let mpd = dir.manifest("manifest.mpd");
+	assert_eq!(mpd.mpdtype.as_deref(), Some("dynamic"));
+	assert_eq!(mpd.profiles.as_deref(), Some("urn:mpeg:dash:profile:isoff-live:2011"));
+	assert_eq!(mpd.mediaPresentationDuration, None);
+	assert!(mpd.publishTime.is_some());
+	assert_eq!(mpd.suggestedPresentationDelay, Some(std::time::Duration::from_secs(4)));
+}
+
+#[test]
+fn real_segment_timing() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse"]) {
+		return;
+	}
+	let dir = Workdir::new("timing");
+
+	// 2.5 s of video, the manifest doesn't round it to whole segments
+	run(&h264(75, "dashcmafsink sync=false target-duration=1")).unwrap();
+
+	let mpd = dir.manifest("manifest.mpd");
+	assert_eq!(mpd.mediaPresentationDuration, Some(std::time::Duration::from_millis(2500)));
+}
+
+#[test]
+fn strict_alignment() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse"]) {
+		return;
+	}
+	let _dir = Workdir::new("alignment");
+	let source = |gop: u32| {
+		format!(
+			"videotestsrc num-buffers=120 ! video/x-raw,width=320,height=240,framerate=30/1 \
+			 ! x264enc key-int-max={gop} bframes=0 ! h264parse"
+		)
+	};
+
+	let pipeline = launch(&format!(
+		"{} ! s.sink_0 {} ! s.sink_1 dashcmafsink name=s sync=false target-duration=1 strict-alignment=true",
+		source(30),
+		source(30)
+	));
+	let aligned = warnings(&pipeline);
+	play(&pipeline).unwrap();
+	assert!(aligned.lock().unwrap().is_empty(), "{:?}", aligned.lock().unwrap());
+
+	let pipeline = launch(&format!(
+		"{} ! s.sink_0 {} ! s.sink_1 dashcmafsink name=s sync=false target-duration=1 strict-alignment=true",
+		source(30),
+		source(45)
+	));
+	let misaligned = warnings(&pipeline);
+	play(&pipeline).unwrap();
+	assert!(misaligned.lock().unwrap().iter().any(|warning| warning.contains("not aligned")));
+}
+
+#[test]
+fn max_segment_duration() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse"]) {
+		return;
+	}
+	let dir = Workdir::new("max-segment-duration");
+
+	// Keyframes every 1.5 s make fragments longer than the target duration
+	run(
+		"videotestsrc num-buffers=90 ! video/x-raw,width=320,height=240,framerate=30/1 \
+		 ! x264enc key-int-max=45 bframes=0 ! h264parse ! dashcmafsink sync=false target-duration=1",
+	)
+	.unwrap();
+
+	let mpd = dir.manifest("manifest.mpd");
+	assert_eq!(mpd.maxSegmentDuration, Some(std::time::Duration::from_millis(1500)));
+}
+
+#[test]
+fn resume_numbering() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse"]) {
+		return;
+	}
+	let dir = Workdir::new("resume");
+
+	run(&h264(60, "dashcmafsink sync=false target-duration=1 start-segment-number=10")).unwrap();
+	let first = dir.manifest("manifest.mpd");
+	let first_duration = first.mediaPresentationDuration.unwrap();
+
+	run(&h264(60, "dashcmafsink sync=false target-duration=1 start-segment-number=10 resume=true")).unwrap();
+	let second = dir.manifest("manifest.mpd");
+
+	let template = representations(&second)[0].SegmentTemplate.clone().unwrap();
+	assert_eq!(template.startNumber, Some(10));
+	assert_eq!(first.availabilityStartTime, second.availabilityStartTime);
+	assert!(second.mediaPresentationDuration.unwrap() > first_duration);
+	// The second run continued after the segments of the first one
+	assert!(dir.path("sink_0_segment_12.cmfv").is_file());
+	assert!(dir.path("sink_0_segment_13.cmfv").is_file());
+}
+
+#[test]
+fn thumbnail_adaptation_set() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse", "jpegenc"]) {
+		return;
+	}
+	let dir = Workdir::new("thumbnails");
+
+	run(&format!(
+		"{} videotestsrc num-buffers=2 ! video/x-raw,width=160,height=90,framerate=1/1 ! jpegenc ! s.thumbnail_0 \
+		 dashcmafsink name=s sync=false target-duration=1 thumbnail-tiles=2x2",
+		h264(60, "s.sink_0")
+	))
+	.unwrap();
+
+	let mpd = dir.manifest("manifest.mpd");
+	let image = mpd.periods[0]
+		.adaptations
+		.iter()
+		.find(|adaptation| adaptation.contentType.as_deref() == Some("image"))
+		.unwrap();
+	let rep = &image.representations[0];
+	assert_eq!(rep.essential_property[0].schemeIdUri, "http://dashif.org/thumbnail_tile");
+	assert_eq!(rep.essential_property[0].value.as_deref(), Some("2x2"));
+	assert_eq!(rep.SegmentTemplate.as_ref().unwrap().duration, Some(4000.0));
+	assert!(dir.path("thumbnail_0_thumbnail_0.jpg").is_file());
+}
+
+#[test]
+fn streams_without_segments_skipped() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse", "audiotestsrc", "aacparse"]) {
+		return;
+	}
+	let Some(aac) = aac_encoder() else {
+		return;
+	};
+	let dir = Workdir::new("no-segments");
+
+	run(&format!(
+		"{} audiotestsrc num-buffers=0 ! {aac} ! aacparse ! s.sink_1 dashcmafsink name=s sync=false target-duration=1",
+		h264(60, "s.sink_0")
+	))
+	.unwrap();
+
+	let mpd = dir.manifest("manifest.mpd");
+	let ids = representations(&mpd).iter().map(|rep| rep.id.clone()).collect::<Vec<_>>();
+	assert_eq!(ids, [Some("sink_0".to_string())]);
+}
+
+#[test]
+fn manifest_indentation() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse"]) {
+		return;
+	}
+	let dir = Workdir::new("indent");
+
+	run(&h264(30, "dashcmafsink sync=false target-duration=1 manifest-indent=0")).unwrap();
+	let compact = std::fs::read_to_string(dir.path("manifest.mpd")).unwrap();
+	assert_eq!(compact.lines().count(), 2);
+
+	run(&h264(30, "dashcmafsink sync=false target-duration=1 manifest-indent=2")).unwrap();
+	let indented = std::fs::read_to_string(dir.path("manifest.mpd")).unwrap();
+	assert!(indented.lines().count() > 2);
+	assert!(indented.contains("\n  <Period"));
+}
+
+
+/// Ingest endpoint recording the request line of every request, answering
+/// them all with 201 after `delay`
+fn ingest_server(delay: std::time::Duration) -> (String, Arc<Mutex<Vec<String>>>) {
+	let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+	let url = format!("http://{}/live", listener.local_addr().unwrap());
+	let requests = Arc::new(Mutex::new(Vec::new()));
+	let recorded = requests.clone();
+	std::thread::spawn(move || {
+		for stream in listener.incoming() {
+			let recorded = recorded.clone();
+			std::thread::spawn(move || {
+				use std::io::{BufRead, Read, Write};
+				let mut stream = stream.unwrap();
+				let mut reader = std::io::BufReader::new(stream.try_clone().unwrap());
+				let mut line = String::new();
+				while reader.read_line(&mut line).unwrap_or(0) > 0 {
+					recorded.lock().unwrap().push(line.trim_end().to_string());
+					while reader.read_line(&mut line).unwrap() > 0 && !line.ends_with("\r\n\r\n") {}
+					loop {
+						line.clear();
+						reader.read_line(&mut line).unwrap();
+						let size = usize::from_str_radix(line.trim_end(), 16).unwrap();
+						reader.read_exact(&mut vec![0; size + 2]).unwrap();
+						if size == 0 {
+							break;
+						}
+					}
+					std::thread::sleep(delay);
+					stream.write_all(b"HTTP/1.1 201 Created\r\nContent-Length: 0\r\n\r\n").unwrap();
+					line.clear();
+				}
+			});
+		}
+	});
+
+	(url, requests)
+}
+
+#[test]
+fn ingest_backend() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse"]) {
+		return;
+	}
+	let dir = Workdir::new("ingest");
+
+	let (url, requests) = ingest_server(std::time::Duration::ZERO);
+
+	run(&h264(60, &format!("dashcmafsink sync=false target-duration=1 backend=ingest ingest-url={url}"))).unwrap();
+
+	let requests = requests.lock().unwrap();
+	assert_eq!(requests[0], "POST /live/sink_0/sink_0_init.cmfi HTTP/1.1");
+	assert!(requests.contains(&"POST /live/sink_0/sink_0_segment_0.cmfv HTTP/1.1".to_string()));
+	assert_eq!(requests.last().unwrap(), "PUT /live/manifest.mpd HTTP/1.1");
+	assert!(std::fs::read_dir(&dir.path).unwrap().next().is_none());
+}
+
+#[test]
+fn cmaf_brand_profiles() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse"]) {
+		return;
+	}
+	let dir = Workdir::new("cmaf-brand");
+
+	run(&h264(30, "dashcmafsink sync=false target-duration=1 cmaf-brand=cmfc")).unwrap();
+	let mpd = dir.manifest("manifest.mpd");
+	let adaptation = &mpd.periods[0].adaptations[0];
+	assert_eq!(adaptation.mimeType.as_deref(), Some("video/mp4; profiles=\"cmfc\""));
+}
+
+#[test]
+fn time_addressing() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse"]) {
+		return;
+	}
+	let dir = Workdir::new("time-addressing");
+
+	run(&h264(60, "dashcmafsink sync=false target-duration=1 addressing=time")).unwrap();
+	let mpd = dir.manifest("manifest.mpd");
+	let template = representations(&mpd)[0].SegmentTemplate.clone().unwrap();
+	assert_eq!(template.media.as_deref(), Some("sink_0_segment_$Time$.cmfv"));
+	let timeline = template.SegmentTimeline.unwrap();
+	assert_eq!(timeline.segments[0].t, Some(0));
+	assert_eq!(timeline.segments[0].d, 1000);
+	assert_eq!(timeline.segments[0].r, Some(1));
+	assert!(dir.path("sink_0_segment_0.cmfv").exists());
+	assert!(dir.path("sink_0_segment_1000.cmfv").exists());
+}
+
+#[test]
+fn appsink_limits() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse"]) {
+		return;
+	}
+	let _dir = Workdir::new("appsink-limits");
+
+	let pipeline = launch(&h264(60, "dashcmafsink name=s sync=false target-duration=1 max-buffers=3 drop=true"));
+	let sink = pipeline.by_name("s").unwrap().downcast::<gst::Bin>().unwrap();
+	wait(&pipeline).unwrap();
+	let appsink = sink
+		.iterate_elements()
+		.into_iter()
+		.map(Result::unwrap)
+		.find(|element| element.factory().is_some_and(|factory| factory.name() == "appsink"))
+		.unwrap();
+	pipeline.set_state(gst::State::Null).unwrap();
+
+	assert_eq!(appsink.property::<u32>("max-buffers"), 3);
+	assert!(appsink.property::<bool>("drop"));
+}
+
+#[test]
+fn registers_with_a_logger_installed() {
+	init();
+	assert!(gst::ElementFactory::find("dashcmafsink").is_some());
+}
+
+#[test]
+fn segment_list_addressing() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse"]) {
+		return;
+	}
+	let dir = Workdir::new("segment-list");
+
+	run(&h264(60, "dashcmafsink sync=false target-duration=1 addressing=segment-list")).unwrap();
+	let mpd = dir.manifest("manifest.mpd");
+	let rep = representations(&mpd)[0];
+	assert!(rep.SegmentTemplate.is_none());
+	let list = rep.SegmentList.as_ref().unwrap();
+	let media = list.segment_urls.iter().map(|url| url.media.clone().unwrap()).collect::<Vec<_>>();
+	assert_eq!(media, ["sink_0_segment_0.cmfv", "sink_0_segment_1.cmfv"]);
+	for location in media {
+		assert!(dir.path(&location).exists());
+	}
+}
+
+#[test]
+fn absolute_urls() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse"]) {
+		return;
+	}
+	let dir = Workdir::new("absolute-urls");
+
+	run(&h264(30, "dashcmafsink sync=false target-duration=1 url-mode=absolute base-url=http://cdn.example.com/live")).unwrap();
+	let mpd = dir.manifest("manifest.mpd");
+	let template = representations(&mpd)[0].SegmentTemplate.clone().unwrap();
+	assert_eq!(template.initialization.as_deref(), Some("http://cdn.example.com/live/sink_0_init.cmfi"));
+	assert_eq!(template.media.as_deref(), Some("http://cdn.example.com/live/sink_0_segment_$Number$.cmfv"));
+}
+
+#[test]
+fn muxed_representation() {
+	if missing(&["isofmp4mux", "videotestsrc", "x264enc", "h264parse", "audiotestsrc", "aacparse"]) {
+		return;
+	}
+	let Some(aac) = aac_encoder() else {
+		return;
+	};
+	let dir = Workdir::new("muxed");
+
+	run(&format!(
+		"{} audiotestsrc num-buffers=100 ! {aac} ! aacparse ! s.sink_1 dashcmafsink name=s sync=false target-duration=1 mux-streams=true",
+		h264(60, "s.sink_0")
+	))
+	.unwrap();
+
+	let mpd = dir.manifest("manifest.mpd");
+	let reps = representations(&mpd);
+	assert_eq!(reps.len(), 1);
+	assert_eq!(reps[0].codecs.as_deref(), Some("avc1.64001e,mp4a.40.2"));
+	assert_eq!((reps[0].width, reps[0].height), (Some(320), Some(240)));
+	assert!(dir.path("sink_0_init.cmfi").exists());
+	assert!(!dir.path("sink_1_init.cmfi").exists());
+}
+
+#[test]
+fn forced_keyframes() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse"]) {
+		return;
+	}
+	let dir = Workdir::new("force-keyframe");
+
+	// The encoder alone would only make a keyframe every 10 s
+	let pipeline = launch(
+		"videotestsrc num-buffers=90 ! video/x-raw,width=320,height=240,framerate=30/1 ! x264enc key-int-max=300 bframes=0 ! h264parse ! \
+		dashcmafsink name=s sync=false target-duration=1 force-keyframe=true",
+	);
+	play(&pipeline).unwrap();
+
+	let sink = pipeline.by_name("s").unwrap();
+	assert_eq!(sink.property::<u64>("segments-written"), 3);
+	for idx in 0..3 {
+		assert!(dir.path(&format!("sink_0_segment_{idx}.cmfv")).exists());
+	}
+}
+
+#[test]
+fn eos_sent_to_the_element() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse"]) {
+		return;
+	}
+	let dir = Workdir::new("send-eos");
+
+	// A live source never ends by itself, the application ends the output
+	let pipeline = launch(
+		"videotestsrc is-live=true ! video/x-raw,width=320,height=240,framerate=30/1 \
+		 ! x264enc key-int-max=30 bframes=0 tune=zerolatency ! h264parse \
+		 ! dashcmafsink name=s sync=false target-duration=1",
+	);
+	let sink = pipeline.by_name("s").unwrap();
+	let bus = pipeline.bus().unwrap();
+	pipeline.set_state(gst::State::Playing).unwrap();
+	while sink.property::<u64>("segments-written") == 0 {
+		assert!(bus.timed_pop_filtered(gst::ClockTime::from_mseconds(100), &[gst::MessageType::Error]).is_none());
+	}
+	std::thread::sleep(std::time::Duration::from_millis(500));
+
+	assert!(sink.send_event(gst::event::Eos::new()));
+	let result = wait(&pipeline);
+	let segments = sink.property::<u64>("segments-written");
+	pipeline.set_state(gst::State::Null).unwrap();
+	result.unwrap();
+
+	// The fragment cmafmux still held is written as a last, shorter segment
+	assert!(segments >= 2);
+	assert!(dir.path(&format!("sink_0_segment_{}.cmfv", segments - 1)).exists());
+	let mpd = dir.manifest("manifest.mpd");
+	let duration = mpd.mediaPresentationDuration.unwrap();
+	assert!(duration > std::time::Duration::from_secs(segments - 1), "{duration:?}");
+	assert!(duration < std::time::Duration::from_secs(segments), "{duration:?}");
+}
+
+#[test]
+fn fragment_duration_per_pad() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse"]) {
+		return;
+	}
+	let dir = Workdir::new("fragment-duration");
+
+	// A keyframe every 15 frames, so 500 ms fragments can be cut
+	let pipeline = launch(
+		"videotestsrc num-buffers=60 ! video/x-raw,width=320,height=240,framerate=30/1 \
+		 ! x264enc key-int-max=15 bframes=0 ! h264parse ! dashcmafsink name=s sync=false target-duration=1",
+	);
+	let sink = pipeline.by_name("s").unwrap();
+	sink.static_pad("sink_0").unwrap().set_property("fragment-duration", 500_000_000u64);
+	play(&pipeline).unwrap();
+
+	assert_eq!(sink.property::<u64>("segments-written"), 4);
+	let mpd = dir.manifest("manifest.mpd");
+	let template = representations(&mpd)[0].SegmentTemplate.clone().unwrap();
+	assert_eq!(template.duration, Some(500.0));
+}
+
+#[test]
+fn init_location_with_pad_name() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse"]) {
+		return;
+	}
+	let dir = Workdir::new("init-location");
+
+	run(&h264(30, "dashcmafsink sync=false target-duration=1 init-location=\"$RepresentationID$/init.cmfi\"")).unwrap();
+	assert!(dir.path("sink_0/init.cmfi").exists());
+	let mpd = dir.manifest("manifest.mpd");
+	let template = representations(&mpd)[0].SegmentTemplate.clone().unwrap();
+	assert_eq!(template.initialization.as_deref(), Some("sink_0/init.cmfi"));
+}
+
+#[test]
+fn program_information_and_labels() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse"]) {
+		return;
+	}
+	let dir = Workdir::new("program-information");
+
+	let pipeline = launch(&h264(30, "dashcmafsink name=s sync=false target-duration=1 title=News copyright=Vicomtech"));
+	let sink = pipeline.by_name("s").unwrap();
+	sink.static_pad("sink_0").unwrap().set_property("label", "Main camera");
+	play(&pipeline).unwrap();
+
+	let mpd = dir.manifest("manifest.mpd");
+	let info = &mpd.ProgramInformation[0];
+	assert_eq!(info.Title.as_ref().and_then(|title| title.content.as_deref()), Some("News"));
+	assert_eq!(info.Copyright.as_ref().and_then(|copyright| copyright.content.as_deref()), Some("Vicomtech"));
+	assert_eq!(mpd.periods[0].adaptations[0].Label[0].content, "Main camera");
+}
+
+#[test]
+fn webm_container() {
+	if missing(&["webmmux", "videotestsrc", "vp8enc"]) {
+		return;
+	}
+	let dir = Workdir::new("webm");
+
+	run(
+		"videotestsrc num-buffers=60 ! video/x-raw,width=320,height=240,framerate=30/1 \
+		 ! vp8enc keyframe-max-dist=30 deadline=1 ! dashcmafsink sync=false target-duration=1 container=webm",
+	)
+	.unwrap();
+
+	let mpd = dir.manifest("manifest.mpd");
+	let adaptation = &mpd.periods[0].adaptations[0];
+	assert_eq!(adaptation.mimeType.as_deref(), Some("video/webm"));
+	assert_eq!(adaptation.representations[0].codecs.as_deref(), Some("vp8"));
+	// The EBML header is the init segment, every Cluster a media segment
+	assert!(std::fs::read(dir.path("sink_0_init.cmfi")).unwrap().starts_with(&[0x1A, 0x45, 0xDF, 0xA3]));
+	assert!(std::fs::read(dir.path("sink_0_segment_0.cmfv")).unwrap().starts_with(&[0x1F, 0x43, 0xB6, 0x75]));
+}
+
+#[test]
+fn max_duration_ends_the_recording() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse"]) {
+		return;
+	}
+	let dir = Workdir::new("max-duration");
+
+	let pipeline = launch(&h264(300, "dashcmafsink name=s sync=false target-duration=1 max-duration=2000000000"));
+	play(&pipeline).unwrap();
+
+	let sink = pipeline.by_name("s").unwrap();
+	assert_eq!(sink.property::<u64>("segments-written"), 2);
+	assert!(!dir.path("sink_0_segment_2.cmfv").exists());
+	let mpd = dir.manifest("manifest.mpd");
+	assert_eq!(mpd.mediaPresentationDuration, Some(std::time::Duration::from_secs(2)));
+}
+
+#[test]
+fn padded_segment_numbers() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse"]) {
+		return;
+	}
+	let dir = Workdir::new("padded-numbers");
+
+	run(&h264(30, "dashcmafsink sync=false target-duration=1 segment-location=segment_%05d.cmfv")).unwrap();
+	assert!(dir.path("sink_0_segment_00000.cmfv").exists());
+	let mpd = dir.manifest("manifest.mpd");
+	let template = representations(&mpd)[0].SegmentTemplate.clone().unwrap();
+	assert_eq!(template.media.as_deref(), Some("sink_0_segment_$Number%05d$.cmfv"));
+}
+
+#[test]
+fn content_protection() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse"]) {
+		return;
+	}
+	let dir = Workdir::new("content-protection");
+
+	run(&h264(30, "dashcmafsink sync=false target-duration=1 widevine-pssh=AAAAQHBzc2g=")).unwrap();
+	let mpd = dir.manifest("manifest.mpd");
+	let descriptors = &mpd.periods[0].adaptations[0].ContentProtection;
+	assert_eq!(descriptors.len(), 2);
+	assert_eq!(descriptors[0].value.as_deref(), Some("cenc"));
+	assert_eq!(descriptors[1].cenc_pssh[0].content.as_deref(), Some("AAAAQHBzc2g="));
+}
+
+#[test]
+fn grouped_audio_codecs() {
+	if missing(&["cmafmux", "audiotestsrc", "aacparse", "avenc_ac3", "ac3parse"]) {
+		return;
+	}
+	let Some(aac) = aac_encoder() else {
+		return;
+	};
+	let dir = Workdir::new("groups");
+
+	let pipeline = launch(&format!(
+		"audiotestsrc num-buffers=100 ! tee name=t \
+		 t. ! queue ! {aac} ! aacparse ! s.sink_0 \
+		 t. ! queue ! audio/x-raw,channels=2 ! avenc_ac3 ! ac3parse ! s.sink_1 \
+		 dashcmafsink name=s sync=false target-duration=1"
+	));
+	let sink = pipeline.by_name("s").unwrap();
+	for pad in ["sink_0", "sink_1"] {
+		let pad = sink.static_pad(pad).unwrap();
+		pad.set_property("group", 1u32);
+		pad.set_property("lang", "en");
+	}
+	play(&pipeline).unwrap();
+
+	// One AdaptationSet per codec, alternatives of the same group
+	let mpd = dir.manifest("manifest.mpd");
+	let adaptations = &mpd.periods[0].adaptations;
+	assert_eq!(adaptations.len(), 2);
+	let mut codecs = adaptations
+		.iter()
+		.map(|adaptation| {
+			assert_eq!(adaptation.group, Some(1));
+			assert_eq!(adaptation.lang.as_deref(), Some("en"));
+			adaptation.representations[0].codecs.clone().unwrap()
+		})
+		.collect::<Vec<_>>();
+	codecs.sort();
+	assert_eq!(codecs, ["ac-3", "mp4a.40.2"]);
+}
+
+#[test]
+fn unsynced_output_faster_than_realtime() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse"]) {
+		return;
+	}
+	let dir = Workdir::new("unsynced");
+
+	// Ten seconds of video
+	let started = std::time::Instant::now();
+	run(&h264(300, "dashcmafsink sync=false target-duration=1")).unwrap();
+	assert!(started.elapsed() < std::time::Duration::from_secs(10), "{:?}", started.elapsed());
+
+	let mpd = dir.manifest("manifest.mpd");
+	assert_eq!(mpd.mediaPresentationDuration, Some(std::time::Duration::from_secs(10)));
+}
+
+#[test]
+fn manifest_location() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse"]) {
+		return;
+	}
+	let dir = Workdir::new("location");
+
+	run(&h264(
+		60,
+		"dashcmafsink sync=false target-duration=1 playlist-type=dynamic \
+		 manifest-location-url=https://cdn.example.com/live/manifest.mpd",
+	))
+	.unwrap();
+
+	let mpd = dir.manifest("manifest.mpd");
+	assert_eq!(mpd.mpdtype.as_deref(), Some("dynamic"));
+	let locations = mpd.locations.iter().map(|location| location.url.as_str()).collect::<Vec<_>>();
+	assert_eq!(locations, ["https://cdn.example.com/live/manifest.mpd"]);
+	// Segments are still relative to the manifest
+	assert!(mpd.base_url.is_empty());
+}
+
+#[test]
+fn he_aac_sampling_rate() {
+	if missing(&["cmafmux", "appsrc"]) {
+		return;
+	}
+	let dir = Workdir::new("he-aac");
+
+	// HE-AAC at 24 kHz, its SBR tool decoding to 48 kHz
+	let pipeline = launch(
+		"appsrc name=src format=time \
+		 caps=audio/mpeg,mpegversion=4,stream-format=raw,rate=24000,channels=2,codec_data=(buffer)2b1188 \
+		 ! dashcmafsink sync=false target-duration=1",
+	);
+	let src = pipeline.by_name("src").unwrap().downcast::<gst_app::AppSrc>().unwrap();
+	let _ = pipeline.set_state(gst::State::Paused);
+	for i in 0..100u64 {
+		let mut buffer = gst::Buffer::from_slice([0u8; 64]);
+		let buffer_mut = buffer.get_mut().unwrap();
+		buffer_mut.set_pts(gst::ClockTime::from_nseconds(i * 1024 * 1_000_000_000 / 24000));
+		buffer_mut.set_duration(gst::ClockTime::from_nseconds(1024 * 1_000_000_000 / 24000));
+		src.push_buffer(buffer).unwrap();
+	}
+	src.end_of_stream().unwrap();
+	play(&pipeline).unwrap();
+
+	let mpd = dir.manifest("manifest.mpd");
+	let rep = representations(&mpd)[0];
+	assert_eq!(rep.codecs.as_deref(), Some("mp4a.40.5"));
+	assert_eq!(rep.audioSamplingRate.as_deref(), Some("48000"));
+}
+
+#[test]
+fn preselection_of_main_and_commentary() {
+	if missing(&["cmafmux", "audiotestsrc", "aacparse"]) {
+		return;
+	}
+	let Some(aac) = aac_encoder() else {
+		return;
+	};
+	let dir = Workdir::new("preselection");
+
+	let pipeline = launch(&format!(
+		"audiotestsrc num-buffers=100 ! {aac} ! aacparse ! s.sink_0 \
+		 audiotestsrc num-buffers=100 ! {aac} ! aacparse ! s.sink_1 \
+		 dashcmafsink name=s sync=false target-duration=1"
+	));
+	let sink = pipeline.by_name("s").unwrap();
+	let main = sink.static_pad("sink_0").unwrap();
+	main.set_property("role", "main");
+	main.set_property("lang", "en");
+	main.set_property("preselection-id", "1");
+	main.set_property("preselection-components", "sink_1");
+	sink.static_pad("sink_1").unwrap().set_property("role", "commentary");
+	play(&pipeline).unwrap();
+
+	let mpd = dir.manifest("manifest.mpd");
+	let period = &mpd.periods[0];
+	let set_id = |role: &str| {
+		period
+			.adaptations
+			.iter()
+			.find(|adaptation| adaptation.Role[0].value.as_deref() == Some(role))
+			.and_then(|adaptation| adaptation.id.clone())
+			.unwrap()
+	};
+	let preselection = &period.pre_selections[0];
+	assert_eq!(preselection.id.as_deref(), Some("1"));
+	assert_eq!(preselection.tag, "1");
+	// The main component comes first
+	assert_eq!(preselection.preselectionComponents, format!("{} {}", set_id("main"), set_id("commentary")));
+	assert_eq!(preselection.codecs, "mp4a.40.2");
+	assert_eq!(preselection.lang.as_deref(), Some("en"));
+}
+
+#[test]
+fn test_manifest_signal() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse", "audiotestsrc", "aacparse"]) {
+		return;
+	}
+	let Some(aac) = aac_encoder() else {
+		return;
+	};
+	let dir = Workdir::new("test-manifest");
+
+	let pipeline = launch(&format!(
+		"videotestsrc num-buffers=30 ! video/x-raw,width=320,height=240,framerate=30/1 \
+		 ! x264enc key-int-max=30 bframes=0 ! h264parse ! s.sink_0 \
+		 audiotestsrc num-buffers=50 ! {aac} ! aacparse ! s.sink_1 \
+		 dashcmafsink name=s target-duration=1"
+	));
+	let sink = pipeline.by_name("s").unwrap();
+	// Without caps the pads can't be described yet
+	assert_eq!(sink.emit_by_name::<Option<String>>("generate-test-manifest", &[]), None);
+
+	// Prerolled, the caps are known but no segment is written
+	pipeline.set_state(gst::State::Paused).unwrap();
+	let (result, _, _) = pipeline.state(gst::ClockTime::from_seconds(10));
+	result.unwrap();
+	let manifest = sink.emit_by_name::<Option<String>>("generate-test-manifest", &[]).unwrap();
+	pipeline.set_state(gst::State::Null).unwrap();
+	assert!(!dir.path("manifest.mpd").exists());
+
+	let mpd = dash_mpd::parse(&manifest).unwrap();
+	assert_eq!(mpd.xmlns.as_deref(), Some("urn:mpeg:dash:schema:mpd:2011"));
+	let adaptations = &mpd.periods[0].adaptations;
+	let content_types = adaptations.iter().map(|adaptation| adaptation.contentType.as_deref()).collect::<Vec<_>>();
+	assert_eq!(content_types, [Some("video"), Some("audio")]);
+	let reps = representations(&mpd);
+	assert_eq!(reps[0].codecs.as_deref().map(|codecs| codecs.starts_with("avc1.")), Some(true));
+	assert_eq!((reps[0].width, reps[0].height), (Some(320), Some(240)));
+	assert_eq!(reps[1].codecs.as_deref(), Some("mp4a.40.2"));
+	// A single dummy segment of target-duration
+	let template = reps[0].SegmentTemplate.as_ref().unwrap();
+	assert_eq!(template.duration, Some(1000.0));
+	assert_eq!(mpd.mediaPresentationDuration, Some(std::time::Duration::from_secs(1)));
+}
+
+#[test]
+fn time_templated_locations() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse"]) {
+		return;
+	}
+	let dir = Workdir::new("time-templates");
+
+	// start-time fixes the time the tokens expand to
+	run(&h264(
+		60,
+		"dashcmafsink sync=false target-duration=1 start-time=2024-05-01T10:30:00Z \
+		 location={%Y-%m-%d}/manifest.mpd segment-location=segment_{%H%M}_%d.cmfv",
+	))
+	.unwrap();
+
+	let mpd = dir.manifest("2024-05-01/manifest.mpd");
+	let template = representations(&mpd)[0].SegmentTemplate.clone().unwrap();
+	assert_eq!(template.media.as_deref(), Some("../sink_0_segment_1030_$Number$.cmfv"));
+	let first = template.startNumber.unwrap();
+	assert!(dir.path(&format!("sink_0_segment_1030_{first}.cmfv")).is_file());
+}
+
+#[test]
+fn muxer_of_a_stream() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse"]) {
+		return;
+	}
+	let _dir = Workdir::new("get-muxer");
+
+	let pipeline = launch(&h264(60, "dashcmafsink name=s sync=false target-duration=1"));
+	let sink = pipeline.by_name("s").unwrap();
+	assert!(sink.emit_by_name::<Option<gst::Element>>("get-muxer", &[&"sink_1"]).is_none());
+
+	let muxer = sink.emit_by_name::<Option<gst::Element>>("get-muxer", &[&"sink_0"]).unwrap();
+	assert_eq!(muxer.factory().map(|factory| factory.name()).as_deref(), Some("cmafmux"));
+	// Tuning the muxer reaches the one the stream goes through
+	muxer.set_property("chunk-duration", gst::ClockTime::from_mseconds(500));
+	play(&pipeline).unwrap();
+
+	let muxer = sink.emit_by_name::<Option<gst::Element>>("get-muxer", &[&"sink_0"]).unwrap();
+	assert_eq!(muxer.property::<Option<gst::ClockTime>>("chunk-duration"), Some(gst::ClockTime::from_mseconds(500)));
+	assert_eq!(muxer.parent().as_ref(), Some(sink.upcast_ref::<gst::Object>()));
+}
+
+#[test]
+fn audio_aligned_to_video() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse", "audiotestsrc", "aacparse"]) {
+		return;
+	}
+	let Some(aac) = aac_encoder() else {
+		return;
+	};
+	let dir = Workdir::new("align-audio");
+
+	// 10 s of both, AAC frames of 1024 samples don't divide 1 s at 44.1 kHz
+	run(&format!(
+		"videotestsrc num-buffers=300 ! video/x-raw,width=320,height=240,framerate=30/1 \
+		 ! x264enc key-int-max=30 bframes=0 ! h264parse ! s.sink_0 \
+		 audiotestsrc num-buffers=431 ! audio/x-raw,rate=44100 ! {aac} ! aacparse ! s.sink_1 \
+		 dashcmafsink name=s sync=false target-duration=1 addressing=time align-audio=true"
+	))
+	.unwrap();
+
+	let mpd = dir.manifest("manifest.mpd");
+	let boundaries = |rep: &dash_mpd::Representation| {
+		let timeline = rep.SegmentTemplate.as_ref().unwrap().SegmentTimeline.as_ref().unwrap();
+		let mut ends = Vec::new();
+		let mut end = 0;
+		for s in &timeline.segments {
+			end = s.t.unwrap_or(end);
+			for _ in 0..=s.r.unwrap_or(0) {
+				end += s.d;
+				ends.push(end as i64);
+			}
+		}
+		ends
+	};
+	let reps = representations(&mpd);
+	let (video, audio) = (boundaries(reps[0]), boundaries(reps[1]));
+	assert!(video.len() >= 9, "{video:?}");
+
+	// Within a frame of 23 ms, and the manifest's millisecond rounding
+	let frame = 1024 * 1000 / 44100 + 1;
+	for (video_end, audio_end) in video.iter().zip(&audio).take(video.len() - 1) {
+		assert!((audio_end - video_end).abs() <= frame, "video {video:?} audio {audio:?}");
+	}
+}
+
+#[test]
+fn segment_write_time() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse"]) {
+		return;
+	}
+	let _dir = Workdir::new("write-time");
+
+	// Every resource takes the ingest server 150 ms to accept
+	let (url, _requests) = ingest_server(std::time::Duration::from_millis(150));
+	let pipeline = launch(&h264(
+		60,
+		&format!("dashcmafsink name=s sync=false target-duration=1 backend=ingest ingest-url={url}"),
+	));
+	wait(&pipeline).unwrap();
+	let sink = pipeline.by_name("s").unwrap();
+	let stats = sink
+		.emit_by_name::<Option<gst::Structure>>("get-stream-stats", &[&"sink_0"])
+		.unwrap();
+	pipeline.set_state(gst::State::Null).unwrap();
+
+	let last = stats.get::<f64>("segment-write-ms").unwrap();
+	let max = stats.get::<f64>("max-segment-write-ms").unwrap();
+	assert!(last >= 150.0, "{last}");
+	assert!(max >= last);
+}
+
+#[test]
+fn inline_init() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse"]) {
+		return;
+	}
+	let dir = Workdir::new("inline-init");
+
+	run(&h264(60, "dashcmafsink sync=false target-duration=1 inline-init=true")).unwrap();
+	let mpd = dir.manifest("manifest.mpd");
+	let template = representations(&mpd)[0].SegmentTemplate.clone().unwrap();
+	let init = template.initialization.unwrap();
+	let data = init.strip_prefix("data:video/mp4;base64,").expect("no data URI");
+	let data = glib::base64_decode(data);
+	assert_eq!(&data[4..8], b"ftyp");
+
+	assert!(!dir.path("sink_0_init.cmfi").exists());
+	assert!(dir.path("sink_0_segment_0.cmfv").exists());
+}
+
+#[test]
+fn concurrent_manifest_writes() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse"]) {
+		return;
+	}
+	let dir = Workdir::new("concurrent-manifest");
+
+	let pipeline = launch(&format!(
+		"{} {} {} {} dashcmafsink name=s sync=false target-duration=1",
+		h264(150, "s.sink_0"),
+		h264(150, "s.sink_1"),
+		h264(150, "s.sink_2"),
+		h264(150, "s.sink_3")
+	));
+
+	// Written atomically and from a snapshot of every stream, a manifest
+	// never has fewer Representations than an earlier one
+	let done = Arc::new(std::sync::atomic::AtomicBool::new(false));
+	let reader = {
+		let done = done.clone();
+		let path = dir.path("manifest.mpd");
+		std::thread::spawn(move || {
+			let mut counts = Vec::new();
+			while !done.load(std::sync::atomic::Ordering::SeqCst) {
+				if let Ok(xml) = std::fs::read_to_string(&path) {
+					let mpd = dash_mpd::parse(&xml).expect("Invalid manifest");
+					counts.push(representations(&mpd).len());
+				}
+			}
+			counts
+		})
+	};
+	let result = play(&pipeline);
+	done.store(true, std::sync::atomic::Ordering::SeqCst);
+	let counts = reader.join().unwrap();
+	result.unwrap();
+
+	assert!(counts.windows(2).all(|pair| pair[0] <= pair[1]), "{counts:?}");
+	assert_eq!(representations(&dir.manifest("manifest.mpd")).len(), 4);
+}
+
+#[test]
+fn output_policies() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse"]) {
+		return;
+	}
+	let dir = Workdir::new("output-policy");
+	// Left by a longer previous run, next to a file of someone else
+	let populate = || {
+		for location in ["manifest.mpd", "sink_0_init.cmfi", "sink_0_segment_0.cmfv", "sink_0_segment_7.cmfv", "notes.txt"] {
+			std::fs::write(dir.path(location), "old").unwrap();
+		}
+	};
+	let sink = |policy: &str| h264(30, &format!("dashcmafsink sync=false target-duration=1 output-policy={policy}"));
+
+	populate();
+	run(&sink("overwrite")).unwrap();
+	assert_ne!(std::fs::read(dir.path("sink_0_segment_0.cmfv")).unwrap(), b"old");
+	assert!(dir.path("sink_0_segment_7.cmfv").exists());
+
+	populate();
+	assert!(run(&sink("fail-if-exists")).is_err());
+	assert_eq!(std::fs::read(dir.path("manifest.mpd")).unwrap(), b"old");
+
+	populate();
+	run(&sink("clean")).unwrap();
+	assert!(!dir.path("sink_0_segment_7.cmfv").exists());
+	assert_ne!(std::fs::read(dir.path("sink_0_segment_0.cmfv")).unwrap(), b"old");
+	assert!(dir.path("notes.txt").exists());
+}
+
+#[test]
+fn representation_directories() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse"]) {
+		return;
+	}
+	let dir = Workdir::new("representation-dirs");
+
+	run(&format!(
+		"{} {} dashcmafsink name=s sync=false target-duration=1 segment-location=$RepresentationID$/seg_$Number$.cmfv",
+		h264(60, "s.sink_0"),
+		h264(60, "s.sink_1")
+	))
+	.unwrap();
+
+	let mpd = dir.manifest("manifest.mpd");
+	for rep in representations(&mpd) {
+		let pad_name = rep.id.as_deref().unwrap();
+		let template = rep.SegmentTemplate.as_ref().unwrap();
+		assert_eq!(template.media.as_deref(), Some(format!("{pad_name}/seg_$Number$.cmfv").as_str()));
+		let first = template.startNumber.unwrap_or(1);
+		assert!(dir.path(&format!("{pad_name}/seg_{first}.cmfv")).exists());
+	}
+	assert!(!dir.path("sink_0_seg_0.cmfv").exists());
+}
+
+#[test]
+fn frozen_manifest_catches_up() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse"]) {
+		return;
+	}
+	let dir = Workdir::new("manifest-frozen");
+
+	let pipeline = launch(&h264(90, "dashcmafsink name=s sync=false target-duration=1 manifest-frozen=true"));
+	wait(&pipeline).unwrap();
+	// Segments are written, the manifest isn't
+	assert!(dir.path("sink_0_segment_2.cmfv").exists());
+	assert!(!dir.path("manifest.mpd").exists());
+
+	let sink = pipeline.by_name("s").unwrap();
+	sink.set_property("manifest-frozen", false);
+	pipeline.set_state(gst::State::Null).unwrap();
+
+	let mpd = dir.manifest("manifest.mpd");
+	assert_eq!(mpd.mediaPresentationDuration, Some(std::time::Duration::from_secs(3)));
+}
+
+#[test]
+fn reset_starts_a_new_period() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse"]) {
+		return;
+	}
+	let dir = Workdir::new("reset");
+
+	let pipeline = launch(
+		"videotestsrc is-live=true num-buffers=120 ! video/x-raw,width=320,height=240,framerate=30/1 \
+		 ! x264enc key-int-max=30 bframes=0 tune=zerolatency ! h264parse ! dashcmafsink name=s target-duration=1",
+	);
+	let sink = pipeline.by_name("s").unwrap();
+	assert!(!sink.emit_by_name::<bool>("reset", &[&None::<String>]));
+
+	// Reset once the first segment is out, the stream going on
+	let resetter = std::thread::spawn(move || {
+		while sink.property::<u64>("segments-written") == 0 {
+			std::thread::sleep(std::time::Duration::from_millis(10));
+		}
+		sink.emit_by_name::<bool>("reset", &[&Some("archive.mpd")])
+	});
+	play(&pipeline).unwrap();
+	assert!(resetter.join().unwrap());
+
+	let archive = dir.manifest("archive.mpd");
+	assert_eq!(archive.periods.len(), 1);
+
+	let mpd = dir.manifest("manifest.mpd");
+	let period = mpd.periods.last().unwrap();
+	assert_ne!(period.id, archive.periods[0].id);
+	assert!(period.start.is_some_and(|start| start >= std::time::Duration::from_secs(1)));
+	let rep = &period.adaptations[0].representations[0];
+	assert!(rep.SegmentTemplate.as_ref().unwrap().presentationTimeOffset.is_some());
+}
+
+#[test]
+fn segments_start_with_styp() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse"]) {
+		return;
+	}
+	let dir = Workdir::new("styp");
+
+	run(&h264(60, "dashcmafsink sync=false target-duration=1 ensure-styp=true")).unwrap();
+	for location in ["sink_0_segment_0.cmfv", "sink_0_segment_1.cmfv"] {
+		let segment = std::fs::read(dir.path(location)).unwrap();
+		assert_eq!(&segment[4..8], b"styp", "{location}");
+		assert_eq!(&segment[8..12], b"cmfs");
+		// Followed by the fragment itself
+		let size = u32::from_be_bytes(segment[0..4].try_into().unwrap()) as usize;
+		assert!(matches!(&segment[size + 4..size + 8], b"moof" | b"prft" | b"emsg"));
+	}
+	// Init segments are untouched
+	let init = std::fs::read(dir.path("sink_0_init.cmfi")).unwrap();
+	assert_eq!(&init[4..8], b"ftyp");
+}
+
+#[test]
+fn manifest_per_period() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse"]) {
+		return;
+	}
+	let dir = Workdir::new("per-period");
+
+	let pipeline = launch(
+		"videotestsrc is-live=true num-buffers=150 ! video/x-raw,width=320,height=240,framerate=30/1 \
+		 ! x264enc key-int-max=30 bframes=0 tune=zerolatency ! h264parse \
+		 ! dashcmafsink name=s target-duration=1 per-period-manifest=true",
+	);
+	let sink = pipeline.by_name("s").unwrap();
+	// Two resets, each after a new segment, close two Periods
+	let resetter = std::thread::spawn(move || {
+		for segments in [1, 3] {
+			while sink.property::<u64>("segments-written") < segments {
+				std::thread::sleep(std::time::Duration::from_millis(10));
+			}
+			assert!(sink.emit_by_name::<bool>("reset", &[&None::<String>]));
+		}
+	});
+	play(&pipeline).unwrap();
+	resetter.join().unwrap();
+
+	let mut standalone = std::fs::read_dir(&dir.path)
+		.unwrap()
+		.map(|entry| entry.unwrap().file_name().into_string().unwrap())
+		.filter(|name| name.starts_with("manifest_") && name.ends_with(".mpd"))
+		.collect::<Vec<_>>();
+	standalone.sort();
+	assert_eq!(standalone.len(), 2, "{standalone:?}");
+
+	// Each closed Period on its own, the live manifest going on with the third
+	let mut ids = standalone
+		.iter()
+		.map(|location| {
+			let mpd = dir.manifest(location);
+			assert_eq!(mpd.mpdtype.as_deref(), Some("static"));
+			assert_eq!(mpd.periods.len(), 1);
+			assert!(!representations(&mpd).is_empty());
+			mpd.periods[0].id.clone()
+		})
+		.collect::<Vec<_>>();
+	ids.extend(dir.manifest("manifest.mpd").periods.iter().map(|period| period.id.clone()));
+	ids.sort();
+	ids.dedup();
+	assert_eq!(ids.len(), 3, "{ids:?}");
+}
+
+#[test]
+fn segments_without_keyframe_start() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse"]) {
+		return;
+	}
+	let _dir = Workdir::new("keyframe-start");
+
+	let pipeline = launch(&h264(60, "dashcmafsink name=s sync=false target-duration=1"));
+	let sink = pipeline.by_name("s").unwrap().downcast::<gst::Bin>().unwrap();
+	pipeline.set_state(gst::State::Ready).unwrap();
+	// Fragments as an encoder not cutting keyframes on the boundaries
+	// would give, their first sample being a delta unit
+	let muxer = sink
+		.iterate_recurse()
+		.into_iter()
+		.flatten()
+		.find(|element| element.factory().is_some_and(|factory| factory.name() == "cmafmux"))
+		.unwrap();
+	muxer.static_pad("src").unwrap().add_probe(gst::PadProbeType::BUFFER_LIST, |_pad, info| {
+		if let Some(gst::PadProbeData::BufferList(ref mut list)) = info.data {
+			if let Some(sample) = list.make_mut().get_mut(1) {
+				sample.set_flags(gst::BufferFlags::DELTA_UNIT);
+			}
+		}
+		gst::PadProbeReturn::Ok
+	});
+
+	let warned = warnings(&pipeline);
+	play(&pipeline).unwrap();
+	let warned = warned.lock().unwrap();
+	assert_eq!(warned.iter().filter(|warning| warning.contains("start with a keyframe")).count(), 1, "{warned:?}");
+}
+
+#[test]
+fn aac_timeline_doesnt_drift() {
+	if missing(&["cmafmux", "audiotestsrc", "aacparse"]) {
+		return;
+	}
+	let Some(aac) = aac_encoder() else {
+		return;
+	};
+	let dir = Workdir::new("aac-drift");
+
+	// 10 minutes of 1024 sample frames at 44.1 kHz
+	let frames = 25_840u64;
+	run(&format!(
+		"audiotestsrc num-buffers={frames} samplesperbuffer=1024 ! audio/x-raw,rate=44100 ! {aac} ! aacparse \
+		 ! dashcmafsink sync=false target-duration=2 addressing=time"
+	))
+	.unwrap();
+
+	let mpd = dir.manifest("manifest.mpd");
+	let template = representations(&mpd)[0].SegmentTemplate.clone().unwrap();
+	assert_eq!(template.timescale, Some(44_100));
+	let timeline = template.SegmentTimeline.unwrap();
+	let (mut end, mut first) = (0, None);
+	for s in &timeline.segments {
+		end = s.t.unwrap_or(end);
+		first.get_or_insert(end);
+		for _ in 0..=s.r.unwrap_or(0) {
+			// Every segment is whole frames
+			assert_eq!(s.d % 1024, 0, "{s:?}");
+			end += s.d;
+		}
+	}
+
+	let duration = end - first.unwrap();
+	assert!(duration.abs_diff(frames * 1024) <= 1024, "{duration} ticks for {frames} frames");
+}
+
+#[test]
+fn custom_writer_signals() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse"]) {
+		return;
+	}
+	let dir = Workdir::new("custom-writer");
+
+	let pipeline = launch(&h264(90, "dashcmafsink name=s sync=false target-duration=1 custom-writer=true"));
+	let sink = pipeline.by_name("s").unwrap();
+	let written = Arc::new(Mutex::new(Vec::<(&str, String, glib::Bytes)>::new()));
+	for signal in ["write-init-segment", "write-media-segment", "write-manifest"] {
+		let written = written.clone();
+		sink.connect(signal, false, move |args| {
+			let location = args[1].get::<String>().unwrap();
+			let data = args[2].get::<glib::Bytes>().unwrap();
+			written.lock().unwrap().push((signal, location, data));
+			Some(true.to_value())
+		});
+	}
+	play(&pipeline).unwrap();
+
+	// Nothing goes to the backend
+	assert!(std::fs::read_dir(&dir.path).unwrap().next().is_none());
+
+	let written = written.lock().unwrap();
+	assert_eq!(written[0].0, "write-init-segment");
+	assert_eq!(written[0].1, "sink_0_init.cmfi");
+	assert_eq!(&written[0].2[4..8], b"ftyp");
+	let segments = written
+		.iter()
+		.filter(|(signal, _, _)| *signal == "write-media-segment")
+		.map(|(_, location, _)| location.as_str())
+		.collect::<Vec<_>>();
+	assert_eq!(segments, ["sink_0_segment_0.cmfv", "sink_0_segment_1.cmfv", "sink_0_segment_2.cmfv"]);
+	// The last manifest lists every segment
+	let (signal, location, manifest) = written.last().unwrap();
+	assert_eq!((*signal, location.as_str()), ("write-manifest", "manifest.mpd"));
+	let mpd = dash_mpd::parse(std::str::from_utf8(manifest).unwrap()).unwrap();
+	assert_eq!(mpd.mediaPresentationDuration, Some(std::time::Duration::from_secs(3)));
+}
+
+/// Releases the `pad_name` pad of the sink `s` once `segments` segments were
+/// written, the valve `v` feeding it dropping the data from then on. Returns
+/// the number of files written by then.
+fn release_after(pipeline: &gst::Pipeline, dir: &Workdir, segments: u64, pad_name: &str) -> std::thread::JoinHandle<usize> {
+	let sink = pipeline.by_name("s").unwrap();
+	let valve = pipeline.by_name("v").unwrap();
+	let pad_name = pad_name.to_string();
+	let path = dir.path("");
+	std::thread::spawn(move || {
+		while sink.property::<u64>("segments-written") < segments {
+			std::thread::sleep(std::time::Duration::from_millis(10));
+		}
+		valve.set_property("drop", true);
+		let pad = sink.static_pad(&pad_name).unwrap();
+		if let Some(peer) = pad.peer() {
+			let _ = peer.unlink(&pad);
+		}
+		sink.release_request_pad(&pad);
+		std::fs::read_dir(path).unwrap().count()
+	})
+}
+
+#[test]
+fn release_pad_while_streaming() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse", "valve"]) {
+		return;
+	}
+	let dir = Workdir::new("release");
+
+	let pipeline = launch(
+		"videotestsrc is-live=true num-buffers=150 ! video/x-raw,width=320,height=240,framerate=30/1 \
+		 ! x264enc key-int-max=30 bframes=0 tune=zerolatency ! h264parse ! s.sink_0 \
+		 videotestsrc is-live=true num-buffers=150 ! video/x-raw,width=160,height=120,framerate=30/1 \
+		 ! x264enc key-int-max=30 bframes=0 tune=zerolatency ! h264parse ! valve name=v ! s.sink_1 \
+		 dashcmafsink name=s target-duration=1",
+	);
+	let releaser = release_after(&pipeline, &dir, 3, "sink_1");
+	play(&pipeline).unwrap();
+	releaser.join().unwrap();
+
+	// The released stream stays in the manifest, ended with a timeline
+	let mpd = dir.manifest("manifest.mpd");
+	let reps = representations(&mpd);
+	assert_eq!(reps.len(), 2);
+	let released = reps.iter().find(|rep| rep.id.as_deref() == Some("sink_1")).unwrap();
+	assert!(released.SegmentTemplate.as_ref().unwrap().SegmentTimeline.is_some());
+	let ongoing = reps.iter().find(|rep| rep.id.as_deref() == Some("sink_0")).unwrap();
+	assert!(ongoing.SegmentTemplate.as_ref().unwrap().SegmentTimeline.is_none());
+	assert!(dir.path("sink_0_segment_4.cmfv").exists());
+
+	// Names aren't reused, nor requested twice
+	let sink = pipeline.by_name("s").unwrap();
+	assert!(sink.static_pad("sink_1").is_none());
+	assert!(sink.request_pad_simple("sink_0").is_none());
+	assert_eq!(sink.request_pad_simple("sink_%u").unwrap().name(), "sink_2");
+}
+
+#[test]
+fn muxed_stream_handed_over() {
+	if missing(&["isofmp4mux", "videotestsrc", "x264enc", "h264parse", "audiotestsrc", "aacparse", "valve"]) {
+		return;
+	}
+	let Some(aac) = aac_encoder() else {
+		return;
+	};
+	let dir = Workdir::new("handover");
+
+	let pipeline = launch(&format!(
+		"videotestsrc is-live=true num-buffers=150 ! video/x-raw,width=320,height=240,framerate=30/1 \
+		 ! x264enc key-int-max=30 bframes=0 tune=zerolatency ! h264parse ! valve name=v ! s.sink_0 \
+		 audiotestsrc is-live=true num-buffers=250 ! {aac} ! aacparse ! s.sink_1 \
+		 dashcmafsink name=s target-duration=1 mux-streams=true"
+	));
+	let releaser = release_after(&pipeline, &dir, 2, "sink_0");
+	play(&pipeline).unwrap();
+	let written = releaser.join().unwrap();
+
+	// The muxed stream goes on with the audio track, under its first name
+	let mpd = dir.manifest("manifest.mpd");
+	let reps = representations(&mpd);
+	assert_eq!(reps.len(), 1);
+	assert_eq!(reps[0].id.as_deref(), Some("sink_0"));
+	assert!(reps[0].SegmentTemplate.as_ref().unwrap().SegmentTimeline.is_none());
+	assert!(std::fs::read_dir(dir.path("")).unwrap().count() > written);
+}
+
+#[test]
+fn sub_second_segment_duration() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse"]) {
+		return;
+	}
+	let dir = Workdir::new("segment-duration");
+
+	let pipeline = launch(&h264(90, "dashcmafsink name=s sync=false segment-duration=1500000000"));
+	let sink = pipeline.by_name("s").unwrap().downcast::<gst::Bin>().unwrap();
+	// The deprecated alias rounds up to whole seconds
+	assert_eq!(sink.property::<u32>("target-duration"), 2);
+	let muxer = sink
+		.iterate_recurse()
+		.into_iter()
+		.flatten()
+		.find(|element| element.factory().is_some_and(|factory| factory.name() == "cmafmux"))
+		.unwrap();
+	assert_eq!(muxer.property::<gst::ClockTime>("fragment-duration"), gst::ClockTime::from_mseconds(1500));
+	play(&pipeline).unwrap();
+
+	let mpd = dir.manifest("manifest.mpd");
+	let template = representations(&mpd)[0].SegmentTemplate.clone().unwrap();
+	assert_eq!(template.duration, Some(1500.0 * template.timescale.unwrap_or(1) as f64 / 1000.0));
+}
+
+#[test]
+fn new_period_splits_segments() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse"]) {
+		return;
+	}
+	let dir = Workdir::new("new-period");
+
+	let pipeline = launch(
+		"videotestsrc is-live=true num-buffers=120 ! video/x-raw,width=320,height=240,framerate=30/1 \
+		 ! x264enc key-int-max=30 bframes=0 tune=zerolatency ! h264parse ! dashcmafsink name=s target-duration=1",
+	);
+	let sink = pipeline.by_name("s").unwrap();
+	let splitter = std::thread::spawn(move || {
+		while sink.property::<u64>("segments-written") == 0 {
+			std::thread::sleep(std::time::Duration::from_millis(10));
+		}
+		sink.emit_by_name::<bool>("new-period", &[&"ad-break", &u64::MAX])
+	});
+	play(&pipeline).unwrap();
+	assert!(splitter.join().unwrap());
+
+	let mpd = dir.manifest("manifest.mpd");
+	assert_eq!(mpd.periods.len(), 2);
+	assert_eq!(mpd.periods[1].id.as_deref(), Some("ad-break"));
+	assert!(mpd.periods[0].duration.is_some());
+	// Each Period lists its own segments, numbered on
+	let numbers = mpd
+		.periods
+		.iter()
+		.map(|period| period.adaptations[0].representations[0].SegmentTemplate.as_ref().unwrap().startNumber)
+		.collect::<Vec<_>>();
+	assert!(numbers[1] > numbers[0]);
+}
+
+#[test]
+fn stalled_stream_is_unhealthy() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse", "valve"]) {
+		return;
+	}
+	let _dir = Workdir::new("healthy");
+
+	let pipeline = launch(
+		"videotestsrc is-live=true ! video/x-raw,width=320,height=240,framerate=30/1 \
+		 ! x264enc key-int-max=30 bframes=0 tune=zerolatency ! h264parse ! s.sink_0 \
+		 videotestsrc is-live=true ! video/x-raw,width=160,height=120,framerate=30/1 \
+		 ! x264enc key-int-max=30 bframes=0 tune=zerolatency ! h264parse ! valve name=v ! s.sink_1 \
+		 dashcmafsink name=s target-duration=1",
+	);
+	let sink = pipeline.by_name("s").unwrap();
+	let valve = pipeline.by_name("v").unwrap();
+	let bus = pipeline.bus().unwrap();
+	pipeline.set_state(gst::State::Playing).unwrap();
+
+	// Stall sink_1 once both streams are going
+	while sink.property::<u64>("segments-written") < 2 {
+		std::thread::sleep(std::time::Duration::from_millis(10));
+	}
+	assert!(sink.property::<bool>("healthy"));
+	valve.set_property("drop", true);
+
+	let msg = bus
+		.timed_pop_filtered(gst::ClockTime::from_seconds(10), &[gst::MessageType::Warning, gst::MessageType::Error])
+		.expect("no warning posted");
+	let gst::MessageView::Warning(warning) = msg.view() else {
+		panic!("unexpected error {msg:?}");
+	};
+	assert_eq!(warning.error().to_string(), "Streams stalled");
+	assert!(warning.debug().is_some_and(|debug| debug.contains("sink_1") && !debug.contains("sink_0")));
+	assert!(!sink.property::<bool>("healthy"));
+	pipeline.set_state(gst::State::Null).unwrap();
+}
+
+#[test]
+fn auto_latency_follows_long_gops() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse"]) {
+		return;
+	}
+	let _dir = Workdir::new("auto-latency");
+
+	// GOPs of 3 s, well above the configured latency
+	let pipeline = launch(
+		"videotestsrc num-buffers=240 ! video/x-raw,width=320,height=240,framerate=30/1 \
+		 ! x264enc key-int-max=90 bframes=0 ! h264parse \
+		 ! dashcmafsink name=s sync=false target-duration=1 latency=500000000 auto-latency=true",
+	);
+	let sink = pipeline.by_name("s").unwrap().downcast::<gst::Bin>().unwrap();
+	wait(&pipeline).unwrap();
+
+	let muxer = sink
+		.iterate_recurse()
+		.into_iter()
+		.flatten()
+		.find(|element| element.factory().is_some_and(|factory| factory.name() == "cmafmux"))
+		.unwrap();
+	assert_eq!(muxer.property::<gst::ClockTime>("latency"), gst::ClockTime::from_seconds(3));
+	let mut query = gst::query::Latency::new();
+	assert!(sink.query(&mut query));
+	assert!(query.result().1 >= gst::ClockTime::from_seconds(3));
+	pipeline.set_state(gst::State::Null).unwrap();
+}
+
+#[test]
+fn manifest_write_modes() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse"]) {
+		return;
+	}
+	let _dir = Workdir::new("manifest-write-mode");
+
+	// Media segments and manifests written with each mode, by two streams
+	let writes = |mode: &str| {
+		let pipeline = launch(&format!(
+			"{} {} dashcmafsink name=s sync=false target-duration=1 custom-writer=true \
+			 manifest-write-mode={mode} manifest-interval=4",
+			h264(150, "s.sink_0"),
+			h264(150, "s.sink_1")
+		));
+		let sink = pipeline.by_name("s").unwrap();
+		let counts = Arc::new(Mutex::new((0, 0)));
+		for signal in ["write-init-segment", "write-media-segment", "write-manifest"] {
+			let counts = counts.clone();
+			sink.connect(signal, false, move |_| {
+				let mut counts = counts.lock().unwrap();
+				match signal {
+					"write-media-segment" => counts.0 += 1,
+					"write-manifest" => counts.1 += 1,
+					_ => (),
+				}
+				Some(true.to_value())
+			});
+		}
+		play(&pipeline).unwrap();
+		let counts = *counts.lock().unwrap();
+		counts
+	};
+
+	let (segments, manifests) = writes("every-segment");
+	assert_eq!(segments, 10);
+	assert_eq!(manifests, segments);
+
+	let (segments, manifests) = writes("on-eos");
+	assert_eq!((segments, manifests), (10, 1));
+
+	// Every 4 segments of any stream, and once at EOS
+	let (segments, manifests) = writes("interval");
+	assert_eq!((segments, manifests), (10, 3));
+}
+
+#[test]
+fn init_segment_without_discont() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse"]) {
+		return;
+	}
+	let dir = Workdir::new("header-without-discont");
+
+	let pipeline = launch(&h264(60, "dashcmafsink name=s sync=false target-duration=1"));
+	let sink = pipeline.by_name("s").unwrap().downcast::<gst::Bin>().unwrap();
+	// As a muxer only flagging its init segment HEADER would push it
+	let muxer = sink
+		.iterate_recurse()
+		.into_iter()
+		.flatten()
+		.find(|element| element.factory().is_some_and(|factory| factory.name() == "cmafmux"))
+		.unwrap();
+	muxer
+		.static_pad("src")
+		.unwrap()
+		.add_probe(gst::PadProbeType::BUFFER | gst::PadProbeType::BUFFER_LIST, |_pad, info| {
+			let buffer = match info.data {
+				Some(gst::PadProbeData::Buffer(ref mut buffer)) => Some(buffer.make_mut()),
+				Some(gst::PadProbeData::BufferList(ref mut list)) => list.make_mut().get_mut(0),
+				_ => None,
+			};
+			if let Some(buffer) = buffer.filter(|buffer| buffer.flags().contains(gst::BufferFlags::HEADER)) {
+				buffer.unset_flags(gst::BufferFlags::DISCONT);
+			}
+			gst::PadProbeReturn::Ok
+		});
+	play(&pipeline).unwrap();
+
+	let init = std::fs::read(dir.path("sink_0_init.cmfi")).unwrap();
+	assert_eq!(&init[4..8], b"ftyp");
+	let segment = std::fs::read(dir.path("sink_0_segment_0.cmfv")).unwrap();
+	assert_ne!(&segment[4..8], b"ftyp");
+}
+
+#[test]
+fn start_number_per_pad() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse"]) {
+		return;
+	}
+	let dir = Workdir::new("start-number");
+
+	let pipeline = launch(&format!(
+		"{} {} dashcmafsink name=s sync=false target-duration=1",
+		h264(60, "s.sink_0"),
+		h264(60, "s.sink_1")
+	));
+	let sink = pipeline.by_name("s").unwrap();
+	sink.static_pad("sink_0").unwrap().set_property("start-number", 100u64);
+	play(&pipeline).unwrap();
+
+	assert!(dir.path("sink_0_segment_100.cmfv").exists());
+	assert!(!dir.path("sink_0_segment_0.cmfv").exists());
+	// The other stream keeps start-segment-number
+	assert!(dir.path("sink_1_segment_0.cmfv").exists());
+	let mpd = dir.manifest("manifest.mpd");
+	let start_number = |id: &str| {
+		let rep = representations(&mpd).into_iter().find(|rep| rep.id.as_deref() == Some(id)).unwrap();
+		rep.SegmentTemplate.as_ref().unwrap().startNumber
+	};
+	assert_eq!(start_number("sink_0"), Some(100));
+	assert_eq!(start_number("sink_1"), Some(0));
+}
+
+#[test]
+fn checksums_of_segments() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse"]) {
+		return;
+	}
+	let dir = Workdir::new("checksums");
+
+	run(&h264(90, "dashcmafsink sync=false target-duration=1 checksum-manifest=true")).unwrap();
+
+	let json = std::fs::read_to_string(dir.path("manifest.checksums.json")).unwrap();
+	let entry = regex::Regex::new(r#""location": "([^"]+)", "sha256": "([0-9a-f]{64})""#).unwrap();
+	let entries = entry
+		.captures_iter(&json)
+		.map(|captures| (captures[1].to_string(), captures[2].to_string()))
+		.collect::<Vec<_>>();
+	assert_eq!(entries.len(), 3, "{json}");
+	for (location, sha256) in entries {
+		let data = std::fs::read(dir.path(&location)).unwrap();
+		let expected = glib::compute_checksum_for_data(glib::ChecksumType::Sha256, &data).unwrap();
+		assert_eq!(sha256, expected.as_str(), "{location}");
+	}
+}
+
+#[test]
+fn negotiation_timeout() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse", "appsrc"]) {
+		return;
+	}
+	let _dir = Workdir::new("negotiation-timeout");
+
+	// appsrc never pushes anything into sink_1
+	let pipeline = launch(
+		"videotestsrc is-live=true ! video/x-raw,width=320,height=240,framerate=30/1 \
+		 ! x264enc key-int-max=30 bframes=0 tune=zerolatency ! h264parse ! s.sink_0 \
+		 appsrc is-live=true format=time ! s.sink_1 \
+		 dashcmafsink name=s target-duration=1 negotiation-timeout=1000000000",
+	);
+	let started = std::time::Instant::now();
+	let err = play(&pipeline).unwrap_err();
+	assert!(err.contains("sink_1 did not negotiate caps"), "{err}");
+	assert!(!err.contains("sink_0"), "{err}");
+	assert!(started.elapsed() >= std::time::Duration::from_secs(1));
+}
+
+#[test]
+fn b_frames_keep_av_sync() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse", "audiotestsrc", "aacparse"]) {
+		return;
+	}
+	let Some(aac) = aac_encoder() else {
+		return;
+	};
+
+	for reorder_delay_offset in [false, true] {
+		let dir = Workdir::new(&format!("b-frames-{reorder_delay_offset}"));
+		run(&format!(
+			"videotestsrc num-buffers=90 ! video/x-raw,width=320,height=240,framerate=30/1 \
+			 ! x264enc key-int-max=30 bframes=2 b-adapt=false ! h264parse ! s.sink_0 \
+			 audiotestsrc num-buffers=130 ! {aac} ! aacparse ! s.sink_1 \
+			 dashcmafsink name=s sync=false target-duration=1 reorder-delay-offset={reorder_delay_offset}"
+		))
+		.unwrap();
+
+		let mpd = dir.manifest("manifest.mpd");
+		let offset = |id: &str| {
+			representations(&mpd)
+				.into_iter()
+				.find(|rep| rep.id.as_deref() == Some(id))
+				.and_then(|rep| rep.SegmentTemplate.as_ref()?.presentationTimeOffset)
+		};
+		// The audio is never shifted, the video only with the option and by
+		// no more than the delay of its two B-frames
+		assert_eq!(offset("sink_1"), None);
+		match reorder_delay_offset {
+			false => assert_eq!(offset("sink_0"), None),
+			true => assert!(offset("sink_0").unwrap_or(0) < 100),
+		}
+	}
+}
+
+#[test]
+fn init_combined_with_first_segment() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse"]) {
+		return;
+	}
+	let dir = Workdir::new("combine-init");
+
+	run(&h264(60, "dashcmafsink sync=false target-duration=1 combine-init-first-segment=true")).unwrap();
+	assert!(!dir.path("init.cmfi").exists());
+
+	let mpd = dir.manifest("manifest.mpd");
+	let template = representations(&mpd)[0].SegmentTemplate.clone().unwrap();
+	assert_eq!(template.initialization, None);
+	let initialization = template.Initialization.unwrap();
+	let first = template.media.unwrap().replace("$Number$", &template.startNumber.unwrap().to_string());
+	assert_eq!(initialization.sourceURL.as_deref(), Some(first.as_str()));
+
+	// The init segment, then the media segment right after its range
+	let data = std::fs::read(dir.path(&first)).unwrap();
+	let (_, end) = initialization.range.as_deref().unwrap().split_once('-').unwrap();
+	let init_size = end.parse::<usize>().unwrap() + 1;
+	assert!(init_size < data.len());
+	assert_eq!(&data[4..8], b"ftyp");
+	assert!(data[..init_size].windows(4).any(|name| name == b"moov"));
+	assert!([&b"styp"[..], b"moof"].contains(&&data[init_size + 4..init_size + 8]));
+}
+
+#[test]
+fn declared_bandwidth() {
+	if missing(&["cmafmux", "videotestsrc", "x264enc", "h264parse"]) {
+		return;
+	}
+	let dir = Workdir::new("bandwidth");
+
+	let pipeline = launch(&h264(90, "dashcmafsink name=s sync=false target-duration=1"));
+	let sink = pipeline.by_name("s").unwrap();
+	sink.static_pad("sink_0").unwrap().set_property("bandwidth", 5_000_000u64);
+	wait(&pipeline).unwrap();
+	let stats = sink
+		.emit_by_name::<Option<gst::Structure>>("get-stream-stats", &[&"sink_0"])
+		.unwrap();
+	pipeline.set_state(gst::State::Null).unwrap();
+
+	// Whatever was measured from the segments
+	let mpd = dir.manifest("manifest.mpd");
+	assert_eq!(representations(&mpd)[0].bandwidth, Some(5_000_000));
+	assert_ne!(stats.get::<u64>("bandwidth").unwrap(), 5_000_000);
+}